@@ -3,7 +3,10 @@ use adblock::lists::ParseOptions;
 use adblock::request::Request;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct AdblockStats {
@@ -33,19 +36,59 @@ impl AdblockEngine {
         let opts = ParseOptions::default();
         let engine = Engine::from_rules(rules.iter().map(|s| s.as_str()), opts);
 
-        Ok(Self {
+        Ok(Self::with_engine(engine))
+    }
+
+    /// То же, что `from_filter_list`, но скомпилированный движок кэшируется в `cache_dir`
+    /// (каталог adblock профиля). Ключ кэша — SHA-256 текста списка, битый кэш
+    /// пересобирается, а кэши прежних версий списка удаляются.
+    pub fn from_filter_list_cached(list: &str, cache_dir: &Path) -> Result<Self> {
+        let digest = Sha256::digest(list.as_bytes());
+        let name = format!(
+            "engine-{}.dat",
+            digest[..16]
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>()
+        );
+        let cache = cache_dir.join(&name);
+        if let Ok(bytes) = fs::read(&cache) {
+            let mut engine = Engine::default();
+            if engine.deserialize(&bytes).is_ok() {
+                return Ok(Self::with_engine(engine));
+            }
+        }
+        let this = Self::from_filter_list(list)?;
+        fs::create_dir_all(cache_dir)?;
+        fs::write(&cache, this.engine.serialize())?;
+        for entry in fs::read_dir(cache_dir)?.flatten() {
+            let file = entry.file_name();
+            let file = file.to_string_lossy();
+            if file != name && file.starts_with("engine-") && file.ends_with(".dat") {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        Ok(this)
+    }
+
+    fn with_engine(engine: Engine) -> Self {
+        Self {
             engine,
             stats: AdblockStats::default(),
             enabled: true,
             whitelist: Vec::new(),
             last_blocked: VecDeque::with_capacity(32),
-        })
+        }
     }
 
     pub fn set_enabled(&mut self, enabled: bool) {
         self.enabled = enabled;
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
     pub fn add_whitelist_host(&mut self, host: String) {
         self.whitelist.push(host);
     }
@@ -57,8 +100,10 @@ impl AdblockEngine {
         }
 
         // В adblock 0.12.x используется check_network_request(&Request)
-        let req = Request::new(url, source_url, resource_type);
-        let matched = self.engine.check_network_request(&req).matched;
+        let matched = match Request::new(url, source_url, resource_type) {
+            Ok(req) => self.engine.check_network_request(&req).matched,
+            Err(_) => false,
+        };
 
         if matched {
             self.stats.blocked += 1;
@@ -69,6 +114,12 @@ impl AdblockEngine {
         matched
     }
 
+    /// Обнуляет счётчики и список последних блокировок.
+    pub fn reset_stats(&mut self) {
+        self.stats = AdblockStats::default();
+        self.last_blocked.clear();
+    }
+
     pub fn last_blocked(&self) -> Vec<String> {
         self.last_blocked.iter().cloned().collect()
     }
@@ -95,4 +146,33 @@ mod tests {
             "script"
         ));
     }
+
+    #[test]
+    fn reuses_cached_engine() {
+        let dir = std::env::temp_dir().join(format!("plus-adblock-cache-{}", std::process::id()));
+        let rules = "||doubleclick.net^";
+        AdblockEngine::from_filter_list_cached(rules, &dir).unwrap();
+        let cached = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        // Имя не зависит от версии компилятора: SHA-256 списка.
+        assert_eq!(
+            cached.file_name().to_string_lossy(),
+            "engine-6a7053eed8841db68cb787369cecde2f.dat"
+        );
+        let mut ad = AdblockEngine::from_filter_list_cached(rules, &dir).unwrap();
+        assert!(ad.should_block(
+            "https://doubleclick.net/track.js",
+            "https://example.org",
+            "script"
+        ));
+
+        // Новый список вытесняет кэш старого.
+        AdblockEngine::from_filter_list_cached("||ads.example^", &dir).unwrap();
+        let names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert_ne!(names[0], cached.file_name());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
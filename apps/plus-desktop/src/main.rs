@@ -1,97 +1,742 @@
 use anyhow::Result;
 use eframe::egui;
 use plus_adblock::AdblockEngine;
-use plus_engine::{BrowserPolicy, EngineController};
-use plus_net::{start_proxy, HistoryStore};
+use plus_downloads::{
+    default_download_dir, DownloadManager, DownloadState, DownloadStore, ReputationList, RiskLevel,
+};
+use plus_engine::{BrowserPolicy, EngineController, VpnRouteMode};
+use plus_net::{
+    clear_browsing_data, run_diagnostics, start_proxy, Bookmark, BookmarkStore, CheckKind,
+    CheckResult, CheckStatus, DiagnosticsConfig, HistoryDay, HistoryEntry, HistoryStore,
+    KillSwitch, PasswordTarget, ProxyHandle, RuntimeStatus, SchemeRouter, BOOKMARK_BAR,
+};
+use plus_privacy::{
+    clear_webview_cookies, ClearDataOptions, ClearDataSettings, Cookie, CookieSettings,
+    PrivacySettings, PrivacyStore, Profile, ProfileLock, ProfileManager, Session, SessionTab,
+    SessionWindow, TimeRange, BROWSER_POLICY_KEY, CLEAR_DATA_KEY, COOKIE_SETTINGS_KEY,
+    DEFAULT_PROFILE, MAX_CLOSED_TABS, PRIVACY_SETTINGS_KEY, SESSION_KEY,
+};
+#[cfg(all(target_os = "linux", feature = "wry"))]
+use plus_renderer::WebViewHostLinux;
+#[cfg(windows)]
 use plus_renderer::WebViewHostWindows;
-use plus_vpn::{VpnManager, VpnMode};
+use plus_renderer::{NavigationEvent, WebViewHost};
+use plus_vpn::{CoreKind, VpnManager, VpnMode, XrayCore};
+#[cfg(any(windows, all(target_os = "linux", feature = "wry")))]
+use raw_window_handle::HasWindowHandle;
+#[cfg(windows)]
 use raw_window_handle::RawWindowHandle;
-use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 
+/// Как часто сессия сохраняется в профиль на случай аварийного завершения.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Сколько ждать, пока запущенный VPN-core начнёт принимать соединения.
+const VPN_START_TIMEOUT: Duration = Duration::from_secs(15);
+
+fn blank_tab() -> SessionTab {
+    SessionTab::new(plus_yandex::new_tab_data_url(), "Новая вкладка")
+}
+
 #[derive(Clone, Default)]
-struct Tab {
+struct BookmarkEditor {
+    id: i64,
+    parent: i64,
+    folder: bool,
     title: String,
     url: String,
+    tags: String,
 }
 
-#[derive(Clone, Default)]
-struct DownloadItem {
-    url: String,
-    path: String,
-    status: String,
+enum BookmarkAction {
+    Edit(Bookmark, i64),
+    Remove(i64),
+    NewFolder(i64),
+}
+
+/// Правки из окна настроек; в хранилище попадают только по кнопке «Сохранить».
+struct SettingsDraft {
+    privacy: PrivacySettings,
+    policy: BrowserPolicy,
+    vpn_domains: String,
+}
+
+/// Версия базы профиля (`HistoryStore::revision`); `None`, если её не удалось узнать.
+type Revision = Option<(u64, i64)>;
+
+/// Результат запроса к базе профиля: пересчитывается, только когда меняется
+/// запрос или содержимое базы, а не в каждом кадре.
+struct QueryCache<K, T> {
+    key: Option<K>,
+    value: T,
+}
+
+impl<K, T: Default> Default for QueryCache<K, T> {
+    fn default() -> Self {
+        Self {
+            key: None,
+            value: T::default(),
+        }
+    }
+}
+
+impl<K: PartialEq, T> QueryCache<K, T> {
+    fn get(&mut self, key: K, query: impl FnOnce() -> T) -> &T {
+        if self.key.as_ref() != Some(&key) {
+            self.value = query();
+            self.key = Some(key);
+        }
+        &self.value
+    }
+}
+
+/// Страница вкладки со слов рендерера; в сессию не попадает.
+#[derive(Default)]
+struct PageState {
+    loading: bool,
+    /// Переход начат приложением: следующий адрес от рендерера — его итог после
+    /// редиректов, а не новый шаг истории.
+    pending: bool,
+    can_go_back: bool,
+    can_go_forward: bool,
+    error: Option<String>,
+    /// Прокрутка из сессии: возвращается, когда страница загрузится.
+    restore_scroll: Option<f64>,
 }
 
 #[derive(Default)]
 struct DiagnosticsState {
-    ip: String,
-    checking: bool,
+    results: BTreeMap<CheckKind, CheckResult>,
+    /// Результаты идущих проверок; `None`, когда все закончились.
+    pending: Option<mpsc::Receiver<CheckResult>>,
 }
 
 struct PlusApp {
-    tabs: Vec<Tab>,
+    tabs: Vec<SessionTab>,
     active: usize,
-    closed_tabs: Vec<Tab>,
+    closed_tabs: Vec<SessionTab>,
+    /// Стек закрытых окон; `window` обновляется при каждом сохранении.
+    session: Session,
+    /// Окно из оборвавшегося запуска, пока пользователь не решил, открывать ли его.
+    recovery: Option<SessionWindow>,
+    /// Последняя записанная сессия без `saved_at`: неизменившуюся не пишем заново.
+    saved_session: Option<Session>,
+    last_session_save: Instant,
     drag_tab: Option<usize>,
     omnibox: String,
-    history: VecDeque<String>,
-    bookmarks: Vec<String>,
-    downloads: Vec<DownloadItem>,
+    history_query: String,
+    history_days: QueryCache<(chrono::NaiveDate, Revision), Vec<HistoryDay>>,
+    history_results: QueryCache<(String, Revision), Vec<HistoryEntry>>,
+    omnibox_suggestions: QueryCache<(String, Revision, Revision), Vec<String>>,
+    show_history: bool,
+    show_bookmarks: bool,
+    bookmark_edit: Option<BookmarkEditor>,
+    bookmark_file: String,
+    bookmark_status: String,
+    import_dir: String,
+    import_status: Arc<Mutex<String>>,
+    profile: Profile,
+    profiles: ProfileManager,
+    show_profiles: bool,
+    profile_input: String,
+    profile_renames: std::collections::HashMap<String, String>,
+    profile_status: String,
+    downloads: DownloadManager,
     download_url: String,
+    download_sha256: String,
+    download_status: String,
     show_settings: bool,
     show_diagnostics: bool,
-    dark_mode: bool,
+    privacy: PrivacySettings,
+    privacy_store: PrivacyStore,
+    settings_changes: mpsc::Receiver<String>,
+    settings_draft: Option<SettingsDraft>,
+    settings_status: String,
+    encrypt_passphrase: String,
+    cookie_rules: Arc<Mutex<CookieSettings>>,
+    show_cookies: bool,
+    /// Снимок банки cookie движка, который ещё не дошёл до хранилища профиля.
+    cookie_sync: Option<mpsc::Receiver<Vec<Cookie>>>,
+    /// Хранилище совпадает с движком с момента открытия менеджера cookie.
+    cookies_synced: bool,
+    cookie_filter: String,
+    show_clear_data: bool,
+    clear_options: ClearDataOptions,
+    clear_settings: ClearDataSettings,
+    clear_status: String,
     adblock: Arc<Mutex<AdblockEngine>>,
+    /// Страницы и JSON `plus://` для WebView2 и локального прокси.
+    internal_pages: SchemeRouter,
     engine: EngineController,
     runtime: Runtime,
     proxy: Option<String>,
+    proxy_handle: Option<ProxyHandle>,
+    kill_switch: KillSwitch,
     vpn: VpnManager,
+    /// Проверка SOCKS-входа запущенного core для kill switch.
+    vpn_monitor: Option<tokio::task::JoinHandle<()>>,
+    /// Ожидание готовности core после `start_vpn`: задача и канал с её итогом.
+    vpn_start: Option<(
+        tokio::task::JoinHandle<()>,
+        mpsc::Receiver<Result<(), String>>,
+    )>,
     vpn_status: String,
     vpn_endpoint: String,
     diagnostics: DiagnosticsState,
     progress: f32,
-    webview: Option<WebViewHostWindows>,
+    webview: Option<Box<dyn WebViewHost>>,
+    navigation: Option<mpsc::Receiver<NavigationEvent>>,
+    /// Состояние страниц по номеру вкладки.
+    pages: HashMap<u64, PageState>,
+    /// Ответ активной вкладки о прокрутке: номер вкладки, её адрес и канал.
+    scroll_query: Option<(u64, String, mpsc::Receiver<String>)>,
     history_store: HistoryStore,
+    bookmark_store: BookmarkStore,
+    /// Держит профиль за этим процессом (`Profile::lock`); у инкогнито `None`.
+    profile_lock: Option<ProfileLock>,
 }
 
 impl PlusApp {
-    fn new() -> Result<Self> {
+    fn new(
+        profiles: ProfileManager,
+        profile: Profile,
+        privacy_store: PrivacyStore,
+    ) -> Result<Self> {
         let runtime = Runtime::new()?;
-        let history_store = HistoryStore::open("plus-history.db")?;
-        let adblock =
-            AdblockEngine::from_filter_list("||doubleclick.net^\n||googlesyndication.com^")?;
-        Ok(Self {
-            tabs: vec![Tab {
-                title: "Новая вкладка".into(),
-                url: plus_yandex::new_tab_data_url(),
-            }],
-            active: 0,
-            closed_tabs: Vec::new(),
+        let history_store = HistoryStore::for_profile(&profile)?;
+        let bookmark_store = BookmarkStore::for_profile(&profile)?;
+        let downloads = DownloadManager::new(
+            DownloadStore::for_profile(&profile)?,
+            default_download_dir(),
+            3,
+            runtime.handle().clone(),
+        )?;
+        // Свой список опасных источников: хост или префикс URL в строке.
+        downloads.set_reputation(ReputationList::load(
+            &profiles.root().join("download-blocklist.txt"),
+        )?);
+        let filters = "||doubleclick.net^\n||googlesyndication.com^";
+        let adblock = if profile.is_incognito() {
+            AdblockEngine::from_filter_list(filters)?
+        } else {
+            AdblockEngine::from_filter_list_cached(filters, &profile.adblock_cache_dir())?
+        };
+        let privacy = privacy_store.get(PRIVACY_SETTINGS_KEY)?;
+        let policy = load_policy(&privacy_store, &profile)?;
+        let settings_changes = privacy_store.subscribe();
+        let cookie_rules: CookieSettings = privacy_store.get(COOKIE_SETTINGS_KEY)?;
+        privacy_store.purge_expired_cookies()?;
+        // Очистка при выходе повторяется при запуске: после аварийного завершения
+        // или если WebView2 ещё держал базу, когда закрывалось окно.
+        if cookie_rules.clear_on_exit && !profile.is_incognito() {
+            let _ = clear_webview_cookies(&profile.webview_data_dir(), &cookie_rules);
+        }
+        let clear_settings: ClearDataSettings = privacy_store.get(CLEAR_DATA_KEY)?;
+        if !profile.is_incognito() {
+            let _ = privacy_store.run_pending_webview_clear(&profile.webview_data_dir());
+        }
+        let mut vpn = VpnManager::new(
+            std::env::var("PLUS_SINGBOX_BIN").unwrap_or_else(|_| "sing-box".into()),
+            profile.vpn_dir(),
+        );
+        if let Ok(xray_bin) = std::env::var("PLUS_XRAY_BIN") {
+            vpn.set_core(CoreKind::Xray, Box::new(XrayCore::new(xray_bin)));
+        }
+        let adblock = Arc::new(Mutex::new(adblock));
+        let mut session: Session = privacy_store.get(SESSION_KEY)?;
+        let mut window = SessionWindow {
+            tabs: vec![blank_tab()],
+            ..SessionWindow::default()
+        };
+        let mut recovery = None;
+        let crashed = session.needs_recovery();
+        let previous = std::mem::take(&mut session.window);
+        if crashed {
+            recovery = Some(previous);
+        } else if !previous.is_empty() {
+            window = previous;
+        }
+        let mut app = Self {
+            active: window.active_index(),
+            tabs: window.tabs,
+            closed_tabs: window.closed_tabs,
+            session,
+            recovery,
+            saved_session: None,
+            last_session_save: Instant::now(),
             drag_tab: None,
             omnibox: String::new(),
-            history: VecDeque::with_capacity(50),
-            bookmarks: Vec::new(),
-            downloads: Vec::new(),
+            history_query: String::new(),
+            history_days: QueryCache::default(),
+            history_results: QueryCache::default(),
+            omnibox_suggestions: QueryCache::default(),
+            show_history: false,
+            show_bookmarks: false,
+            bookmark_edit: None,
+            bookmark_file: String::new(),
+            bookmark_status: String::new(),
+            import_dir: String::new(),
+            import_status: Arc::new(Mutex::new(String::new())),
+            profile,
+            profiles,
+            show_profiles: false,
+            profile_input: String::new(),
+            profile_renames: std::collections::HashMap::new(),
+            profile_status: String::new(),
+            downloads,
             download_url: String::new(),
+            download_sha256: String::new(),
+            download_status: String::new(),
             show_settings: false,
             show_diagnostics: false,
-            dark_mode: true,
-            adblock: Arc::new(Mutex::new(adblock)),
-            engine: EngineController::new(BrowserPolicy::default()),
+            privacy,
+            privacy_store,
+            settings_changes,
+            settings_draft: None,
+            settings_status: String::new(),
+            encrypt_passphrase: String::new(),
+            cookie_rules: Arc::new(Mutex::new(cookie_rules)),
+            show_cookies: false,
+            cookie_sync: None,
+            cookies_synced: false,
+            cookie_filter: String::new(),
+            show_clear_data: false,
+            clear_options: ClearDataOptions::default(),
+            clear_settings,
+            clear_status: String::new(),
+            internal_pages: SchemeRouter::new(adblock.clone()),
+            adblock,
+            engine: EngineController::new(policy),
             runtime,
             proxy: None,
-            vpn: VpnManager::new(
-                std::env::var("PLUS_SINGBOX_BIN").unwrap_or_else(|_| "sing-box".into()),
-                std::env::temp_dir().join("plus-vpn"),
-            ),
+            proxy_handle: None,
+            kill_switch: KillSwitch::default(),
+            vpn,
+            vpn_monitor: None,
+            vpn_start: None,
             diagnostics: DiagnosticsState::default(),
             progress: 0.0,
             vpn_status: "disconnected".into(),
             vpn_endpoint: "".into(),
             webview: None,
+            navigation: None,
+            pages: HashMap::new(),
+            scroll_query: None,
             history_store,
-        })
+            bookmark_store,
+            profile_lock: None,
+        };
+        // Сразу отмечаем сессию незавершённой: после падения её предложат восстановить.
+        app.save_session(false);
+        Ok(app)
+    }
+
+    /// Подхватывает сохранённые настройки: окно настроек и переключатели пишут
+    /// только в `PrivacyStore`, а состояние приложения обновляется отсюда.
+    fn apply_setting_changes(&mut self) {
+        let changed: Vec<String> = self.settings_changes.try_iter().collect();
+        for key in changed {
+            if key == PRIVACY_SETTINGS_KEY {
+                if let Ok(privacy) = self.privacy_store.get(PRIVACY_SETTINGS_KEY) {
+                    self.privacy = privacy;
+                }
+            } else if key == COOKIE_SETTINGS_KEY {
+                if let (Ok(rules), Ok(mut current)) = (
+                    self.privacy_store.get(COOKIE_SETTINGS_KEY),
+                    self.cookie_rules.lock(),
+                ) {
+                    *current = rules;
+                }
+            } else if key == CLEAR_DATA_KEY {
+                if let Ok(settings) = self.privacy_store.get(CLEAR_DATA_KEY) {
+                    self.clear_settings = settings;
+                }
+            } else if key == BROWSER_POLICY_KEY {
+                if let Ok(policy) = load_policy(&self.privacy_store, &self.profile) {
+                    let vpn_on = policy.vpn_mode != VpnRouteMode::Off;
+                    self.kill_switch.set_enabled(policy.kill_switch);
+                    self.kill_switch.set_vpn_expected(vpn_on);
+                    self.engine.set_policy(policy);
+                    let running = self.vpn_monitor.is_some() || self.vpn_start.is_some();
+                    if vpn_on && !running && self.vpn.active.is_some() {
+                        self.start_vpn();
+                    } else if !vpn_on && running {
+                        self.stop_vpn();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Запускает core активного VPN-профиля. Готовности core ждёт задача на
+    /// рантайме, окно тем временем работает; итог подхватывает `poll_vpn`.
+    /// Ошибка запуска остаётся в статусе VPN.
+    fn start_vpn(&mut self) {
+        self.stop_vpn();
+        let probe = self.vpn.probe_addr();
+        let started = self
+            .runtime
+            .block_on(self.vpn.start_core())
+            .and_then(|()| probe.ok_or_else(|| anyhow::anyhow!("vpn config missing")));
+        let addr = match started {
+            Ok(addr) => addr,
+            Err(e) => {
+                self.vpn_status = format!("error: {e}");
+                return;
+            }
+        };
+        self.vpn_status = "starting".into();
+        let (tx, rx) = mpsc::channel();
+        let task = self.runtime.spawn(async move {
+            let deadline = tokio::time::Instant::now() + VPN_START_TIMEOUT;
+            let ready = loop {
+                if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+                    break Ok(());
+                }
+                if tokio::time::Instant::now() >= deadline {
+                    break Err(format!("vpn core not ready on {addr}"));
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            };
+            let _ = tx.send(ready);
+        });
+        self.vpn_start = Some((task, rx));
+    }
+
+    /// Итог запуска core: готов — прокси идёт через него и включается монитор
+    /// kill switch; упал или не ответил — core останавливается.
+    fn poll_vpn(&mut self, ctx: &egui::Context) {
+        let Some((_, pending)) = &self.vpn_start else {
+            return;
+        };
+        let result = match pending.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => match self.vpn.core_exit_status() {
+                Some(status) => Err(format!("vpn core exited: {status}")),
+                None => {
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                    return;
+                }
+            },
+            Err(mpsc::TryRecvError::Disconnected) => Err("vpn start was interrupted".into()),
+        };
+        if let Err(e) = result {
+            self.stop_vpn();
+            self.vpn_status = format!("error: {e}");
+            return;
+        }
+        self.vpn_start = None;
+        let socks = self
+            .vpn
+            .browser_proxy()
+            .map(|p| p.replace("socks5h://", ""));
+        if let Some(proxy) = &self.proxy_handle {
+            proxy.set_upstream(socks.clone());
+        }
+        if let Some(socks) = socks {
+            let _guard = self.runtime.enter();
+            self.vpn_monitor = Some(
+                self.kill_switch
+                    .spawn_monitor(socks, Duration::from_secs(2)),
+            );
+        }
+        self.vpn_status = "connected".into();
+    }
+
+    fn stop_vpn(&mut self) {
+        if let Some((task, _)) = self.vpn_start.take() {
+            task.abort();
+        }
+        if let Some(monitor) = self.vpn_monitor.take() {
+            monitor.abort();
+        }
+        if let Some(proxy) = &self.proxy_handle {
+            proxy.set_upstream(None);
+        }
+        self.kill_switch.set_vpn_healthy(false);
+        let _ = self.runtime.block_on(self.vpn.stop_core());
+        self.vpn_status = "disconnected".into();
+    }
+
+    fn save_settings(&mut self, draft: &SettingsDraft) {
+        let mut policy = draft.policy.clone();
+        policy.vpn_domain_list = draft
+            .vpn_domains
+            .split([',', ' ', '\n'])
+            .map(|d| d.trim().to_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        let result = self
+            .privacy_store
+            .set(PRIVACY_SETTINGS_KEY, &draft.privacy)
+            .and_then(|_| self.privacy_store.set(BROWSER_POLICY_KEY, &policy));
+        self.settings_status = match result {
+            Ok(()) if self.profile.is_incognito() => "Сохранено до закрытия окна".into(),
+            Ok(()) => "Сохранено".into(),
+            Err(e) => format!("Ошибка: {e}"),
+        };
+    }
+
+    /// Правила cookie меняются сразу, без кнопки «Сохранить»: хост WebView2
+    /// получает их через уведомление об изменении настроек.
+    fn update_cookie_rules(&mut self, change: impl FnOnce(&mut CookieSettings)) {
+        let mut rules = self
+            .cookie_rules
+            .lock()
+            .map(|r| r.clone())
+            .unwrap_or_default();
+        change(&mut rules);
+        let _ = self.privacy_store.set(COOKIE_SETTINGS_KEY, &rules);
+    }
+
+    /// Менеджер cookie показывает хранилище профиля, а страницы живут с банкой
+    /// движка: перед показом хранилище заполняется её снимком.
+    fn sync_cookies(&mut self) {
+        let Some(host) = &mut self.webview else {
+            return;
+        };
+        let (tx, rx) = mpsc::channel();
+        let requested = host.get_cookies(Box::new(move |cookies| {
+            let _ = tx.send(cookies);
+        }));
+        if requested.is_ok() {
+            self.cookie_sync = Some(rx);
+        }
+    }
+
+    fn poll_cookie_sync(&mut self, ctx: &egui::Context) {
+        let Some(rx) = &self.cookie_sync else {
+            return;
+        };
+        match rx.try_recv() {
+            Ok(cookies) => {
+                let _ = self.privacy_store.replace_cookies(&cookies);
+                self.cookie_sync = None;
+            }
+            Err(mpsc::TryRecvError::Empty) => ctx.request_repaint_after(Duration::from_millis(100)),
+            Err(mpsc::TryRecvError::Disconnected) => self.cookie_sync = None,
+        }
+    }
+
+    fn cookies_window(&mut self, ctx: &egui::Context) {
+        if !self.cookies_synced {
+            self.cookies_synced = true;
+            self.sync_cookies();
+        }
+        self.poll_cookie_sync(ctx);
+        let loading = self.cookie_sync.is_some();
+        let mut rules = self
+            .cookie_rules
+            .lock()
+            .map(|r| r.clone())
+            .unwrap_or_default();
+        let before = rules.clone();
+        let filter = self.cookie_filter.trim().to_lowercase();
+        let sites: Vec<(String, usize)> = self
+            .privacy_store
+            .cookie_sites()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(site, _)| site.contains(&filter))
+            .collect();
+        let mut remove_site = None;
+        let mut remove_cookie = None;
+        egui::Window::new("Cookie")
+            .open(&mut self.show_cookies)
+            .show(ctx, |ui| {
+                ui.checkbox(&mut rules.block_third_party, "Блокировать сторонние cookie");
+                ui.checkbox(&mut rules.clear_on_exit, "Удалять cookie при выходе");
+                ui.horizontal(|ui| {
+                    ui.label("Поиск");
+                    ui.text_edit_singleline(&mut self.cookie_filter);
+                });
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for (site, count) in &sites {
+                            ui.horizontal(|ui| {
+                                let mut keep = rules.keep_on_exit.contains(site);
+                                if ui.checkbox(&mut keep, "Хранить").changed() {
+                                    toggle_site(&mut rules.keep_on_exit, site, keep);
+                                }
+                                let mut blocked = rules.blocked_sites.contains(site);
+                                if ui.checkbox(&mut blocked, "Блок").changed() {
+                                    toggle_site(&mut rules.blocked_sites, site, blocked);
+                                }
+                                if ui.small_button("🗑").on_hover_text("Удалить").clicked()
+                                {
+                                    remove_site = Some(site.clone());
+                                }
+                                egui::CollapsingHeader::new(format!("{site} ({count})"))
+                                    .id_salt(site)
+                                    .show(ui, |ui| {
+                                        for cookie in self
+                                            .privacy_store
+                                            .site_cookies(site)
+                                            .unwrap_or_default()
+                                        {
+                                            ui.horizontal(|ui| {
+                                                if ui.small_button("✕").clicked() {
+                                                    remove_cookie = Some(cookie.clone());
+                                                }
+                                                let expires = cookie
+                                                    .expires
+                                                    .map(|at| {
+                                                        at.with_timezone(&chrono::Local)
+                                                            .format("%d.%m.%Y %H:%M")
+                                                            .to_string()
+                                                    })
+                                                    .unwrap_or_else(|| "сессия".into());
+                                                ui.label(format!(
+                                                    "{}  {}{}  до {}{}",
+                                                    cookie.name,
+                                                    cookie.domain,
+                                                    cookie.path,
+                                                    expires,
+                                                    if cookie.secure { "  🔒" } else { "" }
+                                                ));
+                                            });
+                                        }
+                                    });
+                            });
+                        }
+                        if loading {
+                            ui.spinner();
+                        } else if sites.is_empty() {
+                            ui.label("Нет cookie");
+                        }
+                    });
+            });
+        let mut removed = remove_cookie.into_iter().collect::<Vec<_>>();
+        if let Some(site) = remove_site {
+            removed.extend(self.privacy_store.site_cookies(&site).unwrap_or_default());
+        }
+        for cookie in &removed {
+            if let Some(host) = &mut self.webview {
+                let _ = host.delete_cookie(cookie);
+            }
+            let _ = self
+                .privacy_store
+                .delete_cookie(&cookie.name, &cookie.domain, &cookie.path);
+        }
+        if !self.show_cookies {
+            self.cookies_synced = false;
+        }
+        if rules != before {
+            self.update_cookie_rules(|r| *r = rules);
+        }
+    }
+
+    fn clear_data(&mut self, options: &ClearDataOptions) -> String {
+        let now = chrono::Utc::now();
+        let report = match clear_browsing_data(
+            options,
+            now,
+            &self.history_store,
+            &self.privacy_store,
+            &self.adblock,
+        ) {
+            Ok(report) => report,
+            Err(e) => return format!("Ошибка: {e}"),
+        };
+        if options.history {
+            // Закрытые вкладки и окна — тоже история; открытые вкладки не трогаем.
+            self.closed_tabs.clear();
+            self.session.closed_windows.clear();
+        }
+        let mut downloads = 0;
+        if options.downloads {
+            downloads = match self.downloads.remove_since(options.range.since(now)) {
+                Ok(removed) => removed,
+                Err(e) => return format!("Ошибка: {e}"),
+            };
+        }
+        let mut status = format!(
+            "Удалено: визитов {}, cookie {}, загрузок {downloads}",
+            report.history_visits, report.cookies
+        );
+        if (options.cookies || options.cache) && !self.clear_engine_data(options, now) {
+            status.push_str(". Кэш и cookie страниц очистятся при следующем запуске");
+        }
+        status
+    }
+
+    /// Кэш и cookie открытых страниц чистит сам движок, сразу. Без движка (при
+    /// выходе) очистка ставится в очередь: файлы WebView2 могут быть ещё заняты.
+    fn clear_engine_data(
+        &mut self,
+        options: &ClearDataOptions,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> bool {
+        let since = options.range.since(now);
+        if let Some(host) = &mut self.webview {
+            if host
+                .clear_browsing_data(options.cookies, options.cache, since)
+                .is_ok()
+            {
+                return true;
+            }
+        }
+        let _ = self.privacy_store.queue_webview_clear(options, since);
+        false
+    }
+
+    fn clear_data_window(&mut self, ctx: &egui::Context) {
+        let mut run = false;
+        let mut settings = self.clear_settings.clone();
+        egui::Window::new("Очистить данные")
+            .open(&mut self.show_clear_data)
+            .show(ctx, |ui| {
+                egui::ComboBox::from_label("Период")
+                    .selected_text(self.clear_options.range.label())
+                    .show_ui(ui, |ui| {
+                        for range in TimeRange::ALL {
+                            ui.selectable_value(
+                                &mut self.clear_options.range,
+                                range,
+                                range.label(),
+                            );
+                        }
+                    });
+                ui.checkbox(&mut self.clear_options.history, "История посещений");
+                ui.checkbox(&mut self.clear_options.cookies, "Cookie и данные сайтов");
+                ui.checkbox(&mut self.clear_options.cache, "Кэш (за всё время)");
+                ui.checkbox(&mut self.clear_options.downloads, "Список загрузок");
+                ui.checkbox(&mut self.clear_options.adblock_stats, "Статистика AdBlock");
+                ui.horizontal(|ui| {
+                    if ui.button("Очистить").clicked() {
+                        run = true;
+                    }
+                    ui.label(&self.clear_status);
+                });
+                ui.separator();
+                if ui
+                    .checkbox(&mut settings.on_exit, "Очищать эти данные при выходе")
+                    .changed()
+                {
+                    settings.on_exit_options = self.clear_options.clone();
+                }
+                if settings.on_exit && settings.on_exit_options != self.clear_options {
+                    ui.horizontal(|ui| {
+                        ui.label("При выходе сейчас очищается другой набор");
+                        if ui.button("Заменить").clicked() {
+                            settings.on_exit_options = self.clear_options.clone();
+                        }
+                    });
+                }
+            });
+        if run {
+            let options = self.clear_options.clone();
+            self.clear_status = self.clear_data(&options);
+        }
+        if settings != self.clear_settings {
+            let _ = self.privacy_store.set(CLEAR_DATA_KEY, &settings);
+        }
     }
 
     fn ensure_webview(&mut self, frame: &eframe::Frame) {
@@ -100,205 +745,1196 @@ impl PlusApp {
         }
         #[cfg(windows)]
         {
-            if let RawWindowHandle::Win32(handle) = frame.raw_window_handle() {
-                let hwnd = handle.hwnd.get() as windows_sys::Win32::Foundation::HWND;
-                let mut host = WebViewHostWindows::new(hwnd);
-                let _ = host.initialize();
-                let _ = host.set_proxy(self.proxy.clone());
-                let _ = host.add_adblock_handler(self.adblock.clone());
-                self.webview = Some(host);
-                self.navigate_current();
+            if let Ok(handle) = frame.window_handle() {
+                if let RawWindowHandle::Win32(handle) = handle.as_raw() {
+                    let hwnd = handle.hwnd.get() as windows_sys::Win32::Foundation::HWND;
+                    self.attach_webview(Box::new(WebViewHostWindows::new(hwnd)));
+                }
+            }
+        }
+        #[cfg(all(target_os = "linux", feature = "wry"))]
+        {
+            // Под Wayland без XWayland встроить WebKitGTK некуда: страницы не показываются.
+            if let Ok(handle) = frame.window_handle() {
+                if let Ok(host) = WebViewHostLinux::new(handle.as_raw()) {
+                    self.attach_webview(Box::new(host));
+                }
+            }
+        }
+    }
+
+    #[cfg_attr(
+        not(any(windows, all(target_os = "linux", feature = "wry"))),
+        allow(dead_code)
+    )]
+    fn attach_webview(&mut self, mut host: Box<dyn WebViewHost>) {
+        host.set_user_data_dir(self.profile.webview_data_dir());
+        let _ = host.initialize();
+        let _ = host.set_proxy(self.proxy.clone());
+        let _ = host.add_adblock_handler(self.adblock.clone());
+        let _ = host.add_cookie_handler(self.cookie_rules.clone());
+        let _ = host.add_internal_scheme_handler(self.internal_pages.clone());
+        self.navigation = Some(host.subscribe());
+        self.webview = Some(host);
+    }
+
+    /// Открывает адрес активной вкладки. В историю он попадёт, когда рендерер
+    /// сообщит, что страница загрузилась.
+    fn navigate_current(&mut self) {
+        let url = self.tabs[self.active].url.clone();
+        let tab = self.tabs[self.active].id;
+        if let Some(host) = &mut self.webview {
+            self.pages.entry(tab).or_default().pending = true;
+            let _ = host.navigate(tab, &url);
+        }
+    }
+
+    fn poll_navigation(&mut self, ctx: &egui::Context) {
+        let Some(events) = &self.navigation else {
+            return;
+        };
+        let events: Vec<NavigationEvent> = events.try_iter().collect();
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            self.apply_navigation_event(event);
+        }
+        ctx.request_repaint();
+    }
+
+    fn apply_navigation_event(&mut self, event: NavigationEvent) {
+        // События могут прийти от вкладки, которую уже закрыли.
+        let Some(index) = self.tabs.iter().position(|tab| tab.id == event.tab()) else {
+            return;
+        };
+        let record = !self.engine.policy().incognito;
+        let active = index == self.active;
+        let page = self.pages.entry(event.tab()).or_default();
+        let tab = &mut self.tabs[index];
+        let mut restore_scroll = None;
+        match event {
+            NavigationEvent::Starting { .. } => {
+                page.loading = true;
+                page.error = None;
+                if active {
+                    self.progress = 0.1;
+                }
+            }
+            NavigationEvent::SourceChanged { url, .. } => {
+                if std::mem::take(&mut page.pending) {
+                    tab.url = url;
+                } else {
+                    tab.follow(url);
+                }
+                if active {
+                    self.omnibox = tab.url.clone();
+                }
+            }
+            NavigationEvent::TitleChanged { title, .. } => {
+                tab.title = title;
+                if record {
+                    let _ = self.history_store.set_title(&tab.url, &tab.title);
+                }
+            }
+            NavigationEvent::FaviconChanged { url, .. } => tab.favicon = Some(url),
+            NavigationEvent::HistoryChanged {
+                can_go_back,
+                can_go_forward,
+                ..
+            } => {
+                page.can_go_back = can_go_back;
+                page.can_go_forward = can_go_forward;
+            }
+            NavigationEvent::Completed { error, .. } => {
+                page.loading = false;
+                // Внутренние страницы из data: URL в историю не пишем.
+                if error.is_none() && record && !tab.url.starts_with("data:") {
+                    let _ = self.history_store.add_visit(&tab.url, &tab.title);
+                }
+                restore_scroll = page
+                    .restore_scroll
+                    .take()
+                    .filter(|_| error.is_none() && active);
+                page.error = error;
+            }
+        }
+        if let (Some(y), Some(host)) = (restore_scroll, &mut self.webview) {
+            let _ = host.execute_script(&format!("window.scrollTo(0, {y})"));
+        }
+    }
+
+    /// Спрашивает у активной вкладки прокрутку; ответ попадёт в сессию при
+    /// следующем сохранении. Пока страница грузится, прокрутки ещё нет.
+    fn capture_scroll(&mut self) {
+        let Some(host) = &mut self.webview else {
+            return;
+        };
+        let tab = &self.tabs[self.active];
+        if self.profile.is_incognito()
+            || self
+                .pages
+                .get(&tab.id)
+                .is_none_or(|page| page.loading || page.restore_scroll.is_some())
+        {
+            return;
+        }
+        let (tx, rx) = mpsc::channel();
+        let requested = host.evaluate_script(
+            "window.scrollY",
+            Box::new(move |json| {
+                let _ = tx.send(json);
+            }),
+        );
+        if requested.is_ok() {
+            self.scroll_query = Some((tab.id, tab.url.clone(), rx));
+        }
+    }
+
+    fn poll_scroll(&mut self) {
+        let Some((id, url, rx)) = &self.scroll_query else {
+            return;
+        };
+        let json = match rx.try_recv() {
+            Ok(json) => json,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.scroll_query = None;
+                return;
+            }
+        };
+        // Ответ о странице, с которой вкладка уже ушла, не годится.
+        if let Some(tab) = self.tabs.iter_mut().find(|t| t.id == *id && t.url == *url) {
+            if let Ok(y) = json.trim().parse::<f64>() {
+                tab.scroll_y = (y > 0.0).then_some(y);
+            }
+        }
+        self.scroll_query = None;
+    }
+
+    /// Показывает WebView активной вкладки и закрывает WebView закрытых.
+    /// Переключение вкладки — не переход: страница не перезагружается и в историю не пишется.
+    fn sync_webviews(&mut self) {
+        let Some(host) = &mut self.webview else {
+            return;
+        };
+        let open: Vec<_> = self.tabs.iter().map(|tab| tab.id).collect();
+        host.retain_tabs(&open);
+        self.pages.retain(|tab, _| open.contains(tab));
+        let tab = &self.tabs[self.active];
+        if let Ok(true) = host.show_tab(tab.id, &tab.url) {
+            let page = self.pages.entry(tab.id).or_default();
+            page.pending = true;
+            page.restore_scroll = tab.scroll_y;
+        }
+    }
+
+    fn open_url(&mut self, input: &str) {
+        let url = plus_yandex::omnibox_to_url(input);
+        self.tabs[self.active].navigate(url.clone());
+        self.omnibox = url;
+        self.navigate_current();
+    }
+
+    /// «Назад» и «Вперёд» идут по истории WebView, пока она есть: так страница
+    /// возвращается из кэша с формами и прокруткой. После выгрузки вкладки или
+    /// перезапуска — по истории вкладки из сессии.
+    fn go_back(&mut self) {
+        let page = self.pages.get(&self.tabs[self.active].id);
+        if let (Some(host), true) = (&mut self.webview, page.is_some_and(|p| p.can_go_back)) {
+            let _ = host.go_back();
+        } else if self.tabs[self.active].go_back() {
+            self.omnibox = self.tabs[self.active].url.clone();
+            self.navigate_current();
+        }
+    }
+
+    fn go_forward(&mut self) {
+        let page = self.pages.get(&self.tabs[self.active].id);
+        if let (Some(host), true) = (&mut self.webview, page.is_some_and(|p| p.can_go_forward)) {
+            let _ = host.go_forward();
+        } else if self.tabs[self.active].go_forward() {
+            self.omnibox = self.tabs[self.active].url.clone();
+            self.navigate_current();
+        }
+    }
+
+    fn new_tab(&mut self) {
+        self.tabs.push(blank_tab());
+        self.active = self.tabs.len() - 1;
+    }
+
+    fn push_closed_tabs(&mut self, tabs: impl IntoIterator<Item = SessionTab>) {
+        self.closed_tabs.extend(tabs);
+        let excess = self.closed_tabs.len().saturating_sub(MAX_CLOSED_TABS);
+        self.closed_tabs.drain(..excess);
+    }
+
+    fn close_tab(&mut self) {
+        if self.tabs.len() == 1 {
+            return;
+        }
+        let closed = self.tabs.remove(self.active);
+        self.push_closed_tabs([closed]);
+        if self.active >= self.tabs.len() {
+            self.active = self.tabs.len() - 1;
+        }
+    }
+
+    fn close_other_tabs(&mut self) {
+        let tab = self.tabs.remove(self.active);
+        let others = std::mem::replace(&mut self.tabs, vec![tab]);
+        self.push_closed_tabs(others);
+        self.active = 0;
+    }
+
+    fn close_right_tabs(&mut self) {
+        let right = self.tabs.split_off(self.active + 1);
+        self.push_closed_tabs(right);
+    }
+
+    fn duplicate_tab(&mut self) {
+        let tab = self.tabs[self.active].duplicate();
+        self.tabs.insert(self.active + 1, tab);
+        self.active += 1;
+    }
+    fn restore_tab(&mut self) {
+        if let Some(tab) = self.closed_tabs.pop() {
+            self.tabs.push(tab);
+            self.active = self.tabs.len() - 1;
+        }
+    }
+
+    fn window_state(&self) -> SessionWindow {
+        SessionWindow {
+            tabs: self.tabs.clone(),
+            active: self.active,
+            closed_tabs: self.closed_tabs.clone(),
+        }
+    }
+
+    /// Окно у нас одно, поэтому «закрыть окно» — убрать его вкладки в стек
+    /// закрытых окон и начать с чистой вкладки.
+    fn close_window(&mut self) {
+        let window = self.window_state();
+        self.session.push_closed_window(window);
+        self.tabs = vec![blank_tab()];
+        self.active = 0;
+        self.closed_tabs.clear();
+        self.omnibox.clear();
+    }
+
+    fn restore_window(&mut self) {
+        if let Some(window) = self.session.pop_closed_window() {
+            self.open_window(window);
+        }
+    }
+
+    /// Добавляет вкладки окна к текущим; одна пустая новая вкладка заменяется.
+    fn open_window(&mut self, window: SessionWindow) {
+        if window.is_empty() {
+            return;
+        }
+        let untouched = self.tabs.len() == 1
+            && self.tabs[0].url == plus_yandex::new_tab_data_url()
+            && !self.tabs[0].can_go_back();
+        if untouched {
+            self.tabs.clear();
+        }
+        self.active = self.tabs.len() + window.active_index();
+        self.tabs.extend(window.tabs);
+        self.push_closed_tabs(window.closed_tabs);
+        self.omnibox = self.tabs[self.active].url.clone();
+    }
+
+    /// Пишет сессию в профиль, если она изменилась. `clean_exit` — только при выходе.
+    fn save_session(&mut self, clean_exit: bool) {
+        self.last_session_save = Instant::now();
+        if self.profile.is_incognito() {
+            return;
+        }
+        let mut session = self.session.clone();
+        session.window = self.window_state();
+        session.clean_exit = clean_exit;
+        // Окно, о котором пользователь ещё не решил, не должно пропасть при новом падении.
+        if let Some(window) = &self.recovery {
+            session.push_closed_window(window.clone());
+        }
+        if self.saved_session.as_ref() == Some(&session) {
+            return;
+        }
+        let snapshot = session.clone();
+        session.saved_at = Some(chrono::Utc::now());
+        let result = self
+            .privacy_store
+            .set(SESSION_KEY, &session)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(self.privacy_store.flush()?));
+        match result {
+            Ok(()) => self.saved_session = Some(snapshot),
+            Err(e) => self.settings_status = format!("error: {e}"),
+        }
+    }
+
+    fn show_recovery_prompt(&mut self, ctx: &egui::Context) {
+        let Some(window) = &self.recovery else {
+            return;
+        };
+        let count = window.tabs.len();
+        let mut restore = None;
+        egui::Window::new("Восстановить сессию?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Браузер был закрыт неожиданно. Открыть вкладки прошлой сессии ({count})?"
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Восстановить").clicked() {
+                        restore = Some(true);
+                    }
+                    if ui
+                        .button("Не восстанавливать")
+                        .on_hover_text("Вкладки останутся в «Открыть закрытое окно»")
+                        .clicked()
+                    {
+                        restore = Some(false);
+                    }
+                });
+            });
+        let Some(restore) = restore else {
+            return;
+        };
+        if let Some(window) = self.recovery.take() {
+            if restore {
+                self.open_window(window);
+            } else {
+                self.session.push_closed_window(window);
+            }
+        }
+        self.save_session(false);
+    }
+
+    fn start_download(&mut self) {
+        let url = self.download_url.trim().to_string();
+        if url.is_empty() {
+            return;
+        }
+        let referrer = self.tabs[self.active].url.clone();
+        let checksum = self.download_sha256.trim().to_string();
+        let started = self.downloads.start(&url, Some(&referrer)).and_then(|id| {
+            if checksum.is_empty() {
+                Ok(None)
+            } else {
+                self.downloads.expect_sha256(id, &checksum)
+            }
+        });
+        self.download_status = match started {
+            Ok(_) => String::new(),
+            Err(e) => format!("error: {e}"),
+        };
+        self.download_url.clear();
+        self.download_sha256.clear();
+    }
+
+    fn downloads_list(&mut self, ui: &mut egui::Ui) {
+        for item in self.downloads.list() {
+            ui.horizontal(|ui| {
+                let name = item
+                    .path
+                    .as_ref()
+                    .and_then(|p| p.file_name())
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| item.url.clone());
+                let source = match &item.referrer {
+                    Some(referrer) => format!("{}\nсо страницы {referrer}", item.url),
+                    None => item.url.clone(),
+                };
+                ui.label(name).on_hover_text(source);
+                match item.total {
+                    Some(total) if item.state.is_active() && total > 0 => {
+                        ui.add(
+                            egui::ProgressBar::new(item.received as f32 / total as f32)
+                                .desired_width(120.0)
+                                .show_percentage(),
+                        );
+                    }
+                    _ => {
+                        ui.label(item.state.label());
+                    }
+                }
+                if let Some(error) = &item.error {
+                    ui.label(error);
+                }
+                if let Some(sha256) = &item.sha256 {
+                    let text = match item.checksum_matches() {
+                        Some(true) => {
+                            egui::RichText::new("SHA-256 ✓").color(egui::Color32::DARK_GREEN)
+                        }
+                        Some(false) => egui::RichText::new("SHA-256 ✗").color(egui::Color32::RED),
+                        None => egui::RichText::new("SHA-256"),
+                    };
+                    if ui
+                        .add(egui::Label::new(text).sense(egui::Sense::click()))
+                        .on_hover_text(format!("{sha256}\nНажмите, чтобы скопировать"))
+                        .clicked()
+                    {
+                        ui.ctx().copy_text(sha256.clone());
+                    }
+                }
+                let result = match item.state {
+                    DownloadState::Queued | DownloadState::InProgress => {
+                        if ui.button("Пауза").clicked() {
+                            self.downloads.pause(item.id);
+                        }
+                        if ui.button("Отмена").clicked() {
+                            self.downloads.cancel(item.id)
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    DownloadState::Paused | DownloadState::Failed => {
+                        if ui.button("Продолжить").clicked() {
+                            self.downloads.resume(item.id)
+                        } else if ui.button("Отмена").clicked() {
+                            self.downloads.cancel(item.id)
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    // Файл уже скачан в `.part`; без подтверждения он не появится под своим именем.
+                    DownloadState::AwaitingApproval => {
+                        if ui
+                            .button("Сохранить")
+                            .on_hover_text("Сохранить файл, несмотря на предупреждение")
+                            .clicked()
+                        {
+                            self.downloads.approve(item.id)
+                        } else if ui.button("Удалить").clicked() {
+                            self.downloads.cancel(item.id)
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    DownloadState::Completed | DownloadState::Cancelled => {
+                        if ui
+                            .button("✕")
+                            .on_hover_text("Убрать из списка, файл останется")
+                            .clicked()
+                        {
+                            self.downloads.remove(item.id).map(|_| ())
+                        } else {
+                            Ok(())
+                        }
+                    }
+                };
+                if let Err(e) = result {
+                    self.download_status = format!("error: {e}");
+                }
+            });
+            if let Some(risk) = item.risk.filter(|r| r.level > RiskLevel::Safe) {
+                let color = match risk.level {
+                    RiskLevel::Dangerous => egui::Color32::RED,
+                    _ => egui::Color32::from_rgb(0xd0, 0x90, 0x00),
+                };
+                ui.colored_label(color, format!("⚠ {}", risk.level.label()));
+                for reason in &risk.reasons {
+                    ui.label(format!("  • {reason}"));
+                }
+            }
+        }
+    }
+
+    fn handle_hotkeys(&mut self, ctx: &egui::Context) {
+        let input = ctx.input(|i| i.clone());
+        if input.modifiers.command && input.key_pressed(egui::Key::T) {
+            self.new_tab();
+        }
+        if input.modifiers.command && input.modifiers.shift && input.key_pressed(egui::Key::T) {
+            self.restore_tab();
+        }
+        if input.modifiers.command && input.modifiers.shift && input.key_pressed(egui::Key::W) {
+            self.close_window();
+        } else if input.modifiers.command && input.key_pressed(egui::Key::W) {
+            self.close_tab();
+        }
+        if input.modifiers.command && input.key_pressed(egui::Key::L) {
+            ctx.memory_mut(|m| m.request_focus("omnibox".into()));
+        }
+        if input.modifiers.command && input.key_pressed(egui::Key::R) {
+            if let Some(host) = &mut self.webview {
+                let _ = host.reload();
+            }
+        }
+        if input.modifiers.alt && input.key_pressed(egui::Key::ArrowLeft) {
+            self.go_back();
+        }
+        if input.modifiers.alt && input.key_pressed(egui::Key::ArrowRight) {
+            self.go_forward();
+        }
+        if input.key_pressed(egui::Key::F5) {
+            if let Some(host) = &mut self.webview {
+                let _ = host.reload();
+            }
+        }
+        if input.key_pressed(egui::Key::Escape) {
+            if let Some(host) = &mut self.webview {
+                let _ = host.stop();
+            }
+        }
+        if input.modifiers.command && input.key_pressed(egui::Key::H) {
+            self.show_history = !self.show_history;
+        }
+        if input.modifiers.command && input.key_pressed(egui::Key::D) {
+            self.toggle_bookmark();
+        }
+        if input.modifiers.command && input.modifiers.shift && input.key_pressed(egui::Key::O) {
+            self.show_bookmarks = !self.show_bookmarks;
+        }
+        if input.modifiers.command && input.modifiers.shift && input.key_pressed(egui::Key::Delete)
+        {
+            self.show_clear_data = true;
+        }
+    }
+
+    /// Состояние для `plus://diagnostics`; копируется каждый кадр, поэтому всегда свежее.
+    fn publish_status(&self) {
+        self.internal_pages.set_status(RuntimeStatus {
+            proxy: self.proxy.clone(),
+            vpn_status: self.vpn_status.clone(),
+            vpn_endpoint: self.vpn.active.as_ref().map(|c| c.endpoint.clone()),
+            kill_switch_engaged: self
+                .kill_switch
+                .is_engaged(self.vpn.browser_proxy().is_some()),
+            policy: self.engine.policy().clone(),
+            ..RuntimeStatus::default()
+        });
+    }
+
+    /// Проверки идут в фоне; результаты забирает `poll_diagnostics`.
+    fn run_diagnostics(&mut self) {
+        if self.diagnostics.pending.is_some() {
+            return;
+        }
+        let mut config = DiagnosticsConfig {
+            proxy: self.proxy.clone(),
+            tunnel_dns: self.vpn.tunnel_dns(),
+            vpn_core: self.vpn.probe_addr(),
+            ..DiagnosticsConfig::default()
+        };
+        if let Ok(endpoints) = std::env::var("PLUS_IP_ENDPOINTS") {
+            config.ip_endpoints = endpoints.split(',').map(|e| e.trim().to_string()).collect();
+        }
+        if let Some(direct) = std::env::var("PLUS_DIRECT_DNS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.direct_dns = direct;
+        }
+        self.diagnostics.results.clear();
+        self.diagnostics.pending = Some(run_diagnostics(config, self.runtime.handle()));
+    }
+
+    fn poll_diagnostics(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.diagnostics.pending else {
+            return;
+        };
+        loop {
+            match pending.try_recv() {
+                Ok(result) => {
+                    self.diagnostics.results.insert(result.kind, result);
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                    return;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.diagnostics.pending = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn history_window(&mut self, ctx: &egui::Context) {
+        let mut open_url = None;
+        let mut delete_url = None;
+        let mut delete_domain = None;
+        let revision = self.history_store.revision().ok();
+        egui::Window::new("История")
+            .open(&mut self.show_history)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Поиск");
+                    ui.text_edit_singleline(&mut self.history_query);
+                    if ui.button("Удалить за последний час").clicked() {
+                        let now = chrono::Utc::now();
+                        let _ = self
+                            .history_store
+                            .delete_range(now - chrono::Duration::hours(1), now);
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let query = self.history_query.trim();
+                    if query.is_empty() {
+                        let today = chrono::Local::now().date_naive();
+                        let days = self.history_days.get((today, revision), || {
+                            self.history_store.visits_by_day(30).unwrap_or_default()
+                        });
+                        for day in days {
+                            ui.heading(day.date.format("%d.%m.%Y").to_string());
+                            for visit in &day.visits {
+                                let label = format!(
+                                    "{} {}",
+                                    visit
+                                        .visited_at
+                                        .with_timezone(&chrono::Local)
+                                        .format("%H:%M"),
+                                    if visit.title.is_empty() {
+                                        &visit.url
+                                    } else {
+                                        &visit.title
+                                    }
+                                );
+                                history_row(
+                                    ui,
+                                    &label,
+                                    &visit.url,
+                                    &mut open_url,
+                                    &mut delete_url,
+                                    &mut delete_domain,
+                                );
+                            }
+                        }
+                    } else {
+                        let entries =
+                            self.history_results.get((query.to_string(), revision), || {
+                                self.history_store.search(query, 100).unwrap_or_default()
+                            });
+                        for entry in entries {
+                            let label = format!("{} ({})", entry.title, entry.visit_count);
+                            history_row(
+                                ui,
+                                &label,
+                                &entry.url,
+                                &mut open_url,
+                                &mut delete_url,
+                                &mut delete_domain,
+                            );
+                        }
+                    }
+                });
+            });
+        if let Some(url) = open_url {
+            self.open_url(&url);
+        }
+        if let Some(url) = delete_url {
+            let _ = self.history_store.delete_url(&url);
+        }
+        if let Some(host) = delete_domain {
+            let _ = self.history_store.delete_domain(&host);
+        }
+    }
+
+    fn toggle_bookmark(&mut self) {
+        let tab = &self.tabs[self.active];
+        match self.bookmark_store.find_url(&tab.url) {
+            Ok(Some(id)) => {
+                let _ = self.bookmark_store.remove(id);
+            }
+            Ok(None) => {
+                let _ = self
+                    .bookmark_store
+                    .add_bookmark(BOOKMARK_BAR, &tab.title, &tab.url, &[]);
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn bookmarks_window(&mut self, ctx: &egui::Context) {
+        let mut open_url = None;
+        let mut action = None;
+        let tree = self.bookmark_store.tree().unwrap_or_default();
+        egui::Window::new("Закладки")
+            .open(&mut self.show_bookmarks)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Файл");
+                    ui.text_edit_singleline(&mut self.bookmark_file);
+                    if ui.button("Импорт").clicked() {
+                        self.bookmark_status = match std::fs::read_to_string(&self.bookmark_file) {
+                            // Chromium хранит закладки в JSON, все остальные экспортируют Netscape HTML.
+                            Ok(data) if data.trim_start().starts_with('{') => {
+                                self.bookmark_store.import_chromium(&data)
+                            }
+                            Ok(data) => self.bookmark_store.import_netscape(&data),
+                            Err(e) => Err(e.into()),
+                        }
+                        .map(|n| format!("Импортировано: {n}"))
+                        .unwrap_or_else(|e| format!("error: {e}"));
+                    }
+                    if ui.button("Экспорт HTML").clicked() {
+                        self.bookmark_status = self
+                            .bookmark_store
+                            .export_netscape()
+                            .and_then(|html| Ok(std::fs::write(&self.bookmark_file, html)?))
+                            .map(|_| "Сохранено".to_string())
+                            .unwrap_or_else(|e| format!("error: {e}"));
+                    }
+                    if ui.button("Экспорт JSON").clicked() {
+                        self.bookmark_status = self
+                            .bookmark_store
+                            .export_chromium()
+                            .and_then(|json| Ok(std::fs::write(&self.bookmark_file, json)?))
+                            .map(|_| "Сохранено".to_string())
+                            .unwrap_or_else(|e| format!("error: {e}"));
+                    }
+                });
+                if !self.bookmark_status.is_empty() {
+                    ui.label(&self.bookmark_status);
+                }
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for root in &tree {
+                            bookmark_tree_node(ui, root, 0, &mut open_url, &mut action);
+                        }
+                    });
+                if let Some(edit) = &mut self.bookmark_edit {
+                    ui.separator();
+                    let mut folders = Vec::new();
+                    collect_folders(&tree, 0, edit.id, &mut folders);
+                    egui::Grid::new("bookmark-edit")
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            ui.label("Название");
+                            ui.text_edit_singleline(&mut edit.title);
+                            ui.end_row();
+                            if !edit.folder {
+                                ui.label("Адрес");
+                                ui.text_edit_singleline(&mut edit.url);
+                                ui.end_row();
+                                ui.label("Теги");
+                                ui.text_edit_singleline(&mut edit.tags);
+                                ui.end_row();
+                            }
+                            ui.label("Папка");
+                            let current = folders
+                                .iter()
+                                .find(|(id, _)| *id == edit.parent)
+                                .map(|(_, name)| name.trim().to_string())
+                                .unwrap_or_default();
+                            egui::ComboBox::from_id_salt("bookmark-parent")
+                                .selected_text(current)
+                                .show_ui(ui, |ui| {
+                                    for (id, name) in &folders {
+                                        ui.selectable_value(&mut edit.parent, *id, name);
+                                    }
+                                });
+                            ui.end_row();
+                        });
+                    ui.horizontal(|ui| {
+                        if ui.button("Сохранить").clicked() {
+                            let store = &self.bookmark_store;
+                            let url = (!edit.folder).then_some(edit.url.trim());
+                            let _ = store.update(edit.id, edit.title.trim(), url);
+                            if !edit.folder {
+                                let tags: Vec<String> =
+                                    edit.tags.split(',').map(ToOwned::to_owned).collect();
+                                let _ = store.set_tags(edit.id, &tags);
+                            }
+                            let _ = store.move_to(edit.id, edit.parent, usize::MAX);
+                            action = Some(BookmarkAction::Remove(0));
+                        }
+                        if ui.button("Отмена").clicked() {
+                            action = Some(BookmarkAction::Remove(0));
+                        }
+                    });
+                }
+            });
+        match action {
+            Some(BookmarkAction::Edit(node, parent)) => {
+                self.bookmark_edit = Some(BookmarkEditor {
+                    id: node.id,
+                    parent,
+                    folder: node.is_folder(),
+                    title: node.title,
+                    url: node.url.unwrap_or_default(),
+                    tags: node.tags.join(", "),
+                });
+            }
+            // id 0 не существует: так редактор просто закрывается.
+            Some(BookmarkAction::Remove(id)) => {
+                if id != 0 {
+                    let _ = self.bookmark_store.remove(id);
+                }
+                self.bookmark_edit = None;
+            }
+            Some(BookmarkAction::NewFolder(parent)) => {
+                if let Ok(id) = self.bookmark_store.add_folder(parent, "Новая папка") {
+                    self.bookmark_edit = Some(BookmarkEditor {
+                        id,
+                        parent,
+                        folder: true,
+                        title: "Новая папка".into(),
+                        ..BookmarkEditor::default()
+                    });
+                }
             }
+            None => {}
+        }
+        if let Some(url) = open_url {
+            self.open_url(&url);
         }
     }
 
-    fn navigate_current(&mut self) {
-        let url = self.tabs[self.active].url.clone();
-        let _ = self
+    /// Зашифрованные базы живут в памяти, поэтому вместе с сессией и при выходе
+    /// изменённые базы сбрасываются в файлы; неизменённые не переписываются.
+    fn flush_stores(&mut self) {
+        let result = self
             .history_store
-            .add_visit(&url, &self.tabs[self.active].title);
-        self.history.push_front(url.clone());
-        self.progress = 0.2;
-        if let Some(host) = &self.webview {
-            let _ = host.navigate(&url);
+            .flush()
+            .and_then(|_| self.bookmark_store.flush())
+            .and_then(|_| self.downloads.flush())
+            .and_then(|_| Ok(self.privacy_store.flush()?));
+        if let Err(e) = result {
+            self.settings_status = format!("error: {e}");
         }
     }
 
-    fn open_url(&mut self, input: &str) {
-        let url = plus_yandex::omnibox_to_url(input);
-        self.tabs[self.active].url = url.clone();
-        self.omnibox = url;
-        self.navigate_current();
-    }
-
-    fn new_tab(&mut self) {
-        self.tabs.push(Tab {
-            title: "Новая вкладка".into(),
-            url: plus_yandex::new_tab_data_url(),
-        });
-        self.active = self.tabs.len() - 1;
-        self.navigate_current();
+    fn enable_encryption(&mut self) {
+        let passphrase = self.encrypt_passphrase.trim().to_string();
+        let passphrase = (!passphrase.is_empty()).then_some(passphrase.as_str());
+        self.settings_status = match self
+            .profiles
+            .enable_encryption(&mut self.profile, passphrase)
+        {
+            Ok(()) => "Базы профиля будут зашифрованы при следующем запуске".into(),
+            Err(e) => format!("error: {e}"),
+        };
+        self.encrypt_passphrase.clear();
     }
 
-    fn close_tab(&mut self) {
-        if self.tabs.len() == 1 {
+    /// Импорт идёт в отдельном потоке со своими соединениями к базам профиля,
+    /// чтобы большой профиль не подвешивал интерфейс. Зашифрованные базы открыты
+    /// только в памяти этого процесса, поэтому туда импорт пишет через них же.
+    /// Пароли переносятся, только если есть ключ паролей профиля (keyring или
+    /// ключ зашифрованного профиля); иначе импортируются история и закладки.
+    fn start_import(&mut self) {
+        let dir = std::path::PathBuf::from(self.import_dir.trim());
+        let key = self.profile.credentials_key().ok();
+        if self.profile.is_encrypted() {
+            let passwords = key.as_ref().map(|key| PasswordTarget {
+                store: &self.privacy_store,
+                key,
+            });
+            let result = plus_net::import_profile(
+                &dir,
+                &self.history_store,
+                &self.bookmark_store,
+                passwords,
+                |_| {},
+            );
+            *self.import_status.lock().unwrap() = import_summary(result);
+            self.flush_stores();
             return;
         }
-        let closed = self.tabs.remove(self.active);
-        self.closed_tabs.push(closed);
-        if self.active >= self.tabs.len() {
-            self.active = self.tabs.len() - 1;
-        }
-        self.navigate_current();
-    }
-
-    fn close_other_tabs(&mut self) {
-        let current = self.active;
-        let tab = self.tabs[current].clone();
-        self.tabs = vec![tab];
-        self.active = 0;
-        self.navigate_current();
-    }
-
-    fn close_right_tabs(&mut self) {
-        self.tabs.truncate(self.active + 1);
-        self.navigate_current();
+        let history_db = self.profile.history_db();
+        let bookmarks_db = self.profile.bookmarks_db();
+        let privacy_db = self.profile.privacy_db();
+        let status = self.import_status.clone();
+        *status.lock().unwrap() = "Импорт…".into();
+        std::thread::spawn(move || {
+            let result = HistoryStore::open(history_db).and_then(|history| {
+                let bookmarks = BookmarkStore::open(bookmarks_db)?;
+                let privacy = PrivacyStore::open(privacy_db)?;
+                let passwords = key.as_ref().map(|key| PasswordTarget {
+                    store: &privacy,
+                    key,
+                });
+                plus_net::import_profile(&dir, &history, &bookmarks, passwords, |p| {
+                    let stage = match p.stage {
+                        plus_net::ImportStage::History => "История",
+                        plus_net::ImportStage::Bookmarks => "Закладки",
+                        plus_net::ImportStage::Passwords => "Пароли",
+                    };
+                    *status.lock().unwrap() = format!("{stage}: {}/{}", p.done, p.total);
+                })
+            });
+            *status.lock().unwrap() = import_summary(result);
+        });
     }
 
-    fn duplicate_tab(&mut self) {
-        let tab = self.tabs[self.active].clone();
-        self.tabs.insert(self.active + 1, tab);
-        self.active += 1;
-        self.navigate_current();
-    }
-    fn restore_tab(&mut self) {
-        if let Some(tab) = self.closed_tabs.pop() {
-            self.tabs.push(tab);
-            self.active = self.tabs.len() - 1;
-            self.navigate_current();
+    /// Другой профиль и инкогнито открываются отдельным процессом, как в Chromium.
+    fn launch_profile(&mut self, name: &str, incognito: bool) {
+        // Занятый профиль новый процесс всё равно не откроет — сообщаем здесь, а не молча.
+        let busy = if incognito {
+            Ok(())
+        } else {
+            self.profiles
+                .open(name)
+                .and_then(|profile| profile.lock())
+                .map(drop)
+        };
+        let result = busy.and_then(|()| std::env::current_exe()).and_then(|exe| {
+            let mut cmd = std::process::Command::new(exe);
+            cmd.arg("--profile").arg(name);
+            if incognito {
+                cmd.arg("--incognito");
+            }
+            cmd.spawn()
+        });
+        if let Err(e) = result {
+            self.profile_status = format!("error: {e}");
         }
     }
 
-    fn start_download(&mut self) {
-        let url = self.download_url.trim().to_string();
-        if url.is_empty() {
-            return;
-        }
-        let filename = url.split('/').last().unwrap_or("download.bin");
-        if filename.ends_with(".exe") || filename.ends_with(".msi") {
-            self.downloads.push(DownloadItem {
-                url: url.clone(),
-                path: "blocked".into(),
-                status: "blocked-unsafe".into(),
+    fn profiles_window(&mut self, ctx: &egui::Context) {
+        let names = self.profiles.list().unwrap_or_default();
+        let mut launch = None;
+        let mut rename = None;
+        let mut delete = None;
+        let mut create = false;
+        egui::Window::new("Профили")
+            .open(&mut self.show_profiles)
+            .show(ctx, |ui| {
+                egui::Grid::new("profiles").num_columns(4).show(ui, |ui| {
+                    for name in &names {
+                        let current = name == self.profile.name();
+                        if ui.selectable_label(current, name).clicked() && !current {
+                            launch = Some(name.clone());
+                        }
+                        let new_name = self
+                            .profile_renames
+                            .entry(name.clone())
+                            .or_insert_with(|| name.clone());
+                        ui.text_edit_singleline(new_name);
+                        // Открытый профиль держит свои базы, его нельзя переименовать или удалить.
+                        if ui
+                            .add_enabled(!current, egui::Button::new("Переименовать"))
+                            .clicked()
+                        {
+                            rename = Some((name.clone(), new_name.trim().to_string()));
+                        }
+                        if ui
+                            .add_enabled(!current, egui::Button::new("Удалить"))
+                            .clicked()
+                        {
+                            delete = Some(name.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.profile_input);
+                    create = ui.button("Создать профиль").clicked();
+                });
+                if !self.profile_status.is_empty() {
+                    ui.label(&self.profile_status);
+                }
             });
-            self.download_url.clear();
+        let result = if let Some(name) = launch {
+            self.launch_profile(&name, false);
+            Ok(())
+        } else if let Some((from, to)) = rename {
+            self.profile_renames.clear();
+            self.profiles.rename(&from, &to).map(|_| ())
+        } else if let Some(name) = delete {
+            self.profile_renames.remove(&name);
+            self.profiles.delete(&name)
+        } else if create {
+            let name = std::mem::take(&mut self.profile_input);
+            self.profiles.create(name.trim()).map(|_| ())
+        } else {
             return;
-        }
-        let downloads_dir = std::env::current_dir().unwrap_or_else(|_| std::env::temp_dir());
-        let path = downloads_dir.join(filename).to_string_lossy().to_string();
-        let status = "downloading".to_string();
-        let mut item = DownloadItem {
-            url: url.clone(),
-            path: path.clone(),
-            status,
         };
-        let result = self.runtime.block_on(async {
-            let bytes = reqwest::get(url).await?.bytes().await?;
-            tokio::fs::write(&path, &bytes).await?;
-            Ok::<_, anyhow::Error>(())
-        });
-        item.status = if result.is_ok() {
-            "completed".into()
-        } else {
-            "error".into()
+        self.profile_status = match result {
+            Ok(()) => String::new(),
+            Err(e) => format!("error: {e}"),
         };
-        self.downloads.push(item);
-        self.download_url.clear();
     }
+}
 
-    fn handle_hotkeys(&mut self, ctx: &egui::Context) {
-        let input = ctx.input(|i| i.clone());
-        if input.modifiers.command && input.key_pressed(egui::Key::T) {
-            self.new_tab();
-        }
-        if input.modifiers.command && input.modifiers.shift && input.key_pressed(egui::Key::T) {
-            self.restore_tab();
-        }
-        if input.modifiers.command && input.key_pressed(egui::Key::W) {
-            self.close_tab();
-        }
-        if input.modifiers.command && input.key_pressed(egui::Key::L) {
-            ctx.memory_mut(|m| m.request_focus("omnibox".into()));
-        }
-        if input.modifiers.command && input.key_pressed(egui::Key::R) {
-            self.progress = 0.2;
-            if let Some(host) = &self.webview {
-                let _ = host.reload();
-            }
+fn history_row(
+    ui: &mut egui::Ui,
+    label: &str,
+    url: &str,
+    open_url: &mut Option<String>,
+    delete_url: &mut Option<String>,
+    delete_domain: &mut Option<String>,
+) {
+    let response = ui.link(label).on_hover_text(url);
+    if response.clicked() {
+        *open_url = Some(url.to_string());
+    }
+    response.context_menu(|ui| {
+        if ui.button("Удалить из истории").clicked() {
+            *delete_url = Some(url.to_string());
+            ui.close_menu();
         }
-        if input.modifiers.alt && input.key_pressed(egui::Key::ArrowLeft) {
-            self.progress = 0.2;
-            if let Some(host) = &self.webview {
-                let _ = host.go_back();
+        if let Some(host) = url::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(ToOwned::to_owned))
+        {
+            if ui.button(format!("Удалить всё с {host}")).clicked() {
+                *delete_domain = Some(host);
+                ui.close_menu();
             }
         }
-        if input.modifiers.alt && input.key_pressed(egui::Key::ArrowRight) {
-            self.progress = 0.2;
-            if let Some(host) = &self.webview {
-                let _ = host.go_forward();
+    });
+}
+
+fn bookmark_label(node: &Bookmark) -> String {
+    let title = if node.title.is_empty() {
+        node.url.as_deref().unwrap_or_default()
+    } else {
+        &node.title
+    };
+    if title.chars().count() > 24 {
+        format!("{}…", title.chars().take(23).collect::<String>())
+    } else {
+        title.to_string()
+    }
+}
+
+fn bookmark_bar_item(ui: &mut egui::Ui, node: &Bookmark, open_url: &mut Option<String>) {
+    match &node.url {
+        Some(url) => {
+            if ui.button(bookmark_label(node)).on_hover_text(url).clicked() {
+                *open_url = Some(url.clone());
+                ui.close_menu();
             }
         }
-        if input.key_pressed(egui::Key::F5) {
-            self.progress = 0.2;
-            if let Some(host) = &self.webview {
-                let _ = host.reload();
-            }
+        None => {
+            ui.menu_button(format!("📁 {}", bookmark_label(node)), |ui| {
+                for child in &node.children {
+                    bookmark_bar_item(ui, child, open_url);
+                }
+            });
         }
-        if input.key_pressed(egui::Key::Escape) {
-            self.progress = 0.2;
-            if let Some(host) = &self.webview {
-                let _ = host.stop();
+    }
+}
+
+fn bookmark_tree_node(
+    ui: &mut egui::Ui,
+    node: &Bookmark,
+    parent: i64,
+    open_url: &mut Option<String>,
+    action: &mut Option<BookmarkAction>,
+) {
+    match &node.url {
+        Some(url) => {
+            let response = ui.link(&node.title).on_hover_text(url);
+            if response.clicked() {
+                *open_url = Some(url.clone());
             }
+            response.context_menu(|ui| {
+                if ui.button("Изменить").clicked() {
+                    *action = Some(BookmarkAction::Edit(node.clone(), parent));
+                    ui.close_menu();
+                }
+                if ui.button("Удалить").clicked() {
+                    *action = Some(BookmarkAction::Remove(node.id));
+                    ui.close_menu();
+                }
+            });
         }
-        if input.modifiers.command && input.key_pressed(egui::Key::D) {
-            let url = self.tabs[self.active].url.clone();
-            self.bookmarks.push(url);
+        None => {
+            let header = egui::CollapsingHeader::new(format!("📁 {}", node.title))
+                .id_salt(("bookmark-folder", node.id))
+                .default_open(node.id == BOOKMARK_BAR)
+                .show(ui, |ui| {
+                    for child in &node.children {
+                        bookmark_tree_node(ui, child, node.id, open_url, action);
+                    }
+                });
+            header.header_response.context_menu(|ui| {
+                if ui.button("Новая папка").clicked() {
+                    *action = Some(BookmarkAction::NewFolder(node.id));
+                    ui.close_menu();
+                }
+                // Корневые папки (parent 0) не переименовываются и не удаляются.
+                if parent != 0 {
+                    if ui.button("Изменить").clicked() {
+                        *action = Some(BookmarkAction::Edit(node.clone(), parent));
+                        ui.close_menu();
+                    }
+                    if ui.button("Удалить").clicked() {
+                        *action = Some(BookmarkAction::Remove(node.id));
+                        ui.close_menu();
+                    }
+                }
+            });
         }
     }
+}
 
-    fn check_ip(&mut self) {
-        if self.diagnostics.checking {
-            return;
-        }
-        self.diagnostics.checking = true;
-        let proxy = self.proxy.clone();
-        let handle = self.runtime.handle().clone();
-        let ip_state = Arc::new(Mutex::new(String::new()));
-        let ip_state_clone = ip_state.clone();
-        handle.spawn(async move {
-            let client = plus_net::NetClient::new(proxy).unwrap();
-            let ip = client
-                .get_egress_ip()
-                .await
-                .unwrap_or_else(|_| "error".into());
-            *ip_state_clone.lock().unwrap() = ip;
-        });
-        if let Ok(ip) = ip_state.lock() {
-            self.diagnostics.ip = ip.clone();
-        }
-        self.diagnostics.checking = false;
+/// Папки для выбора родителя, кроме самой редактируемой и её потомков.
+fn collect_folders(nodes: &[Bookmark], depth: usize, skip: i64, out: &mut Vec<(i64, String)>) {
+    for node in nodes.iter().filter(|n| n.is_folder() && n.id != skip) {
+        out.push((node.id, format!("{}{}", "  ".repeat(depth), node.title)));
+        collect_folders(&node.children, depth + 1, skip, out);
     }
 }
 
 impl eframe::App for PlusApp {
+    fn on_exit(&mut self) {
+        self.webview = None;
+        if self.clear_settings.on_exit {
+            let options = self.clear_settings.on_exit_options.clone();
+            self.clear_data(&options);
+        }
+        let rules = self
+            .cookie_rules
+            .lock()
+            .map(|r| r.clone())
+            .unwrap_or_default();
+        let _ = self.privacy_store.end_cookie_session(&rules);
+        if rules.clear_on_exit && !self.profile.is_incognito() {
+            let _ = clear_webview_cookies(&self.profile.webview_data_dir(), &rules);
+        }
+        // Если WebView2 ещё не отпустил файлы, очистка останется в очереди до запуска.
+        let _ = self
+            .privacy_store
+            .run_pending_webview_clear(&self.profile.webview_data_dir());
+        self.save_session(true);
+        self.flush_stores();
+        let _ = self.profile.wipe();
+    }
+
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if self.dark_mode {
+        self.apply_setting_changes();
+        self.poll_diagnostics(ctx);
+        self.poll_vpn(ctx);
+        self.poll_navigation(ctx);
+        self.poll_scroll();
+        self.publish_status();
+        if self.last_session_save.elapsed() >= SESSION_SAVE_INTERVAL {
+            self.capture_scroll();
+            self.save_session(false);
+            self.flush_stores();
+        }
+        // Прогресс загрузок приходит из фоновых задач, окно само не перерисуется.
+        if self.downloads.list().iter().any(|d| d.state.is_active()) {
+            ctx.request_repaint_after(Duration::from_millis(250));
+        }
+        if self.privacy.dark_mode {
             ctx.set_visuals(egui::Visuals::dark());
         } else {
             ctx.set_visuals(egui::Visuals::light());
         }
         self.ensure_webview(frame);
+        if let Some(host) = &mut self.webview {
+            if host.pump_events() {
+                ctx.request_repaint_after(Duration::from_millis(16));
+            }
+        }
         self.handle_hotkeys(ctx);
+        self.show_recovery_prompt(ctx);
 
         egui::TopBottomPanel::top("tabs")
             .exact_height(40.0)
@@ -336,7 +1972,6 @@ impl eframe::App for PlusApp {
                         });
                         if response.clicked() {
                             self.active = idx;
-                            self.navigate_current();
                         }
                         if response.drag_started() {
                             self.drag_tab = Some(idx);
@@ -355,12 +1990,73 @@ impl eframe::App for PlusApp {
                     if ui.button("+").clicked() {
                         self.new_tab();
                     }
+                    ui.menu_button("⋯", |ui| {
+                        let has_closed_tabs = !self.closed_tabs.is_empty();
+                        if ui
+                            .add_enabled(has_closed_tabs, egui::Button::new("Восстановить вкладку"))
+                            .on_hover_text("Ctrl+Shift+T")
+                            .clicked()
+                        {
+                            self.restore_tab();
+                            ui.close_menu();
+                        }
+                        if ui
+                            .button("Закрыть окно")
+                            .on_hover_text("Ctrl+Shift+W")
+                            .clicked()
+                        {
+                            self.close_window();
+                            ui.close_menu();
+                        }
+                        let has_closed_windows = !self.session.closed_windows.is_empty();
+                        if ui
+                            .add_enabled(
+                                has_closed_windows,
+                                egui::Button::new("Открыть закрытое окно"),
+                            )
+                            .clicked()
+                        {
+                            self.restore_window();
+                            ui.close_menu();
+                        }
+                    });
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                         let settings_btn = ui.button("⚙");
                         settings_btn.on_hover_text("Настройки");
                         if settings_btn.clicked() {
                             self.show_settings = !self.show_settings;
                         }
+                        if ui.button("🕘").on_hover_text("История").clicked() {
+                            self.show_history = !self.show_history;
+                        }
+                        if ui.button("📑").on_hover_text("Закладки").clicked() {
+                            self.show_bookmarks = !self.show_bookmarks;
+                        }
+                        ui.menu_button(format!("👤 {}", self.profile.name()), |ui| {
+                            for name in self.profiles.list().unwrap_or_default() {
+                                let current = name == self.profile.name();
+                                if ui.selectable_label(current, &name).clicked() {
+                                    if !current {
+                                        self.launch_profile(&name, false);
+                                    }
+                                    ui.close_menu();
+                                }
+                            }
+                            ui.separator();
+                            if ui.button("Новое окно инкогнито").clicked() {
+                                let base = if self.profile.is_incognito() {
+                                    DEFAULT_PROFILE.to_string()
+                                } else {
+                                    self.profile.name().to_string()
+                                };
+                                self.launch_profile(&base, true);
+                                ui.close_menu();
+                            }
+                            if ui.button("Управление профилями…").clicked() {
+                                self.show_profiles = true;
+                                ui.close_menu();
+                            }
+                        });
                         let diag_btn = ui.button("🛡");
                         diag_btn.on_hover_text("Диагностика");
                         if diag_btn.clicked() {
@@ -377,32 +2073,24 @@ impl eframe::App for PlusApp {
                     let back = ui.button("←");
                     back.on_hover_text("Назад");
                     if back.clicked() {
-                        self.progress = 0.2;
-                        if let Some(host) = &self.webview {
-                            let _ = host.go_back();
-                        }
+                        self.go_back();
                     }
                     let forward = ui.button("→");
                     forward.on_hover_text("Вперёд");
                     if forward.clicked() {
-                        self.progress = 0.2;
-                        if let Some(host) = &self.webview {
-                            let _ = host.go_forward();
-                        }
+                        self.go_forward();
                     }
                     let reload = ui.button("⟳");
                     reload.on_hover_text("Обновить");
                     if reload.clicked() {
-                        self.progress = 0.2;
-                        if let Some(host) = &self.webview {
+                        if let Some(host) = &mut self.webview {
                             let _ = host.reload();
                         }
                     }
                     let stop = ui.button("⏹");
                     stop.on_hover_text("Стоп");
                     if stop.clicked() {
-                        self.progress = 0.2;
-                        if let Some(host) = &self.webview {
+                        if let Some(host) = &mut self.webview {
                             let _ = host.stop();
                         }
                     }
@@ -416,17 +2104,38 @@ impl eframe::App for PlusApp {
                         egui::TextEdit::singleline(&mut self.omnibox).id_source("omnibox"),
                     );
                     if response.has_focus() {
+                        let key = (
+                            self.omnibox.clone(),
+                            self.history_store.revision().ok(),
+                            self.bookmark_store.revision().ok(),
+                        );
+                        let suggestions = self
+                            .omnibox_suggestions
+                            .get(key, || {
+                                self.history_store
+                                    .search(&self.omnibox, 5)
+                                    .unwrap_or_default()
+                                    .into_iter()
+                                    .map(|e| e.url)
+                                    .chain(
+                                        self.bookmark_store
+                                            .search(&self.omnibox, 5)
+                                            .unwrap_or_default()
+                                            .into_iter()
+                                            .filter_map(|b| b.url),
+                                    )
+                                    .collect()
+                            })
+                            .clone();
                         egui::popup::show_below_widget(
                             ui,
                             egui::Id::new("omnibox-popup"),
                             &response,
                             |ui| {
                                 ui.set_min_width(response.rect.width());
-                                for item in self.history.iter().take(5).chain(self.bookmarks.iter())
-                                {
+                                for item in &suggestions {
                                     if ui.button(item).clicked() {
-                                        let input = item.clone();
-                                        self.open_url(&input);
+                                        self.open_url(item);
                                         ui.close_menu();
                                     }
                                 }
@@ -437,26 +2146,66 @@ impl eframe::App for PlusApp {
                         let input = self.omnibox.clone();
                         self.open_url(&input);
                     }
-                    let star = ui.button("☆");
-                    star.on_hover_text("Добавить в закладки");
-                    if star.clicked() {
-                        self.bookmarks.push(self.tabs[self.active].url.clone());
+                    let bookmarked = matches!(
+                        self.bookmark_store.find_url(&self.tabs[self.active].url),
+                        Ok(Some(_))
+                    );
+                    let (star, hint) = if bookmarked {
+                        ("★", "Удалить из закладок")
+                    } else {
+                        ("☆", "Добавить в закладки")
+                    };
+                    if ui.button(star).on_hover_text(hint).clicked() {
+                        self.toggle_bookmark();
                     }
                 });
             });
 
-        if self.progress > 0.0 {
+        let bar = self
+            .bookmark_store
+            .children(BOOKMARK_BAR)
+            .unwrap_or_default();
+        if !bar.is_empty() {
+            egui::TopBottomPanel::top("bookmark-bar")
+                .exact_height(28.0)
+                .show(ctx, |ui| {
+                    let mut open_url = None;
+                    ui.horizontal(|ui| {
+                        for node in &bar {
+                            bookmark_bar_item(ui, node, &mut open_url);
+                        }
+                    });
+                    if let Some(url) = open_url {
+                        self.open_url(&url);
+                    }
+                });
+        }
+
+        let page = self.pages.get(&self.tabs[self.active].id);
+        let loading = page.is_some_and(|p| p.loading);
+        let error = page.and_then(|p| p.error.clone());
+        if loading {
+            // WebView2 не сообщает процент загрузки: полоса ползёт до 90% и ждёт `Completed`.
+            self.progress = (self.progress + 0.01).clamp(0.1, 0.9);
             egui::TopBottomPanel::top("progress")
                 .exact_height(2.0)
                 .show(ctx, |ui| {
                     ui.add(egui::ProgressBar::new(self.progress).show_percentage(false));
                 });
-            self.progress = (self.progress + 0.05).min(1.0);
-            if self.progress >= 1.0 {
-                self.progress = 0.0;
-            }
+            ctx.request_repaint_after(Duration::from_millis(50));
+        } else {
+            self.progress = 0.0;
+        }
+        if let Some(error) = error {
+            egui::TopBottomPanel::top("page-error").show(ctx, |ui| {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!("Не удалось открыть страницу: {error}"),
+                );
+            });
         }
 
+        self.sync_webviews();
         egui::CentralPanel::default().show(ctx, |ui| {
             let rect = ui.available_rect_before_wrap();
             let pixels_per_point = ctx.pixels_per_point();
@@ -464,8 +2213,7 @@ impl eframe::App for PlusApp {
             let y = (rect.min.y * pixels_per_point) as i32;
             let w = (rect.width() * pixels_per_point) as i32;
             let h = (rect.height() * pixels_per_point) as i32;
-            self.progress = 0.2;
-            if let Some(host) = &self.webview {
+            if let Some(host) = &mut self.webview {
                 let _ = host.set_bounds(x, y, w, h);
             }
         });
@@ -482,22 +2230,102 @@ impl eframe::App for PlusApp {
                 ));
                 ui.label(format!("VPN: {}", self.vpn_status));
                 ui.label(format!("VPN endpoint: {}", self.vpn_endpoint));
+                let mut kill_switch = self.kill_switch.is_enabled();
+                if ui.checkbox(&mut kill_switch, "Kill switch").changed() {
+                    self.engine.set_kill_switch(kill_switch);
+                    self.kill_switch.set_enabled(kill_switch);
+                    let _ = self
+                        .privacy_store
+                        .set(BROWSER_POLICY_KEY, self.engine.policy());
+                }
+                if self
+                    .kill_switch
+                    .is_engaged(self.vpn.browser_proxy().is_some())
+                {
+                    ui.colored_label(egui::Color32::RED, "VPN недоступен: трафик заблокирован");
+                }
                 if let Ok(ad) = self.adblock.lock() {
                     ui.label(format!("Adblock hits: {}", ad.stats.blocked));
                     for url in ad.last_blocked() {
                         ui.label(url);
                     }
                 }
-                if ui.button("Check IP").clicked() {
-                    self.check_ip();
+                let running = self.diagnostics.pending.is_some();
+                if ui
+                    .add_enabled(!running, egui::Button::new("Проверить сеть"))
+                    .on_hover_text("Внешний IP, путь DNS, прокси и VPN core")
+                    .clicked()
+                {
+                    self.run_diagnostics();
                 }
-                if !self.diagnostics.ip.is_empty() {
-                    ui.label(format!("IP: {}", self.diagnostics.ip));
+                for kind in CheckKind::ALL {
+                    match self.diagnostics.results.get(&kind) {
+                        Some(result) => {
+                            let color = match result.status {
+                                CheckStatus::Ok => egui::Color32::DARK_GREEN,
+                                CheckStatus::Warning => egui::Color32::from_rgb(0xd0, 0x90, 0x00),
+                                CheckStatus::Failed => egui::Color32::RED,
+                                CheckStatus::Skipped => egui::Color32::GRAY,
+                            };
+                            let latency = result
+                                .latency
+                                .map(|l| format!(" — {} мс", l.as_millis()))
+                                .unwrap_or_default();
+                            ui.colored_label(
+                                color,
+                                format!("{}: {}{latency}", kind.label(), result.detail),
+                            );
+                        }
+                        None if running => {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label(kind.label());
+                            });
+                        }
+                        None => {}
+                    }
                 }
             });
         }
 
-        if self.show_settings {
+        if self.show_history {
+            self.history_window(ctx);
+        }
+
+        if self.show_bookmarks {
+            self.bookmarks_window(ctx);
+        }
+
+        if self.show_profiles {
+            self.profiles_window(ctx);
+        }
+
+        if self.show_cookies {
+            self.cookies_window(ctx);
+        }
+
+        if self.show_clear_data {
+            self.clear_data_window(ctx);
+        }
+
+        if !self.show_settings {
+            self.settings_draft = None;
+        } else {
+            let mut draft = self.settings_draft.take().unwrap_or_else(|| SettingsDraft {
+                privacy: self.privacy.clone(),
+                policy: self.engine.policy().clone(),
+                vpn_domains: {
+                    let mut domains: Vec<_> = self
+                        .engine
+                        .policy()
+                        .vpn_domain_list
+                        .iter()
+                        .cloned()
+                        .collect();
+                    domains.sort();
+                    domains.join(", ")
+                },
+            });
             egui::Window::new("Настройки").show(ctx, |ui| {
                 ui.horizontal(|ui| {
                     ui.vertical(|ui| {
@@ -513,43 +2341,359 @@ impl eframe::App for PlusApp {
                     ui.separator();
                     ui.vertical(|ui| {
                         ui.heading("Внешний вид");
-                        ui.checkbox(&mut self.dark_mode, "Тёмная тема");
+                        ui.checkbox(&mut draft.privacy.dark_mode, "Тёмная тема");
+                        ui.add_space(8.0);
+                        ui.heading("Поиск");
+                        ui.checkbox(&mut draft.policy.yandex_only_search, "Искать только в Яндексе");
+                        ui.add_space(8.0);
+                        ui.heading("Конфиденциальность");
+                        ui.checkbox(&mut draft.privacy.telemetry_enabled, "Отправлять телеметрию");
+                        ui.checkbox(&mut draft.privacy.incognito, "Всегда запускать в режиме инкогнито");
+                        ui.checkbox(&mut draft.policy.allow_file_scheme, "Разрешить file:// адреса");
+                        if ui.button("Cookie и данные сайтов…").clicked() {
+                            self.show_cookies = true;
+                        }
+                        if ui.button("Очистить данные…").clicked() {
+                            self.show_clear_data = true;
+                        }
+                        if !self.profile.is_incognito() {
+                            ui.add_space(8.0);
+                            ui.heading("Шифрование профиля");
+                            if self.profile.is_encrypted() {
+                                ui.label("Базы профиля зашифрованы, ключ хранится в системном хранилище");
+                            } else {
+                                ui.horizontal(|ui| {
+                                    ui.label("Пароль (необязательно)");
+                                    ui.add(
+                                        egui::TextEdit::singleline(&mut self.encrypt_passphrase)
+                                            .password(true),
+                                    );
+                                });
+                                if ui
+                                    .button("Зашифровать")
+                                    .on_hover_text("Без пароля ключ хранится только в системном хранилище ключей")
+                                    .clicked()
+                                {
+                                    self.enable_encryption();
+                                }
+                            }
+                        }
+                        ui.add_space(8.0);
+                        ui.heading("VPN");
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut draft.policy.vpn_mode, VpnRouteMode::Off, "Выключен");
+                            ui.radio_value(&mut draft.policy.vpn_mode, VpnRouteMode::Global, "Весь трафик");
+                            ui.radio_value(
+                                &mut draft.policy.vpn_mode,
+                                VpnRouteMode::DomainList,
+                                "Только домены",
+                            );
+                        });
+                        if draft.policy.vpn_mode == VpnRouteMode::DomainList {
+                            ui.text_edit_singleline(&mut draft.vpn_domains);
+                        }
+                        ui.checkbox(&mut draft.policy.kill_switch, "Kill switch");
+                        ui.horizontal(|ui| {
+                            if ui.button("Сохранить").clicked() {
+                                self.save_settings(&draft);
+                            }
+                            if ui.button("Сбросить").clicked() {
+                                draft.privacy = PrivacySettings::default();
+                                draft.policy = BrowserPolicy {
+                                    incognito: self.profile.is_incognito(),
+                                    ..BrowserPolicy::default()
+                                };
+                                draft.vpn_domains.clear();
+                            }
+                            ui.label(&self.settings_status);
+                        });
                         ui.add_space(8.0);
                         ui.heading("Загрузки");
                         ui.horizontal(|ui| {
                             ui.text_edit_singleline(&mut self.download_url);
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.download_sha256)
+                                    .hint_text("SHA-256 (необязательно)")
+                                    .desired_width(160.0),
+                            );
                             if ui.button("Скачать").clicked() {
                                 self.start_download();
                             }
                         });
-                        for item in &self.downloads {
-                            ui.label(format!("{} -> {} ({})", item.url, item.path, item.status));
+                        ui.label(format!("Папка: {}", self.downloads.dir().display()));
+                        if !self.download_status.is_empty() {
+                            ui.label(&self.download_status);
+                        }
+                        self.downloads_list(ui);
+                        // Импорт пишет в базы профиля отдельными соединениями, в инкогнито их нет.
+                        if !self.profile.is_incognito() {
+                            ui.add_space(8.0);
+                            ui.heading("Импорт из браузера");
+                            ui.label("Каталог профиля Chromium (History, Bookmarks) или Firefox (places.sqlite)");
+                            ui.horizontal(|ui| {
+                                ui.text_edit_singleline(&mut self.import_dir);
+                                if ui.button("Импортировать").clicked() {
+                                    self.start_import();
+                                }
+                            });
+                            if let Ok(status) = self.import_status.lock() {
+                                if !status.is_empty() {
+                                    ui.label(status.as_str());
+                                }
+                            }
                         }
                     });
                 });
             });
+            self.settings_draft = Some(draft);
+        }
+    }
+}
+
+fn import_summary(result: Result<plus_net::ImportReport>) -> String {
+    match result {
+        Ok(r) => {
+            let mut summary = format!(
+                "Готово: история +{} (дубликатов {}), закладки +{} (дубликатов {}), пароли +{} (дубликатов {})",
+                r.history_added,
+                r.history_skipped,
+                r.bookmarks_added,
+                r.bookmarks_skipped,
+                r.passwords_added,
+                r.passwords_skipped
+            );
+            if r.passwords_locked > 0 {
+                summary += &format!("; не расшифровано паролей: {}", r.passwords_locked);
+            }
+            summary
+        }
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+fn toggle_site(sites: &mut Vec<String>, site: &str, on: bool) {
+    sites.retain(|s| s != site);
+    if on {
+        sites.push(site.to_string());
+        sites.sort();
+    }
+}
+
+/// Политика движка из настроек профиля; инкогнито определяется профилем, а не сохранённым значением.
+fn load_policy(store: &PrivacyStore, profile: &Profile) -> Result<BrowserPolicy> {
+    let mut policy: BrowserPolicy = store.get(BROWSER_POLICY_KEY)?;
+    policy.incognito = profile.is_incognito();
+    Ok(policy)
+}
+
+/// `--profile NAME` или `--profile=NAME`; без флага — профиль по умолчанию.
+/// Флаг `--incognito` разбирается отдельно и от профиля не зависит.
+fn profile_arg(args: impl IntoIterator<Item = String>) -> String {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            if let Some(name) = args.next() {
+                return name;
+            }
+        } else if let Some(name) = arg.strip_prefix("--profile=") {
+            return name.to_string();
+        }
+    }
+    DEFAULT_PROFILE.to_string()
+}
+
+/// Всё, что нужно для запуска окна браузера, кроме ключа зашифрованного профиля.
+struct Launch {
+    profiles: ProfileManager,
+    base: Profile,
+    incognito: bool,
+    lock: Option<ProfileLock>,
+    /// Профиль запущенного окна: его каталог инкогнито `main` стирает после выхода.
+    session: Rc<RefCell<Option<Profile>>>,
+}
+
+impl Launch {
+    /// Открывает хранилища уже разблокированного профиля, прокси и VPN из `PLUS_VPN_IMPORT`.
+    fn start(self) -> Result<PlusApp> {
+        let Self {
+            profiles,
+            base,
+            incognito,
+            mut lock,
+            session,
+        } = self;
+        let base_store = PrivacyStore::for_profile(&base)?;
+        let privacy: PrivacySettings = base_store.get(PRIVACY_SETTINGS_KEY)?;
+        let (profile, privacy_store) = if privacy.incognito || incognito {
+            // Инкогнито начинает с настроек профиля, но правки остаются в памяти.
+            lock = None;
+            let profile = Profile::incognito()?;
+            let store = PrivacyStore::for_profile(&profile)?;
+            for key in [PRIVACY_SETTINGS_KEY, BROWSER_POLICY_KEY] {
+                if let Some(value) = base_store.load_setting(key)? {
+                    store.save_setting(key, &value)?;
+                }
+            }
+            (profile, store)
+        } else {
+            (base, base_store)
+        };
+        *session.borrow_mut() = Some(profile.clone());
+        let mut app = PlusApp::new(profiles, profile, privacy_store)?;
+        app.profile_lock = lock;
+        let adblock = app.adblock.clone();
+        let proxy_handle = app.runtime.block_on(start_proxy(
+            "127.0.0.1:0",
+            adblock,
+            None,
+            Some(app.internal_pages.clone()),
+        ))?;
+        app.proxy = Some(format!("http://{}", proxy_handle.listen_addr));
+        app.downloads.set_proxy(app.proxy.as_deref())?;
+        app.kill_switch = proxy_handle.kill_switch.clone();
+        app.proxy_handle = Some(proxy_handle);
+        // Неудачный VPN не мешает открыть браузер: ошибка видна в статусе VPN.
+        if let Ok(url) = std::env::var("PLUS_VPN_IMPORT") {
+            match app.vpn.import(&url, VpnMode::Global, true) {
+                Ok(_) => {
+                    app.engine.set_vpn_mode(VpnRouteMode::Global, Vec::new());
+                    app.vpn_endpoint = url.clone();
+                    app.start_vpn();
+                }
+                Err(e) => app.vpn_status = format!("error: {e}"),
+            }
+        }
+        app.kill_switch.set_enabled(app.engine.policy().kill_switch);
+        app.kill_switch
+            .set_vpn_expected(app.engine.policy().vpn_mode != VpnRouteMode::Off);
+        Ok(app)
+    }
+}
+
+/// Окно до запуска браузера: зашифрованный профиль, ключа которого нет в keyring,
+/// открывается паролем. Иначе сразу `Running`.
+enum Startup {
+    Locked {
+        launch: Option<Launch>,
+        passphrase: String,
+        error: String,
+    },
+    Running(Box<PlusApp>),
+}
+
+impl Startup {
+    fn unlock_prompt(
+        ctx: &egui::Context,
+        launch: &Launch,
+        passphrase: &mut String,
+        error: &str,
+    ) -> bool {
+        let mut submit = false;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(ui.available_height() / 3.0);
+                ui.heading(format!("Профиль «{}» зашифрован", launch.base.name()));
+                ui.label("Ключа нет в системном хранилище. Введите пароль профиля.");
+                let field = ui.add(
+                    egui::TextEdit::singleline(passphrase)
+                        .password(true)
+                        .hint_text("Пароль"),
+                );
+                field.request_focus();
+                submit = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                submit |= ui.button("Открыть").clicked();
+                if !error.is_empty() {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+        });
+        submit && !passphrase.is_empty()
+    }
+}
+
+impl eframe::App for Startup {
+    fn on_exit(&mut self) {
+        if let Startup::Running(app) = self {
+            app.on_exit();
+        }
+    }
+
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let Startup::Locked {
+            launch,
+            passphrase,
+            error,
+        } = self
+        else {
+            if let Startup::Running(app) = self {
+                app.update(ctx, frame);
+            }
+            return;
+        };
+        let Some(pending) = launch.as_mut() else {
+            // Запуск уже не удался: остаётся только показать ошибку.
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.colored_label(egui::Color32::RED, error.as_str());
+            });
+            return;
+        };
+        if !Self::unlock_prompt(ctx, pending, passphrase, error) {
+            return;
+        }
+        let unlocked = pending.base.unlock(Some(passphrase));
+        passphrase.clear();
+        if let Err(e) = unlocked {
+            *error = match e.kind() {
+                std::io::ErrorKind::PermissionDenied => "Неверный пароль".into(),
+                _ => format!("error: {e}"),
+            };
+            return;
+        }
+        match launch.take().map(Launch::start) {
+            Some(Ok(app)) => *self = Startup::Running(Box::new(app)),
+            Some(Err(e)) => *error = format!("error: {e}"),
+            None => {}
         }
+        ctx.request_repaint();
     }
 }
 
 fn main() -> Result<()> {
-    let mut app = PlusApp::new()?;
-    let mut vpn_socks = None;
-    if let Ok(url) = std::env::var("PLUS_VPN_IMPORT") {
-        let _ = app.vpn.import(&url, VpnMode::Global, true)?;
-        app.vpn_status = "starting".into();
-        app.vpn_endpoint = url.clone();
-        app.runtime.block_on(app.vpn.start_core())?;
-        vpn_socks = app.vpn.browser_proxy().map(|p| p.replace("socks5h://", ""));
-        app.vpn_status = "connected".into();
-    }
-    let adblock = app.adblock.clone();
-    let proxy_handle =
-        app.runtime
-            .block_on(start_proxy("127.0.0.1:0", adblock, vpn_socks.clone()))?;
-    app.proxy = Some(format!("http://{}", proxy_handle.listen_addr));
+    let profiles = ProfileManager::new(ProfileManager::default_root());
+    let mut base = profiles.open(&profile_arg(std::env::args().skip(1)))?;
+    let incognito = std::env::args().any(|a| a == "--incognito");
+    // Окно инкогнито в профиль не пишет, поэтому открывается рядом с обычным.
+    let lock = if incognito { None } else { Some(base.lock()?) };
+    // Ключ не нашёлся в системном хранилище — остаётся пароль профиля: из
+    // `PLUS_PROFILE_PASSPHRASE` или из окна запроса.
+    if base.is_locked() {
+        if let Ok(passphrase) = std::env::var("PLUS_PROFILE_PASSPHRASE") {
+            base.unlock(Some(&passphrase))?;
+        }
+    }
+    let session = Rc::new(RefCell::new(None));
+    let launch = Launch {
+        profiles,
+        base,
+        incognito,
+        lock,
+        session: session.clone(),
+    };
+    let startup = if launch.base.is_locked() {
+        Startup::Locked {
+            launch: Some(launch),
+            passphrase: String::new(),
+            error: String::new(),
+        }
+    } else {
+        Startup::Running(Box::new(launch.start()?))
+    };
 
     let options = eframe::NativeOptions::default();
-    eframe::run_native("Plus", options, Box::new(|_| Ok(Box::new(app))))?;
+    eframe::run_native("Plus", options, Box::new(|_| Ok(Box::new(startup))))?;
+    // Повторно после закрытия окна: к этому моменту WebView2 отпускает свои файлы.
+    if let Some(profile) = session.borrow().as_ref() {
+        profile.wipe()?;
+    }
     Ok(())
 }
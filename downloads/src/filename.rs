@@ -0,0 +1,377 @@
+use plus_privacy::is_reserved_windows_name;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Имя файла, если ни заголовок, ни URL его не дали.
+const FALLBACK_NAME: &str = "download";
+/// Предел большинства файловых систем для одного имени, в байтах.
+const MAX_NAME_LEN: usize = 255;
+/// Составные расширения, которые не разрываются номером копии.
+const COMPOUND_EXTENSIONS: &[&str] = &[".tar.gz", ".tar.bz2", ".tar.xz", ".tar.zst"];
+
+/// Имя файла из заголовка Content-Disposition (RFC 6266): `filename*` (RFC 8187)
+/// важнее `filename`. Имя возвращается как есть, без очистки.
+pub fn disposition_filename(value: &str) -> Option<String> {
+    let params = params(value);
+    let find = |wanted: &str| {
+        params
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.as_str())
+    };
+    find("filename*")
+        .and_then(decode_ext_value)
+        .or_else(|| find("filename").map(str::to_string))
+        .filter(|name| !name.is_empty())
+}
+
+/// Имя для сохранения: из Content-Disposition, иначе из последнего сегмента пути URL,
+/// иначе по имени хоста. Результат безопасен как имя файла на любой платформе.
+pub fn resolve_file_name(url: &Url, content_disposition: Option<&str>) -> String {
+    content_disposition
+        .and_then(disposition_filename)
+        .and_then(|name| clean_name(&name))
+        .or_else(|| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .and_then(|segment| clean_name(&percent_decode(segment)))
+        })
+        .or_else(|| url.host_str().and_then(clean_name))
+        .unwrap_or_else(|| FALLBACK_NAME.into())
+}
+
+/// Делает из произвольной строки имя файла: отбрасывает путь и `..`, управляющие
+/// символы и символы, запрещённые в Windows, точки и пробелы по краям, имена
+/// устройств Windows; обрезает до 255 байт, сохраняя расширение.
+pub fn sanitize_file_name(raw: &str) -> String {
+    clean_name(raw).unwrap_or_else(|| FALLBACK_NAME.into())
+}
+
+/// `None`, если от имени ничего не осталось.
+fn clean_name(raw: &str) -> Option<String> {
+    // Всё до последнего разделителя — путь, который сервер не вправе выбирать.
+    let base = raw.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !is_invisible(*c))
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let mut name = cleaned
+        .trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string();
+    if name.is_empty() {
+        return None;
+    }
+    if is_reserved_windows_name(&name) {
+        name.insert(0, '_');
+    }
+    Some(truncate_name(&name, MAX_NAME_LEN))
+}
+
+/// Свободный путь в `dir`: `name`, иначе `name (1)`, `name (2)`… перед расширением.
+/// `taken` решает, занято ли имя — файлом на диске или другой загрузкой.
+pub fn unique_path(dir: &Path, name: &str, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let candidate = dir.join(name);
+    if !taken(&candidate) {
+        return candidate;
+    }
+    // Как в `truncate_name`: слишком длинное «расширение» — часть имени, иначе
+    // для основы не осталось бы места.
+    let (stem, ext) = match split_extension(name) {
+        (stem, ext) if ext.len() < MAX_NAME_LEN / 2 => (stem, ext),
+        _ => (name, ""),
+    };
+    (1..)
+        .map(|n| {
+            let suffix = format!(" ({n})");
+            let budget = MAX_NAME_LEN.saturating_sub(suffix.len() + ext.len());
+            let stem = truncate_name(stem, budget);
+            dir.join(format!("{stem}{suffix}{ext}"))
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("unbounded range")
+}
+
+/// Параметры `name=value` после типа; значения в кавычках раскавычиваются.
+fn params(value: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut rest = match value.split_once(';') {
+        Some((_, rest)) => rest,
+        None => return out,
+    };
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        let Some((name, after)) = rest.split_once('=') else {
+            break;
+        };
+        let name = name.trim().to_string();
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => unquote(quoted),
+            None => match after.split_once(';') {
+                Some((value, next)) => (value.trim().to_string(), next),
+                None => (after.trim().to_string(), ""),
+            },
+        };
+        out.push((name, value));
+        rest = next;
+    }
+    out
+}
+
+/// Строка в кавычках с `\`-экранированием; возвращает значение и остаток после неё.
+fn unquote(quoted: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    value.push(escaped);
+                }
+            }
+            '"' => return (value, &quoted[i + 1..]),
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+/// `charset'язык'значение` из RFC 8187. Неизвестная кодировка — `None`,
+/// и тогда берётся обычный `filename`.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.trim();
+    let _language = parts.next()?;
+    let bytes = urlencoding::decode_binary(parts.next()?.as_bytes());
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes.into_owned()).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.iter().map(|&b| char::from(b)).collect())
+    } else {
+        None
+    }
+}
+
+fn percent_decode(segment: &str) -> String {
+    String::from_utf8_lossy(&urlencoding::decode_binary(segment.as_bytes())).into_owned()
+}
+
+/// Символы, которые не видны, но меняют вид имени: смена направления текста,
+/// нулевая ширина, BOM.
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' | '\u{feff}'
+    )
+}
+
+fn split_extension(name: &str) -> (&str, &str) {
+    let lower = name.to_ascii_lowercase();
+    if let Some(ext) = COMPOUND_EXTENSIONS
+        .iter()
+        .find(|ext| lower.len() > ext.len() && lower.ends_with(*ext))
+    {
+        return name.split_at(name.len() - ext.len());
+    }
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    }
+}
+
+/// Обрезает имя до `max` байт по границе символа, не трогая расширение.
+fn truncate_name(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+    let (stem, ext) = split_extension(name);
+    let (stem, ext) = if ext.len() < max / 2 {
+        (stem, ext)
+    } else {
+        (name, "")
+    };
+    let mut end = max - ext.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{ext}", &stem[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_content_disposition() {
+        let cases = [
+            ("attachment; filename=report.pdf", Some("report.pdf")),
+            ("attachment; filename=\"annual report.pdf\"", Some("annual report.pdf")),
+            ("inline; FILENAME=\"a\\\"b.txt\"", Some("a\"b.txt")),
+            ("attachment; filename=\"semi;colon.txt\"; size=10", Some("semi;colon.txt")),
+            (
+                "attachment; filename=\"fallback.txt\"; filename*=UTF-8''%D0%BE%D1%82%D1%87%D1%91%D1%82.txt",
+                Some("отчёт.txt"),
+            ),
+            ("attachment; filename*=utf-8'ru'%D1%84.txt; filename=f.txt", Some("ф.txt")),
+            ("attachment; filename*=iso-8859-1'en'%A3%20rates.txt", Some("£ rates.txt")),
+            // Неизвестная кодировка и битый UTF-8 в filename* — берём filename.
+            ("attachment; filename*=KOI8-R''%C6.txt; filename=plain.txt", Some("plain.txt")),
+            ("attachment; filename*=UTF-8''%FF.txt; filename=plain.txt", Some("plain.txt")),
+            ("attachment; filename*=UTF-8''bare.txt", Some("bare.txt")),
+            ("attachment; filename=\"\"", None),
+            ("attachment", None),
+            ("inline; name=value", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(
+                disposition_filename(header).as_deref(),
+                expected,
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn strips_paths_and_dangerous_characters() {
+        let cases = [
+            ("../../etc/passwd", "passwd"),
+            ("..\\..\\Windows\\System32\\evil.dll", "evil.dll"),
+            ("C:\\Users\\me\\file.txt", "file.txt"),
+            ("/absolute/path.bin", "path.bin"),
+            ("..", FALLBACK_NAME),
+            ("", FALLBACK_NAME),
+            ("   ", FALLBACK_NAME),
+            ("...hidden", "hidden"),
+            ("trailing. . .", "trailing"),
+            ("what?<is>:this*|\".txt", "what__is__this___.txt"),
+            ("tab\there\nnewline.txt", "tab_here_newline.txt"),
+            ("invoice\u{202e}fdp.exe", "invoicefdp.exe"),
+            ("zero\u{200b}width.txt", "zerowidth.txt"),
+            ("отчёт 2024.docx", "отчёт 2024.docx"),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(sanitize_file_name(raw), expected, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn renames_windows_device_names() {
+        for raw in [
+            "CON",
+            "con.txt",
+            "Nul.tar.gz",
+            "COM1.log",
+            "lpt9",
+            "aux .txt",
+        ] {
+            assert!(sanitize_file_name(raw).starts_with('_'), "{raw}");
+        }
+        for raw in ["console.txt", "conf", "COM10.txt", "nullable.rs"] {
+            assert!(!sanitize_file_name(raw).starts_with('_'), "{raw}");
+        }
+    }
+
+    #[test]
+    fn truncates_long_names_keeping_extension() {
+        let long = format!("{}.pdf", "a".repeat(400));
+        let name = sanitize_file_name(&long);
+        assert_eq!(name.len(), MAX_NAME_LEN);
+        assert!(name.ends_with(".pdf"));
+
+        let cyrillic = format!("{}.txt", "я".repeat(200));
+        let name = sanitize_file_name(&cyrillic);
+        assert!(name.len() <= MAX_NAME_LEN && name.ends_with(".txt"));
+
+        let no_ext = "b".repeat(300);
+        assert_eq!(sanitize_file_name(&no_ext).len(), MAX_NAME_LEN);
+    }
+
+    #[test]
+    fn resolves_from_header_then_url() {
+        let cd = Some("attachment; filename*=UTF-8''%E2%82%AC%20rates.csv");
+        assert_eq!(
+            resolve_file_name(&url("https://x.example/dl?id=1"), cd),
+            "€ rates.csv"
+        );
+        // Путь в имени из заголовка отбрасывается.
+        let cd = Some("attachment; filename=\"../../.bashrc\"");
+        assert_eq!(resolve_file_name(&url("https://x.example/a"), cd), "bashrc");
+        // Пустое имя в заголовке — как его отсутствие.
+        let cd = Some("attachment; filename=\"..\"");
+        assert_eq!(
+            resolve_file_name(&url("https://x.example/real.zip"), cd),
+            "real.zip"
+        );
+
+        let cases = [
+            (
+                "https://x.example/files/report.pdf?token=abc#frag",
+                "report.pdf",
+            ),
+            ("https://x.example/my%20file%2Ename.txt", "my file.name.txt"),
+            ("https://x.example/a%2F..%2F..%2Fetc%2Fpasswd", "passwd"),
+            (
+                "https://x.example/%D0%BE%D1%82%D1%87%D1%91%D1%82.pdf",
+                "отчёт.pdf",
+            ),
+            ("https://x.example/bad%FFutf8.bin", "bad\u{fffd}utf8.bin"),
+            ("https://x.example/dir/", "x.example"),
+            ("https://x.example/", "x.example"),
+            ("https://x.example/CON", "_CON"),
+            ("https://x.example/%3Cscript%3E.html", "_script_.html"),
+            ("https://127.0.0.1:8080/", "127.0.0.1"),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(resolve_file_name(&url(raw), None), expected, "{raw}");
+        }
+        assert_eq!(
+            resolve_file_name(&url("data:text/plain,hi"), None),
+            FALLBACK_NAME
+        );
+    }
+
+    #[test]
+    fn appends_counter_on_collision() {
+        let dir = Path::new("/downloads");
+        let taken: HashSet<PathBuf> = [
+            "report.pdf",
+            "report (1).pdf",
+            "backup.tar.gz",
+            "README",
+            ".profile",
+        ]
+        .iter()
+        .map(|name| dir.join(name))
+        .collect();
+        let free = |name: &str| unique_path(dir, name, |p| taken.contains(p));
+        assert_eq!(free("new.pdf"), dir.join("new.pdf"));
+        assert_eq!(free("report.pdf"), dir.join("report (2).pdf"));
+        assert_eq!(free("backup.tar.gz"), dir.join("backup (1).tar.gz"));
+        assert_eq!(free("README"), dir.join("README (1)"));
+        assert_eq!(free(".profile"), dir.join(".profile (1)"));
+
+        let long = format!("{}.pdf", "a".repeat(251));
+        let long_taken = dir.join(&long);
+        let next = unique_path(dir, &long, |p| p == long_taken);
+        let name = next.file_name().unwrap().to_str().unwrap();
+        assert!(name.len() <= MAX_NAME_LEN && name.ends_with(" (1).pdf"));
+
+        // Имя на пределе длины почти целиком из «расширения».
+        let dotted = sanitize_file_name(&format!("a.{}", "b".repeat(253)));
+        assert_eq!(dotted.len(), MAX_NAME_LEN);
+        let dotted_taken = dir.join(&dotted);
+        let next = unique_path(dir, &dotted, |p| p == dotted_taken);
+        let name = next.file_name().unwrap().to_str().unwrap();
+        assert!(name.len() <= MAX_NAME_LEN && name.ends_with(" (1)"));
+    }
+}
@@ -0,0 +1,1178 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use reqwest::header::{
+    HeaderName, CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED,
+    RANGE, REFERER,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+use tokio::sync::{watch, Semaphore};
+use url::Url;
+
+mod filename;
+mod quarantine;
+mod risk;
+mod store;
+
+pub use filename::{disposition_filename, resolve_file_name, sanitize_file_name, unique_path};
+pub use risk::{classify, DownloadInfo, ReputationList, RiskAssessment, RiskLevel};
+pub use store::DownloadStore;
+
+pub type DownloadId = i64;
+
+/// Как часто задача шлёт `DownloadEvent::Progress`.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DownloadState {
+    /// Ждёт свободного слота.
+    Queued,
+    InProgress,
+    /// Остановлена пользователем или прервана закрытием браузера; `.part` сохранён.
+    Paused,
+    Completed,
+    /// Скачана в `.part`, но проверка нашла риск: сохранить или удалить решает пользователь.
+    AwaitingApproval,
+    Failed,
+    Cancelled,
+}
+
+impl DownloadState {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::InProgress => "in_progress",
+            Self::Paused => "paused",
+            Self::Completed => "completed",
+            Self::AwaitingApproval => "awaiting_approval",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "queued" => Self::Queued,
+            "in_progress" => Self::InProgress,
+            "paused" => Self::Paused,
+            "completed" => Self::Completed,
+            "awaiting_approval" => Self::AwaitingApproval,
+            "cancelled" => Self::Cancelled,
+            _ => Self::Failed,
+        }
+    }
+
+    pub fn is_active(self) -> bool {
+        matches!(self, Self::Queued | Self::InProgress)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Queued => "в очереди",
+            Self::InProgress => "загружается",
+            Self::Paused => "приостановлена",
+            Self::Completed => "завершена",
+            Self::AwaitingApproval => "требует подтверждения",
+            Self::Failed => "ошибка",
+            Self::Cancelled => "отменена",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Download {
+    pub id: DownloadId,
+    pub url: String,
+    /// Страница, с которой начата загрузка.
+    pub referrer: Option<String>,
+    /// Куда сохраняется файл; известен после первого ответа сервера.
+    pub path: Option<PathBuf>,
+    pub state: DownloadState,
+    pub received: u64,
+    pub total: Option<u64>,
+    /// ETag или Last-Modified: докачка по Range только если файл на сервере не менялся.
+    pub etag: Option<String>,
+    pub error: Option<String>,
+    /// Результат проверки содержимого; `None`, пока не получено начало файла.
+    pub risk: Option<RiskAssessment>,
+    /// SHA-256 скачанного файла (hex); считается по ходу загрузки.
+    pub sha256: Option<String>,
+    /// Контрольная сумма, указанная пользователем.
+    pub expected_sha256: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Download {
+    /// Совпала ли сумма с указанной; `None`, если сравнивать не с чем.
+    pub fn checksum_matches(&self) -> Option<bool> {
+        Some(self.sha256.as_ref()? == self.expected_sha256.as_ref()?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadEvent {
+    /// Загрузка добавлена или сменила состояние.
+    Changed(Box<Download>),
+    Progress {
+        id: DownloadId,
+        received: u64,
+        total: Option<u64>,
+    },
+    Removed(DownloadId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+/// Менеджер загрузок: пишет в `<файл>.part`, по завершении переименовывает,
+/// одновременно качает не больше `max_concurrent` файлов.
+pub struct DownloadManager {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    runtime: Handle,
+    dir: PathBuf,
+    client: RwLock<reqwest::Client>,
+    reputation: RwLock<ReputationList>,
+    slots: Arc<Semaphore>,
+    store: Mutex<DownloadStore>,
+    entries: Mutex<BTreeMap<DownloadId, Download>>,
+    controls: Mutex<HashMap<DownloadId, watch::Sender<Control>>>,
+    watchers: Mutex<Vec<mpsc::Sender<DownloadEvent>>>,
+}
+
+impl DownloadManager {
+    /// Загрузки, прерванные прошлым закрытием браузера, становятся приостановленными.
+    pub fn new(
+        store: DownloadStore,
+        dir: impl Into<PathBuf>,
+        max_concurrent: usize,
+        runtime: Handle,
+    ) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        for mut download in store.list()? {
+            if download.state.is_active() {
+                download.state = DownloadState::Paused;
+                store.save(&download)?;
+            }
+            entries.insert(download.id, download);
+        }
+        Ok(Self {
+            inner: Arc::new(Inner {
+                runtime,
+                dir: dir.into(),
+                client: RwLock::new(build_client(None)?),
+                reputation: RwLock::new(ReputationList::default()),
+                slots: Arc::new(Semaphore::new(max_concurrent.max(1))),
+                store: Mutex::new(store),
+                entries: Mutex::new(entries),
+                controls: Mutex::new(HashMap::new()),
+                watchers: Mutex::new(Vec::new()),
+            }),
+        })
+    }
+
+    /// Загрузки идут через локальный прокси браузера — с AdBlock, VPN и kill switch.
+    /// Действует на загрузки, начатые или продолженные после вызова.
+    pub fn set_proxy(&self, proxy: Option<&str>) -> Result<()> {
+        *self.inner.client.write().expect("client lock") = build_client(proxy)?;
+        Ok(())
+    }
+
+    /// Списки опасных источников для проверки загрузок.
+    pub fn set_reputation(&self, list: ReputationList) {
+        *self.inner.reputation.write().expect("reputation lock") = list;
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.inner.dir
+    }
+
+    pub fn subscribe(&self) -> mpsc::Receiver<DownloadEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.inner.watchers.lock().expect("watchers lock").push(tx);
+        rx
+    }
+
+    /// Все загрузки, новые первыми.
+    pub fn list(&self) -> Vec<Download> {
+        let entries = self.inner.entries.lock().expect("entries lock");
+        entries.values().rev().cloned().collect()
+    }
+
+    pub fn get(&self, id: DownloadId) -> Option<Download> {
+        self.inner.entry(id)
+    }
+
+    /// `referrer` — адрес страницы, откуда начата загрузка; не http(s) отбрасывается.
+    pub fn start(&self, url: &str, referrer: Option<&str>) -> Result<DownloadId> {
+        let parsed = Url::parse(url)?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            bail!("only http(s) downloads are supported: {url}");
+        }
+        let referrer = referrer
+            .and_then(|r| Url::parse(r).ok())
+            .filter(|r| matches!(r.scheme(), "http" | "https"))
+            .map(|r| quarantine::public_url(&r));
+        let started_at = now();
+        let id = self.inner.store.lock().expect("store lock").insert(
+            parsed.as_str(),
+            referrer.as_deref(),
+            started_at,
+        )?;
+        let download = Download {
+            id,
+            url: parsed.to_string(),
+            referrer,
+            path: None,
+            state: DownloadState::Queued,
+            received: 0,
+            total: None,
+            etag: None,
+            error: None,
+            risk: None,
+            sha256: None,
+            expected_sha256: None,
+            started_at,
+            finished_at: None,
+        };
+        self.inner
+            .entries
+            .lock()
+            .expect("entries lock")
+            .insert(id, download.clone());
+        self.inner
+            .notify(DownloadEvent::Changed(Box::new(download)));
+        self.spawn(id);
+        Ok(id)
+    }
+
+    /// Останавливает загрузку, оставляя `.part` для докачки.
+    pub fn pause(&self, id: DownloadId) {
+        self.send(id, Control::Pause);
+    }
+
+    /// Продолжает приостановленную или упавшую загрузку с того места, где она остановилась.
+    pub fn resume(&self, id: DownloadId) -> Result<()> {
+        let state = self
+            .inner
+            .entry(id)
+            .ok_or_else(|| anyhow!("no download {id}"))?
+            .state;
+        if !matches!(state, DownloadState::Paused | DownloadState::Failed) {
+            return Ok(());
+        }
+        self.inner.update(id, |d| {
+            d.state = DownloadState::Queued;
+            d.error = None;
+            d.finished_at = None;
+        })?;
+        self.spawn(id);
+        Ok(())
+    }
+
+    /// Останавливает загрузку и удаляет недокачанный файл.
+    /// Для загрузки, ждущей подтверждения, — отказ её сохранять.
+    pub fn cancel(&self, id: DownloadId) -> Result<()> {
+        if self.send(id, Control::Cancel) {
+            return Ok(());
+        }
+        match self.inner.entry(id) {
+            Some(d)
+                if matches!(
+                    d.state,
+                    DownloadState::Paused | DownloadState::Failed | DownloadState::AwaitingApproval
+                ) =>
+            {
+                self.inner.discard(id)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Сохраняет загрузку, которую проверка отметила как рискованную.
+    pub fn approve(&self, id: DownloadId) -> Result<()> {
+        let download = self
+            .inner
+            .entry(id)
+            .ok_or_else(|| anyhow!("no download {id}"))?;
+        if download.state != DownloadState::AwaitingApproval {
+            return Ok(());
+        }
+        let path = download
+            .path
+            .ok_or_else(|| anyhow!("download {id} has no file"))?;
+        std::fs::rename(part_path(&path), &path)?;
+        self.inner
+            .mark_origin(&download.url, download.referrer.as_deref(), &path);
+        self.inner.update(id, |d| {
+            d.state = DownloadState::Completed;
+            d.finished_at = Some(now());
+        })?;
+        Ok(())
+    }
+
+    /// Запоминает контрольную сумму SHA-256 от пользователя (hex, можно строкой
+    /// из `sha256sum`). Возвращает результат сравнения, если файл уже скачан;
+    /// иначе сравнение будет по завершении, и при несовпадении файл не сохранится
+    /// без подтверждения.
+    pub fn expect_sha256(&self, id: DownloadId, checksum: &str) -> Result<Option<bool>> {
+        let expected =
+            parse_sha256(checksum).ok_or_else(|| anyhow!("not a SHA-256 checksum: {checksum}"))?;
+        let download = self
+            .inner
+            .update(id, |d| d.expected_sha256 = Some(expected))?;
+        Ok(download.checksum_matches())
+    }
+
+    /// Убирает запись из списка; файл на диске остаётся. Активные загрузки не трогает.
+    pub fn remove(&self, id: DownloadId) -> Result<bool> {
+        match self.inner.entry(id) {
+            Some(d) if !d.state.is_active() => {}
+            _ => return Ok(false),
+        }
+        if let Some(part) = self
+            .inner
+            .entry(id)
+            .and_then(|d| d.path)
+            .map(|p| part_path(&p))
+        {
+            let _ = std::fs::remove_file(part);
+        }
+        self.inner.store.lock().expect("store lock").delete(id)?;
+        self.inner.entries.lock().expect("entries lock").remove(&id);
+        self.inner.notify(DownloadEvent::Removed(id));
+        Ok(true)
+    }
+
+    /// Убирает из списка загрузки, начатые с `since` (`None` — все), кроме активных.
+    pub fn remove_since(&self, since: Option<DateTime<Utc>>) -> Result<usize> {
+        let ids: Vec<DownloadId> = self
+            .list()
+            .into_iter()
+            .filter(|d| since.is_none_or(|since| d.started_at >= since))
+            .map(|d| d.id)
+            .collect();
+        let mut removed = 0;
+        for id in ids {
+            if self.remove(id)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Сохраняет список загрузок на диск. Нужно зашифрованному профилю: там список
+    /// живёт в памяти до этого вызова. Сами файлы пишутся по ходу загрузки.
+    pub fn flush(&self) -> Result<()> {
+        self.inner.store.lock().expect("store lock").flush()
+    }
+
+    fn send(&self, id: DownloadId, control: Control) -> bool {
+        let controls = self.inner.controls.lock().expect("controls lock");
+        controls.get(&id).is_some_and(|tx| tx.send(control).is_ok())
+    }
+
+    fn spawn(&self, id: DownloadId) {
+        let (tx, rx) = watch::channel(Control::Run);
+        self.inner
+            .controls
+            .lock()
+            .expect("controls lock")
+            .insert(id, tx);
+        self.inner.runtime.spawn(run(self.inner.clone(), id, rx));
+    }
+}
+
+/// Папка «Загрузки» пользователя.
+pub fn default_download_dir() -> PathBuf {
+    dirs::download_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join("Downloads")))
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+impl Inner {
+    fn entry(&self, id: DownloadId) -> Option<Download> {
+        self.entries.lock().expect("entries lock").get(&id).cloned()
+    }
+
+    fn notify(&self, event: DownloadEvent) {
+        self.watchers
+            .lock()
+            .expect("watchers lock")
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Меняет запись, сохраняет её и сообщает подписчикам.
+    fn update(&self, id: DownloadId, change: impl FnOnce(&mut Download)) -> Result<Download> {
+        let download = {
+            let mut entries = self.entries.lock().expect("entries lock");
+            let download = entries
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("no download {id}"))?;
+            change(download);
+            download.clone()
+        };
+        self.store.lock().expect("store lock").save(&download)?;
+        self.notify(DownloadEvent::Changed(Box::new(download.clone())));
+        Ok(download)
+    }
+
+    /// Прогресс только в памяти: в базу он попадает при смене состояния.
+    fn progress(&self, id: DownloadId, received: u64, total: Option<u64>) {
+        if let Some(download) = self.entries.lock().expect("entries lock").get_mut(&id) {
+            download.received = received;
+        }
+        self.notify(DownloadEvent::Progress {
+            id,
+            received,
+            total,
+        });
+    }
+
+    fn discard(&self, id: DownloadId) -> Result<()> {
+        if let Some(path) = self.entry(id).and_then(|d| d.path) {
+            match std::fs::remove_file(part_path(&path)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        self.update(id, |d| {
+            d.state = DownloadState::Cancelled;
+            d.received = 0;
+            d.finished_at = Some(now());
+        })?;
+        Ok(())
+    }
+
+    /// Выбирает свободное имя в каталоге загрузок и сразу записывает его за загрузкой:
+    /// две загрузки с одинаковым именем получат `name` и `name (1)`.
+    fn reserve_path(&self, id: DownloadId, name: &str) -> PathBuf {
+        let mut entries = self.entries.lock().expect("entries lock");
+        let path = unique_path(&self.dir, name, |candidate| {
+            candidate.exists()
+                || part_path(candidate).exists()
+                || entries.values().any(|d| {
+                    d.id != id
+                        && d.path.as_deref() == Some(candidate)
+                        && !matches!(d.state, DownloadState::Completed | DownloadState::Cancelled)
+                })
+        });
+        if let Some(download) = entries.get_mut(&id) {
+            download.path = Some(path.clone());
+        }
+        path
+    }
+
+    /// Метка происхождения необязательна: ФС без `user.*` или NTFS-потоков
+    /// не должна ломать загрузку.
+    fn mark_origin(&self, url: &str, referrer: Option<&str>, path: &Path) {
+        let Ok(source) = Url::parse(url) else {
+            return;
+        };
+        let referrer = referrer.and_then(|r| Url::parse(r).ok());
+        let _ = quarantine::mark(path, &source, referrer.as_ref());
+    }
+
+    /// Управление снимается до смены состояния: `resume`, увидевший паузу,
+    /// ставит новую задачу со своим каналом, и старая его уже не тронет.
+    fn stop(&self, id: DownloadId, control: Control, received: u64) -> Result<DownloadState> {
+        self.release(id);
+        if control == Control::Cancel {
+            self.discard(id)?;
+            return Ok(DownloadState::Cancelled);
+        }
+        self.update(id, |d| {
+            d.state = DownloadState::Paused;
+            d.received = received;
+        })?;
+        Ok(DownloadState::Paused)
+    }
+
+    fn release(&self, id: DownloadId) {
+        self.controls.lock().expect("controls lock").remove(&id);
+    }
+}
+
+async fn run(inner: Arc<Inner>, id: DownloadId, mut control: watch::Receiver<Control>) {
+    let result = transfer(&inner, id, &mut control).await;
+    // Пауза и отмена уже сняли управление в `stop`.
+    if matches!(result, Ok(DownloadState::Paused | DownloadState::Cancelled)) {
+        return;
+    }
+    // Сначала снимаем управление, потом меняем состояние: иначе `resume`,
+    // успевший запустить новую задачу, потерял бы её канал.
+    inner.release(id);
+    let _ = match result {
+        Ok(DownloadState::Completed) => inner.update(id, |d| {
+            d.state = DownloadState::Completed;
+            d.finished_at = Some(now());
+        }),
+        Ok(DownloadState::AwaitingApproval) => {
+            inner.update(id, |d| d.state = DownloadState::AwaitingApproval)
+        }
+        Ok(_) => return,
+        Err(e) => inner.update(id, |d| {
+            d.state = DownloadState::Failed;
+            d.error = Some(format!("{e:#}"));
+        }),
+    };
+}
+
+async fn transfer(
+    inner: &Inner,
+    id: DownloadId,
+    control: &mut watch::Receiver<Control>,
+) -> Result<DownloadState> {
+    let _permit = tokio::select! {
+        permit = inner.slots.clone().acquire_owned() => permit?,
+        stop = stopped(control) => return inner.stop(id, stop, 0),
+    };
+    let download = inner.update(id, |d| d.state = DownloadState::InProgress)?;
+    let offset = match &download.path {
+        Some(path) => tokio::fs::metadata(part_path(path))
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0),
+        None => 0,
+    };
+    let client = inner.client.read().expect("client lock").clone();
+    let mut request = client.get(&download.url);
+    if let Some(referer) = download
+        .referrer
+        .as_deref()
+        .and_then(|r| referer_header(r, &download.url))
+    {
+        request = request.header(REFERER, referer);
+    }
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+        if let Some(etag) = &download.etag {
+            request = request.header(IF_RANGE, etag);
+        }
+    }
+    let mut response = tokio::select! {
+        response = request.send() => response?,
+        stop = stopped(control) => return inner.stop(id, stop, offset),
+    };
+    let status = response.status();
+    if !status.is_success() {
+        bail!("HTTP {status}");
+    }
+    // 206 — сервер продолжил с `offset`; 200 — прислал файл заново, и `.part` переписывается.
+    let resumed = status == StatusCode::PARTIAL_CONTENT && offset > 0;
+    if status == StatusCode::PARTIAL_CONTENT && !content_range_starts_at(&response, offset) {
+        bail!("server returned an unexpected range");
+    }
+    let final_url = response.url().clone();
+    let disposition = header(&response, CONTENT_DISPOSITION);
+    let mime = header(&response, CONTENT_TYPE);
+    let path = match download.path {
+        Some(path) => path,
+        None => inner.reserve_path(id, &resolve_file_name(&final_url, disposition.as_deref())),
+    };
+    let part = part_path(&path);
+    let mut received = if resumed { offset } else { 0 };
+    let total = response.content_length().map(|len| len + received);
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .or_else(|| response.headers().get(LAST_MODIFIED))
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    inner.update(id, |d| {
+        d.path = Some(path.clone());
+        d.received = received;
+        d.total = total;
+        d.etag = etag;
+        if !resumed {
+            d.risk = None;
+        }
+    })?;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = if resumed {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&part)
+            .await?
+    } else {
+        tokio::fs::File::create(&part).await?
+    };
+    // Начало файла для проверки и сумма уже скачанного; при докачке всё это в `.part`.
+    // Без докачки файл пишется заново, и оценка прошлой попытки к нему не относится.
+    let mut risk = if resumed { download.risk } else { None };
+    let mut head = Vec::new();
+    let mut hasher = Sha256::new();
+    if resumed {
+        let mut existing = tokio::fs::File::open(&part).await?.take(offset);
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = existing.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            fill_head(&mut head, &buf[..n]);
+            hasher.update(&buf[..n]);
+        }
+    }
+    let assess = |head: &[u8]| {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let assessment = classify(
+            &DownloadInfo {
+                url: &final_url,
+                file_name: &file_name,
+                content_disposition: disposition.as_deref(),
+                mime: mime.as_deref(),
+                head,
+            },
+            &inner.reputation.read().expect("reputation lock"),
+        );
+        inner.update(id, |d| d.risk = Some(assessment.clone()))?;
+        anyhow::Ok(assessment)
+    };
+    let mut last_progress = Instant::now();
+    loop {
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk?,
+            stop = stopped(control) => {
+                file.flush().await?;
+                return inner.stop(id, stop, received);
+            }
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+        received += chunk.len() as u64;
+        if risk.is_none() {
+            fill_head(&mut head, &chunk);
+            if head.len() == risk::HEAD_LEN {
+                risk = Some(assess(&head)?);
+            }
+        }
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            inner.progress(id, received, total);
+            last_progress = Instant::now();
+        }
+    }
+    file.sync_all().await?;
+    drop(file);
+    if total.is_some_and(|total| total != received) {
+        bail!("connection closed after {received} bytes");
+    }
+    let mut risk = match risk {
+        Some(risk) => risk,
+        None => assess(&head)?,
+    };
+    let sha256 = to_hex(&hasher.finalize());
+    inner.progress(id, received, Some(received));
+    let download = inner.update(id, |d| {
+        d.total = Some(received);
+        d.sha256 = Some(sha256);
+    })?;
+    if download.checksum_matches() == Some(false) {
+        risk.flag(
+            RiskLevel::Dangerous,
+            "SHA-256 не совпадает с указанной контрольной суммой".into(),
+        );
+        inner.update(id, |d| d.risk = Some(risk.clone()))?;
+    }
+    if risk.level > RiskLevel::Safe {
+        return Ok(DownloadState::AwaitingApproval);
+    }
+    tokio::fs::rename(&part, &path).await?;
+    inner.mark_origin(&download.url, download.referrer.as_deref(), &path);
+    Ok(DownloadState::Completed)
+}
+
+fn fill_head(head: &mut Vec<u8>, data: &[u8]) {
+    let take = data.len().min(risk::HEAD_LEN - head.len());
+    head.extend_from_slice(&data[..take]);
+}
+
+/// Referer по умолчанию браузеров (`strict-origin-when-cross-origin`): полный адрес
+/// для того же сайта, только origin для чужого, ничего при переходе с https на http.
+fn referer_header(referrer: &str, target: &str) -> Option<String> {
+    let referrer = Url::parse(referrer).ok()?;
+    let target = Url::parse(target).ok()?;
+    if referrer.scheme() == "https" && target.scheme() != "https" {
+        return None;
+    }
+    if referrer.origin() == target.origin() {
+        Some(quarantine::public_url(&referrer))
+    } else {
+        Some(format!("{}/", referrer.origin().ascii_serialization()))
+    }
+}
+
+/// Сумма из поля ввода: hex, возможно с именем файла после неё, как выводит `sha256sum`.
+fn parse_sha256(input: &str) -> Option<String> {
+    let hex = input.split_whitespace().next()?.to_ascii_lowercase();
+    (hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit())).then_some(hex)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Ждёт паузы или отмены. Если менеджер закрыт, задача просто доживает до конца.
+async fn stopped(control: &mut watch::Receiver<Control>) -> Control {
+    loop {
+        let current = *control.borrow_and_update();
+        if current != Control::Run {
+            return current;
+        }
+        if control.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Значение заголовка; байты вне ASCII читаются как UTF-8 — так серверы
+/// часто передают имя файла в Content-Disposition.
+fn header(response: &reqwest::Response, name: HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+}
+
+fn content_range_starts_at(response: &reqwest::Response, offset: u64) -> bool {
+    response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes "))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start, _)| start.parse::<u64>().ok())
+        == Some(offset)
+}
+
+fn build_client(proxy: Option<&str>) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().user_agent("PlusBrowser/0.2");
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Время с точностью до миллисекунд, как оно хранится в базе.
+fn now() -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(Utc::now().timestamp_millis()).unwrap_or_default()
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+
+    const WAIT: Duration = Duration::from_secs(10);
+
+    fn body(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// HTTP-сервер с поддержкой `Range: bytes=N-`, отдающий `body` кусками по 4 КиБ
+    /// с паузой `delay`. Запоминает полученные запросы. Соединение — на один запрос
+    /// (`Connection: close`), иначе клиент мог бы взять из пула уже закрытое.
+    async fn spawn_server(body: Vec<u8>, delay: Duration) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        serve(vec![body], delay, true).await
+    }
+
+    /// Как `spawn_server`, но на N-й запрос отдаёт `bodies[N]` (последний — и дальше),
+    /// а без `ranges` на `Range` отвечает файлом целиком.
+    async fn serve(
+        bodies: Vec<Vec<u8>>,
+        delay: Duration,
+        ranges: bool,
+    ) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let bodies: Vec<_> = bodies.into_iter().map(Arc::new).collect();
+        tokio::spawn(async move {
+            let mut accepted = 0;
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let seen = seen.clone();
+                let body = bodies[accepted.min(bodies.len() - 1)].clone();
+                accepted += 1;
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let n = stream.read(&mut buf).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let start = request
+                        .lines()
+                        .find_map(|l| {
+                            l.to_ascii_lowercase()
+                                .strip_prefix("range: bytes=")
+                                .map(str::to_string)
+                        })
+                        .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok())
+                        .filter(|_| ranges);
+                    seen.lock().unwrap().push(request);
+                    let head = match start {
+                        Some(start) => format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                             Content-Range: bytes {start}-{}/{}\r\nETag: \"v1\"\r\n\
+                             Connection: close\r\n\r\n",
+                            body.len() - start,
+                            body.len() - 1,
+                            body.len()
+                        ),
+                        None => format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\n\
+                             Connection: close\r\n\r\n",
+                            body.len()
+                        ),
+                    };
+                    if stream.write_all(head.as_bytes()).await.is_err() {
+                        return;
+                    }
+                    for chunk in body[start.unwrap_or(0)..].chunks(4096) {
+                        if stream.write_all(chunk).await.is_err() {
+                            return;
+                        }
+                        tokio::time::sleep(delay).await;
+                    }
+                });
+            }
+        });
+        (addr, requests)
+    }
+
+    fn manager(rt: &Runtime, db: &Path, dir: &Path, max_concurrent: usize) -> DownloadManager {
+        DownloadManager::new(
+            DownloadStore::open(db).unwrap(),
+            dir,
+            max_concurrent,
+            rt.handle().clone(),
+        )
+        .unwrap()
+    }
+
+    fn wait_state(events: &mpsc::Receiver<DownloadEvent>, id: DownloadId, state: DownloadState) {
+        let deadline = Instant::now() + WAIT;
+        while let Ok(event) =
+            events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            if let DownloadEvent::Changed(d) = event {
+                if d.id == id && d.state == state {
+                    return;
+                }
+                assert_ne!(d.state, DownloadState::Failed, "{:?}", d.error);
+            }
+        }
+        panic!("download {id} did not reach {state:?}");
+    }
+
+    fn wait_progress(events: &mpsc::Receiver<DownloadEvent>, id: DownloadId) {
+        let deadline = Instant::now() + WAIT;
+        while let Ok(event) =
+            events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            if matches!(event, DownloadEvent::Progress { id: got, .. } if got == id) {
+                return;
+            }
+        }
+        panic!("no progress for download {id}");
+    }
+
+    #[test]
+    fn streams_to_part_file_and_persists_list() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let data = body(64 * 1024);
+        let (addr, _) = rt.block_on(spawn_server(data.clone(), Duration::ZERO));
+        let db = tmp.path().join("downloads.db");
+        let downloads = manager(&rt, &db, &tmp.path().join("out"), 2);
+        let events = downloads.subscribe();
+
+        let id = downloads
+            .start(&format!("http://{addr}/files/report.pdf?x=1"), None)
+            .unwrap();
+        wait_state(&events, id, DownloadState::Completed);
+        let done = downloads.get(id).unwrap();
+        let path = done.path.clone().unwrap();
+        assert_eq!(path, tmp.path().join("out/report.pdf"));
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert!(!part_path(&path).exists());
+        assert_eq!(
+            (done.received, done.total),
+            (data.len() as u64, Some(data.len() as u64))
+        );
+        assert!(downloads.start("file:///etc/passwd", None).is_err());
+        let again = downloads
+            .start(&format!("http://{addr}/files/report.pdf"), None)
+            .unwrap();
+        wait_state(&events, again, DownloadState::Completed);
+        let copy = downloads.get(again).unwrap().path.unwrap();
+        assert_eq!(copy, tmp.path().join("out/report (1).pdf"));
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        let done = downloads.get(id).unwrap();
+        drop(downloads);
+
+        let reopened = manager(&rt, &db, &tmp.path().join("out"), 2);
+        assert_eq!(reopened.get(id), Some(done));
+        assert!(reopened.remove(id).unwrap());
+        assert_eq!(reopened.list().len(), 1);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn pauses_and_resumes_with_range() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let data = body(256 * 1024);
+        let (addr, requests) = rt.block_on(spawn_server(data.clone(), Duration::from_millis(20)));
+        let db = tmp.path().join("downloads.db");
+        let downloads = manager(&rt, &db, tmp.path(), 2);
+        let events = downloads.subscribe();
+
+        let id = downloads
+            .start(&format!("http://{addr}/big.bin"), None)
+            .unwrap();
+        wait_progress(&events, id);
+        downloads.pause(id);
+        wait_state(&events, id, DownloadState::Paused);
+        let paused = downloads.get(id).unwrap();
+        let part = part_path(paused.path.as_ref().unwrap());
+        let offset = std::fs::metadata(&part).unwrap().len();
+        assert!(offset > 0 && offset < data.len() as u64);
+        assert_eq!(paused.received, offset);
+
+        // Прерванная загрузка переживает перезапуск и докачивается с того же места.
+        drop(downloads);
+        let downloads = manager(&rt, &db, tmp.path(), 2);
+        let events = downloads.subscribe();
+        downloads.resume(id).unwrap();
+        wait_state(&events, id, DownloadState::Completed);
+        assert_eq!(std::fs::read(tmp.path().join("big.bin")).unwrap(), data);
+        let requests = requests.lock().unwrap();
+        assert!(requests[1].contains(&format!("range: bytes={offset}-")));
+        assert!(requests[1].contains("if-range: \"v1\""));
+    }
+
+    #[test]
+    fn resumed_download_stays_controllable() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let (addr, _) = rt.block_on(spawn_server(body(1024 * 1024), Duration::from_millis(20)));
+        let downloads = manager(&rt, &tmp.path().join("downloads.db"), tmp.path(), 2);
+        let events = downloads.subscribe();
+
+        let id = downloads
+            .start(&format!("http://{addr}/big.bin"), None)
+            .unwrap();
+        // Возобновление сразу после паузы: старая задача ещё завершается.
+        for _ in 0..3 {
+            wait_progress(&events, id);
+            downloads.pause(id);
+            wait_state(&events, id, DownloadState::Paused);
+            downloads.resume(id).unwrap();
+        }
+        wait_progress(&events, id);
+        downloads.cancel(id).unwrap();
+        wait_state(&events, id, DownloadState::Cancelled);
+    }
+
+    #[test]
+    fn limits_concurrency_and_cancels() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let (addr, _) = rt.block_on(spawn_server(body(256 * 1024), Duration::from_millis(20)));
+        let downloads = manager(&rt, &tmp.path().join("downloads.db"), tmp.path(), 1);
+        let events = downloads.subscribe();
+
+        let first = downloads
+            .start(&format!("http://{addr}/a.bin"), None)
+            .unwrap();
+        let second = downloads
+            .start(&format!("http://{addr}/b.bin"), None)
+            .unwrap();
+        wait_progress(&events, first);
+        assert_eq!(downloads.get(second).unwrap().state, DownloadState::Queued);
+
+        downloads.cancel(first).unwrap();
+        wait_state(&events, first, DownloadState::Cancelled);
+        assert!(!tmp.path().join("a.bin.part").exists());
+        assert!(!tmp.path().join("a.bin").exists());
+        wait_state(&events, second, DownloadState::InProgress);
+        downloads.cancel(second).unwrap();
+        wait_state(&events, second, DownloadState::Cancelled);
+    }
+
+    #[test]
+    fn holds_risky_download_until_approved() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let mut data = body(8 * 1024);
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        data[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let (addr, _) = rt.block_on(spawn_server(data.clone(), Duration::ZERO));
+        let downloads = manager(&rt, &tmp.path().join("downloads.db"), tmp.path(), 2);
+        let events = downloads.subscribe();
+
+        let keep = downloads
+            .start(&format!("http://{addr}/cat.png"), None)
+            .unwrap();
+        wait_state(&events, keep, DownloadState::AwaitingApproval);
+        let risk = downloads.get(keep).unwrap().risk.unwrap();
+        assert_eq!(risk.level, RiskLevel::Dangerous);
+        assert!(!tmp.path().join("cat.png").exists());
+        downloads.approve(keep).unwrap();
+        assert_eq!(std::fs::read(tmp.path().join("cat.png")).unwrap(), data);
+
+        let discard = downloads
+            .start(&format!("http://{addr}/dog.png"), None)
+            .unwrap();
+        wait_state(&events, discard, DownloadState::AwaitingApproval);
+        downloads.cancel(discard).unwrap();
+        assert_eq!(
+            downloads.get(discard).unwrap().state,
+            DownloadState::Cancelled
+        );
+        assert!(!tmp.path().join("dog.png").exists());
+        assert!(!tmp.path().join("dog.png.part").exists());
+    }
+
+    #[test]
+    fn reassesses_download_restarted_from_scratch() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let mut risky = body(256 * 1024);
+        risky[..2].copy_from_slice(b"MZ");
+        risky[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        risky[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let safe = body(32 * 1024);
+        // Сервер без Range: после паузы файл приходит заново и уже другой.
+        let (addr, _) = rt.block_on(serve(
+            vec![risky, safe.clone()],
+            Duration::from_millis(20),
+            false,
+        ));
+        let downloads = manager(&rt, &tmp.path().join("downloads.db"), tmp.path(), 2);
+        let events = downloads.subscribe();
+
+        let id = downloads
+            .start(&format!("http://{addr}/cat.png"), None)
+            .unwrap();
+        let deadline = Instant::now() + WAIT;
+        while downloads.get(id).unwrap().risk.is_none() {
+            assert!(Instant::now() < deadline, "download {id} was not assessed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        downloads.pause(id);
+        wait_state(&events, id, DownloadState::Paused);
+        downloads.resume(id).unwrap();
+        wait_state(&events, id, DownloadState::Completed);
+        let done = downloads.get(id).unwrap();
+        assert_eq!(done.risk.unwrap().level, RiskLevel::Safe);
+        assert_eq!(std::fs::read(tmp.path().join("cat.png")).unwrap(), safe);
+    }
+
+    #[test]
+    fn hashes_and_verifies_checksum() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let data = body(32 * 1024);
+        let (addr, requests) = rt.block_on(spawn_server(data.clone(), Duration::ZERO));
+        let downloads = manager(&rt, &tmp.path().join("downloads.db"), tmp.path(), 2);
+        let events = downloads.subscribe();
+        let sha256 = to_hex(&Sha256::digest(&data));
+
+        let page = format!("http://user:pw@{addr}/page.html#files");
+        let id = downloads
+            .start(&format!("http://{addr}/manual.pdf"), Some(&page))
+            .unwrap();
+        wait_state(&events, id, DownloadState::Completed);
+        let done = downloads.get(id).unwrap();
+        assert_eq!(done.sha256.as_deref(), Some(sha256.as_str()));
+        assert_eq!(done.referrer, Some(format!("http://{addr}/page.html")));
+        assert!(
+            requests.lock().unwrap()[0].contains(&format!("referer: http://{addr}/page.html\r\n"))
+        );
+        let line = format!("{}  manual.pdf", sha256.to_uppercase());
+        assert_eq!(downloads.expect_sha256(id, &line).unwrap(), Some(true));
+        assert!(downloads.expect_sha256(id, "abc").is_err());
+        #[cfg(target_os = "linux")]
+        if let Some(origin) = quarantine::get_xattr(&done.path.unwrap(), "user.xdg.origin.url") {
+            assert_eq!(origin, format!("http://{addr}/manual.pdf"));
+        }
+
+        // Указанная заранее сумма не совпала — файл ждёт решения пользователя.
+        let (slow, _) = rt.block_on(spawn_server(data, Duration::from_millis(20)));
+        let wrong = downloads
+            .start(&format!("http://{slow}/other.pdf"), None)
+            .unwrap();
+        downloads.expect_sha256(wrong, &"0".repeat(64)).unwrap();
+        wait_state(&events, wrong, DownloadState::AwaitingApproval);
+        let held = downloads.get(wrong).unwrap();
+        assert_eq!(held.checksum_matches(), Some(false));
+        assert_eq!(held.risk.unwrap().level, RiskLevel::Dangerous);
+        assert!(!tmp.path().join("other.pdf").exists());
+    }
+
+    #[test]
+    fn referer_follows_default_policy() {
+        let page = "https://example.com/a/page?q=1#x";
+        assert_eq!(
+            referer_header(page, "https://example.com/file.zip").as_deref(),
+            Some("https://example.com/a/page?q=1")
+        );
+        assert_eq!(
+            referer_header(page, "https://cdn.example.net/file.zip").as_deref(),
+            Some("https://example.com/")
+        );
+        assert_eq!(referer_header(page, "http://example.com/file.zip"), None);
+    }
+
+    #[test]
+    fn routes_through_proxy() {
+        let rt = Runtime::new().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        // Сервер играет роль HTTP-прокси: запрос приходит с абсолютным URL.
+        let (proxy, requests) = rt.block_on(spawn_server(body(1024), Duration::ZERO));
+        let downloads = manager(&rt, &tmp.path().join("downloads.db"), tmp.path(), 2);
+        downloads
+            .set_proxy(Some(&format!("http://{proxy}")))
+            .unwrap();
+        let events = downloads.subscribe();
+
+        let id = downloads
+            .start("http://files.invalid/via-proxy.bin", None)
+            .unwrap();
+        wait_state(&events, id, DownloadState::Completed);
+        assert!(requests.lock().unwrap()[0]
+            .starts_with("GET http://files.invalid/via-proxy.bin HTTP/1.1"));
+    }
+}
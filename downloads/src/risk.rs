@@ -0,0 +1,634 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, io, path::Path};
+use url::{Host, Url};
+
+use crate::filename::disposition_filename;
+
+/// Сколько первых байт файла смотрит классификатор.
+pub(crate) const HEAD_LEN: usize = 64 * 1024;
+
+/// Запускаются сами или через оболочку Windows.
+const EXECUTABLE: &[&str] = &[
+    "exe",
+    "com",
+    "scr",
+    "pif",
+    "cpl",
+    "msi",
+    "msp",
+    "mst",
+    "msc",
+    "dll",
+    "sys",
+    "drv",
+    "ocx",
+    "bat",
+    "cmd",
+    "ps1",
+    "psm1",
+    "psd1",
+    "vbs",
+    "vbe",
+    "js",
+    "jse",
+    "wsf",
+    "wsh",
+    "hta",
+    "lnk",
+    "url",
+    "scf",
+    "reg",
+    "inf",
+    "jar",
+    "application",
+    "appref-ms",
+    "gadget",
+    "appx",
+    "msix",
+    "apk",
+    "sh",
+    "bash",
+    "run",
+    "appimage",
+    "deb",
+    "rpm",
+    "pkg",
+    "command",
+];
+/// Открываются как папка: содержимое на момент загрузки не проверить.
+const CONTAINER: &[&str] = &[
+    "zip", "rar", "7z", "cab", "tar", "gz", "tgz", "bz2", "xz", "iso", "img", "vhd", "vhdx", "dmg",
+];
+/// Документы, где могут быть макросы.
+const MACRO_DOCUMENT: &[&str] = &["docm", "xlsm", "pptm", "dotm", "xltm", "xlam", "ppam"];
+/// Документы OLE: у них тот же заголовок, что у MSI.
+const OLE_DOCUMENT: &[&str] = &[
+    "doc", "xls", "ppt", "msg", "dot", "xlt", "pot", "vsd", "pub",
+];
+
+const DANGEROUS_MIME: &[&str] = &[
+    "application/x-msdownload",
+    "application/x-msdos-program",
+    "application/x-msi",
+    "application/x-ms-installer",
+    "application/vnd.microsoft.portable-executable",
+    "application/x-dosexec",
+    "application/x-executable",
+    "application/x-sh",
+    "application/x-bat",
+    "application/hta",
+    "application/x-ms-shortcut",
+    "application/java-archive",
+    "application/vnd.android.package-archive",
+];
+
+const PE_MAGIC: &[u8] = b"MZ";
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const OLE_MAGIC: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1";
+const LNK_MAGIC: &[u8] = b"\x4c\x00\x00\x00\x01\x14\x02\x00";
+const ZIP_LOCAL: &[u8] = b"PK\x03\x04";
+const ZIP_CENTRAL: &[u8] = b"PK\x01\x02";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RiskLevel {
+    #[default]
+    Safe,
+    Suspicious,
+    Dangerous,
+}
+
+impl RiskLevel {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Safe => "безопасно",
+            Self::Suspicious => "подозрительный файл",
+            Self::Dangerous => "опасный файл",
+        }
+    }
+}
+
+/// Уровень риска и причины, которые видит пользователь.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskAssessment {
+    pub level: RiskLevel,
+    pub reasons: Vec<String>,
+}
+
+impl RiskAssessment {
+    pub(crate) fn flag(&mut self, level: RiskLevel, reason: String) {
+        self.level = self.level.max(level);
+        if !self.reasons.contains(&reason) {
+            self.reasons.push(reason);
+        }
+    }
+}
+
+/// Что известно о загрузке к моменту проверки.
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadInfo<'a> {
+    pub url: &'a Url,
+    /// Имя, под которым файл будет сохранён.
+    pub file_name: &'a str,
+    pub content_disposition: Option<&'a str>,
+    pub mime: Option<&'a str>,
+    /// Начало файла, до `HEAD_LEN` байт.
+    pub head: &'a [u8],
+}
+
+/// Источники с плохой репутацией: хосты (вместе с поддоменами) и префиксы URL.
+/// Формат списка — по записи в строке, `#` — комментарий.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReputationList {
+    hosts: HashSet<String>,
+    prefixes: Vec<String>,
+}
+
+impl ReputationList {
+    pub fn parse(text: &str) -> Self {
+        let mut list = Self::default();
+        list.extend(text);
+        list
+    }
+
+    /// Читает список из файла; отсутствующий файл — пустой список.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Добавляет записи ещё одного списка.
+    pub fn extend(&mut self, text: &str) {
+        for line in text.lines() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            if entry.contains("://") {
+                self.prefixes.push(entry.to_ascii_lowercase());
+            } else {
+                self.hosts
+                    .insert(entry.trim_start_matches("*.").to_ascii_lowercase());
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.prefixes.is_empty()
+    }
+
+    /// Запись списка, под которую попадает `url`.
+    pub fn lookup(&self, url: &Url) -> Option<String> {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let mut domain = host.as_str();
+        loop {
+            if self.hosts.contains(domain) {
+                return Some(domain.to_string());
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => break,
+            }
+        }
+        let url = url.as_str().to_ascii_lowercase();
+        self.prefixes
+            .iter()
+            .find(|prefix| url.starts_with(prefix.as_str()))
+            .cloned()
+    }
+}
+
+/// Оценивает загрузку по именам (сохраняемому и из Content-Disposition), MIME,
+/// сигнатурам содержимого и репутации источника.
+pub fn classify(info: &DownloadInfo, reputation: &ReputationList) -> RiskAssessment {
+    let mut risk = RiskAssessment::default();
+    let mut names = vec![info.file_name.to_string()];
+    if let Some(name) = info.content_disposition.and_then(disposition_filename) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let head = info.head;
+    let zip = head.starts_with(ZIP_LOCAL).then(|| scan_zip(head));
+    // Полностью просмотренный ZIP уже не «архив с непроверенным содержимым».
+    let zip_checked = zip.as_ref().is_some_and(|z| z.complete);
+    for name in &names {
+        check_name(name, zip_checked, &mut risk);
+    }
+    let ext = extension(info.file_name);
+
+    let mime = info.mime.map(|m| {
+        m.split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    });
+    if let Some(mime) = &mime {
+        if DANGEROUS_MIME.contains(&mime.as_str()) {
+            risk.flag(
+                RiskLevel::Dangerous,
+                format!("сервер передал исполняемый тип {mime}"),
+            );
+        }
+    }
+
+    let disguised = |risk: &mut RiskAssessment, kind: &str| {
+        if !EXECUTABLE.contains(&ext.as_str()) {
+            risk.flag(
+                RiskLevel::Dangerous,
+                format!("{kind} под видом «{}»", info.file_name),
+            );
+        }
+    };
+    if head.starts_with(PE_MAGIC) && is_pe(head) {
+        risk.flag(RiskLevel::Dangerous, "исполняемый файл Windows (PE)".into());
+        disguised(&mut risk, "программа");
+    } else if head.starts_with(ELF_MAGIC) {
+        risk.flag(RiskLevel::Dangerous, "исполняемый файл Linux (ELF)".into());
+        disguised(&mut risk, "программа");
+    } else if head.starts_with(LNK_MAGIC) {
+        risk.flag(RiskLevel::Dangerous, "ярлык Windows (LNK)".into());
+        disguised(&mut risk, "ярлык");
+    } else if head.starts_with(OLE_MAGIC) && !OLE_DOCUMENT.contains(&ext.as_str()) {
+        risk.flag(RiskLevel::Dangerous, "установщик Windows (MSI)".into());
+        disguised(&mut risk, "установщик");
+    } else if head.starts_with(b"#!") {
+        risk.flag(RiskLevel::Suspicious, "сценарий оболочки".into());
+    } else if let Some(zip) = zip {
+        for name in zip.executables {
+            risk.flag(
+                RiskLevel::Dangerous,
+                format!("архив содержит исполняемый файл {name}"),
+            );
+        }
+        for name in zip.archives {
+            risk.flag(
+                RiskLevel::Suspicious,
+                format!("архив содержит вложенный архив {name}"),
+            );
+        }
+        let known_zip = CONTAINER.contains(&ext.as_str()) || ext == "jar" || is_office_zip(&ext);
+        if !known_zip && !zip.complete {
+            risk.flag(
+                RiskLevel::Suspicious,
+                format!("архив ZIP под видом «{}»", info.file_name),
+            );
+        }
+    }
+
+    if let Some(entry) = reputation.lookup(info.url) {
+        risk.flag(
+            RiskLevel::Dangerous,
+            format!("источник в списке опасных: {entry}"),
+        );
+    }
+    if risk.level > RiskLevel::Safe {
+        if info.url.scheme() == "http" {
+            risk.flag(
+                RiskLevel::Suspicious,
+                "загружен по незащищённому HTTP".into(),
+            );
+        }
+        if matches!(info.url.host(), Some(Host::Ipv4(_) | Host::Ipv6(_))) {
+            risk.flag(
+                RiskLevel::Suspicious,
+                "сервер указан IP-адресом, а не доменом".into(),
+            );
+        }
+    }
+    risk
+}
+
+fn check_name(name: &str, zip_checked: bool, risk: &mut RiskAssessment) {
+    // U+202E и соседи переворачивают текст: «invoice<U+202E>fdp.exe» выглядит как «invoiceexe.pdf».
+    if name
+        .chars()
+        .any(|c| matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'))
+    {
+        risk.flag(
+            RiskLevel::Dangerous,
+            format!("имя «{name}» содержит символы смены направления текста"),
+        );
+    }
+    let trimmed = name.trim_end_matches(['.', ' ']);
+    let ext = extension(trimmed);
+    if EXECUTABLE.contains(&ext.as_str()) {
+        risk.flag(RiskLevel::Dangerous, format!("исполняемый файл .{ext}"));
+        let stem = trimmed.rsplit_once('.').map_or("", |(stem, _)| stem);
+        let inner = extension(stem);
+        if stem.ends_with("   ") {
+            risk.flag(
+                RiskLevel::Dangerous,
+                format!("расширение «{name}» спрятано за пробелами"),
+            );
+        } else if !inner.is_empty()
+            && inner.len() <= 4
+            && inner.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            risk.flag(
+                RiskLevel::Dangerous,
+                format!("двойное расширение: «{name}»"),
+            );
+        }
+    } else if CONTAINER.contains(&ext.as_str()) && !(zip_checked && ext == "zip") {
+        risk.flag(
+            RiskLevel::Suspicious,
+            format!("архив .{ext}: содержимое не проверено"),
+        );
+    } else if MACRO_DOCUMENT.contains(&ext.as_str()) {
+        risk.flag(
+            RiskLevel::Suspicious,
+            format!("документ .{ext} может содержать макросы"),
+        );
+    }
+}
+
+/// У PE по смещению из 0x3C лежит `PE\0\0`; без него «MZ» — просто текст.
+/// Настоящие компоновщики кладут заголовок PE в первые килобайты.
+fn is_pe(head: &[u8]) -> bool {
+    let Some(offset) = head.get(0x3c..0x40) else {
+        return head.len() < 0x40;
+    };
+    let offset = u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize;
+    if offset >= 0x1000 {
+        return false;
+    }
+    match head.get(offset..offset + 4) {
+        Some(sig) => sig == b"PE\0\0",
+        None => true,
+    }
+}
+
+struct ZipScan {
+    /// Дошли до центрального каталога: видели все записи архива.
+    complete: bool,
+    executables: Vec<String>,
+    archives: Vec<String>,
+}
+
+/// Проходит локальные заголовки ZIP в пределах `head`.
+fn scan_zip(head: &[u8]) -> ZipScan {
+    let mut scan = ZipScan {
+        complete: false,
+        executables: Vec::new(),
+        archives: Vec::new(),
+    };
+    let mut pos = 0;
+    while let Some(header) = head.get(pos..pos + 30) {
+        if header.starts_with(ZIP_CENTRAL) {
+            scan.complete = true;
+            break;
+        }
+        if !header.starts_with(ZIP_LOCAL) {
+            break;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]) as usize;
+        let flags = u16_at(6);
+        let size = u32::from_le_bytes([header[18], header[19], header[20], header[21]]) as usize;
+        let name_len = u16_at(26);
+        let extra_len = u16_at(28);
+        let Some(name) = head.get(pos + 30..pos + 30 + name_len) else {
+            break;
+        };
+        let name = String::from_utf8_lossy(name).into_owned();
+        let inner = extension(name.trim_end_matches('/'));
+        if EXECUTABLE.contains(&inner.as_str()) {
+            scan.executables.push(name);
+        } else if CONTAINER.contains(&inner.as_str()) {
+            scan.archives.push(name);
+        }
+        // Бит 3: размер записан после данных, дальше по заголовкам не пройти.
+        if flags & 0x08 != 0 {
+            break;
+        }
+        pos += 30 + name_len + extra_len + size;
+    }
+    scan
+}
+
+fn is_office_zip(ext: &str) -> bool {
+    matches!(
+        ext,
+        "docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp" | "epub" | "docm" | "xlsm" | "pptm"
+    )
+}
+
+fn extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.trim().to_ascii_lowercase(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(
+        name: &str,
+        disposition: Option<&str>,
+        mime: Option<&str>,
+        head: &[u8],
+    ) -> RiskAssessment {
+        let url = Url::parse(&format!("https://files.example/{name}")).unwrap();
+        classify(
+            &DownloadInfo {
+                url: &url,
+                file_name: name,
+                content_disposition: disposition,
+                mime,
+                head,
+            },
+            &ReputationList::default(),
+        )
+    }
+
+    fn pe() -> Vec<u8> {
+        let mut data = vec![0u8; 0x100];
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3c] = 0x80;
+        data[0x80..0x84].copy_from_slice(b"PE\0\0");
+        data
+    }
+
+    fn zip(names: &[&str], central: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        for name in names {
+            data.extend_from_slice(ZIP_LOCAL);
+            data.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            data.extend_from_slice(&2u32.to_le_bytes());
+            data.extend_from_slice(&2u32.to_le_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(b"hi");
+        }
+        if central {
+            data.extend_from_slice(ZIP_CENTRAL);
+            data.extend_from_slice(&[0; 42]);
+        }
+        data
+    }
+
+    #[test]
+    fn flags_executable_names_in_any_case() {
+        for name in [
+            "setup.EXE",
+            "screensaver.scr",
+            "run.bat",
+            "install.ps1",
+            "a.Lnk",
+        ] {
+            assert_eq!(
+                check(name, None, None, b"").level,
+                RiskLevel::Dangerous,
+                "{name}"
+            );
+        }
+        assert_eq!(
+            check("report.pdf", None, Some("application/pdf"), b"%PDF-1.7").level,
+            RiskLevel::Safe
+        );
+        assert_eq!(
+            check("notes.docm", None, None, b"").level,
+            RiskLevel::Suspicious
+        );
+    }
+
+    #[test]
+    fn flags_double_extension_and_bidi_names() {
+        let risk = check("invoice.pdf.exe", None, None, b"");
+        assert!(risk
+            .reasons
+            .iter()
+            .any(|r| r.contains("двойное расширение")));
+        let risk = check("photo.jpg     .exe", None, None, b"");
+        assert!(risk.reasons.iter().any(|r| r.contains("пробелами")));
+        let risk = check("invoice\u{202e}fdp.exe", None, None, b"");
+        assert!(risk.reasons.iter().any(|r| r.contains("направления")));
+        assert_eq!(
+            check("archive.tar.gz", None, None, b"").level,
+            RiskLevel::Suspicious
+        );
+    }
+
+    #[test]
+    fn uses_content_disposition_and_mime() {
+        let risk = check(
+            "download.txt",
+            Some("attachment; filename=\"update.exe\""),
+            None,
+            b"",
+        );
+        assert_eq!(risk.level, RiskLevel::Dangerous);
+        let risk = check(
+            "file.bin",
+            None,
+            Some("application/x-msdownload; charset=binary"),
+            b"",
+        );
+        assert_eq!(risk.level, RiskLevel::Dangerous);
+    }
+
+    #[test]
+    fn detects_content_by_magic_bytes() {
+        let risk = check("cat.png", None, Some("image/png"), &pe());
+        assert_eq!(risk.level, RiskLevel::Dangerous);
+        assert!(risk
+            .reasons
+            .iter()
+            .any(|r| r.contains("под видом «cat.png»")));
+        // «MZ» в начале текста без заголовка PE — не программа.
+        let mut text = b"MZ is a state abbreviation".to_vec();
+        text.resize(0x100, b' ');
+        assert_eq!(check("notes.txt", None, None, &text).level, RiskLevel::Safe);
+
+        assert_eq!(
+            check("doc.pdf", None, None, LNK_MAGIC).level,
+            RiskLevel::Dangerous
+        );
+        assert_eq!(
+            check("package.bin", None, None, OLE_MAGIC).level,
+            RiskLevel::Dangerous
+        );
+        assert_eq!(
+            check("legacy.doc", None, None, OLE_MAGIC).level,
+            RiskLevel::Safe
+        );
+        assert_eq!(
+            check("tool", None, None, b"\x7fELF\x02\x01").level,
+            RiskLevel::Dangerous
+        );
+    }
+
+    #[test]
+    fn inspects_zip_entries() {
+        let risk = check(
+            "photos.zip",
+            None,
+            None,
+            &zip(&["a.jpg", "setup.exe"], true),
+        );
+        assert_eq!(risk.level, RiskLevel::Dangerous);
+        assert!(risk.reasons.iter().any(|r| r.contains("setup.exe")));
+        assert_eq!(
+            check("photos.zip", None, None, &zip(&["a.jpg", "b.jpg"], true)).level,
+            RiskLevel::Safe
+        );
+        // Центральный каталог не попал в начало файла — проверено не всё.
+        assert_eq!(
+            check("photos.zip", None, None, &zip(&["a.jpg"], false)).level,
+            RiskLevel::Suspicious
+        );
+        assert_eq!(
+            check(
+                "report.docx",
+                None,
+                None,
+                &zip(&["word/document.xml"], false)
+            )
+            .level,
+            RiskLevel::Safe
+        );
+    }
+
+    #[test]
+    fn matches_reputation_lists() {
+        let mut list = ReputationList::parse("# known bad\nevil.example\n*.bad.test # wildcard\n");
+        list.extend("https://cdn.example/malware/\n");
+        let classify_url = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            classify(
+                &DownloadInfo {
+                    url: &url,
+                    file_name: "report.pdf",
+                    content_disposition: None,
+                    mime: None,
+                    head: b"%PDF",
+                },
+                &list,
+            )
+        };
+        assert_eq!(
+            classify_url("https://dl.evil.example/report.pdf").level,
+            RiskLevel::Dangerous
+        );
+        assert_eq!(
+            classify_url("https://x.bad.test/report.pdf").level,
+            RiskLevel::Dangerous
+        );
+        assert_eq!(
+            classify_url("https://CDN.example/malware/report.pdf").level,
+            RiskLevel::Dangerous
+        );
+        assert_eq!(
+            classify_url("https://cdn.example/docs/report.pdf").level,
+            RiskLevel::Safe
+        );
+        let risk = classify_url("http://evil.example/report.pdf");
+        assert!(risk.reasons.iter().any(|r| r.contains("HTTP")));
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use plus_privacy::{migrate, Migration, Profile, ProfileDb};
+use rusqlite::{params, Connection, Row};
+use std::path::{Path, PathBuf};
+
+use crate::{Download, DownloadId, DownloadState};
+
+/// Схема списка загрузок по версиям (`PRAGMA user_version`).
+/// v2 — результат проверки на опасное содержимое (JSON);
+/// v3 — страница-источник и контрольные суммы.
+const MIGRATIONS: &[Migration] = &[
+    Migration::sql(
+        "CREATE TABLE downloads (
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL,
+        path TEXT,
+        state TEXT NOT NULL,
+        received INTEGER NOT NULL DEFAULT 0,
+        total INTEGER,
+        etag TEXT,
+        error TEXT,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER
+    );
+    CREATE INDEX downloads_started ON downloads(started_at);",
+    ),
+    Migration::sql("ALTER TABLE downloads ADD COLUMN risk TEXT;"),
+    Migration::sql(
+        "ALTER TABLE downloads ADD COLUMN referrer TEXT;
+    ALTER TABLE downloads ADD COLUMN sha256 TEXT;
+    ALTER TABLE downloads ADD COLUMN expected_sha256 TEXT;",
+    ),
+];
+
+const COLUMNS: &str = "id, url, path, state, received, total, etag, error, started_at, finished_at,
+    risk, referrer, sha256, expected_sha256";
+
+/// Список загрузок профиля. Хранит только записи; файлы ведёт `DownloadManager`.
+pub struct DownloadStore {
+    conn: ProfileDb,
+}
+
+impl DownloadStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// У инкогнито список живёт только в памяти.
+    pub fn for_profile(profile: &Profile) -> Result<Self> {
+        Self::from_connection(profile.open_database(&profile.downloads_db())?)
+    }
+
+    fn from_connection(conn: impl Into<ProfileDb>) -> Result<Self> {
+        let mut conn = conn.into();
+        migrate(&mut conn, MIGRATIONS)?;
+        Ok(Self { conn })
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.conn.flush()?)
+    }
+
+    pub(crate) fn insert(
+        &self,
+        url: &str,
+        referrer: Option<&str>,
+        started_at: DateTime<Utc>,
+    ) -> Result<DownloadId> {
+        self.conn.execute(
+            "INSERT INTO downloads(url, referrer, state, started_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                url,
+                referrer,
+                DownloadState::Queued.as_str(),
+                started_at.timestamp_millis()
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub(crate) fn save(&self, download: &Download) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET path = ?2, state = ?3, received = ?4, total = ?5, etag = ?6,
+                error = ?7, finished_at = ?8, risk = ?9, sha256 = ?10, expected_sha256 = ?11
+             WHERE id = ?1",
+            params![
+                download.id,
+                download
+                    .path
+                    .as_ref()
+                    .map(|p| p.to_string_lossy().into_owned()),
+                download.state.as_str(),
+                download.received as i64,
+                download.total.map(|t| t as i64),
+                download.etag,
+                download.error,
+                download.finished_at.map(|at| at.timestamp_millis()),
+                download
+                    .risk
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                download.sha256,
+                download.expected_sha256,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Все записи, новые первыми.
+    pub fn list(&self) -> Result<Vec<Download>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {COLUMNS} FROM downloads ORDER BY started_at DESC, id DESC"
+        ))?;
+        let rows = stmt.query_map([], read_download)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub(crate) fn delete(&self, id: DownloadId) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM downloads WHERE id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+fn read_download(row: &Row) -> rusqlite::Result<Download> {
+    let millis = |value: i64| Utc.timestamp_millis_opt(value).single().unwrap_or_default();
+    Ok(Download {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        referrer: row.get(11)?,
+        path: row.get::<_, Option<String>>(2)?.map(PathBuf::from),
+        state: DownloadState::parse(&row.get::<_, String>(3)?),
+        received: row.get::<_, i64>(4)? as u64,
+        total: row.get::<_, Option<i64>>(5)?.map(|t| t as u64),
+        etag: row.get(6)?,
+        error: row.get(7)?,
+        started_at: millis(row.get(8)?),
+        finished_at: row.get::<_, Option<i64>>(9)?.map(millis),
+        risk: row
+            .get::<_, Option<String>>(10)?
+            .and_then(|raw| serde_json::from_str(&raw).ok()),
+        sha256: row.get(12)?,
+        expected_sha256: row.get(13)?,
+    })
+}
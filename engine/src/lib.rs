@@ -4,6 +4,7 @@ use std::collections::HashSet;
 use url::Url;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BrowserPolicy {
     pub allow_file_scheme: bool,
     pub incognito: bool,
@@ -11,6 +12,7 @@ pub struct BrowserPolicy {
     pub yandex_only_search: bool,
     pub vpn_mode: VpnRouteMode,
     pub vpn_domain_list: HashSet<String>,
+    pub kill_switch: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -29,6 +31,7 @@ impl Default for BrowserPolicy {
             yandex_only_search: true,
             vpn_mode: VpnRouteMode::Off,
             vpn_domain_list: HashSet::new(),
+            kill_switch: false,
         }
     }
 }
@@ -47,11 +50,20 @@ impl EngineController {
         &self.policy
     }
 
+    /// Политика целиком, например после перечитывания сохранённых настроек.
+    pub fn set_policy(&mut self, policy: BrowserPolicy) {
+        self.policy = policy;
+    }
+
     pub fn set_vpn_mode(&mut self, mode: VpnRouteMode, domains: Vec<String>) {
         self.policy.vpn_mode = mode;
         self.policy.vpn_domain_list = domains.into_iter().collect();
     }
 
+    pub fn set_kill_switch(&mut self, enabled: bool) {
+        self.policy.kill_switch = enabled;
+    }
+
     pub fn validate_navigation(&self, url: &str) -> Result<()> {
         let parsed = Url::parse(url)?;
         if parsed.scheme() == "file" && !self.policy.allow_file_scheme {
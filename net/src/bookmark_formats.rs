@@ -0,0 +1,478 @@
+//! Форматы обмена закладками: Netscape HTML (экспорт всех браузеров)
+//! и JSON-файл `Bookmarks` из профиля Chromium.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::bookmarks::{Bookmark, ImportedBookmarks};
+
+/// Микросекунды между 1601-01-01 (эпоха Chromium/Windows) и 1970-01-01.
+const CHROMIUM_EPOCH_OFFSET_US: i64 = 11_644_473_600_000_000;
+
+/// Время Chromium (мкс от 1601-01-01); ноль означает «не задано».
+/// Значения из чужого файла, которые нельзя перевести в дату, тоже дают `None`.
+pub(crate) fn from_chromium_micros(us: i64) -> Option<DateTime<Utc>> {
+    if us == 0 {
+        return None;
+    }
+    DateTime::from_timestamp_micros(us.checked_sub(CHROMIUM_EPOCH_OFFSET_US)?)
+}
+
+pub(crate) fn parse_netscape(html: &str) -> ImportedBookmarks {
+    struct Frame {
+        folder: Bookmark,
+        toolbar: bool,
+        // <DL> без заголовка — корень документа, его содержимое поднимается выше.
+        anonymous: bool,
+    }
+
+    fn close(stack: &mut Vec<Frame>, bar: &mut Vec<Bookmark>) {
+        let Some(frame) = stack.pop() else {
+            return;
+        };
+        let Some(parent) = stack.last_mut() else {
+            stack.push(frame);
+            return;
+        };
+        if frame.toolbar {
+            bar.extend(frame.folder.children);
+        } else if frame.anonymous {
+            parent.folder.children.extend(frame.folder.children);
+        } else {
+            parent.folder.children.push(frame.folder);
+        }
+    }
+
+    let mut bar = Vec::new();
+    let mut stack = vec![Frame {
+        folder: Bookmark::default(),
+        toolbar: false,
+        anonymous: true,
+    }];
+    let mut pending: Option<Frame> = None;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        let name = tag
+            .split(|c: char| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        match name.as_str() {
+            "H3" => {
+                let (text, tail) = element_text(rest, "</H3");
+                rest = tail;
+                let attrs = parse_attrs(tag);
+                let mut folder = Bookmark::folder(decode_entities(text.trim()), Vec::new());
+                apply_dates(&mut folder, &attrs);
+                pending = Some(Frame {
+                    folder,
+                    toolbar: attrs
+                        .get("PERSONAL_TOOLBAR_FOLDER")
+                        .is_some_and(|v| v.eq_ignore_ascii_case("true")),
+                    anonymous: false,
+                });
+            }
+            "A" => {
+                let (text, tail) = element_text(rest, "</A");
+                rest = tail;
+                let attrs = parse_attrs(tag);
+                let Some(href) = attrs.get("HREF") else {
+                    continue;
+                };
+                let mut link = Bookmark::link(decode_entities(text.trim()), href.clone());
+                apply_dates(&mut link, &attrs);
+                if let Some(tags) = attrs.get("TAGS") {
+                    link.tags = tags
+                        .split(',')
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty())
+                        .collect();
+                }
+                if let Some(frame) = stack.last_mut() {
+                    frame.folder.children.push(link);
+                }
+            }
+            "DL" => stack.push(pending.take().unwrap_or(Frame {
+                folder: Bookmark::default(),
+                toolbar: false,
+                anonymous: true,
+            })),
+            "/DL" => close(&mut stack, &mut bar),
+            _ => {}
+        }
+    }
+    while stack.len() > 1 {
+        close(&mut stack, &mut bar);
+    }
+    let other = stack.pop().map(|f| f.folder.children).unwrap_or_default();
+    ImportedBookmarks { bar, other }
+}
+
+/// Экспорт в том же виде, что и у Chromium: панель — папка с
+/// `PERSONAL_TOOLBAR_FOLDER`, остальное лежит в корне документа.
+pub(crate) fn write_netscape(bar: &Bookmark, other: &Bookmark) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+         <!-- This is an automatically generated file.\n     \
+         It will be read and overwritten.\n     \
+         DO NOT EDIT! -->\n\
+         <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+         <TITLE>Bookmarks</TITLE>\n\
+         <H1>Bookmarks</H1>\n\
+         <DL><p>\n",
+    );
+    out.push_str(&format!(
+        "    <DT><H3 ADD_DATE=\"{}\" LAST_MODIFIED=\"{}\" PERSONAL_TOOLBAR_FOLDER=\"true\">{}</H3>\n",
+        bar.created_at.timestamp(),
+        bar.modified_at.timestamp(),
+        escape_html(&bar.title)
+    ));
+    out.push_str("    <DL><p>\n");
+    write_netscape_nodes(&mut out, &bar.children, 2);
+    out.push_str("    </DL><p>\n");
+    write_netscape_nodes(&mut out, &other.children, 1);
+    out.push_str("</DL><p>\n");
+    out
+}
+
+fn write_netscape_nodes(out: &mut String, nodes: &[Bookmark], depth: usize) {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        match &node.url {
+            Some(url) => {
+                let tags = if node.tags.is_empty() {
+                    String::new()
+                } else {
+                    format!(" TAGS=\"{}\"", escape_html(&node.tags.join(",")))
+                };
+                out.push_str(&format!(
+                    "{indent}<DT><A HREF=\"{}\" ADD_DATE=\"{}\" LAST_MODIFIED=\"{}\"{tags}>{}</A>\n",
+                    escape_html(url),
+                    node.created_at.timestamp(),
+                    node.modified_at.timestamp(),
+                    escape_html(&node.title)
+                ));
+            }
+            None => {
+                out.push_str(&format!(
+                    "{indent}<DT><H3 ADD_DATE=\"{}\" LAST_MODIFIED=\"{}\">{}</H3>\n",
+                    node.created_at.timestamp(),
+                    node.modified_at.timestamp(),
+                    escape_html(&node.title)
+                ));
+                out.push_str(&format!("{indent}<DL><p>\n"));
+                write_netscape_nodes(out, &node.children, depth + 1);
+                out.push_str(&format!("{indent}</DL><p>\n"));
+            }
+        }
+    }
+}
+
+pub(crate) fn parse_chromium(json: &str) -> Result<ImportedBookmarks> {
+    let doc: Value = serde_json::from_str(json)?;
+    let roots = doc
+        .get("roots")
+        .ok_or_else(|| anyhow!("not a Chromium Bookmarks file"))?;
+    let children = |key: &str| -> Vec<Bookmark> {
+        roots[key]["children"]
+            .as_array()
+            .map(|nodes| nodes.iter().filter_map(chromium_node).collect())
+            .unwrap_or_default()
+    };
+    let mut other = children("other");
+    other.extend(children("synced"));
+    Ok(ImportedBookmarks {
+        bar: children("bookmark_bar"),
+        other,
+    })
+}
+
+fn chromium_node(node: &Value) -> Option<Bookmark> {
+    let title = node["name"].as_str().unwrap_or_default();
+    let mut bookmark = match node["type"].as_str()? {
+        "url" => Bookmark::link(title, node["url"].as_str()?),
+        "folder" => Bookmark::folder(
+            title,
+            node["children"]
+                .as_array()
+                .map(|nodes| nodes.iter().filter_map(chromium_node).collect())
+                .unwrap_or_default(),
+        ),
+        _ => return None,
+    };
+    if let Some(added) = chromium_time(&node["date_added"]) {
+        bookmark.created_at = added;
+        bookmark.modified_at = chromium_time(&node["date_modified"]).unwrap_or(added);
+    }
+    Some(bookmark)
+}
+
+/// Пишет файл, который Chromium принимает как `Bookmarks` профиля.
+/// `checksum` и `guid` не заполняются: браузер пересчитывает их сам.
+pub(crate) fn write_chromium(bar: &Bookmark, other: &Bookmark) -> Result<String> {
+    let mut next_id = 4;
+    let doc = json!({
+        "roots": {
+            "bookmark_bar": chromium_folder(bar, "1", &mut next_id),
+            "other": chromium_folder(other, "2", &mut next_id),
+            "synced": {
+                "children": [],
+                "date_added": chromium_timestamp(Utc::now()),
+                "date_modified": "0",
+                "id": "3",
+                "name": "Mobile bookmarks",
+                "type": "folder"
+            }
+        },
+        "version": 1
+    });
+    Ok(serde_json::to_string_pretty(&doc)?)
+}
+
+fn chromium_folder(folder: &Bookmark, id: &str, next_id: &mut u64) -> Value {
+    json!({
+        "children": folder
+            .children
+            .iter()
+            .map(|node| chromium_value(node, next_id))
+            .collect::<Vec<_>>(),
+        "date_added": chromium_timestamp(folder.created_at),
+        "date_modified": chromium_timestamp(folder.modified_at),
+        "id": id,
+        "name": folder.title,
+        "type": "folder"
+    })
+}
+
+fn chromium_value(node: &Bookmark, next_id: &mut u64) -> Value {
+    let id = next_id.to_string();
+    *next_id += 1;
+    match &node.url {
+        Some(url) => json!({
+            "date_added": chromium_timestamp(node.created_at),
+            "id": id,
+            "name": node.title,
+            "type": "url",
+            "url": url
+        }),
+        None => chromium_folder(node, &id, next_id),
+    }
+}
+
+fn chromium_time(value: &Value) -> Option<DateTime<Utc>> {
+    from_chromium_micros(value.as_str()?.parse().ok()?)
+}
+
+fn chromium_timestamp(at: DateTime<Utc>) -> String {
+    (at.timestamp_micros() + CHROMIUM_EPOCH_OFFSET_US).to_string()
+}
+
+fn apply_dates(bookmark: &mut Bookmark, attrs: &HashMap<String, String>) {
+    let seconds = |key: &str| {
+        attrs
+            .get(key)
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(|s| DateTime::from_timestamp(s, 0))
+    };
+    if let Some(added) = seconds("ADD_DATE") {
+        bookmark.created_at = added;
+        bookmark.modified_at = added;
+    }
+    if let Some(modified) = seconds("LAST_MODIFIED") {
+        bookmark.modified_at = modified;
+    }
+}
+
+/// Текст до закрывающего тега `close` (без учёта регистра) и остаток после него.
+fn element_text<'a>(rest: &'a str, close: &str) -> (&'a str, &'a str) {
+    let mut from = 0;
+    while let Some(i) = rest[from..].find('<') {
+        let pos = from + i;
+        if rest
+            .get(pos..pos + close.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(close))
+        {
+            let tail = &rest[pos..];
+            let after = tail.find('>').map(|i| &tail[i + 1..]).unwrap_or_default();
+            return (&rest[..pos], after);
+        }
+        from = pos + 1;
+    }
+    (rest, "")
+}
+
+fn parse_attrs(tag: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = tag
+        .trim_start()
+        .split_once(|c: char| c.is_ascii_whitespace())
+        .map(|(_, r)| r)
+        .unwrap_or_default();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_uppercase();
+        rest = rest[key_end..].trim_start();
+        let value = if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+            let (value, tail) = match after_eq.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let body = &after_eq[1..];
+                    let end = body.find(quote).unwrap_or(body.len());
+                    (&body[..end], body.get(end + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let end = after_eq
+                        .find(|c: char| c.is_ascii_whitespace())
+                        .unwrap_or(after_eq.len());
+                    (&after_eq[..end], &after_eq[end..])
+                }
+            };
+            rest = tail;
+            decode_entities(value)
+        } else {
+            String::new()
+        };
+        if !key.is_empty() {
+            attrs.insert(key, value);
+        }
+    }
+    attrs
+}
+
+fn decode_entities(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let ch = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity.strip_prefix('#').and_then(|num| {
+                    match num.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => num.parse().ok(),
+                    }
+                    .and_then(char::from_u32)
+                }),
+            };
+            ch.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape_html(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_firefox_netscape_export() {
+        let html = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks Menu</H1>
+<DL><p>
+    <DT><A HREF="https://www.mozilla.org/about/" ADD_DATE="1700000000" LAST_MODIFIED="1700000100" TAGS="mozilla,About">About &amp; more</A>
+    <DT><H3 ADD_DATE="1700000000" LAST_MODIFIED="1700000000" PERSONAL_TOOLBAR_FOLDER="true">Bookmarks Toolbar</H3>
+    <DL><p>
+        <DT><a href='https://ya.ru/' add_date=1700000200>Я&#x43d;декс</a>
+        <DT><H3>Nested</H3>
+        <DL><p>
+            <DT><A HREF="https://example.org/?q=1&amp;r=2">Ex</A>
+        </DL><p>
+    </DL><p>
+    <DT><H3 UNFILED_BOOKMARKS_FOLDER="true">Other Bookmarks</H3>
+    <DL><p>
+    </DL><p>
+</DL>"#;
+        let parsed = parse_netscape(html);
+        assert_eq!(parsed.bar.len(), 2);
+        assert_eq!(parsed.bar[0].title, "Яндекс");
+        assert_eq!(parsed.bar[0].created_at.timestamp(), 1_700_000_200);
+        assert_eq!(
+            parsed.bar[1].children[0].url.as_deref(),
+            Some("https://example.org/?q=1&r=2")
+        );
+        assert_eq!(parsed.other.len(), 2);
+        assert_eq!(parsed.other[0].title, "About & more");
+        assert_eq!(parsed.other[0].tags, vec!["mozilla", "About"]);
+        assert_eq!(parsed.other[0].modified_at.timestamp(), 1_700_000_100);
+        assert!(parsed.other[1].is_folder());
+    }
+
+    #[test]
+    fn parses_chromium_bookmarks_file() {
+        let json = r#"{
+            "checksum": "0",
+            "roots": {
+                "bookmark_bar": {"children": [
+                    {"date_added": "13340000000000000", "id": "5", "name": "Rust", "type": "url", "url": "https://rust-lang.org/"},
+                    {"children": [], "date_added": "0", "id": "6", "name": "Empty", "type": "folder"}
+                ], "id": "1", "name": "Bookmarks bar", "type": "folder"},
+                "other": {"children": [], "id": "2", "name": "Other bookmarks", "type": "folder"},
+                "synced": {"children": [
+                    {"id": "7", "name": "Phone", "type": "url", "url": "https://m.example/"}
+                ], "id": "3", "name": "Mobile bookmarks", "type": "folder"}
+            },
+            "version": 1
+        }"#;
+        let parsed = parse_chromium(json).unwrap();
+        assert_eq!(parsed.bar.len(), 2);
+        assert_eq!(
+            parsed.bar[0].created_at.timestamp(),
+            13_340_000_000 - 11_644_473_600
+        );
+        assert!(parsed.bar[1].is_folder());
+        assert_eq!(parsed.other[0].url.as_deref(), Some("https://m.example/"));
+        assert!(parse_chromium("{}").is_err());
+    }
+
+    #[test]
+    fn ignores_out_of_range_chromium_times() {
+        assert_eq!(from_chromium_micros(i64::MIN), None);
+        assert_eq!(from_chromium_micros(i64::MAX), None);
+        let json = r#"{"roots": {"bookmark_bar": {"children": [
+            {"date_added": "-9223372036854775808", "name": "Old", "type": "url", "url": "https://old.example/"}
+        ], "type": "folder"}}}"#;
+        let parsed = parse_chromium(json).unwrap();
+        assert_eq!(parsed.bar[0].url.as_deref(), Some("https://old.example/"));
+    }
+}
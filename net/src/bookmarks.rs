@@ -0,0 +1,577 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use plus_privacy::{migrate, Migration, Profile, ProfileDb};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::bookmark_formats::{parse_chromium, parse_netscape, write_chromium, write_netscape};
+use crate::history::{like_pattern, register_fold};
+
+/// Корневые папки создаются миграцией и не удаляются.
+pub const BOOKMARK_BAR: i64 = 1;
+pub const OTHER_BOOKMARKS: i64 = 2;
+
+const MIGRATIONS: &[Migration] = &[Migration::sql(
+    "CREATE TABLE bookmarks (
+        id INTEGER PRIMARY KEY,
+        parent_id INTEGER REFERENCES bookmarks(id) ON DELETE CASCADE,
+        title TEXT NOT NULL DEFAULT '',
+        url TEXT,
+        position INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL,
+        modified_at INTEGER NOT NULL
+    );
+    CREATE TABLE bookmark_tags (
+        bookmark_id INTEGER NOT NULL REFERENCES bookmarks(id) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (bookmark_id, tag)
+    );
+    CREATE INDEX bookmarks_parent ON bookmarks(parent_id, position);
+    CREATE INDEX bookmarks_url ON bookmarks(url);
+    CREATE INDEX bookmark_tags_tag ON bookmark_tags(tag);
+    INSERT INTO bookmarks(id, parent_id, title, position, created_at, modified_at) VALUES
+        (1, NULL, 'Панель закладок', 0,
+            CAST(strftime('%s', 'now') AS INTEGER) * 1000, CAST(strftime('%s', 'now') AS INTEGER) * 1000),
+        (2, NULL, 'Другие закладки', 1,
+            CAST(strftime('%s', 'now') AS INTEGER) * 1000, CAST(strftime('%s', 'now') AS INTEGER) * 1000);",
+)];
+
+/// Узел дерева закладок: папка, если `url` пустой.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: i64,
+    pub title: String,
+    pub url: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+    pub children: Vec<Bookmark>,
+}
+
+impl Bookmark {
+    pub fn folder(title: impl Into<String>, children: Vec<Bookmark>) -> Self {
+        let now = Utc::now();
+        Self {
+            title: title.into(),
+            created_at: now,
+            modified_at: now,
+            children,
+            ..Self::default()
+        }
+    }
+
+    pub fn link(title: impl Into<String>, url: impl Into<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            title: title.into(),
+            url: Some(url.into()),
+            created_at: now,
+            modified_at: now,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.url.is_none()
+    }
+}
+
+/// Результат разбора внешнего файла: содержимое панели и всё остальное.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportedBookmarks {
+    pub bar: Vec<Bookmark>,
+    pub other: Vec<Bookmark>,
+}
+
+pub struct BookmarkStore {
+    conn: ProfileDb,
+}
+
+impl BookmarkStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Инкогнито держит базу только в памяти, зашифрованный профиль — расшифрованной в памяти.
+    pub fn for_profile(profile: &Profile) -> Result<Self> {
+        Self::from_connection(profile.open_database(&profile.bookmarks_db())?)
+    }
+
+    fn from_connection(conn: impl Into<ProfileDb>) -> Result<Self> {
+        let mut conn = conn.into();
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        register_fold(&conn)?;
+        migrate(&mut conn, MIGRATIONS)?;
+        Ok(Self { conn })
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.conn.flush()?)
+    }
+
+    /// Меняется после каждой записи в базу, в том числе из другого соединения.
+    pub fn revision(&self) -> Result<(u64, i64)> {
+        Ok(self.conn.revision()?)
+    }
+
+    pub fn add_folder(&self, parent: i64, title: &str) -> Result<i64> {
+        let now = Utc::now();
+        self.insert(parent, title, None, now, now)
+    }
+
+    pub fn add_bookmark(
+        &self,
+        parent: i64,
+        title: &str,
+        url: &str,
+        tags: &[String],
+    ) -> Result<i64> {
+        let now = Utc::now();
+        let id = self.insert(parent, title, Some(url), now, now)?;
+        self.set_tags(id, tags)?;
+        Ok(id)
+    }
+
+    fn insert(
+        &self,
+        parent: i64,
+        title: &str,
+        url: Option<&str>,
+        created_at: DateTime<Utc>,
+        modified_at: DateTime<Utc>,
+    ) -> Result<i64> {
+        if !self.is_folder(parent)? {
+            bail!("bookmark {parent} is not a folder");
+        }
+        self.conn.execute(
+            "INSERT INTO bookmarks(parent_id, title, url, position, created_at, modified_at)
+             VALUES(?1, ?2, ?3,
+                (SELECT COALESCE(MAX(position) + 1, 0) FROM bookmarks WHERE parent_id = ?1),
+                ?4, ?5)",
+            params![
+                parent,
+                title,
+                url,
+                created_at.timestamp_millis(),
+                modified_at.timestamp_millis()
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Меняет заголовок и, для закладок, адрес. У папок `url` игнорируется.
+    pub fn update(&self, id: i64, title: &str, url: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE bookmarks SET title = ?2,
+                url = CASE WHEN url IS NULL THEN NULL ELSE COALESCE(?3, url) END,
+                modified_at = ?4
+             WHERE id = ?1",
+            params![id, title, url, Utc::now().timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_tags(&self, id: i64, tags: &[String]) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM bookmark_tags WHERE bookmark_id = ?1",
+            params![id],
+        )?;
+        for tag in tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO bookmark_tags(bookmark_id, tag) VALUES(?1, ?2)",
+                params![id, tag.to_lowercase()],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Переносит узел в папку `parent` на позицию `index` (с обрезкой до конца списка).
+    pub fn move_to(&self, id: i64, parent: i64, index: usize) -> Result<()> {
+        if id == BOOKMARK_BAR || id == OTHER_BOOKMARKS {
+            bail!("root folders cannot be moved");
+        }
+        if !self.is_folder(parent)? {
+            bail!("bookmark {parent} is not a folder");
+        }
+        let mut cursor = Some(parent);
+        while let Some(node) = cursor {
+            if node == id {
+                bail!("cannot move a folder into itself");
+            }
+            cursor = self
+                .conn
+                .query_row(
+                    "SELECT parent_id FROM bookmarks WHERE id = ?1",
+                    params![node],
+                    |r| r.get(0),
+                )
+                .optional()?
+                .flatten();
+        }
+        let mut siblings: Vec<i64> = self
+            .conn
+            .prepare(
+                "SELECT id FROM bookmarks WHERE parent_id = ?1 AND id != ?2 ORDER BY position, id",
+            )?
+            .query_map(params![parent, id], |r| r.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        siblings.insert(index.min(siblings.len()), id);
+        let tx = self.conn.unchecked_transaction()?;
+        for (position, sibling) in siblings.iter().enumerate() {
+            tx.execute(
+                "UPDATE bookmarks SET parent_id = ?2, position = ?3 WHERE id = ?1",
+                params![sibling, parent, position as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Удаляет закладку или папку со всем содержимым.
+    pub fn remove(&self, id: i64) -> Result<()> {
+        if id == BOOKMARK_BAR || id == OTHER_BOOKMARKS {
+            bail!("root folders cannot be removed");
+        }
+        self.conn
+            .execute("DELETE FROM bookmarks WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Bookmark>> {
+        let Some(mut bookmark) = self
+            .conn
+            .query_row(
+                "SELECT id, parent_id, title, url, created_at, modified_at
+                 FROM bookmarks WHERE id = ?1",
+                params![id],
+                row_to_bookmark,
+            )
+            .optional()?
+            .map(|(_, b)| b)
+        else {
+            return Ok(None);
+        };
+        bookmark.tags = self.tags(id)?;
+        Ok(Some(bookmark))
+    }
+
+    /// Первая закладка с таким адресом — для звёздочки в омнибоксе.
+    pub fn find_url(&self, url: &str) -> Result<Option<i64>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id FROM bookmarks WHERE url = ?1 ORDER BY id LIMIT 1",
+                params![url],
+                |r| r.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Содержимое папки со всеми вложенными узлами.
+    pub fn children(&self, folder: i64) -> Result<Vec<Bookmark>> {
+        let mut by_parent = self.load_all()?;
+        Ok(assemble(&mut by_parent, Some(folder)))
+    }
+
+    /// Корневые папки (панель и «Другие») с полным содержимым.
+    pub fn tree(&self) -> Result<Vec<Bookmark>> {
+        let mut by_parent = self.load_all()?;
+        Ok(assemble(&mut by_parent, None))
+    }
+
+    /// Закладки (без папок), у которых запрос встречается в заголовке, адресе или теге.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Bookmark>> {
+        let pattern = like_pattern(query);
+        let ids: Vec<i64> = self
+            .conn
+            .prepare(
+                "SELECT DISTINCT b.id FROM bookmarks b
+                 LEFT JOIN bookmark_tags t ON t.bookmark_id = b.id
+                 WHERE b.url IS NOT NULL
+                   AND (fold(b.title) LIKE ?1 ESCAPE '\\'
+                        OR fold(b.url) LIKE ?1 ESCAPE '\\'
+                        OR fold(t.tag) LIKE ?1 ESCAPE '\\')
+                 ORDER BY b.modified_at DESC
+                 LIMIT ?2",
+            )?
+            .query_map(params![pattern, limit as i64], |r| r.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut out = Vec::with_capacity(ids.len());
+        for id in ids {
+            out.extend(self.get(id)?);
+        }
+        Ok(out)
+    }
+
+    /// Вставляет разобранные узлы в папку `parent`, возвращает число добавленных закладок.
+    /// Адреса, которые уже есть в закладках, пропускаются, одноимённые папки сливаются.
+    pub fn import(&self, parent: i64, nodes: &[Bookmark]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let added = self.import_nodes(parent, nodes)?;
+        tx.commit()?;
+        Ok(added)
+    }
+
+    fn import_nodes(&self, parent: i64, nodes: &[Bookmark]) -> Result<usize> {
+        let mut added = 0;
+        for node in nodes {
+            match &node.url {
+                None => {
+                    let id = match self.child_folder(parent, &node.title)? {
+                        Some(id) => id,
+                        None => self.insert(
+                            parent,
+                            &node.title,
+                            None,
+                            node.created_at,
+                            node.modified_at,
+                        )?,
+                    };
+                    added += self.import_nodes(id, &node.children)?;
+                }
+                Some(url) if self.find_url(url)?.is_none() => {
+                    let id = self.insert(
+                        parent,
+                        &node.title,
+                        Some(url),
+                        node.created_at,
+                        node.modified_at,
+                    )?;
+                    self.set_tags(id, &node.tags)?;
+                    added += 1;
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(added)
+    }
+
+    fn child_folder(&self, parent: i64, title: &str) -> Result<Option<i64>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id FROM bookmarks WHERE parent_id = ?1 AND title = ?2 AND url IS NULL
+                 ORDER BY position LIMIT 1",
+                params![parent, title],
+                |r| r.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Панель и «Другие закладки» импортируются одной транзакцией: ошибка не
+    /// оставляет файл импортированным наполовину.
+    pub fn import_parsed(&self, imported: &ImportedBookmarks) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let added = self.import_nodes(BOOKMARK_BAR, &imported.bar)?
+            + self.import_nodes(OTHER_BOOKMARKS, &imported.other)?;
+        tx.commit()?;
+        Ok(added)
+    }
+
+    pub fn import_netscape(&self, html: &str) -> Result<usize> {
+        self.import_parsed(&parse_netscape(html))
+    }
+
+    pub fn import_chromium(&self, json: &str) -> Result<usize> {
+        self.import_parsed(&parse_chromium(json)?)
+    }
+
+    pub fn export_netscape(&self) -> Result<String> {
+        let (bar, other) = self.roots()?;
+        Ok(write_netscape(&bar, &other))
+    }
+
+    pub fn export_chromium(&self) -> Result<String> {
+        let (bar, other) = self.roots()?;
+        write_chromium(&bar, &other)
+    }
+
+    fn roots(&self) -> Result<(Bookmark, Bookmark)> {
+        let mut bar = None;
+        let mut other = None;
+        for root in self.tree()? {
+            match root.id {
+                BOOKMARK_BAR => bar = Some(root),
+                OTHER_BOOKMARKS => other = Some(root),
+                _ => {}
+            }
+        }
+        match (bar, other) {
+            (Some(bar), Some(other)) => Ok((bar, other)),
+            _ => bail!("bookmark roots are missing"),
+        }
+    }
+
+    fn is_folder(&self, id: i64) -> Result<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT url IS NULL FROM bookmarks WHERE id = ?1",
+                params![id],
+                |r| r.get(0),
+            )
+            .optional()?
+            .unwrap_or(false))
+    }
+
+    fn tags(&self, id: i64) -> Result<Vec<String>> {
+        Ok(self
+            .conn
+            .prepare("SELECT tag FROM bookmark_tags WHERE bookmark_id = ?1 ORDER BY tag")?
+            .query_map(params![id], |r| r.get(0))?
+            .collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn load_all(&self) -> Result<HashMap<Option<i64>, Vec<Bookmark>>> {
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT bookmark_id, tag FROM bookmark_tags ORDER BY tag")?;
+        for row in stmt.query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))? {
+            let (id, tag) = row?;
+            tags.entry(id).or_default().push(tag);
+        }
+        let mut by_parent: HashMap<Option<i64>, Vec<Bookmark>> = HashMap::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT id, parent_id, title, url, created_at, modified_at
+             FROM bookmarks ORDER BY position, id",
+        )?;
+        for row in stmt.query_map([], row_to_bookmark)? {
+            let (parent, mut bookmark) = row?;
+            bookmark.tags = tags.remove(&bookmark.id).unwrap_or_default();
+            by_parent.entry(parent).or_default().push(bookmark);
+        }
+        Ok(by_parent)
+    }
+}
+
+fn assemble(
+    by_parent: &mut HashMap<Option<i64>, Vec<Bookmark>>,
+    parent: Option<i64>,
+) -> Vec<Bookmark> {
+    let mut nodes = by_parent.remove(&parent).unwrap_or_default();
+    for node in &mut nodes {
+        if node.is_folder() {
+            node.children = assemble(by_parent, Some(node.id));
+        }
+    }
+    nodes
+}
+
+fn row_to_bookmark(r: &Row<'_>) -> rusqlite::Result<(Option<i64>, Bookmark)> {
+    Ok((
+        r.get(1)?,
+        Bookmark {
+            id: r.get(0)?,
+            title: r.get(2)?,
+            url: r.get(3)?,
+            tags: Vec::new(),
+            created_at: millis_to_utc(r.get(4)?),
+            modified_at: millis_to_utc(r.get(5)?),
+            children: Vec::new(),
+        },
+    ))
+}
+
+fn millis_to_utc(ms: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(ms).single().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> BookmarkStore {
+        BookmarkStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn builds_tree_moves_and_removes() {
+        let b = store();
+        let dev = b.add_folder(BOOKMARK_BAR, "Dev").unwrap();
+        let rust = b
+            .add_bookmark(
+                dev,
+                "Rust",
+                "https://rust-lang.org/",
+                &["Lang".into(), "docs".into()],
+            )
+            .unwrap();
+        let ya = b
+            .add_bookmark(BOOKMARK_BAR, "Яндекс", "https://ya.ru/", &[])
+            .unwrap();
+        assert!(b.add_folder(ya, "nope").is_err());
+
+        let bar = b.children(BOOKMARK_BAR).unwrap();
+        assert_eq!(bar.len(), 2);
+        assert_eq!(bar[0].children[0].tags, vec!["docs", "lang"]);
+        assert_eq!(b.search("LANG", 10).unwrap()[0].id, rust);
+        assert_eq!(b.search("ян", 10).unwrap()[0].id, ya);
+        assert!(b.search("_", 10).unwrap().is_empty());
+        assert_eq!(b.find_url("https://ya.ru/").unwrap(), Some(ya));
+
+        b.move_to(ya, BOOKMARK_BAR, 0).unwrap();
+        assert_eq!(b.children(BOOKMARK_BAR).unwrap()[0].id, ya);
+        assert!(b.move_to(dev, dev, 0).is_err());
+        b.move_to(dev, OTHER_BOOKMARKS, 5).unwrap();
+        assert_eq!(b.children(OTHER_BOOKMARKS).unwrap()[0].children[0].id, rust);
+
+        b.update(rust, "Rust lang", Some("https://www.rust-lang.org/"))
+            .unwrap();
+        assert_eq!(
+            b.get(rust).unwrap().unwrap().url.as_deref(),
+            Some("https://www.rust-lang.org/")
+        );
+        b.remove(dev).unwrap();
+        assert!(b.get(rust).unwrap().is_none());
+        assert!(b.remove(BOOKMARK_BAR).is_err());
+        assert_eq!(b.tree().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let src = store();
+        let dev = src.add_folder(BOOKMARK_BAR, "Dev & Co").unwrap();
+        src.add_bookmark(
+            dev,
+            "Rust <3",
+            "https://rust-lang.org/?a=1&b=2",
+            &["lang".into()],
+        )
+        .unwrap();
+        src.add_bookmark(OTHER_BOOKMARKS, "Почта", "https://mail.yandex.ru/", &[])
+            .unwrap();
+
+        let html = src.export_netscape().unwrap();
+        let dst = store();
+        assert_eq!(dst.import_netscape(&html).unwrap(), 2);
+        assert_eq!(dst.import_netscape(&html).unwrap(), 0);
+        assert_eq!(dst.children(BOOKMARK_BAR).unwrap().len(), 1);
+        let bar = dst.children(BOOKMARK_BAR).unwrap();
+        assert_eq!(bar[0].title, "Dev & Co");
+        assert_eq!(bar[0].children[0].title, "Rust <3");
+        assert_eq!(
+            bar[0].children[0].url.as_deref(),
+            Some("https://rust-lang.org/?a=1&b=2")
+        );
+        assert_eq!(bar[0].children[0].tags, vec!["lang"]);
+        assert_eq!(dst.children(OTHER_BOOKMARKS).unwrap()[0].title, "Почта");
+
+        let json = src.export_chromium().unwrap();
+        let dst = store();
+        assert_eq!(dst.import_chromium(&json).unwrap(), 2);
+        let bar = dst.children(BOOKMARK_BAR).unwrap();
+        assert_eq!(
+            bar[0].children[0].url.as_deref(),
+            Some("https://rust-lang.org/?a=1&b=2")
+        );
+        assert_eq!(
+            bar[0].children[0].created_at.timestamp(),
+            src.children(BOOKMARK_BAR).unwrap()[0].children[0]
+                .created_at
+                .timestamp()
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use plus_adblock::AdblockEngine;
+use plus_privacy::{ClearDataOptions, PrivacyStore};
+use std::sync::Mutex;
+
+use crate::history::HistoryStore;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClearReport {
+    pub history_visits: usize,
+    pub cookies: usize,
+}
+
+/// Удаляет выбранные категории данных за период `options.range`, отсчитанный от `now`.
+/// Загрузки, кэш и cookie движка хранит вызывающий — их он чистит сам по тому же `since`.
+pub fn clear_browsing_data(
+    options: &ClearDataOptions,
+    now: DateTime<Utc>,
+    history: &HistoryStore,
+    privacy: &PrivacyStore,
+    adblock: &Mutex<AdblockEngine>,
+) -> Result<ClearReport> {
+    let since = options.range.since(now);
+    let mut report = ClearReport::default();
+    if options.history {
+        report.history_visits = history.delete_range(
+            since.unwrap_or(DateTime::<Utc>::MIN_UTC),
+            DateTime::<Utc>::MAX_UTC,
+        )?;
+    }
+    if options.cookies {
+        report.cookies = privacy.delete_cookies_since(since)?;
+    }
+    if options.adblock_stats {
+        if let Ok(mut engine) = adblock.lock() {
+            engine.reset_stats();
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use plus_privacy::{Profile, TimeRange};
+    use url::Url;
+
+    use crate::history::HistoryVisit;
+
+    #[test]
+    fn clears_only_selected_range_and_categories() {
+        let profile = Profile::incognito().unwrap();
+        let history = HistoryStore::for_profile(&profile).unwrap();
+        let privacy = PrivacyStore::for_profile(&profile).unwrap();
+        let adblock = Mutex::new(AdblockEngine::from_filter_list("||ads.example^").unwrap());
+        adblock
+            .lock()
+            .unwrap()
+            .should_block("https://ads.example/a.js", "https://ya.ru", "script");
+
+        let now = Utc::now();
+        history
+            .import_visits(&[
+                HistoryVisit {
+                    url: "https://old.example/".into(),
+                    title: String::new(),
+                    visited_at: now - Duration::days(2),
+                },
+                HistoryVisit {
+                    url: "https://new.example/".into(),
+                    title: String::new(),
+                    visited_at: now - Duration::minutes(10),
+                },
+            ])
+            .unwrap();
+        privacy
+            .store_set_cookie(
+                &Url::parse("https://new.example/").unwrap(),
+                "a=1; Max-Age=600",
+            )
+            .unwrap();
+
+        let options = ClearDataOptions {
+            cache: false,
+            range: TimeRange::LastHour,
+            ..ClearDataOptions::default()
+        };
+        let report = clear_browsing_data(&options, now, &history, &privacy, &adblock).unwrap();
+        assert_eq!(report.history_visits, 1);
+        assert_eq!(report.cookies, 1);
+        assert!(history.entry("https://old.example/").unwrap().is_some());
+        assert!(history.entry("https://new.example/").unwrap().is_none());
+        assert_eq!(adblock.lock().unwrap().stats.blocked, 1);
+
+        let stats_only = ClearDataOptions {
+            history: false,
+            cookies: false,
+            cache: false,
+            adblock_stats: true,
+            ..ClearDataOptions::default()
+        };
+        clear_browsing_data(&stats_only, now, &history, &privacy, &adblock).unwrap();
+        assert_eq!(adblock.lock().unwrap().stats.blocked, 0);
+        profile.wipe().unwrap();
+    }
+}
@@ -0,0 +1,456 @@
+use anyhow::{anyhow, bail, Result};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+use url::Url;
+
+use crate::NetClient;
+
+/// Сервисы, возвращающие внешний IP обычным текстом; опрашиваются по порядку.
+pub const DEFAULT_IP_ENDPOINTS: &[&str] = &[
+    "https://api.ipify.org",
+    "https://ifconfig.me/ip",
+    "https://icanhazip.com",
+];
+
+/// Что и где проверять. Собирается из текущего состояния браузера, проверки его не трогают.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsConfig {
+    /// Прокси браузера (`http://127.0.0.1:port`); через него идут IP и задержка.
+    pub proxy: Option<String>,
+    pub ip_endpoints: Vec<String>,
+    /// Имя, которое резолвится для проверки DNS. Через туннель сравнивается ответ
+    /// whoami-имени — адрес резолвера, — поэтому по умолчанию это оно.
+    pub dns_probe: String,
+    pub direct_dns: SocketAddr,
+    /// DNS-вход туннеля, если DNS должен идти через VPN.
+    pub tunnel_dns: Option<SocketAddr>,
+    /// Локальный SOCKS5 VPN core; `None` — VPN не настроен.
+    pub vpn_core: Option<String>,
+    /// Предел для каждой проверки.
+    pub timeout: Duration,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            ip_endpoints: DEFAULT_IP_ENDPOINTS.iter().map(|s| s.to_string()).collect(),
+            dns_probe: plus_vpn::DNS_WHOAMI_PROBE.into(),
+            direct_dns: ([77, 88, 8, 8], 53).into(),
+            tunnel_dns: None,
+            vpn_core: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CheckKind {
+    Proxy,
+    VpnCore,
+    Dns,
+    EgressIp,
+}
+
+impl CheckKind {
+    pub const ALL: [CheckKind; 4] = [Self::Proxy, Self::VpnCore, Self::Dns, Self::EgressIp];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Proxy => "Прокси",
+            Self::VpnCore => "VPN core",
+            Self::Dns => "DNS",
+            Self::EgressIp => "Внешний IP",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// Работает, но не так, как настроено (например, DNS мимо туннеля).
+    Warning,
+    Failed,
+    /// Проверять нечего: прокси или VPN выключены.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub kind: CheckKind,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub latency: Option<Duration>,
+}
+
+impl CheckResult {
+    fn new(kind: CheckKind, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            status,
+            detail: detail.into(),
+            latency: None,
+        }
+    }
+}
+
+/// Внешний IP и сервис, который его сообщил.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EgressIp {
+    pub ip: IpAddr,
+    pub endpoint: String,
+}
+
+/// Запускает все проверки параллельно на `runtime`. Каждая присылает один результат,
+/// когда закончится; канал закрывается после последней — UI опрашивает его `try_recv`.
+pub fn run_diagnostics(config: DiagnosticsConfig, runtime: &Handle) -> mpsc::Receiver<CheckResult> {
+    let (tx, rx) = mpsc::channel();
+    for kind in CheckKind::ALL {
+        let tx = tx.clone();
+        let config = config.clone();
+        runtime.spawn(async move {
+            let _ = tx.send(run_check(kind, &config).await);
+        });
+    }
+    rx
+}
+
+pub async fn run_check(kind: CheckKind, config: &DiagnosticsConfig) -> CheckResult {
+    let started = Instant::now();
+    let result = match kind {
+        CheckKind::Proxy => limit(config.timeout, check_proxy(config)).await,
+        CheckKind::VpnCore => limit(config.timeout, check_vpn_core(config)).await,
+        CheckKind::Dns => limit(config.timeout, check_dns(config)).await,
+        CheckKind::EgressIp => limit(config.timeout, check_egress_ip(config)).await,
+    };
+    match result {
+        Ok(mut result) => {
+            if result.status != CheckStatus::Skipped {
+                result.latency.get_or_insert(started.elapsed());
+            }
+            result
+        }
+        Err(e) => CheckResult {
+            latency: Some(started.elapsed()),
+            ..CheckResult::new(kind, CheckStatus::Failed, format!("{e:#}"))
+        },
+    }
+}
+
+async fn limit<T>(timeout: Duration, check: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(timeout, check)
+        .await
+        .map_err(|_| anyhow!("timed out after {}s", timeout.as_secs_f32()))?
+}
+
+/// Прокси браузера принимает соединения.
+async fn check_proxy(config: &DiagnosticsConfig) -> Result<CheckResult> {
+    let Some(proxy) = &config.proxy else {
+        return Ok(CheckResult::new(
+            CheckKind::Proxy,
+            CheckStatus::Skipped,
+            "прокси выключен",
+        ));
+    };
+    let url = Url::parse(proxy)?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("proxy without host: {proxy}"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("proxy without port: {proxy}"))?;
+    TcpStream::connect((host, port)).await?;
+    Ok(CheckResult::new(
+        CheckKind::Proxy,
+        CheckStatus::Ok,
+        proxy.clone(),
+    ))
+}
+
+/// Core готов, если его SOCKS5 отвечает на приветствие; задержка — до ответа.
+async fn check_vpn_core(config: &DiagnosticsConfig) -> Result<CheckResult> {
+    let Some(addr) = &config.vpn_core else {
+        return Ok(CheckResult::new(
+            CheckKind::VpnCore,
+            CheckStatus::Skipped,
+            "VPN не настроен",
+        ));
+    };
+    let mut stream = TcpStream::connect(addr.as_str())
+        .await
+        .map_err(|e| anyhow!("core is not listening on {addr}: {e}"))?;
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [0x05, 0x00] {
+        bail!("unexpected SOCKS5 reply {reply:02x?} from {addr}");
+    }
+    Ok(CheckResult::new(
+        CheckKind::VpnCore,
+        CheckStatus::Ok,
+        format!("готов на {addr}"),
+    ))
+}
+
+/// Каким путём резолвятся имена: через DNS-вход туннеля или напрямую.
+async fn check_dns(config: &DiagnosticsConfig) -> Result<CheckResult> {
+    let probe = config.dns_probe.as_str();
+    if let Some(tunnel) = config.tunnel_dns {
+        let report = plus_vpn::dns_leak_check(probe, config.direct_dns, tunnel).await?;
+        let (status, path) = if report.leak_suspected {
+            (CheckStatus::Warning, "возможна утечка мимо туннеля")
+        } else if report.fakeip {
+            (CheckStatus::Ok, "через туннель (FakeIP)")
+        } else {
+            (CheckStatus::Ok, "через туннель")
+        };
+        return Ok(CheckResult::new(
+            CheckKind::Dns,
+            status,
+            format!(
+                "{path}: {probe} → {:?} (напрямую {:?})",
+                report.tunnel, report.direct
+            ),
+        ));
+    }
+    let answers = plus_vpn::resolve_a(probe, config.direct_dns).await?;
+    let (status, path) = if config.vpn_core.is_some() {
+        (CheckStatus::Warning, "мимо туннеля")
+    } else {
+        (CheckStatus::Ok, "напрямую")
+    };
+    Ok(CheckResult::new(
+        CheckKind::Dns,
+        status,
+        format!("{path} через {}: {probe} → {answers:?}", config.direct_dns),
+    ))
+}
+
+async fn check_egress_ip(config: &DiagnosticsConfig) -> Result<CheckResult> {
+    let client = NetClient::new(config.proxy.clone())?;
+    let egress = client.egress_ip(&config.ip_endpoints).await?;
+    Ok(CheckResult::new(
+        CheckKind::EgressIp,
+        CheckStatus::Ok,
+        format!("{} ({})", egress.ip, egress.endpoint),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, UdpSocket};
+
+    /// HTTP-сервер, на любой запрос отвечающий `status` и `body`.
+    async fn spawn_http(status: &'static str, body: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 2048];
+                let _ = stream.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        addr
+    }
+
+    /// SOCKS5, который только принимает приветствие без аутентификации.
+    async fn spawn_socks() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut greeting = [0u8; 3];
+                if stream.read_exact(&mut greeting).await.is_ok() {
+                    let _ = stream.write_all(&[0x05, 0x00]).await;
+                }
+            }
+        });
+        addr
+    }
+
+    async fn spawn_dns_stub(answer: [u8; 4]) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+                let mut resp = buf[..n].to_vec();
+                resp[2] = 0x81;
+                resp[3] = 0x80;
+                resp[7] = 1;
+                resp.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                resp.extend_from_slice(&answer);
+                let _ = socket.send_to(&resp, peer).await;
+            }
+        });
+        addr
+    }
+
+    /// Порт, на котором гарантированно никто не слушает.
+    async fn closed_port() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    fn test_config() -> DiagnosticsConfig {
+        DiagnosticsConfig {
+            timeout: Duration::from_secs(5),
+            ..DiagnosticsConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn egress_ip_falls_back_to_next_endpoint() {
+        let broken = spawn_http("500 Internal Server Error", "oops").await;
+        let garbage = spawn_http("200 OK", "<html>not an ip</html>").await;
+        let good = spawn_http("200 OK", "203.0.113.7\n").await;
+        let config = DiagnosticsConfig {
+            ip_endpoints: vec![
+                format!("http://{broken}/"),
+                format!("http://{garbage}/"),
+                format!("http://{good}/ip"),
+            ],
+            ..test_config()
+        };
+        let result = run_check(CheckKind::EgressIp, &config).await;
+        assert_eq!(result.status, CheckStatus::Ok, "{}", result.detail);
+        assert_eq!(result.detail, format!("203.0.113.7 (http://{good}/ip)"));
+        assert!(result.latency.is_some());
+
+        let config = DiagnosticsConfig {
+            ip_endpoints: vec![format!("http://{broken}/")],
+            ..config
+        };
+        let failed = run_check(CheckKind::EgressIp, &config).await;
+        assert_eq!(failed.status, CheckStatus::Failed);
+        assert!(failed.detail.contains("500"), "{}", failed.detail);
+    }
+
+    #[tokio::test]
+    async fn egress_ip_goes_through_proxy() {
+        // Сервер играет роль HTTP-прокси: сам endpoint недоступен.
+        let proxy = spawn_http("200 OK", "198.51.100.1").await;
+        let config = DiagnosticsConfig {
+            proxy: Some(format!("http://{proxy}")),
+            ip_endpoints: vec!["http://ip.invalid/".into()],
+            ..test_config()
+        };
+        let result = run_check(CheckKind::EgressIp, &config).await;
+        assert_eq!(result.detail, "198.51.100.1 (http://ip.invalid/)");
+        assert_eq!(
+            run_check(CheckKind::Proxy, &config).await.status,
+            CheckStatus::Ok
+        );
+        let down = DiagnosticsConfig {
+            proxy: Some(format!("http://{}", closed_port().await)),
+            ..config
+        };
+        assert_eq!(
+            run_check(CheckKind::Proxy, &down).await.status,
+            CheckStatus::Failed
+        );
+        assert_eq!(
+            run_check(CheckKind::Proxy, &DiagnosticsConfig::default())
+                .await
+                .status,
+            CheckStatus::Skipped
+        );
+    }
+
+    #[tokio::test]
+    async fn vpn_core_readiness() {
+        let config = DiagnosticsConfig {
+            vpn_core: Some(spawn_socks().await.to_string()),
+            ..test_config()
+        };
+        let ready = run_check(CheckKind::VpnCore, &config).await;
+        assert_eq!(ready.status, CheckStatus::Ok, "{}", ready.detail);
+        assert!(ready.latency.is_some());
+
+        // Что-то слушает порт, но это не SOCKS5.
+        let http = spawn_http("200 OK", "hello").await;
+        let wrong = DiagnosticsConfig {
+            vpn_core: Some(http.to_string()),
+            ..test_config()
+        };
+        assert_eq!(
+            run_check(CheckKind::VpnCore, &wrong).await.status,
+            CheckStatus::Failed
+        );
+        let down = DiagnosticsConfig {
+            vpn_core: Some(closed_port().await.to_string()),
+            ..test_config()
+        };
+        assert_eq!(
+            run_check(CheckKind::VpnCore, &down).await.status,
+            CheckStatus::Failed
+        );
+    }
+
+    #[tokio::test]
+    async fn dns_path() {
+        let direct = spawn_dns_stub([10, 0, 0, 1]).await;
+        let tunnel = spawn_dns_stub([10, 0, 0, 2]).await;
+        let base = DiagnosticsConfig {
+            dns_probe: "probe.plus.test".into(),
+            direct_dns: direct,
+            ..test_config()
+        };
+        let plain = run_check(CheckKind::Dns, &base).await;
+        assert_eq!(plain.status, CheckStatus::Ok, "{}", plain.detail);
+        assert!(plain.detail.starts_with("напрямую"));
+
+        let bypass = DiagnosticsConfig {
+            vpn_core: Some("127.0.0.1:2080".into()),
+            ..base.clone()
+        };
+        assert_eq!(
+            run_check(CheckKind::Dns, &bypass).await.status,
+            CheckStatus::Warning
+        );
+
+        let tunneled = DiagnosticsConfig {
+            tunnel_dns: Some(tunnel),
+            ..bypass.clone()
+        };
+        let result = run_check(CheckKind::Dns, &tunneled).await;
+        assert_eq!(result.status, CheckStatus::Ok, "{}", result.detail);
+        assert!(result.detail.contains("10.0.0.2"));
+
+        let leaking = DiagnosticsConfig {
+            tunnel_dns: Some(direct),
+            ..bypass
+        };
+        assert_eq!(
+            run_check(CheckKind::Dns, &leaking).await.status,
+            CheckStatus::Warning
+        );
+    }
+
+    #[test]
+    fn delivers_every_result_without_blocking() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let good = runtime.block_on(spawn_http("200 OK", "192.0.2.5"));
+        let config = DiagnosticsConfig {
+            ip_endpoints: vec![format!("http://{good}/")],
+            direct_dns: runtime.block_on(spawn_dns_stub([10, 0, 0, 1])),
+            dns_probe: "probe.plus.test".into(),
+            ..test_config()
+        };
+        let rx = run_diagnostics(config, runtime.handle());
+        let mut kinds: Vec<CheckKind> = rx.iter().map(|r| r.kind).collect();
+        kinds.sort();
+        assert_eq!(kinds, CheckKind::ALL);
+    }
+}
@@ -0,0 +1,456 @@
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use plus_privacy::{migrate, Migration, Profile, ProfileDb};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use url::Url;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Схема истории по версиям (`PRAGMA user_version`).
+/// v1 — одна таблица `history` с RFC 3339 строками, как в ранних профилях.
+/// v2 — `urls` + `visits` с индексами и временем в миллисекундах.
+const MIGRATIONS: &[Migration] = &[
+    Migration::sql(
+        "CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL,
+        title TEXT NOT NULL,
+        visited_at TEXT NOT NULL
+    );",
+    ),
+    Migration::sql(
+        "CREATE TABLE urls (
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL UNIQUE,
+        title TEXT NOT NULL DEFAULT '',
+        host TEXT NOT NULL DEFAULT '',
+        visit_count INTEGER NOT NULL DEFAULT 0,
+        last_visit INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE visits (
+        id INTEGER PRIMARY KEY,
+        url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+        visited_at INTEGER NOT NULL
+    );
+    CREATE INDEX urls_host ON urls(host);
+    CREATE INDEX urls_last_visit ON urls(last_visit);
+    CREATE INDEX visits_url ON visits(url_id);
+    CREATE INDEX visits_time ON visits(visited_at);
+    INSERT INTO urls(url, title, host, visit_count, last_visit)
+        SELECT url,
+               (SELECT h2.title FROM history h2 WHERE h2.url = h.url ORDER BY h2.id DESC LIMIT 1),
+               '',
+               COUNT(*),
+               MAX(CAST(strftime('%s', visited_at) AS INTEGER) * 1000)
+        FROM history h GROUP BY url;
+    INSERT INTO visits(url_id, visited_at)
+        SELECT u.id, CAST(strftime('%s', h.visited_at) AS INTEGER) * 1000
+        FROM history h JOIN urls u ON u.url = h.url;
+    DROP TABLE history;",
+    )
+    .with_fixup(backfill_hosts),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub url: String,
+    pub title: String,
+    pub visit_count: u32,
+    pub last_visit: DateTime<Utc>,
+    pub frecency: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryVisit {
+    pub url: String,
+    pub title: String,
+    pub visited_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryDay {
+    pub date: NaiveDate,
+    pub visits: Vec<HistoryVisit>,
+}
+
+pub struct HistoryStore {
+    conn: ProfileDb,
+}
+
+/// Частота × свежесть по визитам, веса как у Places в Firefox:
+/// ≤4 дней — 100, ≤14 — 70, ≤31 — 50, ≤90 — 30, старше — 10.
+const FRECENCY_SQL: &str = "(SELECT COALESCE(SUM(CASE
+        WHEN ?1 - v.visited_at <= 4 * 86400000 THEN 100
+        WHEN ?1 - v.visited_at <= 14 * 86400000 THEN 70
+        WHEN ?1 - v.visited_at <= 31 * 86400000 THEN 50
+        WHEN ?1 - v.visited_at <= 90 * 86400000 THEN 30
+        ELSE 10 END), 0) FROM visits v WHERE v.url_id = urls.id)";
+
+impl HistoryStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Инкогнито держит базу только в памяти, зашифрованный профиль — расшифрованной в памяти.
+    pub fn for_profile(profile: &Profile) -> Result<Self> {
+        Self::from_connection(profile.open_database(&profile.history_db())?)
+    }
+
+    fn from_connection(conn: impl Into<ProfileDb>) -> Result<Self> {
+        let mut conn = conn.into();
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        register_fold(&conn)?;
+        migrate(&mut conn, MIGRATIONS)?;
+        Ok(Self { conn })
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.conn.flush()?)
+    }
+
+    /// Меняется после каждой записи в базу, в том числе из другого соединения.
+    pub fn revision(&self) -> Result<(u64, i64)> {
+        Ok(self.conn.revision()?)
+    }
+
+    pub fn add_visit(&self, url: &str, title: &str) -> Result<()> {
+        let now = Utc::now().timestamp_millis();
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO urls(url, title, host, visit_count, last_visit) VALUES(?1, ?2, ?3, 1, ?4)
+             ON CONFLICT(url) DO UPDATE SET
+                title = CASE WHEN excluded.title = '' THEN urls.title ELSE excluded.title END,
+                visit_count = urls.visit_count + 1,
+                last_visit = excluded.last_visit",
+            params![url, title, host_of(url), now],
+        )?;
+        tx.execute(
+            "INSERT INTO visits(url_id, visited_at) SELECT id, ?2 FROM urls WHERE url = ?1",
+            params![url, now],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Добавляет визиты с исходным временем; уже известные пары (адрес, время) пропускаются.
+    pub fn import_visits(&self, visits: &[HistoryVisit]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut added = 0;
+        for visit in visits {
+            tx.execute(
+                "INSERT INTO urls(url, title, host) VALUES(?1, ?2, ?3)
+                 ON CONFLICT(url) DO UPDATE SET
+                    title = CASE WHEN urls.title = '' THEN excluded.title ELSE urls.title END",
+                params![visit.url, visit.title, host_of(&visit.url)],
+            )?;
+            added += tx.execute(
+                "INSERT INTO visits(url_id, visited_at)
+                 SELECT u.id, ?2 FROM urls u WHERE u.url = ?1 AND NOT EXISTS
+                    (SELECT 1 FROM visits v WHERE v.url_id = u.id AND v.visited_at = ?2)",
+                params![visit.url, visit.visited_at.timestamp_millis()],
+            )?;
+        }
+        self.recount()?;
+        tx.commit()?;
+        Ok(added)
+    }
+
+    pub fn set_title(&self, url: &str, title: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE urls SET title = ?2 WHERE url = ?1",
+            params![url, title],
+        )?;
+        Ok(())
+    }
+
+    /// Поиск по подстроке в URL и заголовке, самые «частосвежие» первыми.
+    /// Пустой запрос возвращает топ по frecency — для подсказок омнибокса.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let pattern = like_pattern(query);
+        let sql = format!(
+            "SELECT url, title, visit_count, last_visit, {FRECENCY_SQL} AS frecency
+             FROM urls
+             WHERE fold(url) LIKE ?2 ESCAPE '\\' OR fold(title) LIKE ?2 ESCAPE '\\'
+             ORDER BY frecency DESC, last_visit DESC
+             LIMIT ?3"
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            params![Utc::now().timestamp_millis(), pattern, limit as i64],
+            entry_from_row,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn entry(&self, url: &str) -> Result<Option<HistoryEntry>> {
+        let sql = format!(
+            "SELECT url, title, visit_count, last_visit, {FRECENCY_SQL} AS frecency
+             FROM urls WHERE url = ?2"
+        );
+        Ok(self
+            .conn
+            .query_row(
+                &sql,
+                params![Utc::now().timestamp_millis(), url],
+                entry_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Визиты за последние `days` дней, сгруппированные по локальной дате, новые первыми.
+    pub fn visits_by_day(&self, days: u32) -> Result<Vec<HistoryDay>> {
+        let since = Utc::now().timestamp_millis() - i64::from(days) * DAY_MS;
+        let mut stmt = self.conn.prepare(
+            "SELECT u.url, u.title, v.visited_at FROM visits v JOIN urls u ON u.id = v.url_id
+             WHERE v.visited_at >= ?1 ORDER BY v.visited_at DESC, v.id DESC",
+        )?;
+        let rows = stmt.query_map(params![since], |r| {
+            Ok(HistoryVisit {
+                url: r.get(0)?,
+                title: r.get(1)?,
+                visited_at: millis_to_utc(r.get(2)?),
+            })
+        })?;
+        let mut out: Vec<HistoryDay> = Vec::new();
+        for visit in rows {
+            let visit = visit?;
+            let date = visit.visited_at.with_timezone(&Local).date_naive();
+            match out.last_mut() {
+                Some(day) if day.date == date => day.visits.push(visit),
+                _ => out.push(HistoryDay {
+                    date,
+                    visits: vec![visit],
+                }),
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn delete_url(&self, url: &str) -> Result<usize> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM urls WHERE url = ?1", params![url])?)
+    }
+
+    /// Удаляет визиты в `[from, to)`; URL без оставшихся визитов удаляются целиком.
+    pub fn delete_range(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<usize> {
+        let removed = self.conn.execute(
+            "DELETE FROM visits WHERE visited_at >= ?1 AND visited_at < ?2",
+            params![from.timestamp_millis(), to.timestamp_millis()],
+        )?;
+        self.recount()?;
+        Ok(removed)
+    }
+
+    /// Удаляет домен вместе с поддоменами.
+    pub fn delete_domain(&self, host: &str) -> Result<usize> {
+        let host = host.trim().trim_start_matches('.').to_ascii_lowercase();
+        Ok(self.conn.execute(
+            "DELETE FROM urls WHERE host = ?1 OR host LIKE ?2 ESCAPE '\\'",
+            params![host, format!("%.{}", escape_like(&host))],
+        )?)
+    }
+
+    fn recount(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "DELETE FROM urls WHERE NOT EXISTS (SELECT 1 FROM visits v WHERE v.url_id = urls.id);
+             UPDATE urls SET
+                visit_count = (SELECT COUNT(*) FROM visits v WHERE v.url_id = urls.id),
+                last_visit = (SELECT MAX(v.visited_at) FROM visits v WHERE v.url_id = urls.id);",
+        )?;
+        Ok(())
+    }
+}
+
+fn backfill_hosts(conn: &Connection) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("SELECT id, url FROM urls WHERE host = ''")?;
+    let rows = stmt
+        .query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (id, url) in rows {
+        conn.execute(
+            "UPDATE urls SET host = ?2 WHERE id = ?1",
+            params![id, host_of(&url)],
+        )?;
+    }
+    Ok(())
+}
+
+fn entry_from_row(r: &Row<'_>) -> rusqlite::Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        url: r.get(0)?,
+        title: r.get(1)?,
+        visit_count: r.get(2)?,
+        last_visit: millis_to_utc(r.get(3)?),
+        frecency: r.get(4)?,
+    })
+}
+
+fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_ascii_lowercase()))
+        .unwrap_or_default()
+}
+
+fn millis_to_utc(ms: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(ms).single().unwrap_or_default()
+}
+
+/// `fold(text)` — строчные буквы по правилам Unicode. Встроенные `lower()` и
+/// `LIKE` в SQLite складывают регистр только у ASCII, и «ян» не нашло бы «Яндекс».
+pub(crate) fn register_fold(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "fold",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            Ok(ctx
+                .get::<Option<String>>(0)?
+                .map(|text| text.to_lowercase()))
+        },
+    )
+}
+
+/// Шаблон подстроки для `fold(column) LIKE ?1 ESCAPE '\'`.
+pub(crate) fn like_pattern(query: &str) -> String {
+    format!("%{}%", escape_like(&query.trim().to_lowercase()))
+}
+
+fn escape_like(raw: &str) -> String {
+    raw.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> HistoryStore {
+        HistoryStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn counts_visits_and_searches_by_frecency() {
+        let h = store();
+        h.add_visit("https://yandex.ru/", "Яндекс").unwrap();
+        h.add_visit("https://yandex.ru/", "").unwrap();
+        h.add_visit("https://example.org/yandex-faq", "FAQ")
+            .unwrap();
+        h.add_visit("https://rust-lang.org/", "Rust").unwrap();
+
+        let hits = h.search("yandex", 10).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].url, "https://yandex.ru/");
+        assert_eq!(hits[0].title, "Яндекс");
+        assert_eq!(hits[0].visit_count, 2);
+        assert_eq!(hits[0].frecency, 200);
+        assert_eq!(h.search("Rust", 10).unwrap()[0].title, "Rust");
+        assert!(h.search("100%", 10).unwrap().is_empty());
+        assert_eq!(h.search("ян", 10).unwrap()[0].title, "Яндекс");
+        assert_eq!(h.search("ЯНДЕКС", 10).unwrap()[0].title, "Яндекс");
+    }
+
+    #[test]
+    fn groups_by_day_and_deletes() {
+        let h = store();
+        h.add_visit("https://a.example.com/1", "A").unwrap();
+        h.add_visit("https://example.com/2", "B").unwrap();
+        h.add_visit("https://notexample.com/", "C").unwrap();
+        h.add_visit("https://other.org/", "D").unwrap();
+
+        let days = h.visits_by_day(1).unwrap();
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].visits.len(), 4);
+        assert_eq!(days[0].visits[0].url, "https://other.org/");
+
+        assert_eq!(h.delete_domain("example.com").unwrap(), 2);
+        assert!(h.entry("https://notexample.com/").unwrap().is_some());
+        assert_eq!(h.delete_url("https://other.org/").unwrap(), 1);
+
+        let now = Utc::now();
+        h.delete_range(
+            now - chrono::Duration::hours(1),
+            now + chrono::Duration::hours(1),
+        )
+        .unwrap();
+        assert!(h.search("", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrates_v1_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0].sql).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        conn.execute_batch(
+            "INSERT INTO history(url, title, visited_at) VALUES
+                ('https://old.example/', 'Old', '2024-03-01T10:00:00.123456+00:00'),
+                ('https://old.example/', 'Old 2', '2024-03-02T10:00:00+00:00');",
+        )
+        .unwrap();
+        let h = HistoryStore::from_connection(conn).unwrap();
+        let e = h.entry("https://old.example/").unwrap().unwrap();
+        assert_eq!(e.visit_count, 2);
+        assert_eq!(e.title, "Old 2");
+        assert_eq!(e.last_visit.to_rfc3339(), "2024-03-02T10:00:00+00:00");
+        assert_eq!(h.delete_domain("old.example").unwrap(), 1);
+    }
+
+    #[test]
+    fn revision_follows_writes_of_every_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        let h = HistoryStore::open(&path).unwrap();
+        let before = h.revision().unwrap();
+        h.search("a", 5).unwrap();
+        assert_eq!(h.revision().unwrap(), before);
+        h.add_visit("https://a.example/", "A").unwrap();
+        let own = h.revision().unwrap();
+        assert_ne!(own, before);
+
+        // Фоновый импорт пишет через своё соединение.
+        HistoryStore::open(&path)
+            .unwrap()
+            .add_visit("https://b.example/", "B")
+            .unwrap();
+        assert_ne!(h.revision().unwrap(), own);
+    }
+
+    #[test]
+    fn opens_fixture_of_every_version() {
+        let dir = tempfile::tempdir().unwrap();
+        // v0: файл из сборок до user_version, таблица создана напрямую.
+        let v0 = dir.path().join("v0.db");
+        Connection::open(&v0)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE history (id INTEGER PRIMARY KEY, url TEXT NOT NULL,
+                    title TEXT NOT NULL, visited_at TEXT NOT NULL);
+                 INSERT INTO history(url, title, visited_at)
+                    VALUES ('https://A.example/x', 'A', '2024-05-01T08:00:00+00:00');",
+            )
+            .unwrap();
+        let h = HistoryStore::open(&v0).unwrap();
+        assert_eq!(
+            h.entry("https://A.example/x").unwrap().unwrap().visit_count,
+            1
+        );
+        assert_eq!(h.delete_domain("a.example").unwrap(), 1);
+        assert!(dir.path().join("v0.db.v0.bak").exists());
+
+        // v2: текущая схема открывается без изменений и без резервной копии.
+        let v2 = dir.path().join("v2.db");
+        HistoryStore::open(&v2)
+            .unwrap()
+            .add_visit("https://b.example/", "B")
+            .unwrap();
+        let h = HistoryStore::open(&v2).unwrap();
+        assert_eq!(h.search("b.example", 5).unwrap().len(), 1);
+        assert!(!dir.path().join("v2.db.v2.bak").exists());
+        assert!(!dir.path().join("v2.db.v0.bak").exists());
+    }
+}
@@ -0,0 +1,633 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use plus_privacy::{DbKey, PrivacyStore};
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bookmark_formats::{from_chromium_micros, parse_chromium};
+use crate::bookmarks::{Bookmark, BookmarkStore, ImportedBookmarks};
+use crate::history::{HistoryStore, HistoryVisit};
+use crate::password_import::{read_chromium_logins, read_firefox_logins, ImportedLogins};
+
+/// Сколько визитов пишется в историю за одну транзакцию (и между отчётами о прогрессе).
+const VISIT_BATCH: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrowserKind {
+    Chromium,
+    Firefox,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStage {
+    History,
+    Bookmarks,
+    Passwords,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportProgress {
+    pub stage: ImportStage,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Итог импорта; `*_skipped` — записи, которые уже были в хранилищах.
+/// `passwords_locked` — пароли, ключ к которым у исходного браузера недоступен.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub history_added: usize,
+    pub history_skipped: usize,
+    pub bookmarks_added: usize,
+    pub bookmarks_skipped: usize,
+    pub passwords_added: usize,
+    pub passwords_skipped: usize,
+    pub passwords_locked: usize,
+}
+
+/// Куда переносятся пароли: хранилище профиля и ключ `Profile::credentials_key`.
+pub struct PasswordTarget<'a> {
+    pub store: &'a PrivacyStore,
+    pub key: &'a DbKey,
+}
+
+/// Определяет браузер по файлам в каталоге профиля.
+pub fn detect_browser(profile_dir: &Path) -> Option<BrowserKind> {
+    if profile_dir.join("places.sqlite").is_file() {
+        Some(BrowserKind::Firefox)
+    } else if ["History", "Bookmarks", "Login Data"]
+        .iter()
+        .any(|file| profile_dir.join(file).is_file())
+    {
+        Some(BrowserKind::Chromium)
+    } else {
+        None
+    }
+}
+
+/// Переносит историю, закладки и, если задан `passwords`, сохранённые пароли
+/// из каталога профиля Chromium или Firefox. Повторный импорт того же профиля
+/// ничего не дублирует.
+pub fn import_profile(
+    profile_dir: &Path,
+    history: &HistoryStore,
+    bookmarks: &BookmarkStore,
+    passwords: Option<PasswordTarget>,
+    mut progress: impl FnMut(ImportProgress),
+) -> Result<ImportReport> {
+    let Some(kind) = detect_browser(profile_dir) else {
+        bail!(
+            "{} is not a Chromium or Firefox profile directory",
+            profile_dir.display()
+        );
+    };
+    let (visits, imported) = match kind {
+        BrowserKind::Chromium => {
+            let history_file = profile_dir.join("History");
+            let visits = if history_file.is_file() {
+                with_snapshot(&history_file, read_chromium_history)?
+            } else {
+                Vec::new()
+            };
+            let bookmarks_file = profile_dir.join("Bookmarks");
+            let imported = if bookmarks_file.is_file() {
+                parse_chromium(&fs::read_to_string(bookmarks_file)?)?
+            } else {
+                ImportedBookmarks::default()
+            };
+            (visits, imported)
+        }
+        BrowserKind::Firefox => with_snapshot(&profile_dir.join("places.sqlite"), |conn| {
+            Ok((read_firefox_history(conn)?, read_firefox_bookmarks(conn)?))
+        })?,
+    };
+
+    let mut report = ImportReport::default();
+    let total = visits.len();
+    progress(ImportProgress {
+        stage: ImportStage::History,
+        done: 0,
+        total,
+    });
+    for (idx, batch) in visits.chunks(VISIT_BATCH).enumerate() {
+        report.history_added += history.import_visits(batch)?;
+        progress(ImportProgress {
+            stage: ImportStage::History,
+            done: (idx * VISIT_BATCH + batch.len()).min(total),
+            total,
+        });
+    }
+    report.history_skipped = total - report.history_added;
+
+    let total = count_links(&imported.bar) + count_links(&imported.other);
+    progress(ImportProgress {
+        stage: ImportStage::Bookmarks,
+        done: 0,
+        total,
+    });
+    report.bookmarks_added = bookmarks.import_parsed(&imported)?;
+    report.bookmarks_skipped = total - report.bookmarks_added;
+    progress(ImportProgress {
+        stage: ImportStage::Bookmarks,
+        done: total,
+        total,
+    });
+
+    if let Some(target) = passwords {
+        let imported = read_logins(kind, profile_dir)?;
+        let total = imported.logins.len();
+        progress(ImportProgress {
+            stage: ImportStage::Passwords,
+            done: 0,
+            total,
+        });
+        for login in &imported.logins {
+            if target.store.add_login(login, target.key)? {
+                report.passwords_added += 1;
+            }
+        }
+        report.passwords_skipped = total - report.passwords_added;
+        report.passwords_locked = imported.locked;
+        progress(ImportProgress {
+            stage: ImportStage::Passwords,
+            done: total,
+            total,
+        });
+    }
+    Ok(report)
+}
+
+/// Chromium: `Login Data`; Firefox: `logins.json` с ключами из `key4.db`.
+fn read_logins(kind: BrowserKind, profile_dir: &Path) -> Result<ImportedLogins> {
+    match kind {
+        BrowserKind::Chromium => {
+            let logins = profile_dir.join("Login Data");
+            if !logins.is_file() {
+                return Ok(ImportedLogins::default());
+            }
+            with_snapshot(&logins, |conn| read_chromium_logins(profile_dir, conn))
+        }
+        BrowserKind::Firefox => {
+            let (logins, keys) = (profile_dir.join("logins.json"), profile_dir.join("key4.db"));
+            if !logins.is_file() || !keys.is_file() {
+                return Ok(ImportedLogins::default());
+            }
+            let json = fs::read_to_string(logins)?;
+            with_snapshot(&keys, |conn| read_firefox_logins(&json, conn))
+        }
+    }
+}
+
+/// Запущенный браузер держит базу заблокированной, поэтому читаем копию
+/// (вместе с WAL, если он есть) из временного каталога.
+fn with_snapshot<T>(db: &Path, read: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+    let dir = std::env::temp_dir().join(format!(
+        "plus-import-{}-{}",
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    ));
+    fs::create_dir_all(&dir)?;
+    let result = (|| {
+        let name = db.file_name().unwrap_or_default();
+        let copy = dir.join(name);
+        fs::copy(db, &copy)?;
+        let wal = sidecar(db, "-wal");
+        if wal.is_file() {
+            fs::copy(&wal, sidecar(&copy, "-wal"))?;
+        }
+        let conn = Connection::open_with_flags(
+            &copy,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        read(&conn)
+    })();
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn sidecar(db: &Path, suffix: &str) -> PathBuf {
+    let mut name = db.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn read_chromium_history(conn: &Connection) -> Result<Vec<HistoryVisit>> {
+    let mut stmt = conn.prepare(
+        "SELECT u.url, u.title, v.visit_time FROM visits v JOIN urls u ON u.id = v.url
+         WHERE u.url LIKE 'http%' ORDER BY v.visit_time",
+    )?;
+    let rows = stmt.query_map([], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            r.get::<_, Option<String>>(1)?,
+            r.get::<_, i64>(2)?,
+        ))
+    })?;
+    let mut visits = Vec::new();
+    for row in rows {
+        let (url, title, at) = row?;
+        if let Some(visited_at) = from_chromium_micros(at) {
+            visits.push(HistoryVisit {
+                url,
+                title: title.unwrap_or_default(),
+                visited_at,
+            });
+        }
+    }
+    Ok(visits)
+}
+
+fn read_firefox_history(conn: &Connection) -> Result<Vec<HistoryVisit>> {
+    let mut stmt = conn.prepare(
+        "SELECT p.url, p.title, v.visit_date FROM moz_historyvisits v
+         JOIN moz_places p ON p.id = v.place_id
+         WHERE p.url LIKE 'http%' ORDER BY v.visit_date",
+    )?;
+    let rows = stmt.query_map([], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            r.get::<_, Option<String>>(1)?,
+            r.get::<_, i64>(2)?,
+        ))
+    })?;
+    let mut visits = Vec::new();
+    for row in rows {
+        let (url, title, at) = row?;
+        if let Some(visited_at) = DateTime::from_timestamp_micros(at) {
+            visits.push(HistoryVisit {
+                url,
+                title: title.unwrap_or_default(),
+                visited_at,
+            });
+        }
+    }
+    Ok(visits)
+}
+
+struct FirefoxRow {
+    id: i64,
+    parent: i64,
+    kind: i64,
+    title: String,
+    url: Option<String>,
+    added: i64,
+    modified: i64,
+}
+
+/// Панель — `toolbar_____`; меню, «Другие» и мобильные закладки идут в «Другие закладки».
+/// Теги в Firefox — папки внутри `tags________` со ссылками на те же `moz_places`.
+fn read_firefox_bookmarks(conn: &Connection) -> Result<ImportedBookmarks> {
+    let mut roots = HashMap::new();
+    let mut rows_by_parent: HashMap<i64, Vec<FirefoxRow>> = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT b.id, b.parent, b.type, COALESCE(b.title, ''), p.url,
+                COALESCE(b.dateAdded, 0), COALESCE(b.lastModified, 0), b.guid
+         FROM moz_bookmarks b LEFT JOIN moz_places p ON p.id = b.fk
+         ORDER BY b.parent, b.position",
+    )?;
+    let rows = stmt.query_map([], |r| {
+        Ok((
+            FirefoxRow {
+                id: r.get(0)?,
+                parent: r.get(1)?,
+                kind: r.get(2)?,
+                title: r.get(3)?,
+                url: r.get(4)?,
+                added: r.get(5)?,
+                modified: r.get(6)?,
+            },
+            r.get::<_, Option<String>>(7)?,
+        ))
+    })?;
+    for row in rows {
+        let (row, guid) = row?;
+        if let Some(guid) = guid {
+            roots.insert(guid, row.id);
+        }
+        rows_by_parent.entry(row.parent).or_default().push(row);
+    }
+
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    if let Some(tags_root) = roots.get("tags________") {
+        for tag_folder in rows_by_parent.get(tags_root).into_iter().flatten() {
+            for entry in rows_by_parent.get(&tag_folder.id).into_iter().flatten() {
+                if let Some(url) = &entry.url {
+                    tags.entry(url.clone())
+                        .or_default()
+                        .push(tag_folder.title.clone());
+                }
+            }
+        }
+    }
+
+    let children = |guid: &str| {
+        roots
+            .get(guid)
+            .map(|id| firefox_children(&rows_by_parent, &tags, *id))
+            .unwrap_or_default()
+    };
+    let mut other = children("menu________");
+    other.extend(children("unfiled_____"));
+    other.extend(children("mobile______"));
+    Ok(ImportedBookmarks {
+        bar: children("toolbar_____"),
+        other,
+    })
+}
+
+fn firefox_children(
+    rows: &HashMap<i64, Vec<FirefoxRow>>,
+    tags: &HashMap<String, Vec<String>>,
+    parent: i64,
+) -> Vec<Bookmark> {
+    let mut out = Vec::new();
+    for row in rows.get(&parent).into_iter().flatten() {
+        let mut node = match (row.kind, &row.url) {
+            (1, Some(url)) if url.starts_with("http") => {
+                let mut link = Bookmark::link(row.title.clone(), url.clone());
+                link.tags = tags.get(url).cloned().unwrap_or_default();
+                link
+            }
+            (2, _) => Bookmark::folder(row.title.clone(), firefox_children(rows, tags, row.id)),
+            // Разделители и `place:`-запросы не переносим.
+            _ => continue,
+        };
+        if let Some(added) = DateTime::from_timestamp_micros(row.added).filter(|_| row.added > 0) {
+            node.created_at = added;
+            node.modified_at = DateTime::from_timestamp_micros(row.modified)
+                .filter(|_| row.modified > 0)
+                .unwrap_or(added);
+        }
+        out.push(node);
+    }
+    out
+}
+
+fn count_links(nodes: &[Bookmark]) -> usize {
+    nodes
+        .iter()
+        .map(|n| {
+            if n.is_folder() {
+                count_links(&n.children)
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookmarks::{BOOKMARK_BAR, OTHER_BOOKMARKS};
+
+    const CHROMIUM_BOOKMARKS: &str = r#"{
+        "roots": {
+            "bookmark_bar": {"children": [
+                {"date_added": "13340000000000000", "id": "4", "name": "Rust", "type": "url", "url": "https://rust-lang.org/"},
+                {"children": [
+                    {"id": "6", "name": "Docs", "type": "url", "url": "https://docs.rs/"}
+                ], "id": "5", "name": "Dev", "type": "folder"}
+            ], "id": "1", "name": "Bookmarks bar", "type": "folder"},
+            "other": {"children": [
+                {"id": "7", "name": "Mail", "type": "url", "url": "https://mail.yandex.ru/"}
+            ], "id": "2", "name": "Other bookmarks", "type": "folder"},
+            "synced": {"children": [], "id": "3", "name": "Mobile bookmarks", "type": "folder"}
+        },
+        "version": 1
+    }"#;
+
+    fn stores(dir: &Path) -> (HistoryStore, BookmarkStore) {
+        (
+            HistoryStore::open(dir.join("history.db")).unwrap(),
+            BookmarkStore::open(dir.join("bookmarks.db")).unwrap(),
+        )
+    }
+
+    #[test]
+    fn imports_chromium_profile_once() {
+        let profile = tempfile::tempdir().unwrap();
+        let conn = Connection::open(profile.path().join("History")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE urls(id INTEGER PRIMARY KEY, url LONGVARCHAR, title LONGVARCHAR,
+                visit_count INTEGER DEFAULT 0 NOT NULL, typed_count INTEGER DEFAULT 0 NOT NULL,
+                last_visit_time INTEGER NOT NULL, hidden INTEGER DEFAULT 0 NOT NULL);
+             CREATE TABLE visits(id INTEGER PRIMARY KEY, url INTEGER NOT NULL,
+                visit_time INTEGER NOT NULL, from_visit INTEGER, transition INTEGER DEFAULT 0 NOT NULL);
+             INSERT INTO urls VALUES (1, 'https://ya.ru/', 'Яндекс', 2, 0, 13340000000000000, 0);
+             INSERT INTO urls VALUES (2, 'chrome://settings/', 'Settings', 1, 0, 13340000000000000, 0);
+             INSERT INTO visits VALUES (1, 1, 13339000000000000, 0, 0);
+             INSERT INTO visits VALUES (2, 1, 13340000000000000, 0, 0);
+             INSERT INTO visits VALUES (3, 2, 13340000000000000, 0, 0);",
+        )
+        .unwrap();
+        drop(conn);
+        fs::write(profile.path().join("Bookmarks"), CHROMIUM_BOOKMARKS).unwrap();
+        assert_eq!(detect_browser(profile.path()), Some(BrowserKind::Chromium));
+
+        let data = tempfile::tempdir().unwrap();
+        let (history, bookmarks) = stores(data.path());
+        bookmarks
+            .add_bookmark(OTHER_BOOKMARKS, "Почта", "https://mail.yandex.ru/", &[])
+            .unwrap();
+        let mut events = Vec::new();
+        let report = import_profile(profile.path(), &history, &bookmarks, None, |p| {
+            events.push(p)
+        })
+        .unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                history_added: 2,
+                history_skipped: 0,
+                bookmarks_added: 2,
+                bookmarks_skipped: 1,
+                ..ImportReport::default()
+            }
+        );
+        assert_eq!(
+            events.last(),
+            Some(&ImportProgress {
+                stage: ImportStage::Bookmarks,
+                done: 3,
+                total: 3
+            })
+        );
+        assert!(events.contains(&ImportProgress {
+            stage: ImportStage::History,
+            done: 2,
+            total: 2
+        }));
+        let entry = history.entry("https://ya.ru/").unwrap().unwrap();
+        assert_eq!(entry.visit_count, 2);
+        assert_eq!(entry.title, "Яндекс");
+        assert_eq!(
+            entry.last_visit.timestamp(),
+            13_340_000_000 - 11_644_473_600
+        );
+        assert_eq!(bookmarks.children(BOOKMARK_BAR).unwrap()[1].title, "Dev");
+
+        let again = import_profile(profile.path(), &history, &bookmarks, None, |_| {}).unwrap();
+        assert_eq!(again.history_added, 0);
+        assert_eq!(again.history_skipped, 2);
+        assert_eq!(again.bookmarks_added, 0);
+        assert_eq!(bookmarks.children(BOOKMARK_BAR).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn imports_firefox_places() {
+        let profile = tempfile::tempdir().unwrap();
+        let conn = Connection::open(profile.path().join("places.sqlite")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE moz_places(id INTEGER PRIMARY KEY, url LONGVARCHAR, title LONGVARCHAR,
+                rev_host LONGVARCHAR, visit_count INTEGER DEFAULT 0, hidden INTEGER DEFAULT 0 NOT NULL,
+                typed INTEGER DEFAULT 0 NOT NULL, frecency INTEGER DEFAULT -1 NOT NULL,
+                last_visit_date INTEGER, guid TEXT);
+             CREATE TABLE moz_historyvisits(id INTEGER PRIMARY KEY, from_visit INTEGER,
+                place_id INTEGER, visit_date INTEGER, visit_type INTEGER, session INTEGER);
+             CREATE TABLE moz_bookmarks(id INTEGER PRIMARY KEY, type INTEGER, fk INTEGER DEFAULT NULL,
+                parent INTEGER, position INTEGER, title LONGVARCHAR, keyword_id INTEGER,
+                folder_type TEXT, dateAdded INTEGER, lastModified INTEGER, guid TEXT);
+             INSERT INTO moz_places(id, url, title) VALUES
+                (1, 'https://www.mozilla.org/', 'Mozilla'),
+                (2, 'https://rust-lang.org/', 'Rust'),
+                (3, 'place:sort=8&maxResults=10', 'Most visited');
+             INSERT INTO moz_historyvisits(place_id, visit_date) VALUES
+                (1, 1700000000000000), (1, 1700000100000000), (2, 1700000200000000);
+             INSERT INTO moz_bookmarks(id, type, fk, parent, position, title, dateAdded, lastModified, guid) VALUES
+                (1, 2, NULL, 0, 0, '', 0, 0, 'root________'),
+                (2, 2, NULL, 1, 0, 'menu', 0, 0, 'menu________'),
+                (3, 2, NULL, 1, 1, 'toolbar', 0, 0, 'toolbar_____'),
+                (4, 2, NULL, 1, 2, 'tags', 0, 0, 'tags________'),
+                (5, 2, NULL, 1, 3, 'unfiled', 0, 0, 'unfiled_____'),
+                (6, 1, 1, 3, 0, 'Mozilla', 1700000000000000, 1700000500000000, 'a'),
+                (7, 1, 3, 3, 1, 'Most visited', 0, 0, 'b'),
+                (8, 3, NULL, 2, 0, NULL, 0, 0, 'c'),
+                (9, 2, NULL, 2, 1, 'Dev', 0, 0, 'd'),
+                (10, 1, 2, 9, 0, 'Rust', 1700000000000000, 0, 'e'),
+                (11, 2, NULL, 4, 0, 'lang', 0, 0, 'f'),
+                (12, 1, 2, 11, 0, NULL, 0, 0, 'g');",
+        )
+        .unwrap();
+        drop(conn);
+        assert_eq!(detect_browser(profile.path()), Some(BrowserKind::Firefox));
+
+        let data = tempfile::tempdir().unwrap();
+        let (history, bookmarks) = stores(data.path());
+        let report = import_profile(profile.path(), &history, &bookmarks, None, |_| {}).unwrap();
+        assert_eq!(report.history_added, 3);
+        assert_eq!(report.bookmarks_added, 2);
+        assert_eq!(
+            history
+                .entry("https://www.mozilla.org/")
+                .unwrap()
+                .unwrap()
+                .visit_count,
+            2
+        );
+
+        let bar = bookmarks.children(BOOKMARK_BAR).unwrap();
+        assert_eq!(bar.len(), 1);
+        assert_eq!(bar[0].created_at.timestamp(), 1_700_000_000);
+        assert_eq!(bar[0].modified_at.timestamp(), 1_700_000_500);
+        let other = bookmarks.children(OTHER_BOOKMARKS).unwrap();
+        assert_eq!(other[0].title, "Dev");
+        assert_eq!(other[0].children[0].tags, vec!["lang"]);
+
+        let again = import_profile(profile.path(), &history, &bookmarks, None, |_| {}).unwrap();
+        assert_eq!(
+            again,
+            ImportReport {
+                history_added: 0,
+                history_skipped: 3,
+                bookmarks_added: 0,
+                bookmarks_skipped: 2,
+                ..ImportReport::default()
+            }
+        );
+    }
+
+    /// `v10` Chromium на Linux: AES-128-CBC с ключом от `peanuts`.
+    #[cfg(target_os = "linux")]
+    fn chromium_v10(plain: &str) -> Vec<u8> {
+        use cbc::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+        let mut key = [0u8; 16];
+        pbkdf2::pbkdf2_hmac::<sha1::Sha1>(b"peanuts", b"saltysalt", 1, &mut key);
+        let mut buf = vec![0u8; plain.len() + 16];
+        buf[..plain.len()].copy_from_slice(plain.as_bytes());
+        let len = cbc::Encryptor::<aes::Aes128>::new(&key.into(), &[b' '; 16].into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, plain.len())
+            .unwrap()
+            .len();
+        [b"v10".as_slice(), &buf[..len]].concat()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn imports_chromium_passwords_once() {
+        let profile = tempfile::tempdir().unwrap();
+        let conn = Connection::open(profile.path().join("Login Data")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE logins(origin_url VARCHAR NOT NULL, action_url VARCHAR,
+                username_value VARCHAR, password_value BLOB, date_created INTEGER NOT NULL,
+                blacklisted_by_user INTEGER NOT NULL);",
+        )
+        .unwrap();
+        for (url, user, password, never) in [
+            (
+                "https://accounts.example/signin?x=1",
+                "anna",
+                chromium_v10("s3cr3t"),
+                0,
+            ),
+            (
+                "https://bank.example/",
+                "bob",
+                b"v11locked-by-keyring".to_vec(),
+                0,
+            ),
+            ("https://never.example/", "", Vec::new(), 1),
+            ("android://hash@com.example/", "app", chromium_v10("x"), 0),
+        ] {
+            conn.execute(
+                "INSERT INTO logins VALUES (?1, '', ?2, ?3, 13340000000000000, ?4)",
+                rusqlite::params![url, user, password, never],
+            )
+            .unwrap();
+        }
+        drop(conn);
+        assert_eq!(detect_browser(profile.path()), Some(BrowserKind::Chromium));
+
+        let data = tempfile::tempdir().unwrap();
+        let (history, bookmarks) = stores(data.path());
+        let privacy = PrivacyStore::open(data.path().join("privacy.db")).unwrap();
+        let key = DbKey::random();
+        let target = || PasswordTarget {
+            store: &privacy,
+            key: &key,
+        };
+        let report =
+            import_profile(profile.path(), &history, &bookmarks, Some(target()), |_| {}).unwrap();
+        assert_eq!((report.passwords_added, report.passwords_locked), (1, 1));
+        let logins = privacy.logins(&key).unwrap();
+        assert_eq!(logins.len(), 1);
+        assert_eq!(logins[0].origin, "https://accounts.example");
+        assert_eq!(
+            (logins[0].username.as_str(), logins[0].password.as_str()),
+            ("anna", "s3cr3t")
+        );
+
+        let again =
+            import_profile(profile.path(), &history, &bookmarks, Some(target()), |_| {}).unwrap();
+        assert_eq!((again.passwords_added, again.passwords_skipped), (0, 1));
+    }
+
+    #[test]
+    fn rejects_unknown_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let (history, bookmarks) = stores(dir.path());
+        assert!(import_profile(dir.path(), &history, &bookmarks, None, |_| {}).is_err());
+    }
+}
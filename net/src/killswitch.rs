@@ -0,0 +1,91 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// Общий для прокси и UI флаг kill switch.
+///
+/// Включён и VPN ожидается, но upstream SOCKS не задан или нездоров — прокси отвечает
+/// страницей ошибки на любой нелокальный запрос вместо прямого соединения.
+#[derive(Clone, Default)]
+pub struct KillSwitch {
+    inner: Arc<KillSwitchState>,
+}
+
+#[derive(Default)]
+struct KillSwitchState {
+    enabled: AtomicBool,
+    vpn_expected: AtomicBool,
+    vpn_healthy: AtomicBool,
+}
+
+impl KillSwitch {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.inner.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn set_vpn_expected(&self, expected: bool) {
+        self.inner.vpn_expected.store(expected, Ordering::SeqCst);
+    }
+
+    pub fn set_vpn_healthy(&self, healthy: bool) {
+        self.inner.vpn_healthy.store(healthy, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn vpn_healthy(&self) -> bool {
+        self.inner.vpn_healthy.load(Ordering::SeqCst)
+    }
+
+    pub fn is_engaged(&self, has_upstream: bool) -> bool {
+        self.is_enabled()
+            && self.inner.vpn_expected.load(Ordering::SeqCst)
+            && (!has_upstream || !self.vpn_healthy())
+    }
+
+    /// Периодически проверяет, что SOCKS-вход VPN-core принимает соединения.
+    pub fn spawn_monitor(&self, socks_addr: String, interval: Duration) -> JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let ok = tokio::time::timeout(interval, TcpStream::connect(&socks_addr))
+                    .await
+                    .map(|r| r.is_ok())
+                    .unwrap_or(false);
+                this.set_vpn_healthy(ok);
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+pub(crate) fn is_local_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.eq_ignore_ascii_case("localhost") || host.ends_with(".localhost") {
+        return true;
+    }
+    host.parse::<IpAddr>()
+        .map(|ip| ip.is_loopback())
+        .unwrap_or(false)
+}
+
+pub(crate) fn blocked_response() -> Vec<u8> {
+    let body =
+        "<!doctype html><html><head><meta charset='utf-8'><title>VPN недоступен</title></head>\
+        <body style='font-family:Inter,Arial;background:#0f172a;color:#e2e8f0;padding:32px'>\
+        <h1>VPN недоступен</h1>\
+        <p>Включён kill switch: пока VPN не восстановится, запросы в интернет блокируются, \
+        чтобы трафик не ушёл напрямую.</p></body></html>";
+    format!(
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\nX-Plus-Kill-Switch: 1\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
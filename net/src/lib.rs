@@ -1,15 +1,36 @@
-use anyhow::Result;
-use chrono::Utc;
+use anyhow::{anyhow, Result};
 use plus_adblock::AdblockEngine;
 use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
-use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use url::Url;
 
+mod bookmark_formats;
+mod bookmarks;
+mod clear;
+mod diagnostics;
+mod history;
+mod import;
+mod killswitch;
+mod password_import;
+mod scheme;
+
+pub use bookmarks::{Bookmark, BookmarkStore, ImportedBookmarks, BOOKMARK_BAR, OTHER_BOOKMARKS};
+pub use clear::{clear_browsing_data, ClearReport};
+pub use diagnostics::{
+    run_check, run_diagnostics, CheckKind, CheckResult, CheckStatus, DiagnosticsConfig, EgressIp,
+    DEFAULT_IP_ENDPOINTS,
+};
+pub use history::{HistoryDay, HistoryEntry, HistoryStore, HistoryVisit};
+pub use import::{
+    detect_browser, import_profile, BrowserKind, ImportProgress, ImportReport, ImportStage,
+    PasswordTarget,
+};
+pub use killswitch::KillSwitch;
+pub use scheme::{RuntimeStatus, SchemeResponse, SchemeRouter, INTERNAL_SCHEME};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseData {
     pub url: String,
@@ -58,45 +79,82 @@ impl NetClient {
     }
 
     pub async fn get_egress_ip(&self) -> Result<String> {
-        Ok(self
-            .client
-            .get("https://api.ipify.org")
-            .send()
-            .await?
-            .text()
-            .await?)
+        Ok(self.egress_ip(DEFAULT_IP_ENDPOINTS).await?.ip.to_string())
     }
-}
 
-pub struct HistoryStore {
-    conn: Connection,
+    /// Первый ответ из `endpoints`, похожий на IP-адрес; остальные сервисы — запасные.
+    pub async fn egress_ip(&self, endpoints: &[impl AsRef<str>]) -> Result<EgressIp> {
+        let mut errors = Vec::new();
+        for endpoint in endpoints {
+            let endpoint = endpoint.as_ref();
+            let body = match self.client.get(endpoint).send().await {
+                Ok(resp) if resp.status().is_success() => resp.text().await,
+                Ok(resp) => {
+                    errors.push(format!("{endpoint}: HTTP {}", resp.status()));
+                    continue;
+                }
+                Err(e) => Err(e),
+            };
+            match body.map(|b| b.trim().parse::<std::net::IpAddr>()) {
+                Ok(Ok(ip)) => {
+                    return Ok(EgressIp {
+                        ip,
+                        endpoint: endpoint.to_string(),
+                    })
+                }
+                Ok(Err(_)) => errors.push(format!("{endpoint}: not an IP address")),
+                Err(e) => errors.push(format!("{endpoint}: {e}")),
+            }
+        }
+        Err(anyhow!("no IP endpoint answered: {}", errors.join("; ")))
+    }
 }
 
 pub struct ProxyHandle {
     pub listen_addr: String,
+    pub kill_switch: KillSwitch,
+    upstream: Arc<RwLock<Option<String>>>,
+}
+
+impl ProxyHandle {
+    /// SOCKS-вход VPN для новых соединений: VPN можно включить и выключить,
+    /// не перезапуская прокси.
+    pub fn set_upstream(&self, upstream_socks: Option<String>) {
+        *self.upstream.write().expect("upstream lock") = upstream_socks;
+    }
 }
 
+/// `internal` отвечает на запросы `plus://`; без него они получают 404 и не уходят в сеть.
 pub async fn start_proxy(
     listen_addr: &str,
     adblock: Arc<Mutex<AdblockEngine>>,
     upstream_socks: Option<String>,
+    internal: Option<SchemeRouter>,
 ) -> Result<ProxyHandle> {
     let listener = TcpListener::bind(listen_addr).await?;
     let addr = listener.local_addr()?;
+    let kill_switch = KillSwitch::default();
+    let ks = kill_switch.clone();
+    let upstream = Arc::new(RwLock::new(upstream_socks));
+    let current = upstream.clone();
     tokio::spawn(async move {
         loop {
             let Ok((stream, _)) = listener.accept().await else {
                 break;
             };
             let ad = adblock.clone();
-            let upstream = upstream_socks.clone();
+            let upstream = current.read().expect("upstream lock").clone();
+            let ks = ks.clone();
+            let internal = internal.clone();
             tokio::spawn(async move {
-                let _ = handle_client(stream, ad, upstream).await;
+                let _ = handle_client(stream, ad, upstream, ks, internal).await;
             });
         }
     });
     Ok(ProxyHandle {
         listen_addr: format!("{}", addr),
+        kill_switch,
+        upstream,
     })
 }
 
@@ -104,6 +162,8 @@ async fn handle_client(
     mut client: TcpStream,
     adblock: Arc<Mutex<AdblockEngine>>,
     upstream_socks: Option<String>,
+    kill_switch: KillSwitch,
+    internal: Option<SchemeRouter>,
 ) -> Result<()> {
     let mut buf = [0u8; 4096];
     let n = client.read(&mut buf).await?;
@@ -121,15 +181,54 @@ async fn handle_client(
     }
     let method = parts[0];
     let target = parts[1];
+    if target
+        .get(..INTERNAL_SCHEME.len() + 1)
+        .is_some_and(|s| s.eq_ignore_ascii_case(&format!("{INTERNAL_SCHEME}:")))
+    {
+        let initiator = lines
+            .take_while(|l| !l.is_empty())
+            .filter_map(|l| l.split_once(':'))
+            .find(|(name, _)| {
+                name.eq_ignore_ascii_case("origin") || name.eq_ignore_ascii_case("referer")
+            })
+            .map(|(_, value)| value.trim().to_string());
+        let response = match &internal {
+            Some(router) => router.handle(method, target, initiator.as_deref()),
+            None => SchemeResponse {
+                status: 404,
+                content_type: "text/plain; charset=utf-8",
+                body: b"internal pages are not served here".to_vec(),
+                allow_origin: None,
+            },
+        };
+        client.write_all(&response.to_http()).await?;
+        return Ok(());
+    }
     if method.eq_ignore_ascii_case("CONNECT") {
         let host_port = target.to_string();
         let url = format!("https://{}/", host_port);
-        let mut ad = adblock.lock().expect("adblock lock");
-        if ad.should_block(&url, "about:proxy", "connect") {
+        let host = host_port
+            .rsplit_once(':')
+            .map(|(h, _)| h)
+            .unwrap_or(&host_port);
+        if !killswitch::is_local_host(host) && kill_switch.is_engaged(upstream_socks.is_some()) {
+            client.write_all(&killswitch::blocked_response()).await?;
+            return Ok(());
+        }
+        let blocked =
+            adblock
+                .lock()
+                .expect("adblock lock")
+                .should_block(&url, "about:proxy", "connect");
+        if blocked {
             client.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").await?;
             return Ok(());
         }
-        let mut upstream = connect_upstream(&host_port, upstream_socks).await?;
+        let Some(mut upstream) =
+            connect_or_trip(&mut client, &host_port, host, upstream_socks, &kill_switch).await?
+        else {
+            return Ok(());
+        };
         client
             .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
             .await?;
@@ -138,44 +237,93 @@ async fn handle_client(
         let url = Url::parse(target)?;
         let host = url.host_str().unwrap_or_default();
         let port = url.port_or_known_default().unwrap_or(80);
-        let mut ad = adblock.lock().expect("adblock lock");
-        if ad.should_block(url.as_str(), "about:proxy", "http") {
+        if !killswitch::is_local_host(host) && kill_switch.is_engaged(upstream_socks.is_some()) {
+            client.write_all(&killswitch::blocked_response()).await?;
+            return Ok(());
+        }
+        let blocked =
+            adblock
+                .lock()
+                .expect("adblock lock")
+                .should_block(url.as_str(), "about:proxy", "http");
+        if blocked {
             client.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").await?;
             return Ok(());
         }
-        let mut upstream = connect_upstream(&format!("{}:{}", host, port), upstream_socks).await?;
+        let target = format!("{}:{}", host, port);
+        let Some(mut upstream) =
+            connect_or_trip(&mut client, &target, host, upstream_socks, &kill_switch).await?
+        else {
+            return Ok(());
+        };
         upstream.write_all(&buf[..n]).await?;
         tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
     }
     Ok(())
 }
 
-async fn connect_upstream(target: &str, upstream_socks: Option<String>) -> Result<TcpStream> {
-    if let Some(socks) = upstream_socks {
-        let url = Url::parse(&format!("socks5://{}", socks))?;
-        let host = url.host_str().unwrap_or("127.0.0.1");
-        let port = url.port().unwrap_or(1080);
-        let mut stream = TcpStream::connect((host, port)).await?;
-        socks5_connect(&mut stream, target).await?;
-        Ok(stream)
-    } else {
-        let mut split = target.split(':');
-        let host = split.next().unwrap_or("127.0.0.1");
-        let port = split
-            .next()
-            .and_then(|p| p.parse::<u16>().ok())
-            .unwrap_or(80);
-        Ok(TcpStream::connect((host, port)).await?)
+/// Подключается к цели. Если не отвечает сам VPN-upstream, помечает его нездоровым
+/// и при включённом kill switch отдаёт клиенту страницу ошибки. Отказ upstream
+/// соединиться с целью — ответ о цели, а не о VPN: клиент получает 502.
+async fn connect_or_trip(
+    client: &mut TcpStream,
+    target: &str,
+    host: &str,
+    upstream_socks: Option<String>,
+    kill_switch: &KillSwitch,
+) -> Result<Option<TcpStream>> {
+    let Some(socks) = upstream_socks.filter(|_| !killswitch::is_local_host(host)) else {
+        return Ok(Some(connect_direct(target).await?));
+    };
+    let mut stream = match open_socks5(&socks).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            kill_switch.set_vpn_healthy(false);
+            if kill_switch.is_enabled() {
+                client.write_all(&killswitch::blocked_response()).await?;
+                return Ok(None);
+            }
+            return Err(err);
+        }
+    };
+    if socks5_request(&mut stream, target).await.is_err() {
+        client
+            .write_all(
+                b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )
+            .await?;
+        return Ok(None);
     }
+    Ok(Some(stream))
+}
+
+async fn connect_direct(target: &str) -> Result<TcpStream> {
+    let mut split = target.split(':');
+    let host = split.next().unwrap_or("127.0.0.1");
+    let port = split
+        .next()
+        .and_then(|p| p.parse::<u16>().ok())
+        .unwrap_or(80);
+    Ok(TcpStream::connect((host, port)).await?)
 }
 
-async fn socks5_connect(stream: &mut TcpStream, target: &str) -> Result<()> {
+/// Соединение с SOCKS5-входом VPN после приветствия без аутентификации.
+async fn open_socks5(socks: &str) -> Result<TcpStream> {
+    let url = Url::parse(&format!("socks5://{}", socks))?;
+    let host = url.host_str().unwrap_or("127.0.0.1");
+    let port = url.port().unwrap_or(1080);
+    let mut stream = TcpStream::connect((host, port)).await?;
     stream.write_all(&[0x05, 0x01, 0x00]).await?;
     let mut resp = [0u8; 2];
     stream.read_exact(&mut resp).await?;
     if resp[1] != 0x00 {
         anyhow::bail!("SOCKS auth failed");
     }
+    Ok(stream)
+}
+
+/// Команда CONNECT к цели через уже открытое SOCKS5-соединение.
+async fn socks5_request(stream: &mut TcpStream, target: &str) -> Result<()> {
     let mut parts = target.split(':');
     let host = parts.next().unwrap_or("127.0.0.1");
     let port = parts
@@ -193,26 +341,3 @@ async fn socks5_connect(stream: &mut TcpStream, target: &str) -> Result<()> {
     }
     Ok(())
 }
-
-impl HistoryStore {
-    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let conn = Connection::open(path)?;
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS history (
-                id INTEGER PRIMARY KEY,
-                url TEXT NOT NULL,
-                title TEXT NOT NULL,
-                visited_at TEXT NOT NULL
-            );",
-        )?;
-        Ok(Self { conn })
-    }
-
-    pub fn add_visit(&self, url: &str, title: &str) -> Result<()> {
-        self.conn.execute(
-            "INSERT INTO history(url, title, visited_at) VALUES(?1, ?2, ?3)",
-            params![url, title, Utc::now().to_rfc3339()],
-        )?;
-        Ok(())
-    }
-}
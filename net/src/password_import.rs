@@ -0,0 +1,577 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use chrono::{DateTime, Utc};
+use plus_privacy::Login;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::Path;
+use url::Url;
+
+use crate::bookmark_formats::from_chromium_micros;
+
+/// Расшифрованные логины и число тех, чей ключ недоступен: пароль Chromium,
+/// привязанный к приложению (`v20`) или к Secret Service, основной пароль Firefox.
+#[derive(Debug, Default)]
+pub(crate) struct ImportedLogins {
+    pub logins: Vec<Login>,
+    pub locked: usize,
+}
+
+/// Больше итераций PBKDF2 Firefox не использует; `key4.db` с большим числом —
+/// повреждённый или подложенный файл, который иначе надолго занял бы поток.
+const MAX_PBKDF2_ROUNDS: u32 = 1_000_000;
+const PASSWORD_CHECK: &[u8] = b"password-check";
+
+/// `Login Data` Chromium. Ключ `v10`/`v11` на Windows — из `Local State` рядом
+/// с профилем (DPAPI); на Linux `v10` шифруется общеизвестным ключом.
+pub(crate) fn read_chromium_logins(
+    profile_dir: &Path,
+    conn: &Connection,
+) -> Result<ImportedLogins> {
+    let key = os_crypt::key(profile_dir);
+    let mut stmt = conn.prepare(
+        "SELECT origin_url, username_value, password_value, date_created FROM logins
+         WHERE blacklisted_by_user = 0 ORDER BY date_created",
+    )?;
+    let rows = stmt.query_map([], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            r.get::<_, String>(1)?,
+            r.get::<_, Vec<u8>>(2)?,
+            r.get::<_, i64>(3)?,
+        ))
+    })?;
+    let mut out = ImportedLogins::default();
+    for row in rows {
+        let (url, username, value, created) = row?;
+        let Some(origin) = web_origin(&url) else {
+            continue;
+        };
+        let Some(password) =
+            os_crypt::decrypt(&value, key.as_ref()).and_then(|raw| String::from_utf8(raw).ok())
+        else {
+            out.locked += 1;
+            continue;
+        };
+        out.logins.push(Login {
+            origin,
+            username,
+            password,
+            created_at: from_chromium_micros(created).unwrap_or_else(Utc::now),
+        });
+    }
+    Ok(out)
+}
+
+#[derive(Deserialize)]
+struct FirefoxLogins {
+    #[serde(default)]
+    logins: Vec<FirefoxLogin>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FirefoxLogin {
+    hostname: String,
+    encrypted_username: String,
+    encrypted_password: String,
+    #[serde(default)]
+    time_created: i64,
+}
+
+/// `logins.json` Firefox с ключами из `key4.db` (NSS). Профиль с основным паролем
+/// не расшифровывается: все его логины попадают в `locked`.
+pub(crate) fn read_firefox_logins(
+    logins_json: &str,
+    key_db: &Connection,
+) -> Result<ImportedLogins> {
+    let parsed: FirefoxLogins = serde_json::from_str(logins_json)?;
+    let keys = nss_keys(key_db)?;
+    let mut out = ImportedLogins::default();
+    for login in parsed.logins {
+        let Some(origin) = web_origin(&login.hostname) else {
+            continue;
+        };
+        let field =
+            |value: &str| nss_decrypt(&keys, value).and_then(|raw| String::from_utf8(raw).ok());
+        let (Some(username), Some(password)) = (
+            field(&login.encrypted_username),
+            field(&login.encrypted_password),
+        ) else {
+            out.locked += 1;
+            continue;
+        };
+        out.logins.push(Login {
+            origin,
+            username,
+            password,
+            created_at: DateTime::from_timestamp_millis(login.time_created)
+                .filter(|_| login.time_created > 0)
+                .unwrap_or_else(Utc::now),
+        });
+    }
+    Ok(out)
+}
+
+/// Только сайты: записи `android://` и прочие схемы не переносятся.
+fn web_origin(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .map(|u| u.origin().ascii_serialization())
+}
+
+/// Ключи NSS по `CKA_ID`. Пустой список — ключи закрыты основным паролем.
+fn nss_keys(conn: &Connection) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
+    let meta: Option<(Vec<u8>, Vec<u8>)> = conn
+        .query_row(
+            "SELECT item1, item2 FROM metaData WHERE id = 'password'",
+            [],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()?;
+    let Some((global_salt, check)) = meta else {
+        return Ok(HashMap::new());
+    };
+    if decrypt_pbes2(&check, &global_salt).as_deref() != Some(PASSWORD_CHECK) {
+        return Ok(HashMap::new());
+    }
+    let mut stmt = conn.prepare("SELECT a11, a102 FROM nssPrivate")?;
+    let rows = stmt.query_map([], |r| {
+        Ok((r.get::<_, Vec<u8>>(0)?, r.get::<_, Vec<u8>>(1)?))
+    })?;
+    let mut keys = HashMap::new();
+    for row in rows {
+        let (wrapped, id) = row?;
+        if let Some(key) = decrypt_pbes2(&wrapped, &global_salt) {
+            keys.insert(id, key);
+        }
+    }
+    Ok(keys)
+}
+
+/// Поле логина: `SEQUENCE { keyId, SEQUENCE { алгоритм, iv }, шифротекст }`,
+/// алгоритм — 3DES-CBC у старых записей или AES-256-CBC.
+fn nss_decrypt(keys: &HashMap<Vec<u8>, Vec<u8>>, value: &str) -> Option<Vec<u8>> {
+    let raw = B64.decode(value).ok()?;
+    let [key_id, cipher, data] = der::children(der::parse(&raw)?)?;
+    let [oid, iv] = der::children(cipher)?;
+    let key = keys.get(der::octets(key_id)?)?;
+    let (iv, data) = (der::octets(iv)?, der::octets(data)?);
+    match der::oid(oid)? {
+        der::DES_EDE3_CBC => decrypt_cbc::<des::TdesEde3>(key.get(..24)?, iv, data),
+        der::AES256_CBC => decrypt_cbc::<aes::Aes256>(key.get(..32)?, iv, data),
+        _ => None,
+    }
+}
+
+/// PBES2 (PBKDF2-HMAC-SHA256 + AES-256-CBC), которым NSS шифрует ключи и проверочную
+/// строку. Пароль — SHA-1 от глобальной соли и пустого основного пароля.
+fn decrypt_pbes2(blob: &[u8], global_salt: &[u8]) -> Option<Vec<u8>> {
+    let [algorithm, data] = der::children(der::parse(blob)?)?;
+    let [oid, params] = der::children(algorithm)?;
+    if der::oid(oid)? != der::PBES2 {
+        return None;
+    }
+    let [kdf, cipher] = der::children(params)?;
+    let [kdf_oid, kdf_params] = der::children(kdf)?;
+    if der::oid(kdf_oid)? != der::PBKDF2 {
+        return None;
+    }
+    let [salt, rounds, key_len, prf] = der::children(kdf_params)?;
+    let [cipher_oid, iv] = der::children(cipher)?;
+    if der::oid(der::first(prf)?)? != der::HMAC_SHA256
+        || der::oid(cipher_oid)? != der::AES256_CBC
+        || der::uint(key_len)? != 32
+    {
+        return None;
+    }
+    let rounds = der::uint(rounds).filter(|r| (1..=MAX_PBKDF2_ROUNDS).contains(r))?;
+    let password = Sha1::new().chain_update(global_salt).finalize();
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(&password, der::octets(salt)?, rounds, &mut key);
+    // NSS хранит 14 байт IV; полный IV — они же вместе с заголовком OCTET STRING.
+    let iv = match der::octets(iv)? {
+        short if short.len() == 14 => [&[0x04, 0x0e][..], short].concat(),
+        full => full.to_vec(),
+    };
+    decrypt_cbc::<aes::Aes256>(&key, &iv, der::octets(data)?)
+}
+
+fn decrypt_cbc<C>(key: &[u8], iv: &[u8], data: &[u8]) -> Option<Vec<u8>>
+where
+    C: cbc::cipher::BlockCipher + BlockDecryptMut + cbc::cipher::KeyInit,
+{
+    let mut buf = data.to_vec();
+    let plain = cbc::Decryptor::<C>::new_from_slices(key, iv)
+        .ok()?
+        .decrypt_padded_mut::<Pkcs7>(&mut buf)
+        .ok()?;
+    Some(plain.to_vec())
+}
+
+#[cfg(windows)]
+mod os_crypt {
+    use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+    use base64::{engine::general_purpose::STANDARD as B64, Engine};
+    use std::path::Path;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Cryptography::{
+        CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB,
+    };
+
+    pub type Key = Vec<u8>;
+
+    /// `os_crypt.encrypted_key` из `Local State` каталога `User Data`: `DPAPI` + блоб.
+    pub fn key(profile_dir: &Path) -> Option<Key> {
+        let local_state = [profile_dir.parent(), Some(profile_dir)]
+            .into_iter()
+            .flatten()
+            .map(|dir| dir.join("Local State"))
+            .find(|path| path.is_file())?;
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(local_state).ok()?).ok()?;
+        let wrapped = B64
+            .decode(json["os_crypt"]["encrypted_key"].as_str()?)
+            .ok()?;
+        unprotect(wrapped.strip_prefix(b"DPAPI")?)
+    }
+
+    /// `v10`/`v11` — AES-256-GCM с ключом из `Local State`; `v20` привязан
+    /// к самому Chrome и недоступен; без префикса — DPAPI напрямую (старые версии).
+    pub fn decrypt(value: &[u8], key: Option<&Key>) -> Option<Vec<u8>> {
+        match value.get(..3) {
+            Some(b"v10" | b"v11") => {
+                let rest = &value[3..];
+                let (nonce, data) = (rest.get(..12)?, rest.get(12..)?);
+                Aes256Gcm::new_from_slice(key?)
+                    .ok()?
+                    .decrypt(Nonce::from_slice(nonce), data)
+                    .ok()
+            }
+            Some(b"v20") => None,
+            _ => unprotect(value),
+        }
+    }
+
+    fn unprotect(data: &[u8]) -> Option<Vec<u8>> {
+        let input = CRYPT_INTEGER_BLOB {
+            cbData: u32::try_from(data.len()).ok()?,
+            pbData: data.as_ptr() as *mut u8,
+        };
+        let mut output = CRYPT_INTEGER_BLOB {
+            cbData: 0,
+            pbData: std::ptr::null_mut(),
+        };
+        // SAFETY: `input` указывает на живой срез, который DPAPI только читает;
+        // `output` заполняет сама функция буфером из LocalAlloc, который мы
+        // копируем и освобождаем через LocalFree ровно один раз.
+        unsafe {
+            if CryptUnprotectData(
+                &input,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                CRYPTPROTECT_UI_FORBIDDEN,
+                &mut output,
+            ) == 0
+            {
+                return None;
+            }
+            let plain = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+            LocalFree(output.pbData.cast());
+            Some(plain)
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod os_crypt {
+    use super::decrypt_cbc;
+    use sha1::Sha1;
+    use std::path::Path;
+
+    pub type Key = [u8; 16];
+
+    /// Ключ `v10` на Linux: PBKDF2 от `peanuts`. `v11` шифруется паролем из Secret
+    /// Service, до которого Plus не добирается; на macOS пароль лежит в Keychain.
+    pub fn key(_profile_dir: &Path) -> Option<Key> {
+        let mut key = [0u8; 16];
+        pbkdf2::pbkdf2_hmac::<Sha1>(b"peanuts", b"saltysalt", 1, &mut key);
+        Some(key)
+    }
+
+    pub fn decrypt(value: &[u8], key: Option<&Key>) -> Option<Vec<u8>> {
+        let data = value.strip_prefix(b"v10")?;
+        decrypt_cbc::<aes::Aes128>(key?, &[b' '; 16], data)
+    }
+}
+
+/// Ровно столько DER, сколько нужно для структур NSS.
+mod der {
+    pub const PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+    pub const PBKDF2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0c];
+    pub const HMAC_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x09];
+    pub const AES256_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a];
+    pub const DES_EDE3_CBC: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x03, 0x07];
+
+    const INTEGER: u8 = 0x02;
+    const OCTET_STRING: u8 = 0x04;
+    const OID: u8 = 0x06;
+    const SEQUENCE: u8 = 0x30;
+
+    #[derive(Clone, Copy)]
+    pub struct Node<'a> {
+        tag: u8,
+        body: &'a [u8],
+    }
+
+    fn read(input: &[u8]) -> Option<(Node<'_>, &[u8])> {
+        let (&tag, rest) = input.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first < 0x80 {
+            usize::from(first)
+        } else {
+            let n = usize::from(first & 0x7f);
+            if n == 0 || n > 4 || rest.len() < n {
+                return None;
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0usize, |len, &b| len << 8 | usize::from(b));
+            rest = &rest[n..];
+            len
+        };
+        (rest.len() >= len).then(|| {
+            (
+                Node {
+                    tag,
+                    body: &rest[..len],
+                },
+                &rest[len..],
+            )
+        })
+    }
+
+    /// Узел, который занимает весь `input`.
+    pub fn parse(input: &[u8]) -> Option<Node<'_>> {
+        let (node, rest) = read(input)?;
+        rest.is_empty().then_some(node)
+    }
+
+    /// Ровно `N` элементов SEQUENCE.
+    pub fn children<const N: usize>(node: Node<'_>) -> Option<[Node<'_>; N]> {
+        items(node)?.try_into().ok()
+    }
+
+    /// Первый элемент SEQUENCE: OID в AlgorithmIdentifier с параметрами или без.
+    pub fn first(node: Node<'_>) -> Option<Node<'_>> {
+        items(node)?.into_iter().next()
+    }
+
+    fn items(node: Node<'_>) -> Option<Vec<Node<'_>>> {
+        if node.tag != SEQUENCE {
+            return None;
+        }
+        let mut items = Vec::new();
+        let mut rest = node.body;
+        while !rest.is_empty() {
+            let (item, next) = read(rest)?;
+            items.push(item);
+            rest = next;
+        }
+        Some(items)
+    }
+
+    pub fn octets(node: Node<'_>) -> Option<&[u8]> {
+        (node.tag == OCTET_STRING).then_some(node.body)
+    }
+
+    pub fn oid(node: Node<'_>) -> Option<&[u8]> {
+        (node.tag == OID).then_some(node.body)
+    }
+
+    pub fn uint(node: Node<'_>) -> Option<u32> {
+        if node.tag != INTEGER || node.body.is_empty() || node.body[0] & 0x80 != 0 {
+            return None;
+        }
+        let digits = match node.body {
+            [0, rest @ ..] if !rest.is_empty() => rest,
+            body => body,
+        };
+        (digits.len() <= 4).then(|| digits.iter().fold(0u32, |n, &b| n << 8 | u32::from(b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cbc::cipher::{BlockEncryptMut, KeyInit};
+
+    const GLOBAL_SALT: &[u8] = b"global-salt-0123";
+    const KEY_ID: &[u8] = b"\xf8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01";
+
+    fn tlv(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match body.len() {
+            len @ 0..=0x7f => out.push(len as u8),
+            len @ 0x80..=0xff => out.extend([0x81, len as u8]),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn seq(items: &[Vec<u8>]) -> Vec<u8> {
+        tlv(0x30, &items.concat())
+    }
+
+    fn encrypt<C>(key: &[u8], iv: &[u8], plain: &[u8]) -> Vec<u8>
+    where
+        C: cbc::cipher::BlockCipher + BlockEncryptMut + KeyInit,
+    {
+        let mut buf = vec![0u8; plain.len() + 16];
+        buf[..plain.len()].copy_from_slice(plain);
+        let len = cbc::Encryptor::<C>::new_from_slices(key, iv)
+            .unwrap()
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, plain.len())
+            .unwrap()
+            .len();
+        buf.truncate(len);
+        buf
+    }
+
+    /// Блоб PBES2, как его пишет NSS для пустого основного пароля. Шифруется
+    /// одной итерацией; `rounds` — тело INTEGER, которое записывается в параметры.
+    fn pbes2(plain: &[u8], rounds: &[u8]) -> Vec<u8> {
+        let (salt, iv) = ([7u8; 32], [9u8; 14]);
+        let mut key = [0u8; 32];
+        let password = Sha1::digest(GLOBAL_SALT);
+        pbkdf2::pbkdf2_hmac::<Sha256>(&password, &salt, 1, &mut key);
+        let full_iv = [&[0x04, 0x0e][..], &iv].concat();
+        seq(&[
+            seq(&[
+                tlv(0x06, der::PBES2),
+                seq(&[
+                    seq(&[
+                        tlv(0x06, der::PBKDF2),
+                        seq(&[
+                            tlv(0x04, &salt),
+                            tlv(0x02, rounds),
+                            tlv(0x02, &[32]),
+                            seq(&[tlv(0x06, der::HMAC_SHA256)]),
+                        ]),
+                    ]),
+                    seq(&[tlv(0x06, der::AES256_CBC), tlv(0x04, &iv)]),
+                ]),
+            ]),
+            tlv(0x04, &encrypt::<aes::Aes256>(&key, &full_iv, plain)),
+        ])
+    }
+
+    fn key_db(check: &[u8], master_key: &[u8]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE metaData(id PRIMARY KEY UNIQUE ON CONFLICT REPLACE, item1, item2);
+             CREATE TABLE nssPrivate(id PRIMARY KEY UNIQUE ON CONFLICT ABORT, a11, a102);",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO metaData VALUES ('password', ?1, ?2)",
+            rusqlite::params![GLOBAL_SALT, pbes2(check, &[1])],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO nssPrivate VALUES (1, ?1, ?2)",
+            rusqlite::params![pbes2(master_key, &[1]), KEY_ID],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn field_3des(key: &[u8], plain: &str) -> String {
+        let iv = [3u8; 8];
+        B64.encode(seq(&[
+            tlv(0x04, KEY_ID),
+            seq(&[tlv(0x06, der::DES_EDE3_CBC), tlv(0x04, &iv)]),
+            tlv(
+                0x04,
+                &encrypt::<des::TdesEde3>(&key[..24], &iv, plain.as_bytes()),
+            ),
+        ]))
+    }
+
+    fn field_aes(key: &[u8], plain: &str) -> String {
+        let iv = [5u8; 16];
+        B64.encode(seq(&[
+            tlv(0x04, KEY_ID),
+            seq(&[tlv(0x06, der::AES256_CBC), tlv(0x04, &iv)]),
+            tlv(0x04, &encrypt::<aes::Aes256>(key, &iv, plain.as_bytes())),
+        ]))
+    }
+
+    fn logins_json(key: &[u8]) -> String {
+        serde_json::json!({
+            "logins": [
+                {
+                    "hostname": "https://mail.example",
+                    "encryptedUsername": field_3des(key, "anna"),
+                    "encryptedPassword": field_3des(key, "пароль"),
+                    "timeCreated": 1_700_000_000_000i64
+                },
+                {
+                    "hostname": "https://shop.example:8443",
+                    "encryptedUsername": field_aes(key, "bob"),
+                    "encryptedPassword": field_aes(key, "hunter2")
+                },
+                {
+                    "hostname": "chrome://FirefoxAccounts",
+                    "encryptedUsername": field_aes(key, "x"),
+                    "encryptedPassword": field_aes(key, "y")
+                }
+            ]
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn decrypts_firefox_logins_with_nss_keys() {
+        let master = [0x42u8; 32];
+        let conn = key_db(PASSWORD_CHECK, &master);
+        let imported = read_firefox_logins(&logins_json(&master), &conn).unwrap();
+        assert_eq!(imported.locked, 0);
+        let got: Vec<_> = imported
+            .logins
+            .iter()
+            .map(|l| (l.origin.as_str(), l.username.as_str(), l.password.as_str()))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                ("https://mail.example", "anna", "пароль"),
+                ("https://shop.example:8443", "bob", "hunter2"),
+            ]
+        );
+        assert_eq!(imported.logins[0].created_at.timestamp(), 1_700_000_000);
+    }
+
+    #[test]
+    fn primary_password_and_hostile_params_lock_logins() {
+        let master = [0x42u8; 32];
+        // Проверочная строка не сходится: ключи закрыты основным паролем.
+        let conn = key_db(b"other-password", &master);
+        let imported = read_firefox_logins(&logins_json(&master), &conn).unwrap();
+        assert!(imported.logins.is_empty());
+        assert_eq!(imported.locked, 2);
+
+        assert!(decrypt_pbes2(&pbes2(PASSWORD_CHECK, &[1]), GLOBAL_SALT).is_some());
+        // Два миллиарда итераций заняли бы поток на минуты: такой блоб отвергается сразу.
+        let hostile = pbes2(PASSWORD_CHECK, &[0x7f, 0xff, 0xff, 0xff]);
+        assert!(decrypt_pbes2(&hostile, GLOBAL_SALT).is_none());
+    }
+}
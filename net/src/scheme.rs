@@ -0,0 +1,288 @@
+use plus_adblock::AdblockEngine;
+use plus_engine::BrowserPolicy;
+use serde::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+use url::Url;
+
+/// Схема внутренних страниц браузера: `plus://newtab`, `plus://settings`…
+pub const INTERNAL_SCHEME: &str = "plus";
+
+/// Состояние браузера для `plus://diagnostics`; приложение обновляет его по ходу работы.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeStatus {
+    pub version: String,
+    pub proxy: Option<String>,
+    pub vpn_status: String,
+    /// `host:port` сервера, без ключей из ссылки импорта.
+    pub vpn_endpoint: Option<String>,
+    pub kill_switch_engaged: bool,
+    pub policy: BrowserPolicy,
+}
+
+impl Default for RuntimeStatus {
+    fn default() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").into(),
+            proxy: None,
+            vpn_status: "disconnected".into(),
+            vpn_endpoint: None,
+            kill_switch_engaged: false,
+            policy: BrowserPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemeResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    /// `Access-Control-Allow-Origin`: JSON читают страницы с другим origin
+    /// (`plus://diagnostics-ui` → `plus://diagnostics`).
+    pub allow_origin: Option<String>,
+}
+
+impl SchemeResponse {
+    fn html(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: body.into_bytes(),
+            allow_origin: None,
+        }
+    }
+
+    fn json(value: &impl Serialize) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self {
+                status: 200,
+                content_type: "application/json",
+                body,
+                allow_origin: None,
+            },
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: message.as_bytes().to_vec(),
+            allow_origin: None,
+        }
+    }
+
+    fn allow(mut self, origin: Option<String>) -> Self {
+        if self.status == 200 {
+            self.allow_origin = origin;
+        }
+        self
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+
+    /// Ответ целиком в HTTP/1.1 — для локального прокси.
+    pub fn to_http(&self) -> Vec<u8> {
+        let allow_origin = self
+            .allow_origin
+            .as_ref()
+            .map(|origin| format!("Access-Control-Allow-Origin: {origin}\r\n"))
+            .unwrap_or_default();
+        let mut out = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{allow_origin}\
+             Cache-Control: no-store\r\nX-Content-Type-Options: nosniff\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        )
+        .into_bytes();
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
+
+#[derive(Serialize)]
+struct AdblockReport {
+    enabled: bool,
+    blocked: u64,
+    allowed: u64,
+    last_blocked: Vec<String>,
+}
+
+/// Отвечает на запросы `plus://`. Один и тот же роутер подключается к WebView2
+/// (перехват запросов) и к локальному прокси, через который его удобно проверять.
+///
+/// Страницы открываются только переходом из браузера или с других внутренних
+/// страниц; JSON отдаётся только внутренним страницам.
+#[derive(Clone)]
+pub struct SchemeRouter {
+    adblock: Arc<Mutex<AdblockEngine>>,
+    status: Arc<RwLock<RuntimeStatus>>,
+}
+
+impl SchemeRouter {
+    pub fn new(adblock: Arc<Mutex<AdblockEngine>>) -> Self {
+        Self {
+            adblock,
+            status: Arc::new(RwLock::new(RuntimeStatus::default())),
+        }
+    }
+
+    pub fn set_status(&self, status: RuntimeStatus) {
+        *self.status.write().expect("status lock") = status;
+    }
+
+    pub fn status(&self) -> RuntimeStatus {
+        self.status.read().expect("status lock").clone()
+    }
+
+    /// `initiator` — адрес страницы, сделавшей запрос (Origin или Referer);
+    /// `None` — переход, начатый самим браузером (адресная строка, кнопки).
+    /// `Origin: null` шлют страницы с непрозрачным origin — им отказ, как сайтам.
+    pub fn handle(&self, method: &str, url: &str, initiator: Option<&str>) -> SchemeResponse {
+        let Ok(url) = Url::parse(url) else {
+            return SchemeResponse::error(404, "bad internal URL");
+        };
+        if url.scheme() != INTERNAL_SCHEME {
+            return SchemeResponse::error(404, "not an internal URL");
+        }
+        if !method.eq_ignore_ascii_case("GET") {
+            return SchemeResponse::error(405, "only GET is supported");
+        }
+        // Origin внутренней страницы: `plus://diagnostics-ui`, без пути.
+        let internal = match initiator {
+            None => None,
+            Some(initiator) => match Url::parse(initiator) {
+                Ok(initiator) if initiator.scheme() == INTERNAL_SCHEME => Some(format!(
+                    "{INTERNAL_SCHEME}://{}",
+                    initiator.host_str().unwrap_or_default()
+                )),
+                _ => {
+                    return SchemeResponse::error(403, "internal pages are not available to sites")
+                }
+            },
+        };
+        let page = url.host_str().unwrap_or_default();
+        match page {
+            "newtab" => SchemeResponse::html(plus_yandex::new_tab_html()),
+            "settings" => SchemeResponse::html(plus_yandex::settings_html()),
+            "diagnostics-ui" => SchemeResponse::html(plus_yandex::diagnostics_html()),
+            "diagnostics" | "adblock" if internal.is_none() => {
+                SchemeResponse::error(403, "available to internal pages only")
+            }
+            "diagnostics" => SchemeResponse::json(&self.status()).allow(internal),
+            "adblock" => {
+                let report = {
+                    let adblock = self.adblock.lock().expect("adblock lock");
+                    AdblockReport {
+                        enabled: adblock.is_enabled(),
+                        blocked: adblock.stats.blocked,
+                        allowed: adblock.stats.allowed,
+                        last_blocked: adblock.last_blocked(),
+                    }
+                };
+                SchemeResponse::json(&report).allow(internal)
+            }
+            _ => SchemeResponse::error(404, "unknown internal page"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> SchemeRouter {
+        let mut adblock = AdblockEngine::from_filter_list("||ads.example^").unwrap();
+        assert!(adblock.should_block("https://ads.example/a.js", "https://site.example", "script"));
+        SchemeRouter::new(Arc::new(Mutex::new(adblock)))
+    }
+
+    fn json(response: &SchemeResponse) -> serde_json::Value {
+        assert_eq!(
+            response.status,
+            200,
+            "{}",
+            String::from_utf8_lossy(&response.body)
+        );
+        assert_eq!(response.content_type, "application/json");
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn serves_pages_and_live_json() {
+        let router = router();
+        let page = router.handle("GET", "plus://newtab", None);
+        assert_eq!(page.status, 200);
+        assert!(String::from_utf8(page.body)
+            .unwrap()
+            .contains("Plus — Яндекс"));
+        let ui = Some("plus://diagnostics-ui");
+        assert_eq!(router.handle("GET", "plus://settings", ui).status, 200);
+
+        let adblock = router.handle("GET", "plus://adblock", ui);
+        assert_eq!(
+            adblock.allow_origin.as_deref(),
+            Some("plus://diagnostics-ui")
+        );
+        let adblock = json(&adblock);
+        assert_eq!(adblock["blocked"], 1);
+        assert_eq!(adblock["last_blocked"][0], "https://ads.example/a.js");
+
+        router.set_status(RuntimeStatus {
+            vpn_status: "connected".into(),
+            vpn_endpoint: Some("vpn.example:443".into()),
+            policy: BrowserPolicy {
+                kill_switch: true,
+                ..BrowserPolicy::default()
+            },
+            ..RuntimeStatus::default()
+        });
+        let status = json(&router.handle("GET", "plus://diagnostics/", ui));
+        assert_eq!(status["vpn_status"], "connected");
+        assert_eq!(status["vpn_endpoint"], "vpn.example:443");
+        assert_eq!(status["policy"]["kill_switch"], true);
+    }
+
+    #[test]
+    fn restricts_access_to_internal_pages() {
+        let router = router();
+        for (url, initiator, status) in [
+            // JSON — только внутренним страницам.
+            ("plus://adblock", None, 403),
+            ("plus://diagnostics", Some("https://evil.example/"), 403),
+            // Сайт не может ни встроить, ни открыть внутреннюю страницу.
+            ("plus://settings", Some("https://evil.example/"), 403),
+            ("plus://settings", Some("data:text/html,hi"), 403),
+            // Страница с непрозрачным origin (sandbox, незарегистрированная схема).
+            ("plus://diagnostics", Some("null"), 403),
+            // Заголовки, которые шлёт движок: Origin без пути, Referer с путём.
+            ("plus://diagnostics", Some("plus://diagnostics-ui"), 200),
+            ("plus://adblock", Some("plus://diagnostics-ui/"), 200),
+            ("plus://unknown", None, 404),
+            ("https://example.com/", None, 404),
+        ] {
+            assert_eq!(
+                router.handle("GET", url, initiator).status,
+                status,
+                "{url} from {initiator:?}"
+            );
+        }
+        assert_eq!(
+            router
+                .handle("POST", "plus://adblock", Some("plus://diagnostics-ui"))
+                .status,
+            405
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+use crate::PrivacyStore;
+
+/// Ключ `ClearDataSettings` в таблице настроек.
+pub const CLEAR_DATA_KEY: &str = "clear_data";
+/// Очистка WebView2, отложенная до следующего запуска: пока WebView2 работает,
+/// его кэш и база cookie заблокированы.
+const PENDING_WEBVIEW_CLEAR_KEY: &str = "clear_data_pending";
+
+/// Микросекунды между 1601-01-01 (эпоха Chromium) и 1970-01-01.
+const CHROMIUM_EPOCH_OFFSET_US: i64 = 11_644_473_600_000_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeRange {
+    LastHour,
+    LastDay,
+    LastWeek,
+    #[default]
+    All,
+}
+
+impl TimeRange {
+    pub const ALL: [TimeRange; 4] = [Self::LastHour, Self::LastDay, Self::LastWeek, Self::All];
+
+    /// Начало диапазона; `None` — за всё время.
+    pub fn since(self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::LastHour => Some(now - Duration::hours(1)),
+            Self::LastDay => Some(now - Duration::days(1)),
+            Self::LastWeek => Some(now - Duration::weeks(1)),
+            Self::All => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::LastHour => "За последний час",
+            Self::LastDay => "За последние сутки",
+            Self::LastWeek => "За последнюю неделю",
+            Self::All => "За всё время",
+        }
+    }
+}
+
+/// Что и за какой период удалять.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClearDataOptions {
+    pub history: bool,
+    pub cookies: bool,
+    /// Кэш WebView2: HTTP, скрипты, шейдеры. Очищается целиком, без учёта периода.
+    pub cache: bool,
+    pub downloads: bool,
+    pub adblock_stats: bool,
+    pub range: TimeRange,
+}
+
+impl Default for ClearDataOptions {
+    fn default() -> Self {
+        Self {
+            history: true,
+            cookies: true,
+            cache: true,
+            downloads: false,
+            adblock_stats: false,
+            range: TimeRange::LastHour,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClearDataSettings {
+    pub on_exit: bool,
+    pub on_exit_options: ClearDataOptions,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PendingWebviewClear {
+    cookies: bool,
+    cache: bool,
+    since: Option<DateTime<Utc>>,
+}
+
+impl PrivacyStore {
+    /// Cookie, созданные начиная с `since` (`None` — все).
+    pub fn delete_cookies_since(&self, since: Option<DateTime<Utc>>) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "DELETE FROM cookies WHERE created_at >= ?1",
+            [since.map_or(i64::MIN, |at| at.timestamp_millis())],
+        )
+    }
+
+    /// Запоминает очистку данных WebView2 до момента, когда их можно будет удалить.
+    /// Повторный вызов расширяет уже запланированную очистку.
+    pub fn queue_webview_clear(
+        &self,
+        options: &ClearDataOptions,
+        since: Option<DateTime<Utc>>,
+    ) -> rusqlite::Result<()> {
+        if !options.cookies && !options.cache {
+            return Ok(());
+        }
+        let pending = match self.get::<Option<PendingWebviewClear>>(PENDING_WEBVIEW_CLEAR_KEY)? {
+            Some(prev) => PendingWebviewClear {
+                cookies: prev.cookies || options.cookies,
+                cache: prev.cache || options.cache,
+                since: prev.since.zip(since).map(|(a, b)| a.min(b)),
+            },
+            None => PendingWebviewClear {
+                cookies: options.cookies,
+                cache: options.cache,
+                since,
+            },
+        };
+        self.set(PENDING_WEBVIEW_CLEAR_KEY, &Some(pending))
+    }
+
+    /// Выполняет отложенную очистку WebView2; вызывается, пока WebView2 не запущен.
+    /// Если файлы всё ещё заняты, очистка остаётся в очереди.
+    pub fn run_pending_webview_clear(&self, user_data_dir: &Path) -> rusqlite::Result<bool> {
+        let Some(pending) = self.get::<Option<PendingWebviewClear>>(PENDING_WEBVIEW_CLEAR_KEY)?
+        else {
+            return Ok(false);
+        };
+        let options = ClearDataOptions {
+            cookies: pending.cookies,
+            cache: pending.cache,
+            ..ClearDataOptions::default()
+        };
+        if clear_webview_data(user_data_dir, &options, pending.since).is_err() {
+            return Ok(false);
+        }
+        self.set(PENDING_WEBVIEW_CLEAR_KEY, &None::<PendingWebviewClear>)?;
+        Ok(true)
+    }
+}
+
+/// Удаляет кэш и/или cookie из каталога данных WebView2 профиля.
+pub fn clear_webview_data(
+    user_data_dir: &Path,
+    options: &ClearDataOptions,
+    since: Option<DateTime<Utc>>,
+) -> io::Result<()> {
+    let profile = user_data_dir.join("EBWebView/Default");
+    if options.cache {
+        for dir in [
+            "Cache",
+            "Code Cache",
+            "GPUCache",
+            "Service Worker/CacheStorage",
+        ] {
+            match fs::remove_dir_all(profile.join(dir)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+    }
+    if options.cookies {
+        let since = since.map_or(i64::MIN, |at| {
+            at.timestamp_micros() + CHROMIUM_EPOCH_OFFSET_US
+        });
+        for db in [profile.join("Network/Cookies"), profile.join("Cookies")] {
+            if !db.exists() {
+                continue;
+            }
+            Connection::open(&db)
+                .and_then(|conn| {
+                    conn.execute("DELETE FROM cookies WHERE creation_utc >= ?1", [since])
+                })
+                .map_err(io::Error::other)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clears_webview_cache_and_recent_cookies() {
+        let dir = tempfile::tempdir().unwrap();
+        let profile = dir.path().join("EBWebView/Default");
+        fs::create_dir_all(profile.join("Cache/Cache_Data")).unwrap();
+        fs::create_dir_all(profile.join("Network")).unwrap();
+        let now = Utc::now();
+        let micros = |at: DateTime<Utc>| at.timestamp_micros() + CHROMIUM_EPOCH_OFFSET_US;
+        let conn = Connection::open(profile.join("Network/Cookies")).unwrap();
+        conn.execute_batch("CREATE TABLE cookies(creation_utc INTEGER, host_key TEXT, name TEXT);")
+            .unwrap();
+        for at in [now - Duration::days(3), now - Duration::minutes(5)] {
+            conn.execute(
+                "INSERT INTO cookies VALUES (?1, 'ya.ru', 'a')",
+                [micros(at)],
+            )
+            .unwrap();
+        }
+        drop(conn);
+
+        let store = PrivacyStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let options = ClearDataOptions::default();
+        store
+            .queue_webview_clear(&options, options.range.since(now))
+            .unwrap();
+        assert!(store.run_pending_webview_clear(dir.path()).unwrap());
+        assert!(!store.run_pending_webview_clear(dir.path()).unwrap());
+
+        assert!(!profile.join("Cache").exists());
+        let conn = Connection::open(profile.join("Network/Cookies")).unwrap();
+        let left: i64 = conn
+            .query_row("SELECT COUNT(*) FROM cookies", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(left, 1);
+    }
+}
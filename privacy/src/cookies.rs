@@ -0,0 +1,692 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use url::Url;
+
+use crate::PrivacyStore;
+
+/// Ключ `CookieSettings` в таблице настроек.
+pub const COOKIE_SETTINGS_KEY: &str = "cookies";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Strict => "strict",
+            Self::Lax => "lax",
+            Self::None => "none",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value.to_ascii_lowercase().as_str() {
+            "strict" => Self::Strict,
+            "none" => Self::None,
+            // Как в Chromium: без атрибута или с неизвестным значением — Lax.
+            _ => Self::Lax,
+        }
+    }
+}
+
+/// Запись хранилища cookie в модели RFC 6265, раздел 5.3.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Без ведущей точки, в нижнем регистре.
+    pub domain: String,
+    pub path: String,
+    /// `None` — сессионная cookie, живёт до закрытия браузера.
+    pub expires: Option<DateTime<Utc>>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSite,
+    /// Cookie без атрибута Domain отправляется только на тот же хост.
+    pub host_only: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_access: DateTime<Utc>,
+}
+
+impl Cookie {
+    /// Разбирает заголовок `Set-Cookie`, полученный с `url`. `None`, если
+    /// браузер по RFC 6265 должен такую cookie проигнорировать.
+    pub fn parse(set_cookie: &str, url: &Url, now: DateTime<Utc>) -> Option<Self> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: SameSite::Lax,
+            host_only: true,
+            created_at: now,
+            last_access: now,
+        };
+        let mut max_age = None;
+        for attr in parts {
+            let (key, value) = attr.split_once('=').unwrap_or((attr, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "expires" => {
+                    if let Some(at) = parse_cookie_date(value) {
+                        cookie.expires = Some(at);
+                    }
+                }
+                "max-age" => {
+                    let digits = value.strip_prefix('-').unwrap_or(value);
+                    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                        let seconds = value.parse::<i64>().unwrap_or(i64::MAX);
+                        max_age = Some(if seconds <= 0 {
+                            DateTime::<Utc>::MIN_UTC
+                        } else {
+                            now.checked_add_signed(Duration::seconds(seconds.min(i64::MAX / 1000)))
+                                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+                        });
+                    }
+                }
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain.is_empty() {
+                        if !domain_matches(&host, &domain)
+                            || !domain.contains('.') && domain != host
+                        {
+                            return None;
+                        }
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = SameSite::parse(value),
+                _ => {}
+            }
+        }
+        // Max-Age важнее Expires независимо от порядка атрибутов.
+        if max_age.is_some() {
+            cookie.expires = max_age;
+        }
+        let secure_origin = url.scheme() == "https";
+        if cookie.secure && !secure_origin {
+            return None;
+        }
+        if cookie.same_site == SameSite::None && !cookie.secure {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|at| at <= now)
+    }
+
+    /// Подходит ли cookie для запроса к `url` (RFC 6265, 5.4).
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let host_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        host_ok && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "https")
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let same_site: String = row.get(7)?;
+        Ok(Self {
+            name: row.get(0)?,
+            value: row.get(1)?,
+            domain: row.get(2)?,
+            path: row.get(3)?,
+            expires: row.get::<_, Option<i64>>(4)?.map(from_ms),
+            secure: row.get(5)?,
+            http_only: row.get(6)?,
+            same_site: SameSite::parse(&same_site),
+            host_only: row.get(8)?,
+            created_at: from_ms(row.get(9)?),
+            last_access: from_ms(row.get(10)?),
+        })
+    }
+}
+
+/// Правила cookie профиля. Хранятся в настройках и передаются хосту WebView2.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CookieSettings {
+    pub block_third_party: bool,
+    pub clear_on_exit: bool,
+    /// Сайты, cookie которых переживают очистку при выходе.
+    pub keep_on_exit: Vec<String>,
+    /// Сайты, которым cookie не отправляются никогда.
+    pub blocked_sites: Vec<String>,
+}
+
+impl Default for CookieSettings {
+    fn default() -> Self {
+        Self {
+            block_third_party: true,
+            clear_on_exit: false,
+            keep_on_exit: Vec::new(),
+            blocked_sites: Vec::new(),
+        }
+    }
+}
+
+impl CookieSettings {
+    /// Можно ли отправить cookie с запросом к `request_host` со страницы `top_level_host`.
+    pub fn allows(&self, top_level_host: &str, request_host: &str) -> bool {
+        if self
+            .blocked_sites
+            .iter()
+            .any(|site| domain_matches(request_host, site))
+        {
+            return false;
+        }
+        !self.block_third_party || site_of(top_level_host) == site_of(request_host)
+    }
+
+    pub fn keeps_on_exit(&self, domain: &str) -> bool {
+        let domain = domain.trim_start_matches('.');
+        self.keep_on_exit
+            .iter()
+            .any(|site| domain_matches(domain, site))
+    }
+}
+
+/// Сайт хоста: последние две метки, три — для доменов вида `example.co.uk`.
+/// Приближение к списку публичных суффиксов без самого списка.
+pub fn site_of(host: &str) -> String {
+    let host = host.trim_start_matches('.').to_ascii_lowercase();
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let take = match labels.as_slice() {
+        [.., second, tld]
+            if labels.len() > 2
+                && tld.len() == 2
+                && matches!(*second, "co" | "com" | "net" | "org" | "gov" | "edu" | "ac") =>
+        {
+            3
+        }
+        _ => 2,
+    };
+    labels[labels.len().saturating_sub(take)..].join(".")
+}
+
+impl PrivacyStore {
+    /// Сохраняет cookie; одноимённая запись с тем же доменом и путём заменяется,
+    /// но сохраняет время создания. Истёкшая cookie просто удаляет старую.
+    pub fn set_cookie(&self, cookie: &Cookie) -> rusqlite::Result<()> {
+        if cookie.is_expired(Utc::now()) {
+            self.delete_cookie(&cookie.name, &cookie.domain, &cookie.path)?;
+            return Ok(());
+        }
+        let created: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT created_at FROM cookies WHERE name = ?1 AND domain = ?2 AND path = ?3",
+                params![cookie.name, cookie.domain, cookie.path],
+                |r| r.get(0),
+            )
+            .optional()?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO cookies(name, value, domain, path, expires_at, secure,
+                http_only, same_site, host_only, created_at, last_access_at)
+             VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                cookie.name,
+                cookie.value,
+                cookie.domain,
+                cookie.path,
+                cookie.expires.map(|at| at.timestamp_millis()),
+                cookie.secure,
+                cookie.http_only,
+                cookie.same_site.as_str(),
+                cookie.host_only,
+                created.unwrap_or(cookie.created_at.timestamp_millis()),
+                cookie.last_access.timestamp_millis()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Обрабатывает `Set-Cookie` ответа; `false`, если cookie отброшена.
+    pub fn store_set_cookie(&self, url: &Url, header: &str) -> rusqlite::Result<bool> {
+        match Cookie::parse(header, url, Utc::now()) {
+            Some(cookie) => self.set_cookie(&cookie).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Cookie для запроса в порядке RFC 6265: длинный путь первым, затем более старые.
+    pub fn cookies_for_url(&self, url: &Url) -> rusqlite::Result<Vec<Cookie>> {
+        let now = Utc::now();
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let mut cookies: Vec<Cookie> = self
+            .site_cookies(&site_of(&host))?
+            .into_iter()
+            .filter(|c| !c.is_expired(now) && c.matches(url))
+            .collect();
+        cookies.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created_at.cmp(&b.created_at))
+        });
+        let tx = self.conn.unchecked_transaction()?;
+        for cookie in &cookies {
+            tx.execute(
+                "UPDATE cookies SET last_access_at = ?4 WHERE name = ?1 AND domain = ?2 AND path = ?3",
+                params![cookie.name, cookie.domain, cookie.path, now.timestamp_millis()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(cookies)
+    }
+
+    /// Значение заголовка `Cookie` для запроса, если есть что отправить.
+    pub fn cookie_header(&self, url: &Url) -> rusqlite::Result<Option<String>> {
+        let cookies = self.cookies_for_url(url)?;
+        Ok((!cookies.is_empty()).then(|| {
+            cookies
+                .iter()
+                .map(|c| format!("{}={}", c.name, c.value))
+                .collect::<Vec<_>>()
+                .join("; ")
+        }))
+    }
+
+    /// Сайты с cookie и число cookie у каждого, по алфавиту.
+    pub fn cookie_sites(&self) -> rusqlite::Result<Vec<(String, usize)>> {
+        let mut sites = std::collections::BTreeMap::new();
+        let mut stmt = self.conn.prepare("SELECT domain FROM cookies")?;
+        for domain in stmt.query_map([], |r| r.get::<_, String>(0))? {
+            *sites.entry(site_of(&domain?)).or_insert(0) += 1;
+        }
+        Ok(sites.into_iter().collect())
+    }
+
+    pub fn site_cookies(&self, site: &str) -> rusqlite::Result<Vec<Cookie>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, value, domain, path, expires_at, secure, http_only, same_site,
+                host_only, created_at, last_access_at
+             FROM cookies WHERE domain = ?1 OR domain LIKE '%.' || ?1
+             ORDER BY domain, path, name",
+        )?;
+        let rows = stmt.query_map([site], Cookie::from_row)?;
+        rows.collect()
+    }
+
+    pub fn delete_cookie(&self, name: &str, domain: &str, path: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "DELETE FROM cookies WHERE name = ?1 AND domain = ?2 AND path = ?3",
+            params![name, domain, path],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_site_cookies(&self, site: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "DELETE FROM cookies WHERE domain = ?1 OR domain LIKE '%.' || ?1",
+            [site],
+        )
+    }
+
+    /// Приводит хранилище к снимку банки cookie движка: страницы пользуются ею,
+    /// а менеджер cookie показывает это хранилище. Уже известные cookie
+    /// сохраняют время создания, иначе очистка «за последний час» снесла бы всё.
+    pub fn replace_cookies(&self, cookies: &[Cookie]) -> rusqlite::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut keep = std::collections::HashSet::new();
+        for cookie in cookies {
+            self.set_cookie(cookie)?;
+            keep.insert((
+                cookie.name.as_str(),
+                cookie.domain.as_str(),
+                cookie.path.as_str(),
+            ));
+        }
+        let stored = tx
+            .prepare("SELECT name, domain, path FROM cookies")?
+            .query_map([], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (name, domain, path) in stored {
+            if !keep.contains(&(name.as_str(), domain.as_str(), path.as_str())) {
+                self.delete_cookie(&name, &domain, &path)?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn purge_expired_cookies(&self) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "DELETE FROM cookies WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            [Utc::now().timestamp_millis()],
+        )
+    }
+
+    /// Конец сессии: сессионные и истёкшие cookie удаляются; при `clear_on_exit`
+    /// — и все остальные, кроме сайтов из `keep_on_exit`.
+    pub fn end_cookie_session(&self, settings: &CookieSettings) -> rusqlite::Result<usize> {
+        let mut removed = self
+            .conn
+            .execute("DELETE FROM cookies WHERE expires_at IS NULL", [])?;
+        removed += self.purge_expired_cookies()?;
+        if settings.clear_on_exit {
+            let domains = self
+                .conn
+                .prepare("SELECT DISTINCT domain FROM cookies")?
+                .query_map([], |r| r.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for domain in domains {
+                if !settings.keeps_on_exit(&domain) {
+                    removed += self
+                        .conn
+                        .execute("DELETE FROM cookies WHERE domain = ?1", [domain])?;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Удаляет cookie из базы WebView2 (Chromium) в каталоге данных профиля, кроме
+/// сайтов из `keep_on_exit`. Пока WebView2 запущен, база заблокирована,
+/// поэтому вызывается до `initialize` или после закрытия окна.
+pub fn clear_webview_cookies(
+    user_data_dir: &Path,
+    settings: &CookieSettings,
+) -> rusqlite::Result<usize> {
+    let mut removed = 0;
+    for db in [
+        user_data_dir.join("EBWebView/Default/Network/Cookies"),
+        user_data_dir.join("EBWebView/Default/Cookies"),
+    ] {
+        if !db.exists() {
+            continue;
+        }
+        let conn = Connection::open(&db)?;
+        let hosts = conn
+            .prepare("SELECT DISTINCT host_key FROM cookies")?
+            .query_map([], |r| r.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for host in hosts {
+            if !settings.keeps_on_exit(&host) {
+                removed += conn.execute("DELETE FROM cookies WHERE host_key = ?1", [host])?;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// RFC 6265, 5.1.3.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err()
+}
+
+/// RFC 6265, 5.1.4.
+fn path_matches(request: &str, cookie: &str) -> bool {
+    request == cookie
+        || request.starts_with(cookie)
+            && (cookie.ends_with('/') || request[cookie.len()..].starts_with('/'))
+}
+
+/// RFC 6265, 5.1.4: каталог пути запроса.
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".into(),
+        Some(idx) => path[..idx].to_string(),
+    }
+}
+
+/// Разбор даты cookie по алгоритму RFC 6265, 5.1.1: токены в любом порядке,
+/// первое подходящее время, день, месяц и год.
+fn parse_cookie_date(value: &str) -> Option<DateTime<Utc>> {
+    let is_delimiter = |c: char| {
+        c == '\t'
+            || (' '..='/').contains(&c)
+            || (';'..='@').contains(&c)
+            || ('['..='`').contains(&c)
+            || ('{'..='~').contains(&c)
+    };
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in value.split(is_delimiter).filter(|t| !t.is_empty()) {
+        if time.is_none() {
+            let parts: Vec<&str> = token.split(':').collect();
+            if parts.len() == 3 && parts.iter().all(|p| (1..=2).contains(&p.len())) {
+                if let (Ok(h), Ok(m), Ok(s)) = (
+                    parts[0].parse::<u32>(),
+                    parts[1].parse::<u32>(),
+                    leading_digits(parts[2]).parse::<u32>(),
+                ) {
+                    time = Some((h, m, s));
+                    continue;
+                }
+            }
+        }
+        let digits = leading_digits(token);
+        if day.is_none() && (1..=2).contains(&digits.len()) {
+            day = digits.parse::<u32>().ok();
+            continue;
+        }
+        if month.is_none() {
+            const MONTHS: [&str; 12] = [
+                "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+            ];
+            // Байты, а не символы: заголовок от сервера может быть не в ASCII.
+            let prefix = token.as_bytes().get(..3).unwrap_or_default();
+            if let Some(idx) = MONTHS
+                .iter()
+                .position(|m| m.as_bytes().eq_ignore_ascii_case(prefix))
+            {
+                month = Some(idx as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() && (2..=4).contains(&digits.len()) {
+            year = digits.parse::<i32>().ok();
+        }
+    }
+    let year = match year? {
+        y @ 70..=99 => y + 1900,
+        y @ 0..=69 => y + 2000,
+        y => y,
+    };
+    let (h, m, s) = time?;
+    if year < 1601 || h > 23 || m > 59 || s > 59 {
+        return None;
+    }
+    let date = NaiveDate::from_ymd_opt(year, month?, day?)?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(h, m, s)?))
+}
+
+fn leading_digits(token: &str) -> &str {
+    let end = token
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(token.len());
+    &token[..end]
+}
+
+fn from_ms(ms: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(ms).single().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_set_cookie_attributes() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let page = url("https://www.example.com/account/login");
+
+        let c = Cookie::parse(
+            "sid=abc; Expires=Wed, 21 Oct 2037 07:28:00 GMT; Max-Age=60; Domain=.Example.com; Secure; HttpOnly; SameSite=Strict",
+            &page,
+            now,
+        )
+        .unwrap();
+        assert_eq!(c.domain, "example.com");
+        assert!(!c.host_only && c.secure && c.http_only);
+        assert_eq!(c.same_site, SameSite::Strict);
+        assert_eq!(c.expires, Some(now + Duration::seconds(60)));
+        assert_eq!(c.path, "/account");
+
+        let c = Cookie::parse("a=1; expires=Sun, 06-Nov-94 08:49:37 GMT", &page, now).unwrap();
+        assert_eq!(
+            c.expires,
+            Some(Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap())
+        );
+        assert!(c.is_expired(now) && c.host_only);
+
+        let c = Cookie::parse("a=1; Expires=Жж", &page, now).unwrap();
+        assert_eq!(c.expires, None);
+
+        assert!(Cookie::parse("a=1; Domain=other.com", &page, now).is_none());
+        assert!(Cookie::parse("a=1; Domain=com", &page, now).is_none());
+        assert!(Cookie::parse("a=1; SameSite=None", &page, now).is_none());
+        assert!(Cookie::parse("a=1; Secure", &url("http://example.com/"), now).is_none());
+        assert!(Cookie::parse("noequals", &page, now).is_none());
+    }
+
+    #[test]
+    fn matches_requests_and_manages_sites() {
+        let store = PrivacyStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let page = url("https://shop.example.com/cart/items");
+        assert!(store.store_set_cookie(&page, "cart=1; Path=/cart").unwrap());
+        assert!(store
+            .store_set_cookie(&page, "lang=ru; Domain=example.com; Path=/; Max-Age=3600")
+            .unwrap());
+        assert!(store.store_set_cookie(&page, "sess=x").unwrap());
+        store
+            .store_set_cookie(&url("https://tracker.net/"), "id=7; Max-Age=3600")
+            .unwrap();
+
+        assert_eq!(
+            store.cookie_header(&page).unwrap().unwrap(),
+            "cart=1; sess=x; lang=ru"
+        );
+        assert_eq!(
+            store
+                .cookie_header(&url("https://www.example.com/cart"))
+                .unwrap()
+                .unwrap(),
+            "lang=ru"
+        );
+        assert!(store
+            .cookie_header(&url("https://example.com.evil.org/"))
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            store.cookie_sites().unwrap(),
+            vec![
+                ("example.com".to_string(), 3),
+                ("tracker.net".to_string(), 1)
+            ]
+        );
+
+        store
+            .store_set_cookie(&page, "cart=gone; Path=/cart; Max-Age=0")
+            .unwrap();
+        assert_eq!(store.site_cookies("example.com").unwrap().len(), 2);
+
+        let settings = CookieSettings {
+            clear_on_exit: true,
+            keep_on_exit: vec!["example.com".into()],
+            ..CookieSettings::default()
+        };
+        assert_eq!(store.end_cookie_session(&settings).unwrap(), 2);
+        let left = store.site_cookies("example.com").unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].name, "lang");
+        assert_eq!(store.delete_site_cookies("example.com").unwrap(), 1);
+
+        let jar = Cookie::parse("id=8; Max-Age=60", &url("https://mail.ru/"), Utc::now()).unwrap();
+        store.replace_cookies(&[jar]).unwrap();
+        assert_eq!(
+            store.cookie_sites().unwrap(),
+            vec![("mail.ru".to_string(), 1)]
+        );
+
+        // Повторный снимок той же банки не «молодит» cookie.
+        let created = store.site_cookies("mail.ru").unwrap()[0].created_at;
+        let later = Utc::now() + chrono::Duration::hours(2);
+        let again = Cookie::parse("id=9; Max-Age=60", &url("https://mail.ru/"), later).unwrap();
+        store.replace_cookies(&[again]).unwrap();
+        let left = store.site_cookies("mail.ru").unwrap();
+        assert_eq!((left[0].value.as_str(), left[0].created_at), ("9", created));
+    }
+
+    #[test]
+    fn third_party_rules() {
+        let rules = CookieSettings {
+            blocked_sites: vec!["ads.example.org".into()],
+            ..CookieSettings::default()
+        };
+        assert!(rules.allows("www.example.co.uk", "static.example.co.uk"));
+        assert!(!rules.allows("www.example.co.uk", "other.co.uk"));
+        assert!(!rules.allows("example.org", "ads.example.org"));
+        assert!(rules.allows("example.org", "cdn.example.org"));
+        let open = CookieSettings {
+            block_third_party: false,
+            ..CookieSettings::default()
+        };
+        assert!(open.allows("a.com", "b.com"));
+    }
+
+    #[test]
+    fn clears_webview_cookie_db_except_kept_sites() {
+        let dir = tempfile::tempdir().unwrap();
+        let network = dir.path().join("EBWebView/Default/Network");
+        std::fs::create_dir_all(&network).unwrap();
+        let conn = Connection::open(network.join("Cookies")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE cookies(host_key TEXT, name TEXT, path TEXT, encrypted_value BLOB);
+             INSERT INTO cookies VALUES ('.mail.ru', 'a', '/', x''), ('yandex.ru', 'b', '/', x''),
+                 ('.tracker.net', 'c', '/', x'');",
+        )
+        .unwrap();
+        drop(conn);
+        let settings = CookieSettings {
+            keep_on_exit: vec!["mail.ru".into(), "yandex.ru".into()],
+            ..CookieSettings::default()
+        };
+        assert_eq!(clear_webview_cookies(dir.path(), &settings).unwrap(), 1);
+    }
+}
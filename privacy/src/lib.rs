@@ -1,13 +1,46 @@
 use chrono::Utc;
-use dirs::home_dir;
-use rusqlite::{params, Connection};
-use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{mpsc, Mutex},
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+mod clear;
+mod cookies;
+mod migrate;
+mod passwords;
+mod profile;
+mod secure;
+mod session;
+mod vault;
+
+pub use clear::{
+    clear_webview_data, ClearDataOptions, ClearDataSettings, TimeRange, CLEAR_DATA_KEY,
+};
+pub use cookies::{
+    clear_webview_cookies, site_of, Cookie, CookieSettings, SameSite, COOKIE_SETTINGS_KEY,
+};
+pub use migrate::{migrate, schema_version, Migration, MigrationError};
+pub use passwords::Login;
+pub use profile::{
+    is_reserved_windows_name, Profile, ProfileLock, ProfileManager, DEFAULT_PROFILE,
+};
+pub use secure::{check_kdf_params, write_atomic};
+pub use session::{Session, SessionTab, SessionWindow, MAX_CLOSED_TABS, SESSION_KEY};
+pub use vault::{DbKey, ProfileDb};
+
+/// Ключ `PrivacySettings` в таблице настроек.
+pub const PRIVACY_SETTINGS_KEY: &str = "privacy";
+/// Ключ `BrowserPolicy` движка; сам тип живёт в `plus-engine`.
+pub const BROWSER_POLICY_KEY: &str = "policy";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PrivacySettings {
     pub telemetry_enabled: bool,
     pub dark_mode: bool,
+    /// Всегда запускаться в режиме инкогнито.
     pub incognito: bool,
 }
 
@@ -22,49 +55,247 @@ impl Default for PrivacySettings {
 }
 
 pub fn ensure_profile_dir(profile: &str) -> std::io::Result<PathBuf> {
-    let base = home_dir().unwrap_or_else(|| PathBuf::from("."));
-    let dir = base.join(".plus").join(profile);
-    fs::create_dir_all(&dir)?;
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
-    }
-    Ok(dir)
+    ProfileManager::new(ProfileManager::default_root())
+        .open(profile)
+        .map(|p| p.dir().to_path_buf())
+}
+
+/// v1 — таблицы, которые ранние сборки создавали без `user_version`.
+/// v2 — `logins`: сохранённые пароли, зашифрованные ключом паролей профиля.
+/// v3 — значения настроек хранятся как JSON.
+/// v4 — `cookie_jar` заменена таблицей `cookies` с атрибутами RFC 6265.
+const MIGRATIONS: &[Migration] = &[
+    Migration::sql(
+        "CREATE TABLE IF NOT EXISTS settings(key TEXT PRIMARY KEY, value TEXT NOT NULL);
+         CREATE TABLE IF NOT EXISTS cookie_jar(domain TEXT, key TEXT, value TEXT, created_at TEXT);",
+    ),
+    Migration::sql(
+        "CREATE TABLE logins(
+            origin TEXT NOT NULL,
+            username TEXT NOT NULL,
+            password BLOB NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (origin, username)
+        );",
+    ),
+    Migration::sql("").with_fixup(encode_legacy_settings),
+    Migration::sql(
+        "CREATE TABLE cookies(
+            name TEXT NOT NULL,
+            value TEXT NOT NULL,
+            domain TEXT NOT NULL,
+            path TEXT NOT NULL,
+            expires_at INTEGER,
+            secure INTEGER NOT NULL DEFAULT 0,
+            http_only INTEGER NOT NULL DEFAULT 0,
+            same_site TEXT NOT NULL DEFAULT 'lax',
+            host_only INTEGER NOT NULL DEFAULT 1,
+            created_at INTEGER NOT NULL,
+            last_access_at INTEGER NOT NULL,
+            PRIMARY KEY (name, domain, path)
+        );
+        CREATE INDEX cookies_domain ON cookies(domain);
+        INSERT OR REPLACE INTO cookies(name, value, domain, path, created_at, last_access_at)
+            SELECT key, COALESCE(value, ''), lower(ltrim(domain, '.')), '/',
+                COALESCE(CAST(strftime('%s', created_at) AS INTEGER), CAST(strftime('%s', 'now') AS INTEGER)) * 1000,
+                CAST(strftime('%s', 'now') AS INTEGER) * 1000
+            FROM cookie_jar WHERE key IS NOT NULL AND domain IS NOT NULL;
+        DROP TABLE cookie_jar;",
+    ),
+];
+
+/// Старые сборки писали голые строки; всё, что не разбирается как JSON, становится JSON-строкой.
+fn encode_legacy_settings(conn: &Connection) -> rusqlite::Result<()> {
+    let rows = conn
+        .prepare("SELECT key, value FROM settings")?
+        .query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (key, value) in rows {
+        if serde_json::from_str::<serde_json::Value>(&value).is_err() {
+            conn.execute(
+                "UPDATE settings SET value = ?2 WHERE key = ?1",
+                params![key, serde_json::Value::String(value).to_string()],
+            )?;
+        }
+    }
+    Ok(())
 }
 
 pub struct PrivacyStore {
-    conn: Connection,
+    conn: ProfileDb,
+    watchers: Mutex<Vec<mpsc::Sender<String>>>,
 }
 
 impl PrivacyStore {
-    pub fn open(path: PathBuf) -> rusqlite::Result<Self> {
-        let conn = Connection::open(path)?;
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS settings(key TEXT PRIMARY KEY, value TEXT NOT NULL);
-             CREATE TABLE IF NOT EXISTS cookie_jar(domain TEXT, key TEXT, value TEXT, created_at TEXT);",
-        )?;
-        Ok(Self { conn })
+    pub fn open(path: PathBuf) -> Result<Self, MigrationError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Хранилище профиля; у инкогнито — в памяти, чтобы cookie и настройки не попали на диск.
+    pub fn for_profile(profile: &Profile) -> Result<Self, MigrationError> {
+        let conn = profile
+            .open_database(&profile.privacy_db())
+            .map_err(MigrationError::Open)?;
+        Self::from_connection(conn)
+    }
+
+    fn from_connection(conn: impl Into<ProfileDb>) -> Result<Self, MigrationError> {
+        let mut conn = conn.into();
+        migrate(&mut conn, MIGRATIONS)?;
+        Ok(Self {
+            conn,
+            watchers: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.conn.flush()
     }
 
+    /// Записывает значение как есть (JSON-текст) и оповещает подписчиков.
     pub fn save_setting(&self, key: &str, value: &str) -> rusqlite::Result<()> {
         self.conn.execute(
             "INSERT OR REPLACE INTO settings(key, value) VALUES(?1, ?2)",
             params![key, value],
         )?;
+        if let Ok(mut watchers) = self.watchers.lock() {
+            watchers.retain(|tx| tx.send(key.to_string()).is_ok());
+        }
         Ok(())
     }
 
+    pub fn load_setting(&self, key: &str) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |r| {
+                r.get(0)
+            })
+            .optional()
+    }
+
+    /// Типизированное чтение. Отсутствующие поля берутся из `Default`; значение,
+    /// которое не разбирается (например, записанное более новой сборкой), целиком
+    /// заменяется значением по умолчанию, чтобы не мешать запуску.
+    pub fn get<T: DeserializeOwned + Default>(&self, key: &str) -> rusqlite::Result<T> {
+        Ok(self
+            .load_setting(key)?
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> rusqlite::Result<()> {
+        let json = serde_json::to_string(value)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.save_setting(key, &json)
+    }
+
+    /// Канал с ключами изменённых настроек. Отписка — просто уронить `Receiver`.
+    pub fn subscribe(&self) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        if let Ok(mut watchers) = self.watchers.lock() {
+            watchers.push(tx);
+        }
+        rx
+    }
+
+    /// Сессионная cookie только для хоста `domain` с путём `/`.
     pub fn add_cookie(&self, domain: &str, key: &str, value: &str) -> rusqlite::Result<()> {
-        self.conn.execute(
-            "INSERT INTO cookie_jar(domain, key, value, created_at) VALUES(?1, ?2, ?3, ?4)",
-            params![domain, key, value, Utc::now().to_rfc3339()],
-        )?;
-        Ok(())
+        let now = Utc::now();
+        self.set_cookie(&Cookie {
+            name: key.to_string(),
+            value: value.to_string(),
+            domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+            path: "/".into(),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: SameSite::Lax,
+            host_only: true,
+            created_at: now,
+            last_access: now,
+        })
     }
 
     pub fn clear_cookies(&self) -> rusqlite::Result<()> {
-        self.conn.execute("DELETE FROM cookie_jar", [])?;
+        self.conn.execute("DELETE FROM cookies", [])?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_unversioned_legacy_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("privacy.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE settings(key TEXT PRIMARY KEY, value TEXT NOT NULL);
+                 CREATE TABLE cookie_jar(domain TEXT, key TEXT, value TEXT, created_at TEXT);
+                 INSERT INTO settings VALUES ('dark_mode', 'true');
+                 INSERT INTO cookie_jar VALUES ('.Example.com', 'sid', 'abc', '2024-01-01T00:00:00+00:00');",
+            )
+            .unwrap();
+        }
+        let store = PrivacyStore::open(path.clone()).unwrap();
+        assert_eq!(schema_version(&store.conn).unwrap(), 4);
+        assert!(store.get::<bool>("dark_mode").unwrap());
+        let cookies = store.site_cookies("example.com").unwrap();
+        assert_eq!(cookies[0].value, "abc");
+        assert_eq!(cookies[0].created_at.timestamp(), 1_704_067_200);
+        assert!(dir.path().join("privacy.db.v0.bak").exists());
+    }
+
+    #[test]
+    fn legacy_strings_become_json() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, &MIGRATIONS[..1]).unwrap();
+        conn.execute("INSERT INTO settings VALUES ('homepage', 'ya.ru')", [])
+            .unwrap();
+        let store = PrivacyStore::from_connection(conn).unwrap();
+        assert_eq!(store.get::<String>("homepage").unwrap(), "ya.ru");
+        assert_eq!(
+            store.load_setting("homepage").unwrap().unwrap(),
+            "\"ya.ru\""
+        );
+    }
+
+    #[test]
+    fn typed_settings_fall_back_to_defaults_and_notify() {
+        let store = PrivacyStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        assert_eq!(
+            store.get::<PrivacySettings>(PRIVACY_SETTINGS_KEY).unwrap(),
+            PrivacySettings::default()
+        );
+
+        let changes = store.subscribe();
+        store
+            .save_setting(PRIVACY_SETTINGS_KEY, r#"{"dark_mode":false}"#)
+            .unwrap();
+        let settings: PrivacySettings = store.get(PRIVACY_SETTINGS_KEY).unwrap();
+        assert!(!settings.dark_mode);
+        assert!(!settings.telemetry_enabled);
+
+        store
+            .set(
+                PRIVACY_SETTINGS_KEY,
+                &PrivacySettings {
+                    incognito: true,
+                    ..settings
+                },
+            )
+            .unwrap();
+        assert!(
+            store
+                .get::<PrivacySettings>(PRIVACY_SETTINGS_KEY)
+                .unwrap()
+                .incognito
+        );
+        assert_eq!(changes.try_iter().count(), 2);
+
+        store.save_setting("broken", "{").unwrap();
+        assert_eq!(store.get::<u32>("broken").unwrap(), 0);
+    }
+}
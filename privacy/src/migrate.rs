@@ -0,0 +1,157 @@
+use rusqlite::Connection;
+use std::{fs, io, path::PathBuf};
+
+/// Один шаг схемы. Номер версии — позиция в списке + 1 (`PRAGMA user_version`),
+/// поэтому шаги только дописываются в конец и никогда не меняются.
+pub struct Migration {
+    pub sql: &'static str,
+    /// Доработка данных, которую не выразить в SQL; выполняется в той же транзакции.
+    pub fixup: Option<fn(&Connection) -> rusqlite::Result<()>>,
+}
+
+impl Migration {
+    pub const fn sql(sql: &'static str) -> Self {
+        Self { sql, fixup: None }
+    }
+
+    pub const fn with_fixup(self, fixup: fn(&Connection) -> rusqlite::Result<()>) -> Self {
+        Self {
+            sql: self.sql,
+            fixup: Some(fixup),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("sqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("backup before migration failed: {0}")]
+    Backup(#[from] io::Error),
+    #[error("cannot open profile database: {0}")]
+    Open(io::Error),
+    #[error("database schema v{found} is newer than supported v{supported}")]
+    TooNew { found: u32, supported: u32 },
+}
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |r| r.get(0))
+}
+
+/// Доводит схему до последней версии. Каждый шаг — отдельная транзакция:
+/// при ошибке база остаётся на предыдущей версии. Перед первым шагом
+/// непустой файл копируется в `<db>.v<версия>.bak`.
+pub fn migrate(conn: &mut Connection, migrations: &[Migration]) -> Result<u32, MigrationError> {
+    let version = schema_version(conn)?;
+    let target = migrations.len() as u32;
+    if version > target {
+        return Err(MigrationError::TooNew {
+            found: version,
+            supported: target,
+        });
+    }
+    if version == target {
+        return Ok(version);
+    }
+    backup(conn, version)?;
+    for (idx, step) in migrations.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(step.sql)?;
+        if let Some(fixup) = step.fixup {
+            fixup(&tx)?;
+        }
+        tx.pragma_update(None, "user_version", idx as u32 + 1)?;
+        tx.commit()?;
+    }
+    Ok(target)
+}
+
+fn backup(conn: &Connection, version: u32) -> Result<(), MigrationError> {
+    let Some(path) = conn.path().filter(|p| !p.is_empty()) else {
+        return Ok(());
+    };
+    let objects: i64 = conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |r| r.get(0))?;
+    if objects == 0 {
+        return Ok(());
+    }
+    let dest = PathBuf::from(format!("{path}.v{version}.bak"));
+    match fs::remove_file(&dest) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    // VACUUM INTO даёт согласованную копию даже при открытом WAL.
+    conn.execute("VACUUM INTO ?1", [dest.to_string_lossy()])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: &[Migration] = &[
+        Migration::sql("CREATE TABLE a(x INTEGER);"),
+        Migration::sql("ALTER TABLE a ADD COLUMN y TEXT NOT NULL DEFAULT '';"),
+    ];
+
+    fn fill_y(conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute("UPDATE a SET y = 'x' || x", []).map(|_| ())
+    }
+
+    #[test]
+    fn applies_pending_steps_and_backs_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.db");
+        let mut conn = Connection::open(&path).unwrap();
+        assert_eq!(migrate(&mut conn, &STEPS[..1]).unwrap(), 1);
+        assert!(!dir.path().join("store.db.v0.bak").exists());
+        conn.execute("INSERT INTO a(x) VALUES (7)", []).unwrap();
+
+        let steps = [
+            Migration::sql(STEPS[0].sql),
+            Migration::sql(STEPS[1].sql).with_fixup(fill_y),
+        ];
+        assert_eq!(migrate(&mut conn, &steps).unwrap(), 2);
+        let y: String = conn.query_row("SELECT y FROM a", [], |r| r.get(0)).unwrap();
+        assert_eq!(y, "x7");
+
+        let backup = Connection::open(dir.path().join("store.db.v1.bak")).unwrap();
+        assert_eq!(schema_version(&backup).unwrap(), 1);
+        let x: i64 = backup
+            .query_row("SELECT x FROM a", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(x, 7);
+        assert_eq!(migrate(&mut conn, &steps).unwrap(), 2);
+    }
+
+    #[test]
+    fn failed_step_rolls_back() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let steps = [
+            Migration::sql(STEPS[0].sql),
+            Migration::sql("CREATE TABLE b(z INTEGER); INSERT INTO missing VALUES (1);"),
+        ];
+        assert!(migrate(&mut conn, &steps).is_err());
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        let b: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'b'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(b, 0);
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", 5).unwrap();
+        assert!(matches!(
+            migrate(&mut conn, STEPS),
+            Err(MigrationError::TooNew {
+                found: 5,
+                supported: 2
+            })
+        ));
+    }
+}
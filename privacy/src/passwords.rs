@@ -0,0 +1,122 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension};
+
+use crate::vault::{self, DbKey};
+use crate::PrivacyStore;
+
+/// Сохранённый логин сайта. `origin` — схема, хост и порт, как у `Url::origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Login {
+    pub origin: String,
+    pub username: String,
+    pub password: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PrivacyStore {
+    /// Сохраняет логин; пароль шифруется ключом `Profile::credentials_key`.
+    /// `false`, если для сайта уже есть логин с таким именем — он не меняется.
+    pub fn add_login(&self, login: &Login, key: &DbKey) -> rusqlite::Result<bool> {
+        let exists = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM logins WHERE origin = ?1 AND username = ?2",
+                params![login.origin, login.username],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if exists {
+            return Ok(false);
+        }
+        let sealed = vault::seal(key, login.password.as_bytes())
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            "INSERT INTO logins(origin, username, password, created_at) VALUES(?1, ?2, ?3, ?4)",
+            params![
+                login.origin,
+                login.username,
+                sealed,
+                login.created_at.timestamp_millis()
+            ],
+        )?;
+        Ok(true)
+    }
+
+    /// Логины по сайтам, пароли расшифрованы.
+    pub fn logins(&self, key: &DbKey) -> rusqlite::Result<Vec<Login>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT origin, username, password, created_at FROM logins ORDER BY origin, username",
+        )?;
+        let rows = stmt.query_map([], |r| {
+            let sealed: Vec<u8> = r.get(2)?;
+            let password = vault::open(key, &sealed)
+                .map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        2,
+                        rusqlite::types::Type::Blob,
+                        e.into(),
+                    )
+                })
+                .and_then(|raw| {
+                    String::from_utf8(raw).map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            2,
+                            rusqlite::types::Type::Blob,
+                            e.into(),
+                        )
+                    })
+                })?;
+            Ok(Login {
+                origin: r.get(0)?,
+                username: r.get(1)?,
+                password,
+                created_at: DateTime::from_timestamp_millis(r.get(3)?).unwrap_or_else(Utc::now),
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn delete_login(&self, origin: &str, username: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "DELETE FROM logins WHERE origin = ?1 AND username = ?2",
+            params![origin, username],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn stores_passwords_encrypted_without_duplicates() {
+        let store = PrivacyStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let key = DbKey::random();
+        let login = Login {
+            origin: "https://mail.example".into(),
+            username: "anna".into(),
+            password: "s3cr3t".into(),
+            created_at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        assert!(store.add_login(&login, &key).unwrap());
+        let changed = Login {
+            password: "other".into(),
+            ..login.clone()
+        };
+        assert!(!store.add_login(&changed, &key).unwrap());
+        assert_eq!(store.logins(&key).unwrap(), vec![login.clone()]);
+
+        let raw: Vec<u8> = store
+            .conn
+            .query_row("SELECT password FROM logins", [], |r| r.get(0))
+            .unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"s3cr3t"));
+        assert!(store.logins(&DbKey::random()).is_err());
+
+        store.delete_login(&login.origin, &login.username).unwrap();
+        assert!(store.logins(&key).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,566 @@
+use dirs::home_dir;
+use rusqlite::Connection;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::vault::{self, DbKey, EncryptionMeta, ProfileDb, META_FILE};
+
+pub const DEFAULT_PROFILE: &str = "default";
+const LOCK_FILE: &str = "plus.lock";
+/// Имена устройств Windows: `CON`, `nul.txt` и т. п. открываются как устройство,
+/// а не как файл или каталог.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM0", "COM1", "COM2", "COM3", "COM4",
+    "COM5", "COM6", "COM7", "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9",
+];
+
+/// Каталог профиля и пути ко всем его хранилищам.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    name: String,
+    dir: PathBuf,
+    incognito: bool,
+    key: Option<DbKey>,
+}
+
+impl Profile {
+    /// Одноразовый профиль инкогнито. Базы открываются в памяти (`for_profile` у хранилищ),
+    /// на диск пишет только WebView2 — во временный каталог, который удаляет `wipe`.
+    pub fn incognito() -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "plus-incognito-{}-{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default()
+        ));
+        create_private_dir(&dir)?;
+        Ok(Self {
+            name: "Инкогнито".into(),
+            dir,
+            incognito: true,
+            key: None,
+        })
+    }
+
+    pub fn is_incognito(&self) -> bool {
+        self.incognito
+    }
+
+    /// Стирает временный каталог инкогнито; у обычного профиля ничего не трогает.
+    pub fn wipe(&self) -> io::Result<()> {
+        if !self.incognito {
+            return Ok(());
+        }
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Базы профиля зашифрованы (есть `encryption.json`).
+    pub fn is_encrypted(&self) -> bool {
+        !self.incognito && self.dir.join(META_FILE).exists()
+    }
+
+    /// Зашифрован, но ключ не получен: keyring недоступен, нужен пароль.
+    pub fn is_locked(&self) -> bool {
+        self.key.is_none() && self.is_encrypted()
+    }
+
+    /// Достаёт ключ из keyring, а если передан пароль — из `encryption.json`.
+    pub fn unlock(&mut self, passphrase: Option<&str>) -> io::Result<()> {
+        let Some(meta) = EncryptionMeta::read(&self.dir)? else {
+            return Ok(());
+        };
+        self.key = Some(match passphrase {
+            Some(passphrase) => meta.key_from_passphrase(passphrase)?,
+            None => meta.key_from_keyring().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "profile key is not in the OS keyring; a passphrase is required",
+                )
+            })?,
+        });
+        Ok(())
+    }
+
+    /// Открывает базу профиля: у инкогнито — в памяти, у зашифрованного профиля —
+    /// расшифрованной в память, иначе — обычным файлом.
+    pub fn open_database(&self, path: &Path) -> io::Result<ProfileDb> {
+        if self.incognito {
+            return Connection::open_in_memory()
+                .map(ProfileDb::from)
+                .map_err(io::Error::other);
+        }
+        if let Some(key) = &self.key {
+            return ProfileDb::open_encrypted(path, key);
+        }
+        if self.is_encrypted() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("profile {} is locked", self.name),
+            ));
+        }
+        Connection::open(path)
+            .map(ProfileDb::from)
+            .map_err(io::Error::other)
+    }
+
+    /// Исключительная блокировка профиля на время работы процесса: две копии Plus
+    /// с одним профилем молча перезаписывали бы базы друг друга.
+    pub fn lock(&self) -> io::Result<ProfileLock> {
+        lock_dir(&self.dir, &self.name)
+    }
+
+    /// Ключ, которым шифруются сохранённые пароли (`PrivacyStore::add_login`).
+    /// Инкогнито пароли не сохраняет; запертому профилю сначала нужен `unlock`.
+    pub fn credentials_key(&self) -> io::Result<DbKey> {
+        if self.incognito {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "incognito profile does not save passwords",
+            ));
+        }
+        if self.is_locked() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("profile {} is locked", self.name),
+            ));
+        }
+        vault::credentials_key(&self.dir, self.key.as_ref())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn history_db(&self) -> PathBuf {
+        self.dir.join("history.db")
+    }
+
+    pub fn bookmarks_db(&self) -> PathBuf {
+        self.dir.join("bookmarks.db")
+    }
+
+    /// Список загрузок; сами файлы лежат в каталоге загрузок пользователя.
+    pub fn downloads_db(&self) -> PathBuf {
+        self.dir.join("downloads.db")
+    }
+
+    /// Настройки и cookie (`PrivacyStore`).
+    pub fn privacy_db(&self) -> PathBuf {
+        self.dir.join("privacy.db")
+    }
+
+    pub fn adblock_cache_dir(&self) -> PathBuf {
+        self.dir.join("adblock")
+    }
+
+    /// Рабочий каталог VPN-core: конфиги и зашифрованные профили подключения.
+    pub fn vpn_dir(&self) -> PathBuf {
+        self.dir.join("vpn")
+    }
+
+    pub fn webview_data_dir(&self) -> PathBuf {
+        self.dir.join("webview2")
+    }
+}
+
+/// Пока значение живо, профиль не откроет другой процесс (`Profile::lock`).
+#[derive(Debug)]
+pub struct ProfileLock {
+    _file: fs::File,
+}
+
+fn lock_dir(dir: &Path, name: &str) -> io::Result<ProfileLock> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(ProfileLock { _file: file }),
+        Err(fs::TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!("profile {name} is already open in another Plus window"),
+        )),
+        Err(fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Профили — подкаталоги `root` (по умолчанию `~/.plus`).
+pub struct ProfileManager {
+    root: PathBuf,
+}
+
+impl ProfileManager {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn default_root() -> PathBuf {
+        home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".plus")
+    }
+
+    /// Каталог с профилями; здесь же лежат файлы, общие для всех профилей.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(names),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                if let Some(name) = entry.file_name().to_str() {
+                    if validate_name(name).is_ok() {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Открывает профиль, создавая каталог при первом запуске. Ключ зашифрованного
+    /// профиля берётся из keyring; если его там нет, профиль остаётся `is_locked`.
+    pub fn open(&self, name: &str) -> io::Result<Profile> {
+        validate_name(name)?;
+        let dir = self.root.join(name);
+        create_private_dir(&dir)?;
+        Ok(Self::load(name, dir))
+    }
+
+    fn load(name: &str, dir: PathBuf) -> Profile {
+        let mut profile = Profile {
+            name: name.to_string(),
+            dir,
+            incognito: false,
+            key: None,
+        };
+        let _ = profile.unlock(None);
+        profile
+    }
+
+    /// Включает шифрование баз профиля. Ключ кладётся в keyring, а с паролем — ещё и
+    /// в `encryption.json` в зашифрованном паролем виде. Существующие базы
+    /// шифруются на месте при следующем открытии (`Profile::open_database`),
+    /// поэтому уже открытые соединения дописывают в старые файлы без потерь.
+    pub fn enable_encryption(
+        &self,
+        profile: &mut Profile,
+        passphrase: Option<&str>,
+    ) -> io::Result<()> {
+        if profile.incognito {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "incognito profile is never written to disk",
+            ));
+        }
+        if profile.is_encrypted() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("profile {} is already encrypted", profile.name),
+            ));
+        }
+        let (meta, key) = EncryptionMeta::create(passphrase)?;
+        meta.write(&profile.dir)?;
+        profile.key = Some(key);
+        Ok(())
+    }
+
+    pub fn create(&self, name: &str) -> io::Result<Profile> {
+        validate_name(name)?;
+        if self.root.join(name).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("profile {name} already exists"),
+            ));
+        }
+        self.open(name)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> io::Result<Profile> {
+        validate_name(from)?;
+        validate_name(to)?;
+        drop(lock_dir(&self.root.join(from), from)?);
+        let target = self.root.join(to);
+        if target.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("profile {to} already exists"),
+            ));
+        }
+        fs::rename(self.root.join(from), &target)?;
+        Ok(Self::load(to, target))
+    }
+
+    /// Удаляет профиль со всеми данными. Профиль, открытый в другом процессе, не удаляется;
+    /// свой открытый профиль вызывающий не удаляет сам.
+    pub fn delete(&self, name: &str) -> io::Result<()> {
+        validate_name(name)?;
+        let dir = self.root.join(name);
+        drop(lock_dir(&dir, name)?);
+        if let Ok(Some(meta)) = EncryptionMeta::read(&dir) {
+            meta.forget();
+        }
+        vault::forget_credentials(&dir);
+        fs::remove_dir_all(dir)
+    }
+}
+
+/// Занято ли имя устройством Windows. Расширение не спасает: `nul.txt` — тоже `NUL`.
+pub fn is_reserved_windows_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+fn validate_name(name: &str) -> io::Result<()> {
+    let invalid = name.trim().is_empty()
+        || name != name.trim()
+        || name.starts_with('.')
+        // Windows молча отрезает точку в конце: `work.` и `work` — один каталог.
+        || name.ends_with('.')
+        || is_reserved_windows_name(name)
+        || name.len() > 64
+        || name.chars().any(|c| {
+            c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+        });
+    if invalid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid profile name {name:?}"),
+        ));
+    }
+    Ok(())
+}
+
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_renames_and_deletes_profiles() {
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path());
+        assert!(profiles.list().unwrap().is_empty());
+
+        let work = profiles.create("Работа").unwrap();
+        fs::write(work.history_db(), b"x").unwrap();
+        profiles.open(DEFAULT_PROFILE).unwrap();
+        assert_eq!(profiles.list().unwrap(), vec!["default", "Работа"]);
+        assert_eq!(
+            profiles.create("default").unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        let renamed = profiles.rename("Работа", "work").unwrap();
+        assert!(renamed.history_db().exists());
+        assert!(profiles.rename("work", "default").is_err());
+
+        profiles.delete("work").unwrap();
+        assert_eq!(profiles.list().unwrap(), vec!["default"]);
+    }
+
+    #[test]
+    fn encrypts_existing_profile_in_place() {
+        // Mock-keyring не сохраняет записи между `Entry`, как недоступный keyring:
+        // открыть профиль потом можно только паролем.
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path());
+        let mut profile = profiles.open(DEFAULT_PROFILE).unwrap();
+        {
+            let store = crate::PrivacyStore::for_profile(&profile).unwrap();
+            store.add_cookie("secret.example", "sid", "s3cr3t").unwrap();
+        }
+        // Копия, оставленная прошлой миграцией, тоже открытый текст.
+        fs::copy(
+            profile.privacy_db(),
+            root.path().join("default/privacy.db.v2.bak"),
+        )
+        .unwrap();
+
+        profiles
+            .enable_encryption(&mut profile, Some("correct horse"))
+            .unwrap();
+        assert!(profile.is_encrypted() && !profile.is_locked());
+        {
+            let store = crate::PrivacyStore::for_profile(&profile).unwrap();
+            store.add_cookie("secret.example", "lang", "ru").unwrap();
+        }
+        let raw = fs::read(profile.privacy_db()).unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"s3cr3t"));
+        assert!(!root.path().join("default/privacy.db.v2.bak").exists());
+
+        let mut reopened = profiles.open(DEFAULT_PROFILE).unwrap();
+        assert!(reopened.is_locked());
+        assert!(crate::PrivacyStore::for_profile(&reopened).is_err());
+        assert_eq!(
+            reopened.unlock(Some("wrong")).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        reopened.unlock(Some("correct horse")).unwrap();
+        let store = crate::PrivacyStore::for_profile(&reopened).unwrap();
+        assert_eq!(store.site_cookies("secret.example").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn encrypted_database_is_written_only_when_changed() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path());
+        let mut profile = profiles.open(DEFAULT_PROFILE).unwrap();
+        profiles
+            .enable_encryption(&mut profile, Some("correct horse"))
+            .unwrap();
+        let store = crate::PrivacyStore::for_profile(&profile).unwrap();
+        store.add_cookie("example.com", "sid", "1").unwrap();
+        store.flush().unwrap();
+        let written = fs::read(profile.privacy_db()).unwrap();
+        store.flush().unwrap();
+        drop(store);
+        // Каждая запись шифруется с новым nonce, так что лишняя запись изменила бы файл.
+        assert_eq!(fs::read(profile.privacy_db()).unwrap(), written);
+
+        let reader = crate::PrivacyStore::for_profile(&profile).unwrap();
+        assert_eq!(reader.site_cookies("example.com").unwrap().len(), 1);
+        drop(reader);
+        assert_eq!(fs::read(profile.privacy_db()).unwrap(), written);
+    }
+
+    #[test]
+    fn rejects_hostile_key_derivation_parameters() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path());
+        let mut profile = profiles.open(DEFAULT_PROFILE).unwrap();
+        profiles
+            .enable_encryption(&mut profile, Some("correct horse"))
+            .unwrap();
+        let mut meta = EncryptionMeta::read(profile.dir()).unwrap().unwrap();
+        if let Some(wrapped) = &mut meta.wrapped_key {
+            wrapped.m_cost = u32::MAX;
+        }
+        meta.write(profile.dir()).unwrap();
+
+        let mut reopened = profiles.open(DEFAULT_PROFILE).unwrap();
+        assert_eq!(
+            reopened.unlock(Some("correct horse")).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn credentials_key_survives_passphrase_unlock() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path());
+        let plain = profiles.open("plain").unwrap();
+        // Mock-keyring «теряет» ключ, а спасти его у незашифрованного профиля нечем.
+        plain.credentials_key().unwrap();
+        assert_eq!(
+            plain.credentials_key().unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        let mut profile = profiles.open(DEFAULT_PROFILE).unwrap();
+        profiles
+            .enable_encryption(&mut profile, Some("correct horse"))
+            .unwrap();
+        let key = profile.credentials_key().unwrap();
+        let mut reopened = profiles.open(DEFAULT_PROFILE).unwrap();
+        assert!(reopened.credentials_key().is_err());
+        reopened.unlock(Some("correct horse")).unwrap();
+        assert_eq!(reopened.credentials_key().unwrap(), key);
+        let incognito = Profile::incognito().unwrap();
+        assert!(incognito.credentials_key().is_err());
+        incognito.wipe().unwrap();
+    }
+
+    #[test]
+    fn incognito_dir_is_wiped() {
+        let profile = Profile::incognito().unwrap();
+        assert!(profile.is_incognito());
+        fs::create_dir_all(profile.webview_data_dir()).unwrap();
+        fs::write(profile.webview_data_dir().join("Cookies"), b"x").unwrap();
+        profile.wipe().unwrap();
+        assert!(!profile.dir().exists());
+        profile.wipe().unwrap();
+    }
+
+    #[test]
+    fn rejects_path_like_names() {
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path().join("profiles"));
+        for name in [
+            "",
+            " x",
+            "..",
+            ".hidden",
+            "a/b",
+            "a\\b",
+            "c:",
+            "CON",
+            "nul",
+            "Com1.work",
+            "lpt9",
+            "work.",
+        ] {
+            assert_eq!(
+                profiles.create(name).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput,
+                "{name}"
+            );
+        }
+        assert!(profiles.delete("../profiles").is_err());
+        assert!(profiles.create("console").is_ok());
+    }
+
+    #[test]
+    fn profile_opens_in_one_process_at_a_time() {
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path());
+        let work = profiles.create("work").unwrap();
+        let lock = work.lock().unwrap();
+        assert_eq!(work.lock().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(
+            profiles.delete("work").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert!(profiles.rename("work", "home").is_err());
+        drop(lock);
+        let home = profiles.rename("work", "home").unwrap();
+        drop(home.lock().unwrap());
+        profiles.delete("home").unwrap();
+    }
+}
@@ -0,0 +1,33 @@
+use std::{fs, io, path::Path};
+
+/// Верхние границы Argon2 из заголовков зашифрованных файлов: подменённый файл
+/// не должен заставлять чтение занимать гигабайты памяти и минуты времени.
+const MAX_M_COST: u32 = 1 << 20;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// Проверяет параметры Argon2, прочитанные из файла, до вывода ключа.
+pub fn check_kdf_params(m_cost: u32, t_cost: u32, p_cost: u32) -> io::Result<()> {
+    if m_cost > MAX_M_COST
+        || !(1..=MAX_T_COST).contains(&t_cost)
+        || !(1..=MAX_P_COST).contains(&p_cost)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "key derivation parameters are out of range",
+        ));
+    }
+    Ok(())
+}
+
+/// Пишет во временный файл рядом и переименовывает: при сбое посреди записи
+/// на месте остаётся старый файл, а не обрезанный.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&tmp)?;
+        io::Write::write_all(&mut file, data)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,293 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Ключ `Session` в таблице настроек.
+pub const SESSION_KEY: &str = "session";
+
+/// Сколько адресов назад и вперёд помнит вкладка.
+const MAX_TAB_HISTORY: usize = 50;
+/// Сколько закрытых вкладок можно вернуть через «Восстановить вкладку».
+pub const MAX_CLOSED_TABS: usize = 25;
+const MAX_CLOSED_WINDOWS: usize = 10;
+
+static NEXT_TAB_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_tab_id() -> u64 {
+    NEXT_TAB_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionTab {
+    /// Номер вкладки в текущем запуске: по нему рендерер находит её WebView.
+    /// Не сохраняется, при загрузке выдаётся заново.
+    #[serde(skip, default = "next_tab_id")]
+    pub id: u64,
+    pub title: String,
+    pub url: String,
+    /// Адреса для «Назад», последний — ближайший.
+    pub back: Vec<String>,
+    /// Адреса для «Вперёд», последний — ближайший.
+    pub forward: Vec<String>,
+    /// Прокрутка страницы, если рендерер её сообщил.
+    pub scroll_y: Option<f64>,
+    /// Адрес значка сайта, если страница его сообщила.
+    pub favicon: Option<String>,
+}
+
+impl Default for SessionTab {
+    fn default() -> Self {
+        Self {
+            id: next_tab_id(),
+            title: String::new(),
+            url: String::new(),
+            back: Vec::new(),
+            forward: Vec::new(),
+            scroll_y: None,
+            favicon: None,
+        }
+    }
+}
+
+/// Сравнивает содержимое вкладки, без номера.
+impl PartialEq for SessionTab {
+    fn eq(&self, other: &Self) -> bool {
+        self.title == other.title
+            && self.url == other.url
+            && self.back == other.back
+            && self.forward == other.forward
+            && self.scroll_y == other.scroll_y
+            && self.favicon == other.favicon
+    }
+}
+
+impl SessionTab {
+    pub fn new(url: impl Into<String>, title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            url: url.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Копия со своим номером — для «Дублировать».
+    pub fn duplicate(&self) -> Self {
+        Self {
+            id: next_tab_id(),
+            ..self.clone()
+        }
+    }
+
+    /// Переход на новый адрес: текущий уходит в «Назад», «Вперёд» сбрасывается.
+    pub fn navigate(&mut self, url: impl Into<String>) {
+        let url = url.into();
+        if url == self.url {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.url, url);
+        if !previous.is_empty() {
+            self.back.push(previous);
+            if self.back.len() > MAX_TAB_HISTORY {
+                self.back.remove(0);
+            }
+        }
+        self.forward.clear();
+        self.left_page();
+    }
+
+    /// Страница сама сменила адрес (ссылка, «Назад» в движке): история
+    /// сдвигается так же, как у движка.
+    pub fn follow(&mut self, url: impl Into<String>) {
+        let url = url.into();
+        if url == self.url {
+            return;
+        }
+        if self.back.last() == Some(&url) {
+            self.go_back();
+        } else if self.forward.last() == Some(&url) {
+            self.go_forward();
+        } else {
+            self.navigate(url);
+        }
+    }
+
+    fn left_page(&mut self) {
+        self.scroll_y = None;
+        self.favicon = None;
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        !self.back.is_empty()
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        !self.forward.is_empty()
+    }
+
+    pub fn go_back(&mut self) -> bool {
+        let Some(url) = self.back.pop() else {
+            return false;
+        };
+        self.forward.push(std::mem::replace(&mut self.url, url));
+        self.left_page();
+        true
+    }
+
+    pub fn go_forward(&mut self) -> bool {
+        let Some(url) = self.forward.pop() else {
+            return false;
+        };
+        self.back.push(std::mem::replace(&mut self.url, url));
+        self.left_page();
+        true
+    }
+}
+
+/// Вкладки окна в порядке на панели.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionWindow {
+    pub tabs: Vec<SessionTab>,
+    pub active: usize,
+    /// Закрытые вкладки, последняя закрытая — в конце.
+    pub closed_tabs: Vec<SessionTab>,
+}
+
+impl SessionWindow {
+    pub fn is_empty(&self) -> bool {
+        self.tabs.is_empty()
+    }
+
+    /// Номер активной вкладки в пределах списка.
+    pub fn active_index(&self) -> usize {
+        self.active.min(self.tabs.len().saturating_sub(1))
+    }
+}
+
+/// Сессия профиля: открытое окно и стек закрытых окон.
+///
+/// `clean_exit` сбрасывается при запуске и выставляется при нормальном выходе:
+/// если при следующем запуске он `false`, браузер завершился аварийно.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub window: SessionWindow,
+    /// Закрытые окна, последнее — в конце.
+    pub closed_windows: Vec<SessionWindow>,
+    pub clean_exit: bool,
+    pub saved_at: Option<DateTime<Utc>>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            window: SessionWindow::default(),
+            closed_windows: Vec::new(),
+            clean_exit: true,
+            saved_at: None,
+        }
+    }
+}
+
+impl Session {
+    /// Прошлый запуск оборвался, и есть что восстанавливать.
+    pub fn needs_recovery(&self) -> bool {
+        !self.clean_exit && !self.window.is_empty()
+    }
+
+    pub fn push_closed_window(&mut self, window: SessionWindow) {
+        if window.is_empty() {
+            return;
+        }
+        self.closed_windows.push(window);
+        if self.closed_windows.len() > MAX_CLOSED_WINDOWS {
+            self.closed_windows.remove(0);
+        }
+    }
+
+    pub fn pop_closed_window(&mut self) -> Option<SessionWindow> {
+        self.closed_windows.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PrivacyStore;
+
+    #[test]
+    fn tab_history_moves_between_back_and_forward() {
+        let mut tab = SessionTab::new("https://a.example/", "A");
+        tab.navigate("https://b.example/");
+        tab.navigate("https://c.example/");
+        assert!(tab.go_back());
+        assert!(tab.go_back());
+        assert!(!tab.go_back());
+        assert_eq!(tab.url, "https://a.example/");
+        assert!(tab.go_forward());
+        assert_eq!(tab.url, "https://b.example/");
+        assert_eq!(tab.forward, ["https://c.example/"]);
+        let copy = tab.duplicate();
+        assert_eq!(copy, tab);
+        assert_ne!(copy.id, tab.id);
+
+        // Новый переход обрезает «Вперёд».
+        tab.scroll_y = Some(120.0);
+        tab.navigate("https://d.example/");
+        assert!(!tab.can_go_forward());
+        assert_eq!(tab.back, ["https://a.example/", "https://b.example/"]);
+        assert_eq!(tab.scroll_y, None);
+
+        // Переходы внутри страницы: «Назад» движка и новая ссылка.
+        tab.follow("https://b.example/");
+        assert_eq!(tab.forward, ["https://d.example/"]);
+        tab.follow("https://d.example/");
+        assert!(!tab.can_go_forward());
+        tab.follow("https://f.example/");
+        assert_eq!(tab.back.last().unwrap(), "https://d.example/");
+
+        for i in 0..MAX_TAB_HISTORY + 5 {
+            tab.navigate(format!("https://e.example/{i}"));
+        }
+        assert_eq!(tab.back.len(), MAX_TAB_HISTORY);
+    }
+
+    #[test]
+    fn survives_store_round_trip_and_detects_crash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = PrivacyStore::open(tmp.path().join("privacy.db")).unwrap();
+        assert!(!store.get::<Session>(SESSION_KEY).unwrap().needs_recovery());
+
+        let mut tab = SessionTab::new("https://a.example/", "A");
+        tab.navigate("https://b.example/");
+        let mut session = Session {
+            window: SessionWindow {
+                tabs: vec![tab, SessionTab::new("https://c.example/", "C")],
+                active: 1,
+                closed_tabs: vec![SessionTab::new("https://closed.example/", "X")],
+            },
+            clean_exit: false,
+            saved_at: Some(Utc::now()),
+            ..Session::default()
+        };
+        session.push_closed_window(SessionWindow {
+            tabs: vec![SessionTab::new("https://old.example/", "Old")],
+            ..SessionWindow::default()
+        });
+        session.push_closed_window(SessionWindow::default());
+        store.set(SESSION_KEY, &session).unwrap();
+
+        let mut loaded: Session = store.get(SESSION_KEY).unwrap();
+        assert_eq!(loaded, session);
+        assert_ne!(loaded.window.tabs[0].id, loaded.window.tabs[1].id);
+        assert!(loaded.needs_recovery());
+        assert_eq!(loaded.window.tabs[0].back, ["https://a.example/"]);
+        let window = loaded.pop_closed_window().unwrap();
+        assert_eq!(window.tabs[0].url, "https://old.example/");
+        assert!(loaded.pop_closed_window().is_none());
+
+        loaded.clean_exit = true;
+        assert!(!loaded.needs_recovery());
+    }
+}
@@ -0,0 +1,441 @@
+use crate::secure::{check_kdf_params, write_atomic};
+use aes_gcm_siv::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256GcmSiv, Nonce,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use rand::RngCore;
+use rusqlite::{ffi, serialize::OwnedData, Connection, DatabaseName};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::Cell,
+    fmt, fs, io,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    ptr::NonNull,
+};
+
+/// Заголовок зашифрованной базы; он же AAD, так что подмена версии ломает расшифровку.
+const MAGIC: &[u8; 8] = b"PLUSDB\x00\x01";
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+const KEYRING_SERVICE: &str = "plus-browser";
+pub(crate) const META_FILE: &str = "encryption.json";
+const CREDENTIALS_FILE: &str = "credentials.json";
+
+/// Ключ шифрования баз профиля. В `Debug` не печатается.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DbKey([u8; 32]);
+
+impl DbKey {
+    pub fn random() -> Self {
+        let mut key = [0u8; 32];
+        rand::rng().fill_bytes(&mut key);
+        Self(key)
+    }
+}
+
+impl fmt::Debug for DbKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DbKey(..)")
+    }
+}
+
+/// `encryption.json` в каталоге профиля. Сам ключ лежит в keyring под `id`;
+/// `wrapped_key` — он же, зашифрованный ключом из пароля, на случай недоступного keyring.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct EncryptionMeta {
+    pub version: u32,
+    pub id: String,
+    #[serde(default)]
+    pub wrapped_key: Option<WrappedKey>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct WrappedKey {
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub blob: String,
+}
+
+impl EncryptionMeta {
+    pub fn read(dir: &Path) -> io::Result<Option<Self>> {
+        match fs::read(dir.join(META_FILE)) {
+            Ok(raw) => serde_json::from_slice(&raw).map(Some).map_err(invalid),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        write_atomic(
+            &dir.join(META_FILE),
+            &serde_json::to_vec_pretty(self).map_err(invalid)?,
+        )
+    }
+
+    fn keyring_entry(&self) -> io::Result<keyring::Entry> {
+        keyring::Entry::new(KEYRING_SERVICE, &format!("profile-{}", self.id))
+            .map_err(io::Error::other)
+    }
+
+    /// Ключ из keyring; `None`, если keyring недоступен или записи нет.
+    pub fn key_from_keyring(&self) -> Option<DbKey> {
+        let raw = self.keyring_entry().ok()?.get_password().ok()?;
+        B64.decode(raw).ok()?.try_into().ok().map(DbKey)
+    }
+
+    pub fn key_from_passphrase(&self, passphrase: &str) -> io::Result<DbKey> {
+        let wrapped = self.wrapped_key.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "profile key is only stored in the OS keyring",
+            )
+        })?;
+        let salt = B64.decode(&wrapped.salt).map_err(invalid)?;
+        let kek = derive_key(passphrase, &salt, wrapped)?;
+        let blob = B64.decode(&wrapped.blob).map_err(invalid)?;
+        let key = open(&kek, &blob).map_err(|_| {
+            io::Error::new(io::ErrorKind::PermissionDenied, "wrong profile passphrase")
+        })?;
+        key.try_into()
+            .map(DbKey)
+            .map_err(|_| invalid("bad key size"))
+    }
+
+    /// Новый ключ: в keyring и, если задан пароль, в обёртке в `encryption.json`.
+    pub fn create(passphrase: Option<&str>) -> io::Result<(Self, DbKey)> {
+        let mut key = [0u8; 32];
+        let mut id = [0u8; 16];
+        rand::rng().fill_bytes(&mut key);
+        rand::rng().fill_bytes(&mut id);
+        let key = DbKey(key);
+        let mut meta = Self {
+            version: 1,
+            id: id.iter().map(|b| format!("{b:02x}")).collect(),
+            wrapped_key: None,
+        };
+        if let Some(passphrase) = passphrase {
+            let mut salt = [0u8; SALT_LEN];
+            rand::rng().fill_bytes(&mut salt);
+            let mut wrapped = WrappedKey {
+                salt: B64.encode(salt),
+                m_cost: Params::DEFAULT_M_COST,
+                t_cost: Params::DEFAULT_T_COST,
+                p_cost: Params::DEFAULT_P_COST,
+                blob: String::new(),
+            };
+            let kek = derive_key(passphrase, &salt, &wrapped)?;
+            wrapped.blob = B64.encode(seal(&kek, &key.0)?);
+            meta.wrapped_key = Some(wrapped);
+        }
+        let stored = meta
+            .keyring_entry()
+            .and_then(|e| e.set_password(&B64.encode(key.0)).map_err(io::Error::other));
+        if stored.is_err() && passphrase.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "OS keyring is unavailable; set a passphrase",
+            ));
+        }
+        Ok((meta, key))
+    }
+
+    pub fn forget(&self) {
+        if let Ok(entry) = self.keyring_entry() {
+            let _ = entry.delete_credential();
+        }
+    }
+}
+
+/// `credentials.json`: ключ сохранённых паролей лежит в keyring под `id`.
+/// У зашифрованного профиля он же хранится здесь под ключом баз — на случай,
+/// когда профиль открыт паролем, а keyring недоступен.
+#[derive(Debug, Serialize, Deserialize)]
+struct CredentialsMeta {
+    id: String,
+    #[serde(default)]
+    wrapped_key: Option<String>,
+}
+
+/// Ключ паролей профиля в `dir`; создаётся при первом обращении. `db_key` — ключ
+/// баз зашифрованного профиля, если он открыт.
+pub(crate) fn credentials_key(dir: &Path, db_key: Option<&DbKey>) -> io::Result<DbKey> {
+    let path = dir.join(CREDENTIALS_FILE);
+    let meta = match fs::read(&path) {
+        Ok(raw) => Some(serde_json::from_slice::<CredentialsMeta>(&raw).map_err(invalid)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let Some(mut meta) = meta else {
+        let key = DbKey::random();
+        let mut id = [0u8; 16];
+        rand::rng().fill_bytes(&mut id);
+        let meta = CredentialsMeta {
+            id: id.iter().map(|b| format!("{b:02x}")).collect(),
+            wrapped_key: db_key
+                .map(|db_key| seal(db_key, &key.0).map(|blob| B64.encode(blob)))
+                .transpose()?,
+        };
+        let stored = credentials_entry(&meta.id)
+            .and_then(|e| e.set_password(&B64.encode(key.0)).map_err(io::Error::other));
+        if stored.is_err() && meta.wrapped_key.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "OS keyring is unavailable; encrypt the profile to save passwords",
+            ));
+        }
+        write_atomic(&path, &serde_json::to_vec_pretty(&meta).map_err(invalid)?)?;
+        return Ok(key);
+    };
+    let from_keyring = credentials_entry(&meta.id)
+        .ok()
+        .and_then(|e| e.get_password().ok())
+        .and_then(|raw| B64.decode(raw).ok())
+        .and_then(|raw| <[u8; 32]>::try_from(raw).ok())
+        .map(DbKey);
+    let key = match (from_keyring, &meta.wrapped_key, db_key) {
+        (Some(key), _, _) => key,
+        (None, Some(wrapped), Some(db_key)) => {
+            let raw = open(db_key, &B64.decode(wrapped).map_err(invalid)?)?;
+            raw.try_into()
+                .map(DbKey)
+                .map_err(|_| invalid("bad key size"))?
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the key for saved passwords is not in the OS keyring",
+            ))
+        }
+    };
+    // Профиль зашифровали позже, чем появились пароли.
+    if let (None, Some(db_key)) = (&meta.wrapped_key, db_key) {
+        meta.wrapped_key = Some(B64.encode(seal(db_key, &key.0)?));
+        write_atomic(&path, &serde_json::to_vec_pretty(&meta).map_err(invalid)?)?;
+    }
+    Ok(key)
+}
+
+/// Убирает ключ паролей профиля из keyring.
+pub(crate) fn forget_credentials(dir: &Path) {
+    let meta = fs::read(dir.join(CREDENTIALS_FILE))
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<CredentialsMeta>(&raw).ok());
+    if let Some(entry) = meta.and_then(|meta| credentials_entry(&meta.id).ok()) {
+        let _ = entry.delete_credential();
+    }
+}
+
+fn credentials_entry(id: &str) -> io::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, &format!("credentials-{id}")).map_err(io::Error::other)
+}
+
+/// Соединение с базой профиля. У зашифрованного профиля база расшифрована
+/// в память и записывается обратно по `flush` и при закрытии, если с прошлой
+/// записи в ней что-то менялось.
+pub struct ProfileDb {
+    conn: Connection,
+    sealed: Option<(PathBuf, DbKey)>,
+    /// `total_changes` на момент последней записи файла; `None` — файла ещё нет.
+    flushed: Cell<Option<u64>>,
+}
+
+impl ProfileDb {
+    /// Открывает `path` ключом `key`. Обычная база SQLite на этом месте — профиль,
+    /// который только что зашифровали: она шифруется на месте вместе с копиями миграций.
+    pub(crate) fn open_encrypted(path: &Path, key: &DbKey) -> io::Result<Self> {
+        let mut conn = Connection::open_in_memory().map_err(io::Error::other)?;
+        let exists = match fs::read(path) {
+            Ok(bytes) if bytes.starts_with(SQLITE_MAGIC) => {
+                let bytes = {
+                    let plain = Connection::open(path).map_err(io::Error::other)?;
+                    plain
+                        .execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
+                        .map_err(io::Error::other)?;
+                    plain
+                        .serialize(DatabaseName::Main)
+                        .map_err(io::Error::other)?
+                        .to_vec()
+                };
+                write_atomic(path, &seal(key, &bytes)?)?;
+                remove_plaintext_leftovers(path)?;
+                load(&mut conn, &bytes)?;
+                true
+            }
+            Ok(bytes) => {
+                let plain = open(key, &bytes)?;
+                load(&mut conn, &plain)?;
+                true
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            flushed: Cell::new(exists.then(|| conn.total_changes())),
+            conn,
+            sealed: Some((path.to_path_buf(), key.clone())),
+        })
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.sealed.is_some()
+    }
+
+    /// Шифрует текущее состояние базы и атомарно заменяет файл. Для обычной базы
+    /// и для зашифрованной без изменений с прошлой записи ничего не делает, поэтому
+    /// звать можно часто. Чтение не пишет файл, и закрытие базы, которую только
+    /// читали, не затрёт записи другого процесса.
+    pub fn flush(&self) -> io::Result<()> {
+        let Some((path, key)) = &self.sealed else {
+            return Ok(());
+        };
+        let changes = self.conn.total_changes();
+        if self.flushed.get() == Some(changes) {
+            return Ok(());
+        }
+        let data = self
+            .conn
+            .serialize(DatabaseName::Main)
+            .map_err(io::Error::other)?;
+        write_atomic(path, &seal(key, &data)?)?;
+        self.flushed.set(Some(changes));
+        Ok(())
+    }
+
+    /// Версия содержимого: меняется после каждой записи — этого соединения
+    /// (`total_changes`) и других (`PRAGMA data_version`, например фонового импорта).
+    /// По ней кэшируют результаты запросов.
+    pub fn revision(&self) -> io::Result<(u64, i64)> {
+        let data_version = self
+            .conn
+            .query_row("PRAGMA data_version", [], |r| r.get(0))
+            .map_err(io::Error::other)?;
+        Ok((self.conn.total_changes(), data_version))
+    }
+}
+
+impl From<Connection> for ProfileDb {
+    fn from(conn: Connection) -> Self {
+        Self {
+            conn,
+            sealed: None,
+            flushed: Cell::new(None),
+        }
+    }
+}
+
+impl Deref for ProfileDb {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl DerefMut for ProfileDb {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+}
+
+impl Drop for ProfileDb {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn load(conn: &mut Connection, bytes: &[u8]) -> io::Result<()> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    // SQLite освобождает буфер сам (SQLITE_DESERIALIZE_FREEONCLOSE), поэтому он
+    // выделяется через sqlite3_malloc64, а не аллокатором Rust.
+    let ptr = unsafe { ffi::sqlite3_malloc64(bytes.len() as u64) }.cast::<u8>();
+    let ptr = NonNull::new(ptr).ok_or_else(|| io::Error::from(io::ErrorKind::OutOfMemory))?;
+    let data = unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.as_ptr(), bytes.len());
+        OwnedData::from_raw_nonnull(ptr, bytes.len())
+    };
+    conn.deserialize(DatabaseName::Main, data, false)
+        .map_err(io::Error::other)
+}
+
+/// WAL, журнал и копии миграций (`<db>.vN.bak`) остались бы открытым текстом.
+fn remove_plaintext_leftovers(path: &Path) -> io::Result<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return Ok(());
+    };
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file = entry.file_name();
+        let Some(file) = file.to_str() else {
+            continue;
+        };
+        let leftover = file.strip_prefix(name).is_some_and(|rest| {
+            matches!(rest, "-wal" | "-shm" | "-journal")
+                || rest.starts_with(".v") && rest.ends_with(".bak")
+        });
+        if leftover {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn seal(key: &DbKey, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut nonce);
+    let cipher = Aes256GcmSiv::new_from_slice(&key.0).map_err(invalid)?;
+    let data = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: MAGIC,
+            },
+        )
+        .map_err(|_| io::Error::other("encrypt failed"))?;
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + data.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&data);
+    Ok(out)
+}
+
+pub(crate) fn open(key: &DbKey, blob: &[u8]) -> io::Result<Vec<u8>> {
+    let rest = blob
+        .strip_prefix(MAGIC.as_slice())
+        .filter(|rest| rest.len() >= NONCE_LEN)
+        .ok_or_else(|| invalid("not an encrypted profile database"))?;
+    let (nonce, data) = rest.split_at(NONCE_LEN);
+    Aes256GcmSiv::new_from_slice(&key.0)
+        .map_err(invalid)?
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: data,
+                aad: MAGIC,
+            },
+        )
+        .map_err(|_| invalid("decrypt failed: wrong key or corrupted database"))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], wrapped: &WrappedKey) -> io::Result<DbKey> {
+    check_kdf_params(wrapped.m_cost, wrapped.t_cost, wrapped.p_cost)?;
+    let params =
+        Params::new(wrapped.m_cost, wrapped.t_cost, wrapped.p_cost, Some(32)).map_err(invalid)?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(invalid)?;
+    Ok(DbKey(key))
+}
+
+fn invalid(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
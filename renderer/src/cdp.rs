@@ -0,0 +1,87 @@
+//! Разбор и сборка сообщений DevTools Protocol: банка cookie WebView2 читается
+//! и чистится через домен `Network`, в том же формате, что и у других движков.
+
+use chrono::{TimeZone, Utc};
+use plus_privacy::{Cookie, SameSite};
+use serde_json::{json, Value};
+
+/// Ответ `Network.getAllCookies`.
+pub(crate) fn parse_cookies(response: &str) -> Vec<Cookie> {
+    let Ok(response) = serde_json::from_str::<Value>(response) else {
+        return Vec::new();
+    };
+    let now = Utc::now();
+    let cookies = response["cookies"].as_array().cloned().unwrap_or_default();
+    cookies
+        .iter()
+        .filter_map(|cookie| {
+            let domain = cookie["domain"].as_str()?;
+            // `expires` — секунды; у сессионных cookie -1.
+            let expires = cookie["expires"]
+                .as_f64()
+                .filter(|_| !cookie["session"].as_bool().unwrap_or(false))
+                .filter(|at| *at > 0.0)
+                .and_then(|at| Utc.timestamp_millis_opt((at * 1000.0) as i64).single());
+            Some(Cookie {
+                name: cookie["name"].as_str()?.to_string(),
+                value: cookie["value"].as_str().unwrap_or_default().to_string(),
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                path: cookie["path"].as_str().unwrap_or("/").to_string(),
+                expires,
+                secure: cookie["secure"].as_bool().unwrap_or(false),
+                http_only: cookie["httpOnly"].as_bool().unwrap_or(false),
+                same_site: match cookie["sameSite"].as_str() {
+                    Some("Strict") => SameSite::Strict,
+                    Some("None") => SameSite::None,
+                    _ => SameSite::Lax,
+                },
+                host_only: !domain.starts_with('.'),
+                created_at: now,
+                last_access: now,
+            })
+        })
+        .collect()
+}
+
+/// Параметры `Network.deleteCookies`: домен сравнивается точно, у доменных
+/// cookie Chromium хранит его с ведущей точкой.
+pub(crate) fn delete_cookie_params(cookie: &Cookie) -> String {
+    let domain = if cookie.host_only {
+        cookie.domain.clone()
+    } else {
+        format!(".{}", cookie.domain)
+    };
+    json!({ "name": cookie.name, "domain": domain, "path": cookie.path }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_engine_cookies_and_deletes_them_exactly() {
+        let cookies = parse_cookies(
+            r#"{"cookies":[
+                {"name":"sid","value":"1","domain":".ya.ru","path":"/","expires":2000000000.5,
+                 "size":4,"httpOnly":true,"secure":true,"session":false,"sameSite":"Strict"},
+                {"name":"tmp","value":"","domain":"mail.ru","path":"/inbox","expires":-1,
+                 "size":3,"httpOnly":false,"secure":false,"session":true}
+            ]}"#,
+        );
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].domain, "ya.ru");
+        assert!(!cookies[0].host_only && cookies[0].http_only && cookies[0].secure);
+        assert_eq!(cookies[0].same_site, SameSite::Strict);
+        assert_eq!(cookies[0].expires.unwrap().timestamp(), 2_000_000_000);
+        assert!(cookies[1].host_only && cookies[1].expires.is_none());
+        assert_eq!(cookies[1].same_site, SameSite::Lax);
+
+        let params: Value = serde_json::from_str(&delete_cookie_params(&cookies[0])).unwrap();
+        assert_eq!(params["domain"], ".ya.ru");
+        let params: Value = serde_json::from_str(&delete_cookie_params(&cookies[1])).unwrap();
+        assert_eq!(params["domain"], "mail.ru");
+        assert_eq!(params["path"], "/inbox");
+
+        assert!(parse_cookies("not json").is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::tabs::TabId;
+use std::sync::{mpsc, Arc, Mutex};
+
+/// Что происходит со страницей вкладки. События приходят в порядке, в котором
+/// их сообщил движок; приложение разбирает их в своём цикле отрисовки.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NavigationEvent {
+    /// Началась загрузка адреса — по ссылке, из адресной строки или «Назад».
+    Starting {
+        tab: TabId,
+        url: String,
+    },
+    /// Адрес документа сменился: переход зафиксирован, редиректы уже пройдены.
+    SourceChanged {
+        tab: TabId,
+        url: String,
+    },
+    TitleChanged {
+        tab: TabId,
+        title: String,
+    },
+    FaviconChanged {
+        tab: TabId,
+        url: String,
+    },
+    /// Изменилась история WebView вкладки.
+    HistoryChanged {
+        tab: TabId,
+        can_go_back: bool,
+        can_go_forward: bool,
+    },
+    /// Загрузка закончилась; `error` — причина неудачи, если она есть.
+    Completed {
+        tab: TabId,
+        error: Option<String>,
+    },
+}
+
+impl NavigationEvent {
+    pub fn tab(&self) -> TabId {
+        match self {
+            Self::Starting { tab, .. }
+            | Self::SourceChanged { tab, .. }
+            | Self::TitleChanged { tab, .. }
+            | Self::FaviconChanged { tab, .. }
+            | Self::HistoryChanged { tab, .. }
+            | Self::Completed { tab, .. } => *tab,
+        }
+    }
+}
+
+/// Рассылает события подписчикам; клоны уходят в обработчики движка.
+#[derive(Clone, Default)]
+pub(crate) struct EventSink {
+    watchers: Arc<Mutex<Vec<mpsc::Sender<NavigationEvent>>>>,
+}
+
+impl EventSink {
+    pub fn subscribe(&self) -> mpsc::Receiver<NavigationEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.watchers.lock().expect("watchers lock").push(tx);
+        rx
+    }
+
+    pub fn emit(&self, event: NavigationEvent) {
+        self.watchers
+            .lock()
+            .expect("watchers lock")
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_to_live_subscribers() {
+        let sink = EventSink::default();
+        let first = sink.subscribe();
+        let second = sink.subscribe();
+        drop(second);
+        let engine = sink.clone();
+        engine.emit(NavigationEvent::TitleChanged {
+            tab: 1,
+            title: "Пример".into(),
+        });
+        assert_eq!(
+            first.try_recv().unwrap(),
+            NavigationEvent::TitleChanged {
+                tab: 1,
+                title: "Пример".into()
+            }
+        );
+        assert_eq!(sink.watchers.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::events::{EventSink, NavigationEvent};
+use crate::tabs::{TabId, TabViews, MAX_BACKGROUND_VIEWS};
+use crate::WebViewHost;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use plus_adblock::AdblockEngine;
+use plus_net::{SchemeRouter, INTERNAL_SCHEME};
+use plus_privacy::CookieSettings;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+
+#[derive(Default)]
+struct Page {
+    url: String,
+    back: Vec<String>,
+    forward: Vec<String>,
+}
+
+/// Движок без окна: страница — это только адрес, сеть не трогается. События
+/// приходят сразу, в том же порядке, что у настоящего движка; заголовок
+/// страницы — её адрес. Для тестов и сборок без WebView.
+pub struct HeadlessHost {
+    views: TabViews<Page>,
+    events: EventSink,
+    adblock: Option<Arc<Mutex<AdblockEngine>>>,
+    internal_pages: Option<SchemeRouter>,
+}
+
+impl HeadlessHost {
+    pub fn new() -> Self {
+        Self {
+            views: TabViews::new(),
+            events: EventSink::default(),
+            adblock: None,
+            internal_pages: None,
+        }
+    }
+
+    /// Почему адрес не открылся бы в настоящем движке.
+    fn load_error(&self, url: &str) -> Option<String> {
+        if url.starts_with(&format!("{INTERNAL_SCHEME}:")) {
+            let router = self.internal_pages.as_ref()?;
+            let response = router.handle("GET", url, None);
+            return (response.status != 200).then(|| response.reason().to_string());
+        }
+        let adblock = self.adblock.as_ref()?;
+        let blocked = adblock
+            .lock()
+            .expect("adblock lock")
+            .should_block(url, url, "document");
+        blocked.then(|| "blocked".to_string())
+    }
+
+    /// Событие загрузки: `url` уже стал адресом страницы вкладки.
+    fn load(&mut self, tab: TabId) {
+        let Some(page) = self.views.get(tab) else {
+            return;
+        };
+        let url = page.url.clone();
+        let (can_go_back, can_go_forward) = (!page.back.is_empty(), !page.forward.is_empty());
+        let error = self.load_error(&url);
+        self.events.emit(NavigationEvent::Starting {
+            tab,
+            url: url.clone(),
+        });
+        self.events.emit(NavigationEvent::SourceChanged {
+            tab,
+            url: url.clone(),
+        });
+        self.events.emit(NavigationEvent::HistoryChanged {
+            tab,
+            can_go_back,
+            can_go_forward,
+        });
+        if error.is_none() {
+            self.events
+                .emit(NavigationEvent::TitleChanged { tab, title: url });
+        }
+        self.events.emit(NavigationEvent::Completed { tab, error });
+    }
+
+    fn active_page(&mut self) -> Result<(TabId, &mut Page)> {
+        let tab = self
+            .views
+            .active_id()
+            .ok_or_else(|| anyhow!("no active tab"))?;
+        let page = self
+            .views
+            .get_mut(tab)
+            .ok_or_else(|| anyhow!("no active tab"))?;
+        Ok((tab, page))
+    }
+}
+
+impl Default for HeadlessHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WebViewHost for HeadlessHost {
+    fn set_user_data_dir(&mut self, _dir: PathBuf) {}
+
+    fn initialize(&mut self) -> Result<()> {
+        self.views.clear();
+        Ok(())
+    }
+
+    fn set_proxy(&mut self, _proxy: Option<String>) -> Result<()> {
+        self.initialize()
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<NavigationEvent> {
+        self.events.subscribe()
+    }
+
+    fn show_tab(&mut self, tab: TabId, url: &str) -> Result<bool> {
+        if self.views.active_id() == Some(tab) && self.views.contains(tab) {
+            return Ok(false);
+        }
+        let created = !self.views.contains(tab);
+        if created {
+            self.navigate(tab, url)?;
+        }
+        self.views.activate(tab);
+        self.views.discard_background(MAX_BACKGROUND_VIEWS);
+        Ok(created)
+    }
+
+    fn retain_tabs(&mut self, open: &[TabId]) {
+        self.views.retain(open);
+    }
+
+    fn navigate(&mut self, tab: TabId, url: &str) -> Result<()> {
+        if !self.views.contains(tab) {
+            self.views.insert(tab, Page::default());
+        }
+        let page = self
+            .views
+            .get_mut(tab)
+            .ok_or_else(|| anyhow!("no tab {tab}"))?;
+        if !page.url.is_empty() {
+            page.back.push(std::mem::take(&mut page.url));
+        }
+        page.url = url.to_string();
+        page.forward.clear();
+        self.load(tab);
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let (tab, _) = self.active_page()?;
+        self.load(tab);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn go_back(&mut self) -> Result<()> {
+        let (tab, page) = self.active_page()?;
+        let Some(url) = page.back.pop() else {
+            return Ok(());
+        };
+        page.forward.push(std::mem::replace(&mut page.url, url));
+        self.load(tab);
+        Ok(())
+    }
+
+    fn go_forward(&mut self) -> Result<()> {
+        let (tab, page) = self.active_page()?;
+        let Some(url) = page.forward.pop() else {
+            return Ok(());
+        };
+        page.back.push(std::mem::replace(&mut page.url, url));
+        self.load(tab);
+        Ok(())
+    }
+
+    /// Скриптов без движка нет: вызов ничего не делает.
+    fn execute_script(&mut self, _js: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_bounds(&mut self, _x: i32, _y: i32, _width: i32, _height: i32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Заблокированный документ завершается ошибкой `blocked`.
+    fn add_adblock_handler(&mut self, adblock: Arc<Mutex<AdblockEngine>>) -> Result<()> {
+        self.adblock = Some(adblock);
+        Ok(())
+    }
+
+    /// Запросов в сеть нет, снимать cookie не с чего.
+    fn add_cookie_handler(&mut self, _rules: Arc<Mutex<CookieSettings>>) -> Result<()> {
+        Ok(())
+    }
+
+    fn add_internal_scheme_handler(&mut self, router: SchemeRouter) -> Result<()> {
+        self.internal_pages = Some(router);
+        Ok(())
+    }
+
+    /// Ни кэша, ни cookie у движка без сети нет.
+    fn clear_browsing_data(
+        &mut self,
+        _cookies: bool,
+        _cache: bool,
+        _since: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(events: &mpsc::Receiver<NavigationEvent>) -> Vec<NavigationEvent> {
+        events.try_iter().collect()
+    }
+
+    #[test]
+    fn tab_switch_keeps_page_and_history() {
+        let mut host = HeadlessHost::new();
+        let events = host.subscribe();
+        assert!(host.show_tab(1, "https://a.example/").unwrap());
+        host.navigate(1, "https://b.example/").unwrap();
+        assert!(host.show_tab(2, "https://c.example/").unwrap());
+        drain(&events);
+
+        // Возврат на вкладку не перезагружает её.
+        assert!(!host.show_tab(1, "https://a.example/").unwrap());
+        assert!(drain(&events).is_empty());
+
+        host.go_back().unwrap();
+        let events = drain(&events);
+        assert_eq!(
+            events[1],
+            NavigationEvent::SourceChanged {
+                tab: 1,
+                url: "https://a.example/".into()
+            }
+        );
+        assert_eq!(
+            events[2],
+            NavigationEvent::HistoryChanged {
+                tab: 1,
+                can_go_back: false,
+                can_go_forward: true
+            }
+        );
+        assert_eq!(
+            events.last(),
+            Some(&NavigationEvent::Completed {
+                tab: 1,
+                error: None
+            })
+        );
+
+        // Закрытая вкладка при следующем показе открывается заново.
+        host.retain_tabs(&[1]);
+        assert!(host.show_tab(2, "https://c.example/").unwrap());
+    }
+
+    #[test]
+    fn has_no_cookie_jar_to_snapshot() {
+        let mut host = HeadlessHost::new();
+        let called = Arc::new(Mutex::new(false));
+        let flag = called.clone();
+        let requested = host.get_cookies(Box::new(move |_| *flag.lock().unwrap() = true));
+        assert!(requested.is_err());
+        assert!(!*called.lock().unwrap());
+    }
+
+    #[test]
+    fn reports_blocked_and_unknown_pages() {
+        let adblock = Arc::new(Mutex::new(
+            AdblockEngine::from_filter_list("||ads.example^").unwrap(),
+        ));
+        let mut host = HeadlessHost::new();
+        host.add_adblock_handler(adblock.clone()).unwrap();
+        host.add_internal_scheme_handler(SchemeRouter::new(adblock))
+            .unwrap();
+        let events = host.subscribe();
+
+        for (url, error) in [
+            ("https://ads.example/", Some("blocked")),
+            ("plus://newtab", None),
+            ("plus://missing", Some("Not Found")),
+        ] {
+            host.navigate(1, url).unwrap();
+            let completed = drain(&events).pop().unwrap();
+            assert_eq!(
+                completed,
+                NavigationEvent::Completed {
+                    tab: 1,
+                    error: error.map(String::from)
+                },
+                "{url}"
+            );
+        }
+    }
+}
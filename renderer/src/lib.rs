@@ -1,8 +1,112 @@
+#[cfg_attr(not(windows), allow(dead_code))]
+mod cdp;
+mod events;
+mod headless;
+#[cfg(all(target_os = "linux", feature = "wry"))]
+mod linux;
+mod tabs;
 #[cfg(windows)]
 mod windows;
 
+pub use events::NavigationEvent;
+pub use headless::HeadlessHost;
+#[cfg(all(target_os = "linux", feature = "wry"))]
+pub use linux::WebViewHostLinux;
+pub use tabs::TabId;
 #[cfg(windows)]
 pub use windows::WebViewHostWindows;
 
-#[cfg(not(windows))]
-pub use windows::WebViewHostWindows;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use plus_adblock::AdblockEngine;
+use plus_net::SchemeRouter;
+use plus_privacy::{Cookie, CookieSettings};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+
+/// Движок, показывающий страницы вкладок: WebView2 на Windows, WebKitGTK через
+/// wry на Linux, `HeadlessHost` без окна. Приложение работает только с этим
+/// трейтом, поэтому логику вкладок можно проверять без движка.
+///
+/// Вкладка получает своё представление при первом показе или переходе;
+/// `reload`, `stop`, «Назад» и «Вперёд» относятся к активной вкладке.
+pub trait WebViewHost {
+    /// Каталог данных движка (cookie, кэш, localStorage); у каждого профиля свой.
+    /// Задаётся до `initialize`.
+    fn set_user_data_dir(&mut self, dir: PathBuf);
+
+    /// Готовит движок. Открытые вкладки закрываются и пересоздадутся при следующем показе.
+    fn initialize(&mut self) -> Result<()>;
+
+    /// Прокси вида `http://host:port` для всех вкладок; движок пересоздаётся.
+    fn set_proxy(&mut self, proxy: Option<String>) -> Result<()>;
+
+    /// События навигации всех вкладок.
+    fn subscribe(&self) -> mpsc::Receiver<NavigationEvent>;
+
+    /// Показывает вкладку. Если её представления ещё нет или оно было выгружено,
+    /// создаёт его, открывает `url` и возвращает `true`; иначе страница остаётся как была.
+    fn show_tab(&mut self, tab: TabId, url: &str) -> Result<bool>;
+
+    /// Закрывает представления вкладок, которых больше нет в окне.
+    fn retain_tabs(&mut self, open: &[TabId]);
+
+    /// Открывает адрес во вкладке, создавая для неё представление при необходимости.
+    fn navigate(&mut self, tab: TabId, url: &str) -> Result<()>;
+
+    fn reload(&mut self) -> Result<()>;
+
+    fn stop(&mut self) -> Result<()>;
+
+    fn go_back(&mut self) -> Result<()>;
+
+    fn go_forward(&mut self) -> Result<()>;
+
+    fn execute_script(&mut self, js: &str) -> Result<()>;
+
+    /// Выполняет скрипт в активной вкладке; его результат в JSON приходит позже в `done`.
+    /// Если результата нет (ошибка скрипта, вкладку закрыли), `done` не вызывается.
+    fn evaluate_script(&mut self, _js: &str, _done: Box<dyn FnOnce(String) + Send>) -> Result<()> {
+        bail!("the engine does not return script results")
+    }
+
+    /// Область страницы в физических пикселях окна.
+    fn set_bounds(&mut self, x: i32, y: i32, width: i32, height: i32) -> Result<()>;
+
+    /// Блокировка запросов AdBlock для открытых и будущих вкладок.
+    fn add_adblock_handler(&mut self, adblock: Arc<Mutex<AdblockEngine>>) -> Result<()>;
+
+    /// Снимает `Cookie` с запросов, которым правила профиля запрещают cookie.
+    fn add_cookie_handler(&mut self, rules: Arc<Mutex<CookieSettings>>) -> Result<()>;
+
+    /// Отвечает на запросы `plus://` из роутера внутренних страниц, не выпуская их в сеть.
+    fn add_internal_scheme_handler(&mut self, router: SchemeRouter) -> Result<()>;
+
+    /// Снимок банки cookie движка — тех, что страницы получают на самом деле.
+    /// Ответ приходит позже в `done`; если снимок не получен, `done` не вызывается.
+    /// Движок без своей банки отвечает ошибкой: пустой снимок стёр бы хранилище профиля.
+    fn get_cookies(&mut self, _done: Box<dyn FnOnce(Vec<Cookie>)>) -> Result<()> {
+        bail!("the engine has no cookie jar of its own")
+    }
+
+    fn delete_cookie(&mut self, _cookie: &Cookie) -> Result<()> {
+        Ok(())
+    }
+
+    /// Удаляет cookie, созданные начиная с `since` (`None` — все), и/или кэш
+    /// целиком — сразу, у работающего движка.
+    fn clear_browsing_data(
+        &mut self,
+        _cookies: bool,
+        _cache: bool,
+        _since: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        bail!("the engine cannot clear its data while running")
+    }
+
+    /// Обрабатывает накопившиеся события движка, если у него нет своего цикла
+    /// (GTK внутри окна egui). `true` — позвать снова в следующем кадре.
+    fn pump_events(&mut self) -> bool {
+        false
+    }
+}
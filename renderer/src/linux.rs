@@ -0,0 +1,285 @@
+use crate::events::{EventSink, NavigationEvent};
+use crate::tabs::{TabId, TabViews, MAX_BACKGROUND_VIEWS, MEMORY_PRESSURE_LOAD};
+use crate::WebViewHost;
+use anyhow::{anyhow, bail, Result};
+use plus_adblock::AdblockEngine;
+use plus_net::{SchemeRouter, INTERNAL_SCHEME};
+use plus_privacy::CookieSettings;
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use wry::dpi::{PhysicalPosition, PhysicalSize};
+use wry::http::{header, Request, Response};
+use wry::raw_window_handle::{HandleError, HasWindowHandle, RawWindowHandle, WindowHandle};
+use wry::{PageLoadEvent, ProxyConfig, ProxyEndpoint, Rect, WebContext, WebView, WebViewBuilder};
+
+/// Окно приложения, в которое встраиваются WebView вкладок.
+struct ParentWindow(RawWindowHandle);
+
+impl HasWindowHandle for ParentWindow {
+    fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+        // SAFETY: хост создаётся из окна приложения и закрывается раньше него.
+        Ok(unsafe { WindowHandle::borrow_raw(self.0) })
+    }
+}
+
+/// WebKitGTK через wry, WebView на каждую вкладку — как `WebViewHostWindows`.
+///
+/// wry не даёт перехватывать запросы страниц: AdBlock работает только в локальном
+/// прокси, заданном `set_proxy`, а правила cookie к страницам не применяются.
+pub struct WebViewHostLinux {
+    parent: ParentWindow,
+    context: Option<WebContext>,
+    views: TabViews<WebView>,
+    events: EventSink,
+    bounds: Rect,
+    proxy: Option<String>,
+    user_data_dir: Option<PathBuf>,
+    internal_pages: Option<SchemeRouter>,
+}
+
+impl WebViewHostLinux {
+    /// `parent` — X11-окно приложения: встраивать WebKitGTK в чужое окно wry
+    /// умеет только в X11 (под Wayland — через XWayland, с пустым `WAYLAND_DISPLAY`).
+    pub fn new(parent: RawWindowHandle) -> Result<Self> {
+        if !matches!(parent, RawWindowHandle::Xlib(_) | RawWindowHandle::Xcb(_)) {
+            bail!("WebKitGTK can only be embedded into an X11 window");
+        }
+        gtk::init()?;
+        Ok(Self {
+            parent: ParentWindow(parent),
+            context: None,
+            views: TabViews::new(),
+            events: EventSink::default(),
+            bounds: Rect::default(),
+            proxy: None,
+            user_data_dir: None,
+            internal_pages: None,
+        })
+    }
+
+    fn create_view(&mut self, tab: TabId, url: &str) -> Result<WebView> {
+        let context = self
+            .context
+            .as_mut()
+            .ok_or_else(|| anyhow!("webview not initialized"))?;
+        // Схема регистрируется на весь WebContext, повторная регистрация — ошибка.
+        let register_scheme = !context.is_custom_protocol_registered(INTERNAL_SCHEME.to_string());
+        let id = tab.to_string();
+        let mut builder = WebViewBuilder::with_web_context(context)
+            .with_id(&id)
+            .with_url(url)
+            .with_visible(false)
+            .with_bounds(self.bounds);
+        if let Some(proxy) = self.proxy.as_deref().and_then(proxy_endpoint) {
+            builder = builder.with_proxy_config(ProxyConfig::Http(proxy));
+        }
+        if let (true, Some(router)) = (register_scheme, self.internal_pages.clone()) {
+            builder = builder.with_custom_protocol(INTERNAL_SCHEME.into(), move |_, request| {
+                internal_response(&router, &request)
+            });
+        }
+        let sink = self.events.clone();
+        builder = builder.with_on_page_load_handler(move |event, url| match event {
+            PageLoadEvent::Started => sink.emit(NavigationEvent::Starting { tab, url }),
+            PageLoadEvent::Finished => {
+                sink.emit(NavigationEvent::SourceChanged { tab, url });
+                sink.emit(NavigationEvent::Completed { tab, error: None });
+            }
+        });
+        let sink = self.events.clone();
+        builder = builder.with_document_title_changed_handler(move |title| {
+            sink.emit(NavigationEvent::TitleChanged { tab, title });
+        });
+        Ok(builder.build_as_child(&self.parent)?)
+    }
+
+    fn discard_background(&mut self) {
+        let keep = if memory_load().is_some_and(|load| load >= MEMORY_PRESSURE_LOAD) {
+            0
+        } else {
+            MAX_BACKGROUND_VIEWS
+        };
+        self.views.discard_background(keep);
+    }
+
+    fn active_webview(&self) -> Result<&WebView> {
+        self.views
+            .active()
+            .ok_or_else(|| anyhow!("webview not initialized"))
+    }
+}
+
+impl WebViewHost for WebViewHostLinux {
+    fn set_user_data_dir(&mut self, dir: PathBuf) {
+        self.user_data_dir = Some(dir);
+    }
+
+    fn initialize(&mut self) -> Result<()> {
+        self.views.clear();
+        self.context = Some(WebContext::new(self.user_data_dir.clone()));
+        Ok(())
+    }
+
+    fn set_proxy(&mut self, proxy: Option<String>) -> Result<()> {
+        self.proxy = proxy;
+        self.initialize()
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<NavigationEvent> {
+        self.events.subscribe()
+    }
+
+    fn show_tab(&mut self, tab: TabId, url: &str) -> Result<bool> {
+        if self.views.active_id() == Some(tab) && self.views.contains(tab) {
+            return Ok(false);
+        }
+        let created = !self.views.contains(tab);
+        if created {
+            self.navigate(tab, url)?;
+        }
+        if let Some(previous) = self.views.activate(tab).and_then(|t| self.views.get(t)) {
+            previous.set_visible(false)?;
+        }
+        if let Some(view) = self.views.get(tab) {
+            view.set_bounds(self.bounds)?;
+            view.set_visible(true)?;
+        }
+        self.discard_background();
+        Ok(created)
+    }
+
+    fn retain_tabs(&mut self, open: &[TabId]) {
+        self.views.retain(open);
+    }
+
+    fn navigate(&mut self, tab: TabId, url: &str) -> Result<()> {
+        match self.views.get(tab) {
+            Some(view) => view.load_url(url)?,
+            None => {
+                let view = self.create_view(tab, url)?;
+                self.views.insert(tab, view);
+            }
+        }
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        self.execute_script("location.reload()")
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.execute_script("window.stop()")
+    }
+
+    fn go_back(&mut self) -> Result<()> {
+        self.execute_script("history.back()")
+    }
+
+    fn go_forward(&mut self) -> Result<()> {
+        self.execute_script("history.forward()")
+    }
+
+    fn execute_script(&mut self, js: &str) -> Result<()> {
+        self.active_webview()?.evaluate_script(js)?;
+        Ok(())
+    }
+
+    /// wry зовёт обработчик как `Fn`, а ответ нужен один.
+    fn evaluate_script(&mut self, js: &str, done: Box<dyn FnOnce(String) + Send>) -> Result<()> {
+        let done = Mutex::new(Some(done));
+        self.active_webview()?
+            .evaluate_script_with_callback(js, move |json| {
+                if let Some(done) = done.lock().ok().and_then(|mut d| d.take()) {
+                    done(json);
+                }
+            })?;
+        Ok(())
+    }
+
+    fn set_bounds(&mut self, x: i32, y: i32, width: i32, height: i32) -> Result<()> {
+        self.bounds = Rect {
+            position: PhysicalPosition::new(x, y).into(),
+            size: PhysicalSize::new(width.max(0) as u32, height.max(0) as u32).into(),
+        };
+        if let Some(view) = self.views.active() {
+            view.set_bounds(self.bounds)?;
+        }
+        Ok(())
+    }
+
+    /// Запросы страниц не перехватываются: блокировка идёт в локальном прокси.
+    fn add_adblock_handler(&mut self, _adblock: Arc<Mutex<AdblockEngine>>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Заголовки запросов страниц wry не отдаёт, правила cookie здесь не применяются.
+    fn add_cookie_handler(&mut self, _rules: Arc<Mutex<CookieSettings>>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Задаётся до первой вкладки: схема регистрируется вместе с первым WebView.
+    fn add_internal_scheme_handler(&mut self, router: SchemeRouter) -> Result<()> {
+        self.internal_pages = Some(router);
+        Ok(())
+    }
+
+    fn pump_events(&mut self) -> bool {
+        while gtk::events_pending() {
+            gtk::main_iteration_do(false);
+        }
+        true
+    }
+}
+
+/// `http://host:port` → адрес прокси для WebKitGTK.
+fn proxy_endpoint(proxy: &str) -> Option<ProxyEndpoint> {
+    let address = proxy.split_once("://").map_or(proxy, |(_, rest)| rest);
+    let (host, port) = address.trim_end_matches('/').rsplit_once(':')?;
+    Some(ProxyEndpoint {
+        host: host.to_string(),
+        port: port.to_string(),
+    })
+}
+
+/// Инициатор запроса `plus://` берётся из Origin или Referer.
+fn internal_response(
+    router: &SchemeRouter,
+    request: &Request<Vec<u8>>,
+) -> Response<Cow<'static, [u8]>> {
+    let initiator = [header::ORIGIN, header::REFERER]
+        .iter()
+        .find_map(|name| request.headers().get(name)?.to_str().ok());
+    let response = router.handle(
+        request.method().as_str(),
+        &request.uri().to_string(),
+        initiator,
+    );
+    let mut builder = Response::builder()
+        .status(response.status)
+        .header(header::CONTENT_TYPE, response.content_type)
+        .header(header::CACHE_CONTROL, "no-store");
+    if let Some(origin) = &response.allow_origin {
+        builder = builder.header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+    builder
+        .body(Cow::Owned(response.body))
+        .unwrap_or_else(|_| Response::new(Cow::Borrowed(&[][..])))
+}
+
+/// Загрузка памяти в процентах по `/proc/meminfo`.
+fn memory_load() -> Option<u32> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = meminfo.lines().find(|line| line.starts_with(name))?;
+        line[name.len()..]
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()
+    };
+    let total = field("MemTotal:")?;
+    let available = field("MemAvailable:")?;
+    (total > 0).then(|| (100 - available.min(total) * 100 / total) as u32)
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+/// Номер вкладки в текущем запуске; его выдаёт приложение.
+pub type TabId = u64;
+
+/// Сколько фоновых вкладок держать загруженными в обычном режиме.
+pub(crate) const MAX_BACKGROUND_VIEWS: usize = 8;
+/// Загрузка памяти системы (в процентах), после которой выгружаются все фоновые вкладки.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) const MEMORY_PRESSURE_LOAD: u32 = 85;
+
+struct Slot<V> {
+    view: V,
+    /// Когда вкладку показывали последний раз — по нему выбираются кандидаты на выгрузку.
+    last_shown: u64,
+}
+
+/// Представления вкладок: создаются по требованию, показывается одно — активное.
+/// Платформенный хост хранит здесь свои WebView и сам их прячет и закрывает.
+pub(crate) struct TabViews<V> {
+    slots: HashMap<TabId, Slot<V>>,
+    active: Option<TabId>,
+    clock: u64,
+}
+
+impl<V> TabViews<V> {
+    pub fn new() -> Self {
+        Self {
+            slots: HashMap::new(),
+            active: None,
+            clock: 0,
+        }
+    }
+
+    pub fn contains(&self, tab: TabId) -> bool {
+        self.slots.contains_key(&tab)
+    }
+
+    pub fn get(&self, tab: TabId) -> Option<&V> {
+        self.slots.get(&tab).map(|slot| &slot.view)
+    }
+
+    pub fn get_mut(&mut self, tab: TabId) -> Option<&mut V> {
+        self.slots.get_mut(&tab).map(|slot| &mut slot.view)
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.slots.values().map(|slot| &slot.view)
+    }
+
+    pub fn active_id(&self) -> Option<TabId> {
+        self.active
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn active(&self) -> Option<&V> {
+        self.get(self.active?)
+    }
+
+    pub fn insert(&mut self, tab: TabId, view: V) {
+        self.slots.insert(
+            tab,
+            Slot {
+                view,
+                last_shown: 0,
+            },
+        );
+    }
+
+    /// Делает вкладку активной и возвращает ту, что была активной до неё.
+    pub fn activate(&mut self, tab: TabId) -> Option<TabId> {
+        self.clock += 1;
+        if let Some(slot) = self.slots.get_mut(&tab) {
+            slot.last_shown = self.clock;
+        }
+        let previous = self.active.replace(tab);
+        previous.filter(|previous| *previous != tab)
+    }
+
+    pub fn remove(&mut self, tab: TabId) -> Option<V> {
+        if self.active == Some(tab) {
+            self.active = None;
+        }
+        self.slots.remove(&tab).map(|slot| slot.view)
+    }
+
+    /// Убирает представления закрытых вкладок.
+    pub fn retain(&mut self, open: &[TabId]) -> Vec<V> {
+        let closed: Vec<TabId> = self
+            .slots
+            .keys()
+            .filter(|tab| !open.contains(tab))
+            .copied()
+            .collect();
+        closed
+            .into_iter()
+            .filter_map(|tab| self.remove(tab))
+            .collect()
+    }
+
+    /// Выгружает фоновые вкладки, оставляя `keep` показанных последними.
+    /// Активная вкладка не выгружается никогда.
+    pub fn discard_background(&mut self, keep: usize) -> Vec<V> {
+        let mut background: Vec<(u64, TabId)> = self
+            .slots
+            .iter()
+            .filter(|(tab, _)| Some(**tab) != self.active)
+            .map(|(tab, slot)| (slot.last_shown, *tab))
+            .collect();
+        if background.len() <= keep {
+            return Vec::new();
+        }
+        background.sort_unstable();
+        let excess = background.len() - keep;
+        background
+            .into_iter()
+            .take(excess)
+            .filter_map(|(_, tab)| self.remove(tab))
+            .collect()
+    }
+
+    pub fn clear(&mut self) -> Vec<V> {
+        self.active = None;
+        self.slots.drain().map(|(_, slot)| slot.view).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_without_recreating_views() {
+        let mut views = TabViews::new();
+        views.insert(1, "a");
+        assert_eq!(views.activate(1), None);
+        views.insert(2, "b");
+        assert_eq!(views.activate(2), Some(1));
+        assert_eq!(views.activate(2), None);
+        assert_eq!(views.active(), Some(&"b"));
+        assert_eq!(views.activate(1), Some(2));
+        assert!(views.contains(2));
+
+        assert_eq!(views.retain(&[2]), ["a"]);
+        assert_eq!(views.active_id(), None);
+        assert_eq!(views.get(2), Some(&"b"));
+    }
+
+    #[test]
+    fn discards_least_recently_shown_background_tabs() {
+        let mut views = TabViews::new();
+        for tab in 1..=4 {
+            views.insert(tab, tab);
+            views.activate(tab);
+        }
+        views.activate(2);
+        // Фон: 1, 3, 4; дольше всех не показывались 1 и 3.
+        let mut discarded = views.discard_background(1);
+        discarded.sort_unstable();
+        assert_eq!(discarded, [1, 3]);
+        assert!(views.contains(4));
+        assert_eq!(views.discard_background(0), [4]);
+        assert_eq!(views.active(), Some(&2));
+        assert_eq!(views.clear(), [2]);
+    }
+}
@@ -1,156 +1,706 @@
-#[cfg(windows)]
-use anyhow::{anyhow, Result};
-#[cfg(windows)]
-use std::sync::{Arc, Mutex};
-#[cfg(windows)]
-use webview2::{
-    Environment, EnvironmentOptions, WebView, WebViewBuilder, WebViewController,
-    WebViewControllerBuilder,
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use webview2_com::Microsoft::Web::WebView2::Win32::{
+    CreateCoreWebView2EnvironmentWithOptions, ICoreWebView2, ICoreWebView2Controller,
+    ICoreWebView2Environment, ICoreWebView2EnvironmentOptions, ICoreWebView2Profile2,
+    ICoreWebView2WebResourceResponse, ICoreWebView2_13, ICoreWebView2_15,
+    COREWEBVIEW2_BROWSING_DATA_KINDS_COOKIES, COREWEBVIEW2_BROWSING_DATA_KINDS_DISK_CACHE,
+    COREWEBVIEW2_WEB_ERROR_STATUS, COREWEBVIEW2_WEB_RESOURCE_CONTEXT_ALL,
 };
-#[cfg(windows)]
-use windows_sys::Win32::Foundation::HWND;
-#[cfg(windows)]
-use windows_sys::Win32::UI::WindowsAndMessaging::{GetClientRect, SetWindowPos, SWP_NOZORDER};
+use webview2_com::{
+    take_pwstr, CallDevToolsProtocolMethodCompletedHandler, ClearBrowsingDataCompletedHandler,
+    CoreWebView2CustomSchemeRegistration, CoreWebView2EnvironmentOptions,
+    CreateCoreWebView2ControllerCompletedHandler, CreateCoreWebView2EnvironmentCompletedHandler,
+    DocumentTitleChangedEventHandler, ExecuteScriptCompletedHandler, FaviconChangedEventHandler,
+    HistoryChangedEventHandler, NavigationCompletedEventHandler, NavigationStartingEventHandler,
+    SourceChangedEventHandler, WebResourceRequestedEventHandler,
+};
+use windows::core::{Interface, BOOL, HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{HWND, RECT};
+use windows::Win32::System::Com::{CoInitializeEx, COINIT_APARTMENTTHREADED};
+use windows::Win32::UI::Shell::SHCreateMemStream;
+use windows_sys::Win32::System::SystemInformation::{GlobalMemoryStatusEx, MEMORYSTATUSEX};
 
-#[cfg(windows)]
+use crate::cdp;
+use crate::events::{EventSink, NavigationEvent};
+use crate::tabs::{TabId, TabViews, MAX_BACKGROUND_VIEWS, MEMORY_PRESSURE_LOAD};
+use crate::WebViewHost;
 use plus_adblock::AdblockEngine;
+use plus_net::{SchemeRouter, INTERNAL_SCHEME};
+use plus_privacy::{Cookie, CookieSettings};
+
+struct TabView {
+    controller: ICoreWebView2Controller,
+    webview: ICoreWebView2,
+}
 
-#[cfg(windows)]
+/// WebView2 на каждую вкладку: создаётся при первом показе или переходе,
+/// прячется, когда вкладка неактивна, и выгружается при нехватке памяти.
+/// Переключение вкладок не перезагружает страницу.
+///
+/// Все вызовы COM идут из потока окна: WebView2 однопоточный (STA).
 pub struct WebViewHostWindows {
     hwnd_parent: HWND,
-    controller: Option<WebViewController>,
-    webview: Option<WebView>,
+    env: Option<ICoreWebView2Environment>,
+    views: TabViews<TabView>,
+    events: EventSink,
+    bounds: (i32, i32, i32, i32),
     proxy: Option<String>,
+    user_data_dir: Option<PathBuf>,
+    adblock: Option<Arc<Mutex<AdblockEngine>>>,
+    cookie_rules: Option<Arc<Mutex<CookieSettings>>>,
+    internal_pages: Option<SchemeRouter>,
 }
 
-#[cfg(windows)]
 impl WebViewHostWindows {
-    pub fn new(hwnd_parent: HWND) -> Self {
+    pub fn new(hwnd_parent: windows_sys::Win32::Foundation::HWND) -> Self {
         Self {
-            hwnd_parent,
-            controller: None,
-            webview: None,
+            hwnd_parent: HWND(hwnd_parent),
+            env: None,
+            views: TabViews::new(),
+            events: EventSink::default(),
+            bounds: (0, 0, 0, 0),
             proxy: None,
+            user_data_dir: None,
+            adblock: None,
+            cookie_rules: None,
+            internal_pages: None,
+        }
+    }
+
+    fn create_view(&self, tab: TabId) -> Result<TabView> {
+        let env = self
+            .env
+            .clone()
+            .ok_or_else(|| anyhow!("webview not initialized"))?;
+        let parent = self.hwnd_parent;
+        let (tx, rx) = mpsc::channel();
+        let creator = env.clone();
+        CreateCoreWebView2ControllerCompletedHandler::wait_for_async_operation(
+            Box::new(move |handler| {
+                // SAFETY: родительское окно живёт дольше хоста; вызов в его потоке.
+                unsafe { creator.CreateCoreWebView2Controller(parent, &handler) }
+                    .map_err(webview2_com::Error::WindowsError)
+            }),
+            Box::new(move |result, controller| {
+                result?;
+                let _ = tx.send(controller);
+                Ok(())
+            }),
+        )?;
+        let controller = rx
+            .recv()?
+            .ok_or_else(|| anyhow!("WebView2 did not create a controller"))?;
+        // SAFETY: контроллер только что создан в этом потоке.
+        let webview = unsafe {
+            controller.SetIsVisible(false)?;
+            controller.CoreWebView2()?
+        };
+        attach_navigation_events(&webview, tab, self.events.clone())?;
+        if let Some(adblock) = &self.adblock {
+            attach_adblock(&webview, &env, adblock.clone())?;
+        }
+        if let Some(rules) = &self.cookie_rules {
+            attach_cookie_rules(&webview, rules.clone())?;
+        }
+        if let Some(router) = &self.internal_pages {
+            attach_internal_scheme(&webview, &env, router.clone())?;
+        }
+        Ok(TabView {
+            controller,
+            webview,
+        })
+    }
+
+    fn discard_background(&mut self) {
+        let keep = if memory_pressure() {
+            0
+        } else {
+            MAX_BACKGROUND_VIEWS
+        };
+        for view in self.views.discard_background(keep) {
+            close(view);
         }
     }
 
-    pub fn initialize(&mut self) -> Result<()> {
-        let options = self
-            .proxy
+    fn active_webview(&self) -> Result<&ICoreWebView2> {
+        self.views
+            .active()
+            .map(|view| &view.webview)
+            .ok_or_else(|| anyhow!("webview not initialized"))
+    }
+
+    fn environment(&self) -> Result<&ICoreWebView2Environment> {
+        self.env
             .as_ref()
-            .map(|p| {
-                EnvironmentOptions::builder()
-                    .additional_browser_arguments(format!("--proxy-server={}", p))
-                    .build()
-            })
-            .unwrap_or_else(EnvironmentOptions::new);
-        let env = Environment::create_with_options(None, None, Some(options))?;
-        let controller = WebViewControllerBuilder::new(env)
-            .parent_window(self.hwnd_parent)
-            .build()?;
-        let webview = WebViewBuilder::new(controller.webview())?.build()?;
-        self.controller = Some(controller);
-        self.webview = Some(webview);
+            .ok_or_else(|| anyhow!("webview not initialized"))
+    }
+
+    fn apply_bounds(&self) {
+        let (x, y, width, height) = self.bounds;
+        if let Some(view) = self.views.active() {
+            let bounds = RECT {
+                left: x,
+                top: y,
+                right: x + width,
+                bottom: y + height,
+            };
+            // SAFETY: контроллер открыт и принадлежит потоку окна.
+            let _ = unsafe { view.controller.SetBounds(bounds) };
+        }
+    }
+}
+
+impl WebViewHost for WebViewHostWindows {
+    fn set_user_data_dir(&mut self, dir: PathBuf) {
+        self.user_data_dir = Some(dir);
+    }
+
+    /// Создаёт окружение WebView2. Открытые вкладки закрываются: их WebView
+    /// привязаны к старому окружению и пересоздадутся при следующем показе.
+    fn initialize(&mut self) -> Result<()> {
+        for view in self.views.clear() {
+            close(view);
+        }
+        self.env = None;
+        // SAFETY: WebView2 требует COM в режиме STA. Если winit уже
+        // инициализировал COM в этом потоке, вызов лишь увеличит счётчик
+        // или вернёт RPC_E_CHANGED_MODE — оба случая нам подходят.
+        let _ = unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) };
+        let options = environment_options(self.proxy.as_deref());
+        let data_dir = self
+            .user_data_dir
+            .as_deref()
+            .map(|dir| HSTRING::from(dir.as_os_str()));
+        let (tx, rx) = mpsc::channel();
+        CreateCoreWebView2EnvironmentCompletedHandler::wait_for_async_operation(
+            Box::new(move |handler| {
+                let data_dir = data_dir
+                    .as_ref()
+                    .map_or(PCWSTR::null(), |d| PCWSTR(d.as_ptr()));
+                // SAFETY: строки и опции живут до возврата из вызова, WebView2 копирует их.
+                unsafe {
+                    CreateCoreWebView2EnvironmentWithOptions(
+                        PCWSTR::null(),
+                        data_dir,
+                        &options,
+                        &handler,
+                    )
+                }
+                .map_err(webview2_com::Error::WindowsError)
+            }),
+            Box::new(move |result, env| {
+                result?;
+                let _ = tx.send(env);
+                Ok(())
+            }),
+        )?;
+        let env = rx
+            .recv()?
+            .ok_or_else(|| anyhow!("WebView2 did not create an environment"))?;
+        self.env = Some(env);
         Ok(())
     }
 
-    pub fn set_proxy(&mut self, proxy: Option<String>) -> Result<()> {
+    fn set_proxy(&mut self, proxy: Option<String>) -> Result<()> {
         self.proxy = proxy;
         self.initialize()
     }
 
-    pub fn navigate(&self, url: &str) -> Result<()> {
-        self.webview
-            .as_ref()
-            .ok_or_else(|| anyhow!("webview not initialized"))?
-            .navigate(url)?;
-        Ok(())
+    fn subscribe(&self) -> mpsc::Receiver<NavigationEvent> {
+        self.events.subscribe()
     }
 
-    pub fn reload(&self) -> Result<()> {
-        self.webview
-            .as_ref()
-            .ok_or_else(|| anyhow!("webview not initialized"))?
-            .reload()?;
+    fn show_tab(&mut self, tab: TabId, url: &str) -> Result<bool> {
+        if self.views.active_id() == Some(tab) && self.views.contains(tab) {
+            return Ok(false);
+        }
+        let created = !self.views.contains(tab);
+        if created {
+            self.navigate(tab, url)?;
+        }
+        // SAFETY: контроллеры открыты и принадлежат потоку окна.
+        unsafe {
+            if let Some(previous) = self.views.activate(tab).and_then(|t| self.views.get(t)) {
+                previous.controller.SetIsVisible(false)?;
+            }
+            if let Some(view) = self.views.get(tab) {
+                view.controller.SetIsVisible(true)?;
+            }
+        }
+        self.apply_bounds();
+        self.discard_background();
+        Ok(created)
+    }
+
+    fn retain_tabs(&mut self, open: &[TabId]) {
+        for view in self.views.retain(open) {
+            close(view);
+        }
+    }
+
+    fn navigate(&mut self, tab: TabId, url: &str) -> Result<()> {
+        if !self.views.contains(tab) {
+            let view = self.create_view(tab)?;
+            self.views.insert(tab, view);
+        }
+        let view = self
+            .views
+            .get(tab)
+            .ok_or_else(|| anyhow!("webview not initialized"))?;
+        // SAFETY: страница открыта; WebView2 копирует адрес до возврата.
+        unsafe { view.webview.Navigate(&HSTRING::from(url))? };
         Ok(())
     }
 
-    pub fn stop(&self) -> Result<()> {
-        self.webview
-            .as_ref()
-            .ok_or_else(|| anyhow!("webview not initialized"))?
-            .stop()?;
+    fn reload(&mut self) -> Result<()> {
+        // SAFETY: вызов в потоке окна у открытой страницы.
+        unsafe { self.active_webview()?.Reload()? };
         Ok(())
     }
 
-    pub fn go_back(&self) -> Result<()> {
-        self.webview
-            .as_ref()
-            .ok_or_else(|| anyhow!("webview not initialized"))?
-            .go_back()?;
+    fn stop(&mut self) -> Result<()> {
+        // SAFETY: вызов в потоке окна у открытой страницы.
+        unsafe { self.active_webview()?.Stop()? };
         Ok(())
     }
 
-    pub fn go_forward(&self) -> Result<()> {
-        self.webview
-            .as_ref()
-            .ok_or_else(|| anyhow!("webview not initialized"))?
-            .go_forward()?;
+    fn go_back(&mut self) -> Result<()> {
+        // SAFETY: вызов в потоке окна у открытой страницы.
+        unsafe { self.active_webview()?.GoBack()? };
         Ok(())
     }
 
-    pub fn execute_script(&self, js: &str) -> Result<()> {
-        self.webview
-            .as_ref()
-            .ok_or_else(|| anyhow!("webview not initialized"))?
-            .execute_script(js)?;
+    fn go_forward(&mut self) -> Result<()> {
+        // SAFETY: вызов в потоке окна у открытой страницы.
+        unsafe { self.active_webview()?.GoForward()? };
         Ok(())
     }
 
-    pub fn set_bounds(&self, x: i32, y: i32, width: i32, height: i32) -> Result<()> {
+    fn execute_script(&mut self, js: &str) -> Result<()> {
+        let handler = ExecuteScriptCompletedHandler::create(Box::new(|_, _| Ok(())));
+        // SAFETY: скрипт копируется до возврата; WebView2 держит ссылку на обработчик.
         unsafe {
-            if let Some(controller) = &self.controller {
-                let hwnd = controller.window();
-                SetWindowPos(hwnd, 0, x, y, width, height, SWP_NOZORDER);
-            } else {
-                let mut rect = std::mem::zeroed();
-                GetClientRect(self.hwnd_parent, &mut rect);
+            self.active_webview()?
+                .ExecuteScript(&HSTRING::from(js), &handler)?
+        };
+        Ok(())
+    }
+
+    fn evaluate_script(&mut self, js: &str, done: Box<dyn FnOnce(String) + Send>) -> Result<()> {
+        let handler = ExecuteScriptCompletedHandler::create(Box::new(move |result, json| {
+            if result.is_ok() {
+                done(json);
             }
-        }
+            Ok(())
+        }));
+        // SAFETY: скрипт копируется до возврата; WebView2 держит ссылку на обработчик.
+        unsafe {
+            self.active_webview()?
+                .ExecuteScript(&HSTRING::from(js), &handler)?
+        };
         Ok(())
     }
 
-    pub fn add_adblock_handler(&self, adblock: Arc<Mutex<AdblockEngine>>) -> Result<()> {
-        let webview = self
-            .webview
-            .as_ref()
-            .ok_or_else(|| anyhow!("webview not initialized"))?;
-        webview.add_web_resource_requested_filter("*", webview2::WebResourceContext::All)?;
-        webview.add_web_resource_requested(move |args| {
-            if let Ok(request) = args.request() {
-                if let Ok(uri) = request.uri() {
-                    let mut engine = adblock.lock().expect("adblock lock");
-                    if engine.should_block(&uri, "about:blank", "resource") {
-                        let response = webview2::WebResourceResponse::new("")
-                            .with_status_code(204)
-                            .with_reason_phrase("Blocked")
-                            .build();
-                        let _ = args.set_response(&response);
-                    }
+    /// Банка cookie общая для всех вкладок окружения: спрашиваем через активную.
+    fn get_cookies(&mut self, done: Box<dyn FnOnce(Vec<Cookie>)>) -> Result<()> {
+        let Some(view) = self.views.active() else {
+            bail!("no open tab to read cookies through");
+        };
+        let handler =
+            CallDevToolsProtocolMethodCompletedHandler::create(Box::new(move |result, json| {
+                if result.is_ok() {
+                    done(cdp::parse_cookies(&json));
                 }
+                Ok(())
+            }));
+        // SAFETY: строки копируются до возврата; WebView2 держит ссылку на обработчик.
+        unsafe {
+            view.webview.CallDevToolsProtocolMethod(
+                &HSTRING::from("Network.getAllCookies"),
+                &HSTRING::from("{}"),
+                &handler,
+            )?
+        };
+        Ok(())
+    }
+
+    fn delete_cookie(&mut self, cookie: &Cookie) -> Result<()> {
+        let handler = CallDevToolsProtocolMethodCompletedHandler::create(Box::new(|_, _| Ok(())));
+        // SAFETY: строки копируются до возврата; WebView2 держит ссылку на обработчик.
+        unsafe {
+            self.active_webview()?.CallDevToolsProtocolMethod(
+                &HSTRING::from("Network.deleteCookies"),
+                &HSTRING::from(cdp::delete_cookie_params(cookie)),
+                &handler,
+            )?
+        };
+        Ok(())
+    }
+
+    /// Профиль общий для всех вкладок окружения: чистим через активную.
+    fn clear_browsing_data(
+        &mut self,
+        cookies: bool,
+        cache: bool,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let profile = webview_profile(self.active_webview()?)?;
+        // SAFETY: вызовы COM в потоке окна; обработчик живёт до завершения вызова.
+        unsafe {
+            if cookies {
+                let start = since.map_or(0.0, |at| at.timestamp_millis() as f64 / 1000.0);
+                let end = Utc::now().timestamp_millis() as f64 / 1000.0 + 1.0;
+                profile.ClearBrowsingDataInTimeRange(
+                    COREWEBVIEW2_BROWSING_DATA_KINDS_COOKIES,
+                    start,
+                    end,
+                    &ClearBrowsingDataCompletedHandler::create(Box::new(|_| Ok(()))),
+                )?;
             }
-            Ok(())
-        })?;
+            // Кэш чистится целиком, как и в очереди на следующий запуск.
+            if cache {
+                profile.ClearBrowsingData(
+                    COREWEBVIEW2_BROWSING_DATA_KINDS_DISK_CACHE,
+                    &ClearBrowsingDataCompletedHandler::create(Box::new(|_| Ok(()))),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_bounds(&mut self, x: i32, y: i32, width: i32, height: i32) -> Result<()> {
+        self.bounds = (x, y, width, height);
+        self.apply_bounds();
+        Ok(())
+    }
+
+    /// Подключает AdBlock к открытым вкладкам и ко всем, что откроются позже.
+    fn add_adblock_handler(&mut self, adblock: Arc<Mutex<AdblockEngine>>) -> Result<()> {
+        let env = self.environment()?;
+        for view in self.views.iter() {
+            attach_adblock(&view.webview, env, adblock.clone())?;
+        }
+        self.adblock = Some(adblock);
+        Ok(())
+    }
+
+    fn add_internal_scheme_handler(&mut self, router: SchemeRouter) -> Result<()> {
+        let env = self.environment()?;
+        for view in self.views.iter() {
+            attach_internal_scheme(&view.webview, env, router.clone())?;
+        }
+        self.internal_pages = Some(router);
+        Ok(())
+    }
+
+    /// Снимает заголовок `Cookie` с запросов, которым правила профиля запрещают
+    /// cookie. Правила читаются на каждом запросе, поэтому их можно менять на лету.
+    fn add_cookie_handler(&mut self, rules: Arc<Mutex<CookieSettings>>) -> Result<()> {
+        for view in self.views.iter() {
+            attach_cookie_rules(&view.webview, rules.clone())?;
+        }
+        self.cookie_rules = Some(rules);
         Ok(())
     }
 }
 
-#[cfg(not(windows))]
-pub struct WebViewHostWindows;
+/// Закрывает представление вкладки; ошибки уже закрытого контроллера неважны.
+fn close(view: TabView) {
+    // SAFETY: контроллер принадлежит потоку окна и после вызова не используется.
+    let _ = unsafe { view.controller.Close() };
+}
 
-#[cfg(not(windows))]
-impl WebViewHostWindows {
-    pub fn new(_hwnd_parent: usize) -> Self {
-        Self
+/// Система сообщает, что память почти закончилась.
+fn memory_pressure() -> bool {
+    // SAFETY: структура обнулена и её размер записан в `dwLength`, как требует API.
+    unsafe {
+        let mut status: MEMORYSTATUSEX = std::mem::zeroed();
+        status.dwLength = std::mem::size_of::<MEMORYSTATUSEX>() as u32;
+        GlobalMemoryStatusEx(&mut status) != 0 && status.dwMemoryLoad >= MEMORY_PRESSURE_LOAD
+    }
+}
+
+/// Строка-результат геттера WebView2: её выделяет WebView2 через `CoTaskMemAlloc`,
+/// `take_pwstr` копирует и освобождает.
+fn read_string(get: impl FnOnce(*mut PWSTR) -> windows::core::Result<()>) -> Option<String> {
+    let mut value = PWSTR::null();
+    get(&mut value).ok()?;
+    Some(take_pwstr(value))
+}
+
+/// Флаг-результат геттера WebView2.
+fn read_flag(get: impl FnOnce(*mut BOOL) -> windows::core::Result<()>) -> bool {
+    let mut value = BOOL::default();
+    get(&mut value).is_ok() && value.as_bool()
+}
+
+// Во всех обработчиках ниже `sender` и `args` действительны на время вызова,
+// а вызовы идут в потоке окна, где WebView2 их и отправляет.
+fn attach_navigation_events(webview: &ICoreWebView2, tab: TabId, events: EventSink) -> Result<()> {
+    let mut token = 0;
+    let sink = events.clone();
+    let handler = NavigationStartingEventHandler::create(Box::new(move |_, args| {
+        if let Some(url) = args.and_then(|args| read_string(|v| unsafe { args.Uri(v) })) {
+            sink.emit(NavigationEvent::Starting { tab, url });
+        }
+        Ok(())
+    }));
+    // SAFETY: вызов в потоке окна; WebView2 держит свою ссылку на обработчик.
+    unsafe { webview.add_NavigationStarting(&handler, &mut token)? };
+    let sink = events.clone();
+    let handler = SourceChangedEventHandler::create(Box::new(move |sender, _| {
+        if let Some(url) = sender.and_then(|page| read_string(|v| unsafe { page.Source(v) })) {
+            sink.emit(NavigationEvent::SourceChanged { tab, url });
+        }
+        Ok(())
+    }));
+    // SAFETY: как выше.
+    unsafe { webview.add_SourceChanged(&handler, &mut token)? };
+    let sink = events.clone();
+    let handler = DocumentTitleChangedEventHandler::create(Box::new(move |sender, _| {
+        if let Some(title) =
+            sender.and_then(|page| read_string(|v| unsafe { page.DocumentTitle(v) }))
+        {
+            sink.emit(NavigationEvent::TitleChanged { tab, title });
+        }
+        Ok(())
+    }));
+    // SAFETY: как выше.
+    unsafe { webview.add_DocumentTitleChanged(&handler, &mut token)? };
+    attach_favicon(webview, tab, events.clone())?;
+    let sink = events.clone();
+    let handler = HistoryChangedEventHandler::create(Box::new(move |sender, _| {
+        let Some(page) = sender else {
+            return Ok(());
+        };
+        sink.emit(NavigationEvent::HistoryChanged {
+            tab,
+            can_go_back: read_flag(|v| unsafe { page.CanGoBack(v) }),
+            can_go_forward: read_flag(|v| unsafe { page.CanGoForward(v) }),
+        });
+        Ok(())
+    }));
+    // SAFETY: как выше.
+    unsafe { webview.add_HistoryChanged(&handler, &mut token)? };
+    let handler = NavigationCompletedEventHandler::create(Box::new(move |_, args| {
+        let Some(args) = args else {
+            return Ok(());
+        };
+        let error = if read_flag(|v| unsafe { args.IsSuccess(v) }) {
+            None
+        } else {
+            let mut status = COREWEBVIEW2_WEB_ERROR_STATUS::default();
+            Some(match unsafe { args.WebErrorStatus(&mut status) } {
+                Ok(()) => format!("{status:?}"),
+                Err(_) => "unknown".into(),
+            })
+        };
+        events.emit(NavigationEvent::Completed { tab, error });
+        Ok(())
+    }));
+    // SAFETY: как выше.
+    unsafe { webview.add_NavigationCompleted(&handler, &mut token)? };
+    Ok(())
+}
+
+/// Значок страницы. Событие есть только в `ICoreWebView2_15` (WebView2 Runtime 1.0.1185+).
+fn attach_favicon(webview: &ICoreWebView2, tab: TabId, events: EventSink) -> Result<()> {
+    let core = webview.cast::<ICoreWebView2_15>()?;
+    let handler = FaviconChangedEventHandler::create(Box::new(move |sender, _| {
+        let Some(sender) = sender else {
+            return Ok(());
+        };
+        let page = sender.cast::<ICoreWebView2_15>()?;
+        let url = read_string(|v| unsafe { page.FaviconUri(v) }).unwrap_or_default();
+        if !url.is_empty() {
+            events.emit(NavigationEvent::FaviconChanged { tab, url });
+        }
+        Ok(())
+    }));
+    let mut token = 0;
+    // SAFETY: вызов в потоке окна; WebView2 держит свою ссылку на обработчик.
+    unsafe { core.add_FaviconChanged(&handler, &mut token)? };
+    Ok(())
+}
+
+/// Ответ на перехваченный запрос: тело копируется в поток в памяти,
+/// заголовки — строки `Имя: значение` через CRLF.
+fn resource_response(
+    env: &ICoreWebView2Environment,
+    status: u16,
+    reason: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> windows::core::Result<ICoreWebView2WebResourceResponse> {
+    let headers = headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    // SAFETY: `SHCreateMemStream` копирует тело; строки живут до возврата.
+    unsafe {
+        let content = SHCreateMemStream(Some(body));
+        env.CreateWebResourceResponse(
+            content.as_ref(),
+            status as i32,
+            &HSTRING::from(reason),
+            &HSTRING::from(headers),
+        )
+    }
+}
+
+fn attach_adblock(
+    webview: &ICoreWebView2,
+    env: &ICoreWebView2Environment,
+    adblock: Arc<Mutex<AdblockEngine>>,
+) -> Result<()> {
+    let env = env.clone();
+    let handler = WebResourceRequestedEventHandler::create(Box::new(move |_, args| {
+        let Some(args) = args else {
+            return Ok(());
+        };
+        // SAFETY: `args` действительны на время вызова обработчика.
+        let request = unsafe { args.Request()? };
+        let Some(uri) = read_string(|v| unsafe { request.Uri(v) }) else {
+            return Ok(());
+        };
+        let mut engine = adblock.lock().expect("adblock lock");
+        if engine.should_block(&uri, "about:blank", "resource") {
+            let response = resource_response(&env, 204, "Blocked", &[], &[])?;
+            // SAFETY: как выше.
+            let _ = unsafe { args.SetResponse(&response) };
+        }
+        Ok(())
+    }));
+    let mut token = 0;
+    // SAFETY: вызовы в потоке окна; WebView2 держит свою ссылку на обработчик.
+    unsafe {
+        webview.AddWebResourceRequestedFilter(
+            &HSTRING::from("*"),
+            COREWEBVIEW2_WEB_RESOURCE_CONTEXT_ALL,
+        )?;
+        webview.add_WebResourceRequested(&handler, &mut token)?;
     }
+    Ok(())
+}
+
+/// Опции окружения с регистрацией схемы `plus:`. Без неё WebView2 не отдаёт
+/// запросы схемы в WebResourceRequested, а её страницы получают непрозрачный
+/// origin и шлют `Origin: null`.
+fn environment_options(proxy: Option<&str>) -> ICoreWebView2EnvironmentOptions {
+    let options = CoreWebView2EnvironmentOptions::default();
+    let scheme = CoreWebView2CustomSchemeRegistration::new(INTERNAL_SCHEME.to_string());
+    // SAFETY: объекты опций живут в этом потоке и только заполняются.
+    unsafe {
+        if let Some(proxy) = proxy {
+            options.set_additional_browser_arguments(format!("--proxy-server={proxy}"));
+        }
+        // `plus://<страница>` — у каждой страницы свой origin, как у сайта.
+        scheme.set_has_authority_component(true);
+        scheme.set_treat_as_secure(true);
+        scheme.set_allowed_origins(vec![format!("{INTERNAL_SCHEME}://*")]);
+        options.set_scheme_registrations(vec![Some(scheme.into())]);
+    }
+    options.into()
+}
+
+/// Профиль WebView2 (`ICoreWebView2Profile2`) той же страницы.
+fn webview_profile(webview: &ICoreWebView2) -> Result<ICoreWebView2Profile2> {
+    // SAFETY: вызов в потоке окна у открытой страницы.
+    let profile = unsafe { webview.cast::<ICoreWebView2_13>()?.Profile()? };
+    Ok(profile.cast()?)
+}
+
+/// Инициатор запроса `plus://` берётся из Origin или Referer.
+fn attach_internal_scheme(
+    webview: &ICoreWebView2,
+    env: &ICoreWebView2Environment,
+    router: SchemeRouter,
+) -> Result<()> {
+    let env = env.clone();
+    let handler = WebResourceRequestedEventHandler::create(Box::new(move |_, args| {
+        let Some(args) = args else {
+            return Ok(());
+        };
+        // SAFETY: `args` и запрос действительны на время вызова обработчика.
+        let request = unsafe { args.Request()? };
+        let Some(uri) = read_string(|v| unsafe { request.Uri(v) }) else {
+            return Ok(());
+        };
+        if !uri.starts_with(&format!("{INTERNAL_SCHEME}:")) {
+            return Ok(());
+        }
+        let method = read_string(|v| unsafe { request.Method(v) }).unwrap_or_else(|| "GET".into());
+        let headers = unsafe { request.Headers()? };
+        let header =
+            |name: &str| read_string(|v| unsafe { headers.GetHeader(&HSTRING::from(name), v) });
+        let initiator = header("Origin").or_else(|| header("Referer"));
+        let response = router.handle(&method, &uri, initiator.as_deref());
+        let mut extra = vec![
+            ("Content-Type", response.content_type),
+            ("Cache-Control", "no-store"),
+        ];
+        if let Some(origin) = &response.allow_origin {
+            extra.push(("Access-Control-Allow-Origin", origin));
+        }
+        let reply = resource_response(
+            &env,
+            response.status,
+            response.reason(),
+            &extra,
+            &response.body,
+        )?;
+        // SAFETY: как выше.
+        let _ = unsafe { args.SetResponse(&reply) };
+        Ok(())
+    }));
+    let mut token = 0;
+    // SAFETY: вызовы в потоке окна; WebView2 держит свою ссылку на обработчик.
+    unsafe {
+        webview.AddWebResourceRequestedFilter(
+            &HSTRING::from(format!("{INTERNAL_SCHEME}:*")),
+            COREWEBVIEW2_WEB_RESOURCE_CONTEXT_ALL,
+        )?;
+        webview.add_WebResourceRequested(&handler, &mut token)?;
+    }
+    Ok(())
+}
+
+/// Сторонние для открытой страницы запросы и запросы к заблокированным сайтам
+/// уходят без cookie.
+fn attach_cookie_rules(webview: &ICoreWebView2, rules: Arc<Mutex<CookieSettings>>) -> Result<()> {
+    let handler = WebResourceRequestedEventHandler::create(Box::new(move |sender, args| {
+        let (Some(page), Some(args)) = (sender, args) else {
+            return Ok(());
+        };
+        // SAFETY: `sender`, `args` и запрос действительны на время вызова обработчика.
+        let request = unsafe { args.Request()? };
+        let host = |url: String| {
+            url::Url::parse(&url)
+                .ok()
+                .and_then(|u| u.host_str().map(str::to_string))
+        };
+        let (Some(request_host), Some(top_level_host)) = (
+            read_string(|v| unsafe { request.Uri(v) }).and_then(host),
+            read_string(|v| unsafe { page.Source(v) }).and_then(host),
+        ) else {
+            return Ok(());
+        };
+        let allowed = rules
+            .lock()
+            .map(|r| r.allows(&top_level_host, &request_host))
+            .unwrap_or(true);
+        if !allowed {
+            // SAFETY: как выше.
+            unsafe { request.Headers()?.RemoveHeader(&HSTRING::from("Cookie"))? };
+        }
+        Ok(())
+    }));
+    let mut token = 0;
+    // SAFETY: вызов в потоке окна; WebView2 держит свою ссылку на обработчик.
+    unsafe { webview.add_WebResourceRequested(&handler, &mut token)? };
+    Ok(())
 }
@@ -1,9 +1,15 @@
 #[cfg(test)]
 mod smoke {
     use plus_adblock::AdblockEngine;
-    use plus_net::{start_proxy, NetClient};
+    use plus_downloads::{DownloadManager, DownloadStore};
+    use plus_net::{
+        start_proxy, BookmarkStore, HistoryStore, NetClient, SchemeRouter, BOOKMARK_BAR,
+    };
+    use plus_privacy::{PrivacyStore, Profile, ProfileManager, DEFAULT_PROFILE};
     use plus_vpn::{VpnManager, VpnMode};
     use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
 
     #[test]
     fn adblock_blocks_tracker() {
@@ -20,7 +26,7 @@ mod smoke {
         let mut ad = AdblockEngine::from_filter_list("||blocked.example^").unwrap();
         ad.set_enabled(true);
         let ad = Arc::new(Mutex::new(ad));
-        let proxy = start_proxy("127.0.0.1:0", ad, None).await.unwrap();
+        let proxy = start_proxy("127.0.0.1:0", ad, None, None).await.unwrap();
         let client = reqwest::Client::builder()
             .proxy(reqwest::Proxy::http(format!("http://{}", proxy.listen_addr)).unwrap())
             .build()
@@ -29,6 +35,262 @@ mod smoke {
         assert_eq!(resp.status().as_u16(), 403);
     }
 
+    /// Запрос к прокси «как есть»: reqwest не ходит по схеме `plus://`.
+    async fn raw_request(proxy: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn proxy_serves_internal_scheme() {
+        let mut ad = AdblockEngine::from_filter_list("||blocked.example^").unwrap();
+        ad.set_enabled(true);
+        let ad = Arc::new(Mutex::new(ad));
+        let router = SchemeRouter::new(ad.clone());
+        let proxy = start_proxy("127.0.0.1:0", ad.clone(), None, Some(router))
+            .await
+            .unwrap();
+        let client = proxied_client(&proxy.listen_addr);
+        let resp = client.get("http://blocked.example/").send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let stats = raw_request(
+            &proxy.listen_addr,
+            "GET plus://adblock HTTP/1.1\r\nHost: adblock\r\nOrigin: plus://diagnostics-ui\r\n\r\n",
+        )
+        .await;
+        assert!(stats.starts_with("HTTP/1.1 200 OK"), "{stats}");
+        assert!(stats.contains("\"blocked\":1"), "{stats}");
+        assert!(stats.contains("http://blocked.example/"), "{stats}");
+
+        let page = raw_request(
+            &proxy.listen_addr,
+            "GET plus://newtab HTTP/1.1\r\nHost: newtab\r\n\r\n",
+        )
+        .await;
+        assert!(page.starts_with("HTTP/1.1 200 OK"), "{page}");
+        assert!(page.contains("text/html"));
+
+        let foreign = raw_request(
+            &proxy.listen_addr,
+            "GET plus://diagnostics HTTP/1.1\r\nReferer: https://evil.example/\r\n\r\n",
+        )
+        .await;
+        assert!(foreign.starts_with("HTTP/1.1 403"), "{foreign}");
+
+        // Без роутера внутренние адреса не уходят в сеть.
+        let bare = start_proxy("127.0.0.1:0", ad, None, None).await.unwrap();
+        let missing = raw_request(&bare.listen_addr, "GET plus://newtab HTTP/1.1\r\n\r\n").await;
+        assert!(missing.starts_with("HTTP/1.1 404"), "{missing}");
+    }
+
+    async fn spawn_http_ok() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let _ = stream.read(&mut buf).await;
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                        )
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// SOCKS5 без авторизации, который любой CONNECT отправляет на `target`.
+    async fn spawn_socks_stub(target: std::net::SocketAddr) -> std::net::SocketAddr {
+        spawn_socks_stub_replying(target, 0x00).await
+    }
+
+    /// SOCKS5 без аутентификации, отвечающий на CONNECT кодом `reply`.
+    async fn spawn_socks_stub_replying(
+        target: std::net::SocketAddr,
+        reply: u8,
+    ) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 3];
+                    if client.read_exact(&mut greeting).await.is_err() {
+                        return;
+                    }
+                    client.write_all(&[0x05, 0x00]).await.unwrap();
+                    let mut head = [0u8; 5];
+                    client.read_exact(&mut head).await.unwrap();
+                    let mut rest = vec![0u8; head[4] as usize + 2];
+                    client.read_exact(&mut rest).await.unwrap();
+                    client
+                        .write_all(&[0x05, reply, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                        .await
+                        .unwrap();
+                    if reply != 0x00 {
+                        return;
+                    }
+                    let mut upstream = TcpStream::connect(target).await.unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+        addr
+    }
+
+    fn proxied_client(proxy: &str) -> reqwest::Client {
+        reqwest::Client::builder()
+            .proxy(reqwest::Proxy::http(format!("http://{}", proxy)).unwrap())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn kill_switch_blocks_direct_traffic_without_vpn() {
+        let ad = Arc::new(Mutex::new(AdblockEngine::from_filter_list("").unwrap()));
+        let proxy = start_proxy("127.0.0.1:0", ad, None, None).await.unwrap();
+        proxy.kill_switch.set_enabled(true);
+        proxy.kill_switch.set_vpn_expected(true);
+        proxy.kill_switch.set_vpn_healthy(true);
+        let client = proxied_client(&proxy.listen_addr);
+
+        let resp = client.get("http://remote.example/").send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 503);
+        assert!(resp.text().await.unwrap().contains("VPN недоступен"));
+
+        let local = spawn_http_ok().await;
+        let resp = client
+            .get(format!("http://{}/", local))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    #[tokio::test]
+    async fn kill_switch_resumes_when_vpn_recovers() {
+        let origin = spawn_http_ok().await;
+        let socks = spawn_socks_stub(origin).await;
+        let ad = Arc::new(Mutex::new(AdblockEngine::from_filter_list("").unwrap()));
+        let proxy = start_proxy("127.0.0.1:0", ad, Some(socks.to_string()), None)
+            .await
+            .unwrap();
+        proxy.kill_switch.set_enabled(true);
+        proxy.kill_switch.set_vpn_expected(true);
+        let client = proxied_client(&proxy.listen_addr);
+
+        let resp = client.get("http://remote.example/").send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 503);
+
+        let monitor = proxy
+            .kill_switch
+            .spawn_monitor(socks.to_string(), std::time::Duration::from_millis(50));
+        for _ in 0..40 {
+            if proxy.kill_switch.vpn_healthy() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(25)).await;
+        }
+        monitor.abort();
+        let resp = client.get("http://remote.example/").send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.text().await.unwrap(), "ok");
+    }
+
+    #[test]
+    fn incognito_session_writes_nothing_to_profiles() {
+        let root = tempfile::tempdir().unwrap();
+        let profiles = ProfileManager::new(root.path());
+        let regular = profiles.open(DEFAULT_PROFILE).unwrap();
+        let incognito = Profile::incognito().unwrap();
+
+        let history = HistoryStore::for_profile(&incognito).unwrap();
+        history
+            .add_visit("https://example.org/", "Example")
+            .unwrap();
+        let bookmarks = BookmarkStore::for_profile(&incognito).unwrap();
+        bookmarks
+            .add_bookmark(BOOKMARK_BAR, "Example", "https://example.org/", &[])
+            .unwrap();
+        let privacy = PrivacyStore::for_profile(&incognito).unwrap();
+        privacy.add_cookie("example.org", "sid", "1").unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let downloads = DownloadManager::new(
+            DownloadStore::for_profile(&incognito).unwrap(),
+            root.path().join("Downloads"),
+            1,
+            runtime.handle().clone(),
+        )
+        .unwrap();
+        downloads
+            .start("http://127.0.0.1:9/file.bin", None)
+            .unwrap();
+
+        assert_eq!(std::fs::read_dir(regular.dir()).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(incognito.dir()).unwrap().count(), 0);
+        incognito.wipe().unwrap();
+        assert!(!incognito.dir().exists());
+    }
+
+    #[tokio::test]
+    async fn unreachable_target_does_not_engage_kill_switch() {
+        let origin = spawn_http_ok().await;
+        // 0x04 — «хост недоступен»: VPN работает, не отвечает только цель.
+        let socks = spawn_socks_stub_replying(origin, 0x04).await;
+        let ad = Arc::new(Mutex::new(AdblockEngine::from_filter_list("").unwrap()));
+        let proxy = start_proxy("127.0.0.1:0", ad, Some(socks.to_string()), None)
+            .await
+            .unwrap();
+        proxy.kill_switch.set_enabled(true);
+        proxy.kill_switch.set_vpn_expected(true);
+        proxy.kill_switch.set_vpn_healthy(true);
+        let client = proxied_client(&proxy.listen_addr);
+
+        let resp = client.get("http://dead.example/").send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 502);
+        assert!(proxy.kill_switch.vpn_healthy());
+        assert!(!proxy.kill_switch.is_engaged(true));
+    }
+
+    #[tokio::test]
+    async fn vpn_started_later_is_used_and_monitored() {
+        let origin = spawn_http_ok().await;
+        let socks = spawn_socks_stub(origin).await;
+        let ad = Arc::new(Mutex::new(AdblockEngine::from_filter_list("").unwrap()));
+        let proxy = start_proxy("127.0.0.1:0", ad, None, None).await.unwrap();
+        proxy.kill_switch.set_enabled(true);
+        proxy.kill_switch.set_vpn_expected(true);
+        let client = proxied_client(&proxy.listen_addr);
+        let resp = client.get("http://remote.example/").send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 503);
+
+        proxy.set_upstream(Some(socks.to_string()));
+        let monitor = proxy
+            .kill_switch
+            .spawn_monitor(socks.to_string(), std::time::Duration::from_millis(50));
+        for _ in 0..40 {
+            if proxy.kill_switch.vpn_healthy() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(25)).await;
+        }
+        monitor.abort();
+        let resp = client.get("http://remote.example/").send().await.unwrap();
+        assert_eq!(resp.text().await.unwrap(), "ok");
+
+        // VPN выключен в настройках: kill switch больше не ждёт его.
+        proxy.set_upstream(None);
+        proxy.kill_switch.set_vpn_expected(false);
+        assert!(!proxy.kill_switch.is_engaged(false));
+    }
+
     #[tokio::test]
     async fn vpn_changes_egress_when_env_configured() {
         let Some(vpn_url) = std::env::var("PLUS_TEST_VPN_URL").ok() else {
@@ -0,0 +1,362 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+use crate::dns::{dns_config, TUNNEL_DNS_LISTEN};
+use crate::route::{route_config, route_plan, DIRECT_TAG, PROXY_TAG};
+use crate::VpnConfig;
+
+/// Какой core запускать для профиля.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CoreKind {
+    #[default]
+    SingBox,
+    Xray,
+}
+
+/// Внешний VPN-core: формат конфига, аргументы запуска и определение версии.
+pub trait VpnCore: Send + Sync {
+    fn binary(&self) -> &Path;
+
+    fn config_file_name(&self) -> &str;
+
+    fn render_config(&self, cfg: &VpnConfig, local_socks: &str) -> Result<Value>;
+
+    fn spawn_args(&self, config_path: &Path) -> Vec<OsString>;
+
+    fn version_args(&self) -> Vec<OsString> {
+        vec!["version".into()]
+    }
+
+    fn parse_version(&self, output: &str) -> Option<String>;
+
+    /// Адрес, который должен начать принимать TCP-соединения, когда core готов.
+    fn probe_addr(&self, local_socks: &str) -> String {
+        local_socks.to_string()
+    }
+
+    /// Локальный DNS-вход в туннель, который core открывает при `dns_via_tunnel`.
+    fn tunnel_dns(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub struct SingBoxCore {
+    bin: PathBuf,
+}
+
+impl SingBoxCore {
+    pub fn new(bin: impl AsRef<Path>) -> Self {
+        Self {
+            bin: bin.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl VpnCore for SingBoxCore {
+    fn binary(&self) -> &Path {
+        &self.bin
+    }
+
+    fn config_file_name(&self) -> &str {
+        "singbox-config.json"
+    }
+
+    fn render_config(&self, cfg: &VpnConfig, local_socks: &str) -> Result<Value> {
+        let outbound = if cfg.protocol == "json" {
+            json_outbound(&cfg.raw, CoreKind::SingBox)?
+        } else {
+            let (host, port) = split_endpoint(&cfg.endpoint);
+            json!({
+                "type": cfg.protocol,
+                "server": host,
+                "server_port": port,
+                "tag": PROXY_TAG
+            })
+        };
+        let (listen, listen_port) = split_listen(local_socks)?;
+        let mut inbounds = vec![
+            json!({"type": "socks", "listen": listen, "listen_port": listen_port, "tag":"plus-in"}),
+        ];
+        let mut outbounds = vec![outbound, json!({"type":"direct","tag":DIRECT_TAG})];
+        let mut route = route_config(&cfg.mode)?;
+        if cfg.dns_via_tunnel {
+            let (host, port) = split_listen(TUNNEL_DNS_LISTEN)?;
+            inbounds.push(json!({
+                "type": "direct",
+                "listen": host,
+                "listen_port": port,
+                "tag": "dns-in"
+            }));
+            outbounds.push(json!({"type": "dns", "tag": "dns-out"}));
+            if let Some(rules) = route["rules"].as_array_mut() {
+                rules.insert(0, json!({"inbound": ["dns-in"], "outbound": "dns-out"}));
+            }
+        }
+        Ok(json!({
+            "log": {"level": "warn"},
+            "dns": dns_config(&cfg.mode, cfg.dns_via_tunnel, &cfg.dns)?,
+            "inbounds": inbounds,
+            "outbounds": outbounds,
+            "route": route
+        }))
+    }
+
+    fn spawn_args(&self, config_path: &Path) -> Vec<OsString> {
+        vec!["run".into(), "-c".into(), config_path.into()]
+    }
+
+    fn tunnel_dns(&self) -> Option<&str> {
+        Some(TUNNEL_DNS_LISTEN)
+    }
+
+    fn parse_version(&self, output: &str) -> Option<String> {
+        // "sing-box version 1.10.1"
+        output
+            .lines()
+            .find_map(|l| l.trim().strip_prefix("sing-box version "))
+            .map(|v| v.trim().to_string())
+    }
+}
+
+pub struct XrayCore {
+    bin: PathBuf,
+}
+
+impl XrayCore {
+    pub fn new(bin: impl AsRef<Path>) -> Self {
+        Self {
+            bin: bin.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl VpnCore for XrayCore {
+    fn binary(&self) -> &Path {
+        &self.bin
+    }
+
+    fn config_file_name(&self) -> &str {
+        "xray-config.json"
+    }
+
+    fn render_config(&self, cfg: &VpnConfig, local_socks: &str) -> Result<Value> {
+        let outbound = if cfg.protocol == "json" {
+            json_outbound(&cfg.raw, CoreKind::Xray)?
+        } else {
+            xray_outbound(cfg)?
+        };
+        let (listen, port) = split_listen(local_socks)?;
+        let (rules, matched, fallback) = route_plan(&cfg.mode)?;
+        let mut routing_rules = rules.to_xray_rules(matched)?;
+        routing_rules.push(json!({"type": "field", "network": "tcp,udp", "outboundTag": fallback}));
+        Ok(json!({
+            "log": {"loglevel": "warning"},
+            "dns": xray_dns(cfg)?,
+            "inbounds": [{
+                "tag": "plus-in",
+                "listen": listen,
+                "port": port,
+                "protocol": "socks",
+                "settings": {"udp": true}
+            }],
+            "outbounds": [outbound, {"protocol": "freedom", "tag": DIRECT_TAG}],
+            // Без IPIfNonMatch правила `ip` не срабатывают для запросов по имени,
+            // а браузер ходит через SOCKS именно по именам.
+            "routing": {"domainStrategy": "IPIfNonMatch", "rules": routing_rules}
+        }))
+    }
+
+    fn spawn_args(&self, config_path: &Path) -> Vec<OsString> {
+        vec!["run".into(), "-c".into(), config_path.into()]
+    }
+
+    fn parse_version(&self, output: &str) -> Option<String> {
+        // "Xray 1.8.24 (Xray, Penetrates Everything.) ..."
+        output
+            .lines()
+            .find_map(|l| l.trim().strip_prefix("Xray "))
+            .and_then(|rest| rest.split_whitespace().next())
+            .map(ToOwned::to_owned)
+    }
+}
+
+/// Outbound из JSON-профиля. Он должен быть в схеме выбранного core: поле типа —
+/// `type` у sing-box и `protocol` у Xray. Правила маршрутизации ссылаются на тег
+/// `proxy`, поэтому outbound без тега его получает, а с другим тегом отклоняется.
+fn json_outbound(raw: &str, core: CoreKind) -> Result<Value> {
+    let mut outbound = serde_json::from_str::<Value>(raw)?;
+    let Some(fields) = outbound.as_object_mut() else {
+        bail!("json profile must be a single outbound object");
+    };
+    let (kind, foreign) = match core {
+        CoreKind::SingBox => ("type", ["protocol", "settings"]),
+        CoreKind::Xray => ("protocol", ["type", "server"]),
+    };
+    if !fields.get(kind).is_some_and(Value::is_string)
+        || foreign.iter().any(|key| fields.contains_key(*key))
+    {
+        bail!("json profile is not a {core:?} outbound");
+    }
+    match fields.get("tag") {
+        None => {
+            fields.insert("tag".into(), PROXY_TAG.into());
+        }
+        Some(tag) if tag.as_str() == Some(PROXY_TAG) => {}
+        Some(tag) => bail!("json outbound tag must be \"{PROXY_TAG}\", not {tag}"),
+    }
+    Ok(outbound)
+}
+
+/// Секция `dns` конфига Xray. DoT и FakeIP Xray не поддерживает: такие настройки —
+/// ошибка, а не молча другой DNS.
+fn xray_dns(cfg: &VpnConfig) -> Result<Value> {
+    if !cfg.dns_via_tunnel {
+        return Ok(json!({"servers": ["localhost"]}));
+    }
+    if cfg.dns.fakeip {
+        bail!("FakeIP DNS is supported only by sing-box");
+    }
+    if !cfg.dns.remote.starts_with("https://") {
+        bail!("remote DNS for Xray must be DoH (https://)");
+    }
+    Ok(json!({"servers": [cfg.dns.remote]}))
+}
+
+fn xray_outbound(cfg: &VpnConfig) -> Result<Value> {
+    let url = Url::parse(&cfg.raw)?;
+    let (host, port) = split_endpoint(&cfg.endpoint);
+    let user = urlencoding::decode(url.username())?.into_owned();
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let param = |key: &str| {
+        params
+            .get(key)
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    };
+    let settings = match cfg.protocol.as_str() {
+        "vless" => {
+            let mut user = json!({"id": user, "encryption": "none"});
+            if let Some(flow) = param("flow") {
+                user["flow"] = flow.into();
+            }
+            json!({"vnext": [{"address": host, "port": port, "users": [user]}]})
+        }
+        "vmess" => json!({"vnext": [{
+            "address": host, "port": port,
+            "users": [{"id": user, "security": "auto"}]
+        }]}),
+        "trojan" => json!({"servers": [{"address": host, "port": port, "password": user}]}),
+        "ss" => {
+            let (method, password) = user
+                .split_once(':')
+                .ok_or_else(|| anyhow!("ss url must contain method:password"))?;
+            json!({"servers": [{
+                "address": host, "port": port, "method": method, "password": password
+            }]})
+        }
+        other => bail!("xray does not support {other}"),
+    };
+    if cfg.protocol == "ss" {
+        return Ok(json!({"protocol": "shadowsocks", "tag": PROXY_TAG, "settings": settings}));
+    }
+    Ok(json!({
+        "protocol": cfg.protocol,
+        "tag": PROXY_TAG,
+        "settings": settings,
+        "streamSettings": xray_stream_settings(&cfg.protocol, host, param)?
+    }))
+}
+
+/// `streamSettings` из параметров ссылки: транспорт (`type`, `path`, `host`,
+/// `serviceName`) и шифрование (`security`, `sni`, `fp`, `alpn`, для REALITY —
+/// `pbk`, `sid`, `spx`). Без них Xray пошёл бы к серверу голым TCP.
+fn xray_stream_settings<'a>(
+    protocol: &str,
+    host: &'a str,
+    param: impl Fn(&str) -> Option<&'a str>,
+) -> Result<Value> {
+    let network = match param("type").unwrap_or("tcp") {
+        "h2" => "http",
+        other => other,
+    };
+    // Trojan без TLS не бывает, у остальных по умолчанию его нет.
+    let security = param("security").unwrap_or(if protocol == "trojan" { "tls" } else { "none" });
+    let server_name = param("sni").or_else(|| param("peer")).unwrap_or(host);
+    let mut stream = json!({"network": network, "security": security});
+    match security {
+        "none" => {}
+        "tls" => {
+            let mut tls = json!({"serverName": server_name});
+            if let Some(fingerprint) = param("fp") {
+                tls["fingerprint"] = fingerprint.into();
+            }
+            if let Some(alpn) = param("alpn") {
+                tls["alpn"] = alpn.split(',').collect::<Vec<_>>().into();
+            }
+            stream["tlsSettings"] = tls;
+        }
+        "reality" => {
+            let public_key =
+                param("pbk").ok_or_else(|| anyhow!("reality link must contain pbk"))?;
+            stream["realitySettings"] = json!({
+                "serverName": server_name,
+                "fingerprint": param("fp").unwrap_or("chrome"),
+                "publicKey": public_key,
+                "shortId": param("sid").unwrap_or_default(),
+                "spiderX": param("spx").unwrap_or_default(),
+            });
+        }
+        other => bail!("xray does not support security {other}"),
+    }
+    let path = param("path").unwrap_or("/");
+    match network {
+        "tcp" => {}
+        "ws" => {
+            let mut ws = json!({"path": path});
+            if let Some(host) = param("host") {
+                ws["headers"] = json!({"Host": host});
+            }
+            stream["wsSettings"] = ws;
+        }
+        "grpc" => {
+            stream["grpcSettings"] =
+                json!({"serviceName": param("serviceName").unwrap_or_default()});
+        }
+        "http" => {
+            let hosts: Vec<&str> = param("host")
+                .map(|h| h.split(',').collect())
+                .unwrap_or_default();
+            stream["httpSettings"] = json!({"path": path, "host": hosts});
+        }
+        "httpupgrade" | "xhttp" => {
+            stream[format!("{network}Settings")] =
+                json!({"path": path, "host": param("host").unwrap_or_default()});
+        }
+        other => bail!("xray does not support transport {other}"),
+    }
+    Ok(stream)
+}
+
+fn split_endpoint(endpoint: &str) -> (&str, u16) {
+    let host = endpoint.split(':').next().unwrap_or("127.0.0.1");
+    let port = endpoint
+        .split(':')
+        .nth(1)
+        .and_then(|p| p.parse::<u16>().ok())
+        .unwrap_or(443);
+    (host, port)
+}
+
+fn split_listen(addr: &str) -> Result<(&str, u16)> {
+    let (host, port) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("invalid listen address {addr}"))?;
+    Ok((host, port.parse()?))
+}
@@ -0,0 +1,231 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+use crate::route::{route_plan, PROXY_TAG};
+use crate::VpnMode;
+
+pub const DNS_REMOTE_TAG: &str = "dns-remote";
+pub const DNS_DIRECT_TAG: &str = "dns-direct";
+pub const DNS_FAKEIP_TAG: &str = "dns-fakeip";
+
+/// Локальный DNS-вход sing-box: запросы к нему резолвятся по правилам ниже.
+pub const TUNNEL_DNS_LISTEN: &str = "127.0.0.1:2053";
+/// Имя, на которое авторитетный сервер отвечает адресом спросившего его рекурсивного
+/// резолвера: по ответу видно, кто на самом деле резолвит имена.
+pub const DNS_WHOAMI_PROBE: &str = "whoami.akamai.net";
+const FAKEIP_INET4_RANGE: &str = "198.18.0.0/15";
+const FAKEIP_INET6_RANGE: &str = "fc00::/18";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsSettings {
+    /// DoH (`https://…/dns-query`) или DoT (`tls://…`, только sing-box),
+    /// ходит через outbound `proxy`.
+    pub remote: String,
+    /// Резолвер для доменов, которые идут напрямую; `local` — системный.
+    pub direct: String,
+    pub fakeip: bool,
+}
+
+impl Default for DnsSettings {
+    fn default() -> Self {
+        Self {
+            remote: "https://1.1.1.1/dns-query".into(),
+            direct: "local".into(),
+            fakeip: false,
+        }
+    }
+}
+
+/// Секция `dns` конфига sing-box. Серверы выбираются так же, как outbound в `route`:
+/// домены, идущие через прокси, резолвятся удалённым DoH/DoT через туннель.
+pub fn dns_config(mode: &VpnMode, via_tunnel: bool, settings: &DnsSettings) -> Result<Value> {
+    let direct = json!({"tag": DNS_DIRECT_TAG, "address": settings.direct, "detour": "direct"});
+    if !via_tunnel {
+        return Ok(json!({"servers": [direct], "final": DNS_DIRECT_TAG}));
+    }
+    if !is_encrypted_dns(&settings.remote) {
+        bail!("remote DNS must be DoH (https://) or DoT (tls://)");
+    }
+
+    let (rules, matched, fallback) = route_plan(mode)?;
+    let server_for = |outbound: &str| {
+        if outbound == PROXY_TAG {
+            DNS_REMOTE_TAG
+        } else {
+            DNS_DIRECT_TAG
+        }
+    };
+    let mut servers = vec![
+        json!({"tag": DNS_REMOTE_TAG, "address": settings.remote, "detour": PROXY_TAG}),
+        direct,
+    ];
+    let mut dns_rules = rules.to_singbox_rules("server", server_for(matched), false)?;
+    let mut dns = json!({
+        "servers": [],
+        "rules": [],
+        "final": server_for(fallback),
+        "strategy": "prefer_ipv4",
+        "independent_cache": true,
+    });
+    if settings.fakeip {
+        servers.push(json!({"tag": DNS_FAKEIP_TAG, "address": "fakeip"}));
+        // FakeIP только для проксируемых имён: прямые домены должны получать реальные адреса.
+        if matched == PROXY_TAG {
+            dns_rules = dns_rules
+                .into_iter()
+                .map(|mut r| {
+                    r["server"] = DNS_FAKEIP_TAG.into();
+                    r
+                })
+                .collect();
+        }
+        if fallback == PROXY_TAG {
+            dns["final"] = DNS_FAKEIP_TAG.into();
+        }
+        dns["fakeip"] = json!({
+            "enabled": true,
+            "inet4_range": FAKEIP_INET4_RANGE,
+            "inet6_range": FAKEIP_INET6_RANGE,
+        });
+    }
+    dns["servers"] = servers.into();
+    dns["rules"] = dns_rules.into();
+    Ok(dns)
+}
+
+fn is_encrypted_dns(address: &str) -> bool {
+    address.starts_with("https://") || address.starts_with("tls://")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsLeakReport {
+    pub probe: String,
+    pub direct: Vec<IpAddr>,
+    pub tunnel: Vec<IpAddr>,
+    /// Туннель ответил адресами FakeIP: имена резолвит сервер VPN.
+    pub fakeip: bool,
+    pub leak_suspected: bool,
+}
+
+/// Спрашивает whoami-имя `probe` (см. [`DNS_WHOAMI_PROBE`]) у прямого резолвера и у
+/// DNS-входа туннеля. Утечка подозревается, когда за туннелем виден тот же рекурсивный
+/// резолвер, что и напрямую. Ответ из диапазона FakeIP значит, что имя до резолвера
+/// не дошло. Отказ туннельного резолвера — ошибка, а не вердикт.
+pub async fn dns_leak_check(
+    probe: &str,
+    direct_resolver: SocketAddr,
+    tunnel_resolver: SocketAddr,
+) -> Result<DnsLeakReport> {
+    let direct = resolve_a(probe, direct_resolver).await?;
+    let tunnel = resolve_a(probe, tunnel_resolver).await?;
+    if tunnel.is_empty() {
+        bail!("tunnel resolver {tunnel_resolver} returned no address for {probe}");
+    }
+    let fakeip = tunnel.iter().all(is_fakeip);
+    let leak_suspected = !fakeip && tunnel.iter().any(|ip| direct.contains(ip));
+    Ok(DnsLeakReport {
+        probe: probe.to_string(),
+        direct,
+        tunnel,
+        fakeip,
+        leak_suspected,
+    })
+}
+
+fn is_fakeip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.octets()[0] == 198 && v4.octets()[1] & 0xfe == 18,
+        IpAddr::V6(v6) => v6.segments()[0] & 0xffc0 == 0xfc00,
+    }
+}
+
+/// Минимальный DNS-клиент поверх UDP: один A-запрос, разбор A/AAAA из ответа.
+pub async fn resolve_a(name: &str, resolver: SocketAddr) -> Result<Vec<IpAddr>> {
+    let bind = if resolver.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    let id = rand::random::<u16>();
+    socket.send_to(&build_query(id, name)?, resolver).await?;
+    let mut buf = [0u8; 1500];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf))
+        .await
+        .map_err(|_| anyhow!("dns timeout from {resolver}"))??;
+    parse_answers(id, &buf[..n])
+}
+
+fn build_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut q = Vec::with_capacity(32 + name.len());
+    q.extend_from_slice(&id.to_be_bytes());
+    q.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("invalid dns name: {name}");
+        }
+        q.push(label.len() as u8);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.push(0);
+    q.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+    Ok(q)
+}
+
+fn parse_answers(id: u16, msg: &[u8]) -> Result<Vec<IpAddr>> {
+    if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+        bail!("malformed dns response");
+    }
+    let rcode = msg[3] & 0x0f;
+    if rcode != 0 {
+        bail!("dns error rcode {rcode}");
+    }
+    let qdcount = u16::from_be_bytes([msg[4], msg[5]]);
+    let ancount = u16::from_be_bytes([msg[6], msg[7]]);
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    let mut out = Vec::new();
+    for _ in 0..ancount {
+        pos = skip_name(msg, pos)?;
+        let header = msg
+            .get(pos..pos + 10)
+            .ok_or_else(|| anyhow!("truncated dns answer"))?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+        pos += 10;
+        let data = msg
+            .get(pos..pos + len)
+            .ok_or_else(|| anyhow!("truncated dns answer"))?;
+        match (rtype, len) {
+            (1, 4) => out.push(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            ))),
+            (28, 16) => {
+                let octets: [u8; 16] = data.try_into()?;
+                out.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+    Ok(out)
+}
+
+fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let len = *msg.get(pos).ok_or_else(|| anyhow!("truncated dns name"))?;
+        match len {
+            0 => return Ok(pos + 1),
+            l if l & 0xc0 == 0xc0 => return Ok(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
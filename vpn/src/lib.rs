@@ -1,25 +1,34 @@
-use aes_gcm_siv::{
-    aead::{Aead, KeyInit},
-    Aes256GcmSiv, Nonce,
-};
 use anyhow::{anyhow, Result};
-use base64::Engine;
-use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use std::{
+    collections::HashMap,
     fs,
     path::{Path, PathBuf},
     process::Stdio,
+    time::Duration,
 };
 use tokio::process::{Child, Command};
 use url::Url;
 
+mod core;
+mod dns;
+mod route;
+mod secure;
+
+pub use crate::core::{CoreKind, SingBoxCore, VpnCore, XrayCore};
+pub use dns::{
+    dns_config, dns_leak_check, resolve_a, DnsLeakReport, DnsSettings, DNS_WHOAMI_PROBE,
+    TUNNEL_DNS_LISTEN,
+};
+pub use route::{route_config, RouteRules, RuleSetFile, RuleSetKind};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum VpnMode {
     Off,
     Global,
     DomainList(Vec<String>),
+    Split(RouteRules),
+    Bypass(RouteRules),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,27 +38,51 @@ pub struct VpnConfig {
     pub raw: String,
     pub mode: VpnMode,
     pub dns_via_tunnel: bool,
+    #[serde(default)]
+    pub dns: DnsSettings,
+    #[serde(default)]
+    pub core: CoreKind,
 }
 
 pub struct VpnManager {
     pub active: Option<VpnConfig>,
     child: Option<Child>,
     local_socks: String,
-    core_bin: PathBuf,
+    cores: HashMap<CoreKind, Box<dyn VpnCore>>,
     workdir: PathBuf,
 }
 
 impl VpnManager {
+    /// `core_bin` — путь к sing-box; другие core регистрируются через `set_core`.
     pub fn new(core_bin: impl AsRef<Path>, workdir: impl AsRef<Path>) -> Self {
+        let mut cores: HashMap<CoreKind, Box<dyn VpnCore>> = HashMap::new();
+        cores.insert(CoreKind::SingBox, Box::new(SingBoxCore::new(core_bin)));
         Self {
             active: None,
             child: None,
             local_socks: "127.0.0.1:2080".into(),
-            core_bin: core_bin.as_ref().to_path_buf(),
+            cores,
             workdir: workdir.as_ref().to_path_buf(),
         }
     }
 
+    pub fn set_core(&mut self, kind: CoreKind, core: Box<dyn VpnCore>) {
+        self.cores.insert(kind, core);
+    }
+
+    pub fn set_local_socks(&mut self, addr: impl Into<String>) {
+        self.local_socks = addr.into();
+    }
+
+    /// Core, выбранный в активном профиле.
+    pub fn core(&self) -> Result<&dyn VpnCore> {
+        let kind = self.active.as_ref().map(|c| c.core).unwrap_or_default();
+        self.cores
+            .get(&kind)
+            .map(|c| c.as_ref())
+            .ok_or_else(|| anyhow!("vpn core {kind:?} is not configured"))
+    }
+
     pub fn import(
         &mut self,
         input: &str,
@@ -69,6 +102,8 @@ impl VpnManager {
                 raw: input.to_string(),
                 mode,
                 dns_via_tunnel,
+                dns: DnsSettings::default(),
+                core: CoreKind::default(),
             }
         } else {
             let url = Url::parse(input)?;
@@ -87,6 +122,8 @@ impl VpnManager {
                 raw: input.to_string(),
                 mode,
                 dns_via_tunnel,
+                dns: DnsSettings::default(),
+                core: CoreKind::default(),
             }
         };
         self.active = Some(config.clone());
@@ -99,41 +136,44 @@ impl VpnManager {
             .map(|_| format!("socks5h://{}", self.local_socks))
     }
 
-    pub async fn start_core(&mut self) -> Result<()> {
+    /// Локальный адрес core, по которому видно, что он запущен; `None` без профиля.
+    pub fn probe_addr(&self) -> Option<String> {
+        self.active.as_ref()?;
+        Some(self.core().ok()?.probe_addr(&self.local_socks))
+    }
+
+    pub fn set_dns(&mut self, dns: DnsSettings) -> Result<()> {
         let cfg = self
             .active
-            .clone()
+            .as_mut()
+            .ok_or_else(|| anyhow!("vpn config missing"))?;
+        cfg.dns = dns;
+        Ok(())
+    }
+
+    /// Адрес DNS-входа core, если DNS идёт через туннель и core такой вход открывает.
+    pub fn tunnel_dns(&self) -> Option<std::net::SocketAddr> {
+        self.active.as_ref().filter(|c| c.dns_via_tunnel)?;
+        self.core().ok()?.tunnel_dns()?.parse().ok()
+    }
+
+    pub fn render_config(&self) -> Result<serde_json::Value> {
+        let cfg = self
+            .active
+            .as_ref()
             .ok_or_else(|| anyhow!("vpn config missing"))?;
+        self.core()?.render_config(cfg, &self.local_socks)
+    }
+
+    pub async fn start_core(&mut self) -> Result<()> {
+        let full = self.render_config()?;
+        let core = self.core()?;
         fs::create_dir_all(&self.workdir)?;
-        let cfg_file = self.workdir.join("singbox-config.json");
-        let outbound = if cfg.protocol == "json" {
-            serde_json::from_str::<serde_json::Value>(&cfg.raw)?
-        } else {
-            serde_json::json!({
-                "type": cfg.protocol,
-                "server": cfg.endpoint.split(':').next().unwrap_or("127.0.0.1"),
-                "server_port": cfg.endpoint.split(':').nth(1).and_then(|p| p.parse::<u16>().ok()).unwrap_or(443),
-                "tag": "proxy"
-            })
-        };
-        let dns = if cfg.dns_via_tunnel {
-            serde_json::json!({"strategy":"prefer_ipv4"})
-        } else {
-            serde_json::json!({})
-        };
-        let full = serde_json::json!({
-            "log": {"level": "warn"},
-            "dns": dns,
-            "inbounds": [{"type": "socks", "listen": "127.0.0.1", "listen_port": 2080, "tag":"plus-in"}],
-            "outbounds": [outbound, {"type":"direct","tag":"direct"}],
-            "route": {"final": "proxy"}
-        });
+        let cfg_file = self.workdir.join(core.config_file_name());
         fs::write(&cfg_file, serde_json::to_vec_pretty(&full)?)?;
 
-        let child = Command::new(&self.core_bin)
-            .arg("run")
-            .arg("-c")
-            .arg(cfg_file)
+        let child = Command::new(core.binary())
+            .args(core.spawn_args(&cfg_file))
             .stderr(Stdio::inherit())
             .stdout(Stdio::inherit())
             .spawn()?;
@@ -141,6 +181,42 @@ impl VpnManager {
         Ok(())
     }
 
+    /// Ждёт, пока core начнёт принимать соединения на своём локальном адресе.
+    pub async fn wait_ready(&mut self, timeout: Duration) -> Result<()> {
+        let addr = self.core()?.probe_addr(&self.local_socks);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Some(child) = &mut self.child {
+                if let Some(status) = child.try_wait()? {
+                    return Err(anyhow!("vpn core exited: {status}"));
+                }
+            }
+            if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(anyhow!("vpn core not ready on {addr}"));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Код выхода core, если процесс уже завершился; без ожидания.
+    pub fn core_exit_status(&mut self) -> Option<std::process::ExitStatus> {
+        self.child.as_mut()?.try_wait().ok().flatten()
+    }
+
+    pub async fn core_version(&self) -> Result<String> {
+        let core = self.core()?;
+        let output = Command::new(core.binary())
+            .args(core.version_args())
+            .output()
+            .await?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        core.parse_version(&text)
+            .ok_or_else(|| anyhow!("cannot parse core version from {:?}", text.trim()))
+    }
+
     pub async fn stop_core(&mut self) -> Result<()> {
         if let Some(child) = &mut self.child {
             child.kill().await?;
@@ -162,27 +238,568 @@ impl VpnManager {
                 return Ok(());
             }
         }
+        self.store_encrypted_file(path_fallback, passphrase)
+    }
+
+    /// Читает конфиг из keyring, а если там пусто — из зашифрованного файла.
+    /// Файл старого формата (SHA-256 без соли) перешифровывается на месте.
+    pub fn load_secure(
+        &mut self,
+        service: &str,
+        account: &str,
+        path_fallback: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<Option<VpnConfig>> {
+        if let Ok(entry) = keyring::Entry::new(service, account) {
+            if let Ok(raw) = entry.get_password() {
+                self.active = serde_json::from_str(&raw)?;
+                return Ok(self.active.clone());
+            }
+        }
+        self.load_encrypted_file(path_fallback, passphrase)
+    }
 
-        let mut nonce = [0u8; 12];
-        rand::rng().fill_bytes(&mut nonce);
-        let key = derive_key(passphrase);
-        let cipher = Aes256GcmSiv::new_from_slice(&key)?;
-        let data = cipher
-            .encrypt(Nonce::from_slice(&nonce), raw.as_bytes())
-            .map_err(|_| anyhow!("encrypt failed"))?;
-        let blob = format!(
-            "{}:{}",
-            base64::engine::general_purpose::STANDARD.encode(nonce),
-            base64::engine::general_purpose::STANDARD.encode(data)
-        );
-        fs::write(path_fallback, blob)?;
-        Ok(())
+    pub fn store_encrypted_file(&self, path: impl AsRef<Path>, passphrase: &str) -> Result<()> {
+        let raw = serde_json::to_string(&self.active)?;
+        let blob = secure::seal(raw.as_bytes(), passphrase, secure::KdfParams::default())?;
+        Ok(plus_privacy::write_atomic(path.as_ref(), blob.as_bytes())?)
+    }
+
+    pub fn load_encrypted_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<Option<VpnConfig>> {
+        let raw = secure::read_file(path.as_ref(), passphrase)?;
+        self.active = serde_json::from_slice(&raw)?;
+        Ok(self.active.clone())
     }
 }
 
-fn derive_key(passphrase: &str) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(passphrase.as_bytes());
-    let out = hasher.finalize();
-    out.into()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager_with(mode: VpnMode) -> VpnManager {
+        let mut vpn = VpnManager::new("sing-box", std::env::temp_dir());
+        vpn.import("vless://id@vpn.example:8443", mode, false)
+            .unwrap();
+        vpn
+    }
+
+    #[test]
+    fn global_mode_routes_everything_to_proxy() {
+        let route = &manager_with(VpnMode::Global).render_config().unwrap()["route"];
+        assert_eq!(route["final"], "proxy");
+        assert_eq!(route["rules"].as_array().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn domain_list_becomes_split_rules_with_direct_fallback() {
+        let mode = VpnMode::DomainList(vec![
+            "example.com".into(),
+            ".ru".into(),
+            "keyword:google".into(),
+            "10.0.0.0/8".into(),
+            "geosite:/opt/plus/geosite-ru.srs".into(),
+            "geoip:/opt/plus/geoip-ru.json".into(),
+        ]);
+        let route = &manager_with(mode).render_config().unwrap()["route"];
+        assert_eq!(route["final"], "direct");
+        assert_eq!(
+            route["rules"],
+            serde_json::json!([
+                {"domain": ["example.com"], "outbound": "proxy"},
+                {"domain_suffix": ["ru"], "outbound": "proxy"},
+                {"domain_keyword": ["google"], "outbound": "proxy"},
+                {"ip_cidr": ["10.0.0.0/8"], "outbound": "proxy"},
+                {"rule_set": ["geosite:geosite-ru", "geoip:geoip-ru"], "outbound": "proxy"},
+            ])
+        );
+        assert_eq!(
+            route["rule_set"],
+            serde_json::json!([
+                {"tag": "geosite:geosite-ru", "type": "local", "format": "binary", "path": "/opt/plus/geosite-ru.srs"},
+                {"tag": "geoip:geoip-ru", "type": "local", "format": "source", "path": "/opt/plus/geoip-ru.json"},
+            ])
+        );
+    }
+
+    #[test]
+    fn rule_set_tags_include_the_kind() {
+        let mode = VpnMode::DomainList(vec![
+            "geosite:/opt/plus/ru.srs".into(),
+            "geoip:/opt/plus/ru.srs".into(),
+        ]);
+        let route = &manager_with(mode).render_config().unwrap()["route"];
+        assert_eq!(
+            route["rules"][0]["rule_set"],
+            serde_json::json!(["geosite:ru", "geoip:ru"])
+        );
+        assert_eq!(route["rule_set"][1]["tag"], "geoip:ru");
+
+        // Один вид, одно имя, разные каталоги — sing-box такой конфиг не примет.
+        let clash =
+            VpnMode::DomainList(vec!["geosite:/a/ru.srs".into(), "geosite:/b/ru.srs".into()]);
+        assert!(manager_with(clash).render_config().is_err());
+
+        // Конфиги прежних версий хранили пути без вида.
+        let old: RouteRules = serde_json::from_str(
+            r#"{"domain": [], "domain_suffix": [], "domain_keyword": [],
+                "ip_cidr": [], "rule_sets": ["/opt/plus/ru.srs"]}"#,
+        )
+        .unwrap();
+        assert_eq!(old.rule_sets[0].kind, RuleSetKind::Other);
+        let saved =
+            serde_json::to_value(RouteRules::parse(&["geoip:/opt/plus/ru.srs".into()]).unwrap())
+                .unwrap();
+        assert_eq!(
+            saved["rule_sets"],
+            serde_json::json!(["geoip:/opt/plus/ru.srs"])
+        );
+    }
+
+    #[test]
+    fn bypass_mode_inverts_outbounds() {
+        let rules = RouteRules {
+            domain_suffix: vec!["yandex.ru".into()],
+            ip_cidr: vec!["192.168.0.0/16".into()],
+            ..RouteRules::default()
+        };
+        let route = &manager_with(VpnMode::Bypass(rules))
+            .render_config()
+            .unwrap()["route"];
+        assert_eq!(route["final"], "proxy");
+        assert_eq!(
+            route["rules"],
+            serde_json::json!([
+                {"domain_suffix": ["yandex.ru"], "outbound": "direct"},
+                {"ip_cidr": ["192.168.0.0/16"], "outbound": "direct"},
+            ])
+        );
+        assert!(route.get("rule_set").is_none());
+    }
+
+    #[test]
+    fn dns_without_tunnel_uses_local_resolver_only() {
+        let cfg = manager_with(VpnMode::Global).render_config().unwrap();
+        assert_eq!(
+            cfg["dns"],
+            serde_json::json!({
+                "servers": [{"tag": "dns-direct", "address": "local", "detour": "direct"}],
+                "final": "dns-direct"
+            })
+        );
+        assert_eq!(cfg["inbounds"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn dns_via_tunnel_splits_servers_like_routes() {
+        let mut vpn = manager_with(VpnMode::DomainList(vec![".example".into()]));
+        vpn.active.as_mut().unwrap().dns_via_tunnel = true;
+        let cfg = vpn.render_config().unwrap();
+        let dns = &cfg["dns"];
+        assert_eq!(dns["final"], "dns-direct");
+        assert_eq!(dns["servers"][0]["detour"], "proxy");
+        assert_eq!(dns["servers"][0]["address"], "https://1.1.1.1/dns-query");
+        assert_eq!(
+            dns["rules"],
+            serde_json::json!([{"domain_suffix": ["example"], "server": "dns-remote"}])
+        );
+        assert_eq!(cfg["route"]["rules"][0]["outbound"], "dns-out");
+        assert_eq!(cfg["inbounds"][1]["listen_port"], 2053);
+        assert!(vpn.tunnel_dns().is_some());
+    }
+
+    #[test]
+    fn xray_dns_via_tunnel_accepts_only_doh_without_fakeip() {
+        let mut vpn = manager_with(VpnMode::Global);
+        vpn.set_core(CoreKind::Xray, Box::new(XrayCore::new("xray")));
+        let active = vpn.active.as_mut().unwrap();
+        active.core = CoreKind::Xray;
+        active.dns_via_tunnel = true;
+        let cfg = vpn.render_config().unwrap();
+        assert_eq!(
+            cfg["dns"],
+            serde_json::json!({"servers": ["https://1.1.1.1/dns-query"]})
+        );
+
+        vpn.active.as_mut().unwrap().dns.remote = "tls://9.9.9.9".into();
+        assert!(vpn.render_config().is_err());
+
+        vpn.active.as_mut().unwrap().dns = DnsSettings {
+            fakeip: true,
+            ..DnsSettings::default()
+        };
+        assert!(vpn.render_config().is_err());
+    }
+
+    #[test]
+    fn fakeip_applies_to_proxied_names() {
+        let mut vpn = manager_with(VpnMode::Global);
+        vpn.active.as_mut().unwrap().dns_via_tunnel = true;
+        vpn.set_dns(DnsSettings {
+            remote: "tls://9.9.9.9".into(),
+            fakeip: true,
+            ..DnsSettings::default()
+        })
+        .unwrap();
+        let dns = &vpn.render_config().unwrap()["dns"];
+        assert_eq!(dns["final"], "dns-fakeip");
+        assert_eq!(dns["fakeip"]["enabled"], true);
+        assert_eq!(dns["servers"][2]["address"], "fakeip");
+
+        vpn.set_dns(DnsSettings {
+            remote: "udp://8.8.8.8".into(),
+            ..DnsSettings::default()
+        })
+        .unwrap();
+        assert!(vpn.render_config().is_err());
+    }
+
+    async fn spawn_dns_stub(answer: [u8; 4]) -> std::net::SocketAddr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+                let mut resp = buf[..n].to_vec();
+                resp[2] = 0x81;
+                resp[3] = 0x80;
+                resp[7] = 1;
+                resp.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                resp.extend_from_slice(&answer);
+                let _ = socket.send_to(&resp, peer).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn dns_leak_check_compares_resolver_identity() {
+        // Заглушки отвечают «адресом резолвера», как whoami-имя.
+        let direct = spawn_dns_stub([10, 0, 0, 1]).await;
+        let tunnel = spawn_dns_stub([10, 0, 0, 2]).await;
+        let report = dns_leak_check(DNS_WHOAMI_PROBE, direct, tunnel)
+            .await
+            .unwrap();
+        assert_eq!(report.direct, vec![std::net::IpAddr::from([10, 0, 0, 1])]);
+        assert_eq!(report.tunnel, vec![std::net::IpAddr::from([10, 0, 0, 2])]);
+        assert!(!report.leak_suspected);
+
+        let same = dns_leak_check(DNS_WHOAMI_PROBE, direct, direct)
+            .await
+            .unwrap();
+        assert!(same.leak_suspected);
+
+        let fakeip = spawn_dns_stub([198, 19, 0, 7]).await;
+        let report = dns_leak_check(DNS_WHOAMI_PROBE, fakeip, fakeip)
+            .await
+            .unwrap();
+        assert!(report.fakeip);
+        assert!(!report.leak_suspected);
+    }
+
+    #[tokio::test]
+    async fn dns_leak_check_reports_tunnel_resolver_errors() {
+        let direct = spawn_dns_stub([10, 0, 0, 1]).await;
+        let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let result = dns_leak_check(DNS_WHOAMI_PROBE, direct, silent.local_addr().unwrap()).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn encrypted_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vpn.enc");
+        let vpn = manager_with(VpnMode::Global);
+        vpn.store_encrypted_file(&path, "correct horse").unwrap();
+
+        let blob = std::fs::read_to_string(&path).unwrap();
+        assert!(blob.starts_with("plus-vpn$2$argon2id$"));
+        assert!(!blob.contains("vpn.example"));
+
+        let mut restored = VpnManager::new("sing-box", dir.path());
+        let cfg = restored
+            .load_encrypted_file(&path, "correct horse")
+            .unwrap()
+            .unwrap();
+        assert_eq!(cfg.endpoint, "vpn.example:8443");
+        assert!(restored
+            .load_encrypted_file(&path, "wrong passphrase")
+            .is_err());
+    }
+
+    #[test]
+    fn same_passphrase_uses_fresh_salt() {
+        let a = secure::seal(b"x", "pw", secure::KdfParams::default()).unwrap();
+        let b = secure::seal(b"x", "pw", secure::KdfParams::default()).unwrap();
+        let salt = |blob: &str| blob.split('$').nth(4).unwrap().to_string();
+        assert_ne!(salt(&a), salt(&b));
+    }
+
+    #[test]
+    fn tampered_file_is_rejected() {
+        let blob = secure::seal(b"{\"secret\":1}", "pw", secure::KdfParams::default()).unwrap();
+        let (header, data) = blob.rsplit_once('$').unwrap();
+
+        let mut bytes =
+            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, data).unwrap();
+        bytes[0] ^= 1;
+        let flipped = format!(
+            "{header}${}",
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes)
+        );
+        assert!(secure::open(&flipped, "pw").is_err());
+
+        let weaker = blob.replace("t=2,", "t=1,");
+        assert!(secure::open(&weaker, "pw").is_err());
+
+        let unknown = blob.replacen("$2$", "$3$", 1);
+        assert!(secure::open(&unknown, "pw").is_err());
+
+        // Заголовок, который заставил бы Argon2 выделить 4 ТиБ, отвергается до вывода ключа.
+        let m_cost = format!("m={}", secure::KdfParams::default().m_cost);
+        for params in [
+            "m=4294967295",
+            "m=19456,t=2",
+            "m=19456,t=2,p=1,p=1",
+            "t=2,m=19456,p=1",
+        ] {
+            let hostile = header.replacen(&format!("{m_cost},t=2,p=1"), params, 1);
+            assert!(
+                secure::open(&format!("{hostile}${data}"), "pw").is_err(),
+                "{params}"
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_file_is_migrated_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vpn.enc");
+        let vpn = manager_with(VpnMode::Global);
+        let raw = serde_json::to_string(&vpn.active).unwrap();
+        std::fs::write(&path, secure::seal_legacy(raw.as_bytes(), "pw")).unwrap();
+
+        let mut restored = VpnManager::new("sing-box", dir.path());
+        let cfg = restored.load_encrypted_file(&path, "pw").unwrap().unwrap();
+        assert_eq!(cfg.protocol, "vless");
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("plus-vpn$2$"));
+        assert!(restored.load_encrypted_file(&path, "pw").unwrap().is_some());
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    struct MockCore {
+        probe: String,
+    }
+
+    impl VpnCore for MockCore {
+        fn binary(&self) -> &Path {
+            Path::new("sh")
+        }
+
+        fn config_file_name(&self) -> &str {
+            "mock-config.json"
+        }
+
+        fn render_config(&self, cfg: &VpnConfig, local_socks: &str) -> Result<serde_json::Value> {
+            Ok(serde_json::json!({"endpoint": cfg.endpoint, "socks": local_socks}))
+        }
+
+        fn spawn_args(&self, config_path: &Path) -> Vec<std::ffi::OsString> {
+            vec![
+                "-c".into(),
+                format!("test -f '{}' && sleep 5", config_path.display()).into(),
+            ]
+        }
+
+        fn version_args(&self) -> Vec<std::ffi::OsString> {
+            vec!["-c".into(), "echo mock-core 0.1.0".into()]
+        }
+
+        fn parse_version(&self, output: &str) -> Option<String> {
+            output
+                .trim()
+                .strip_prefix("mock-core ")
+                .map(ToOwned::to_owned)
+        }
+
+        fn probe_addr(&self, _local_socks: &str) -> String {
+            self.probe.clone()
+        }
+    }
+
+    #[test]
+    fn xray_profile_renders_xray_schema() {
+        let mut vpn = manager_with(VpnMode::DomainList(vec![".example".into()]));
+        vpn.set_core(CoreKind::Xray, Box::new(XrayCore::new("xray")));
+        vpn.active.as_mut().unwrap().core = CoreKind::Xray;
+        let cfg = vpn.render_config().unwrap();
+        assert_eq!(cfg["inbounds"][0]["protocol"], "socks");
+        assert_eq!(cfg["inbounds"][0]["port"], 2080);
+        assert_eq!(cfg["outbounds"][0]["protocol"], "vless");
+        assert_eq!(
+            cfg["outbounds"][0]["settings"]["vnext"][0]["users"][0]["id"],
+            "id"
+        );
+        assert_eq!(
+            cfg["routing"]["rules"],
+            serde_json::json!([
+                {"type": "field", "domain": ["domain:example"], "outboundTag": "proxy"},
+                {"type": "field", "network": "tcp,udp", "outboundTag": "direct"},
+            ])
+        );
+        assert_eq!(cfg["routing"]["domainStrategy"], "IPIfNonMatch");
+        assert_eq!(vpn.core().unwrap().config_file_name(), "xray-config.json");
+        // Свой DNS-вход открывает только sing-box.
+        vpn.active.as_mut().unwrap().dns_via_tunnel = true;
+        assert_eq!(vpn.tunnel_dns(), None);
+    }
+
+    #[test]
+    fn xray_outbound_carries_stream_settings_from_link() {
+        let mut vpn = VpnManager::new("sing-box", std::env::temp_dir());
+        vpn.set_core(CoreKind::Xray, Box::new(XrayCore::new("xray")));
+        let mut outbound = |link: &str| {
+            vpn.import(link, VpnMode::Global, false).unwrap();
+            vpn.active.as_mut().unwrap().core = CoreKind::Xray;
+            vpn.render_config().unwrap()["outbounds"][0].clone()
+        };
+
+        let reality = outbound(
+            "vless://id@vpn.example:443?type=tcp&security=reality&sni=www.microsoft.com\
+             &fp=firefox&pbk=KEY&sid=ab12&flow=xtls-rprx-vision",
+        );
+        assert_eq!(
+            reality["settings"]["vnext"][0]["users"][0]["flow"],
+            "xtls-rprx-vision"
+        );
+        assert_eq!(
+            reality["streamSettings"],
+            serde_json::json!({
+                "network": "tcp",
+                "security": "reality",
+                "realitySettings": {
+                    "serverName": "www.microsoft.com",
+                    "fingerprint": "firefox",
+                    "publicKey": "KEY",
+                    "shortId": "ab12",
+                    "spiderX": "",
+                },
+            })
+        );
+
+        let trojan =
+            outbound("trojan://secret@vpn.example:443?type=ws&path=%2Fws&host=cdn.example");
+        assert_eq!(
+            trojan["streamSettings"],
+            serde_json::json!({
+                "network": "ws",
+                "security": "tls",
+                "tlsSettings": {"serverName": "vpn.example"},
+                "wsSettings": {"path": "/ws", "headers": {"Host": "cdn.example"}},
+            })
+        );
+
+        let grpc =
+            outbound("vless://id@vpn.example:443?type=grpc&serviceName=tun&security=tls&alpn=h2");
+        assert_eq!(grpc["streamSettings"]["grpcSettings"]["serviceName"], "tun");
+        assert_eq!(
+            grpc["streamSettings"]["tlsSettings"]["alpn"],
+            serde_json::json!(["h2"])
+        );
+
+        let ss = outbound("ss://aes-256-gcm%3Apass@vpn.example:8388");
+        assert!(ss.get("streamSettings").is_none());
+
+        vpn.import(
+            "vless://id@vpn.example:443?security=reality",
+            VpnMode::Global,
+            false,
+        )
+        .unwrap();
+        vpn.active.as_mut().unwrap().core = CoreKind::Xray;
+        assert!(vpn.render_config().is_err());
+    }
+
+    #[test]
+    fn json_outbound_gets_proxy_tag_and_matches_core() {
+        let mut vpn = VpnManager::new("sing-box", std::env::temp_dir());
+        vpn.set_core(CoreKind::Xray, Box::new(XrayCore::new("xray")));
+        let mut render = |json: &str, core: CoreKind| {
+            vpn.import(json, VpnMode::Global, false).unwrap();
+            vpn.active.as_mut().unwrap().core = core;
+            vpn.render_config().map(|cfg| cfg["outbounds"][0].clone())
+        };
+        let singbox = r#"{"type":"vless","server":"vpn.example","server_port":443,"uuid":"id"}"#;
+        let xray = r#"{"protocol":"vless","settings":{"vnext":[]}}"#;
+
+        assert_eq!(render(singbox, CoreKind::SingBox).unwrap()["tag"], "proxy");
+        assert_eq!(render(xray, CoreKind::Xray).unwrap()["tag"], "proxy");
+        assert!(render(singbox, CoreKind::Xray).is_err());
+        assert!(render(xray, CoreKind::SingBox).is_err());
+        assert!(render(
+            r#"{"type":"vless","server":"vpn.example","tag":"out"}"#,
+            CoreKind::SingBox
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn missing_core_for_profile_is_an_error() {
+        let mut vpn = manager_with(VpnMode::Global);
+        vpn.active.as_mut().unwrap().core = CoreKind::Xray;
+        assert!(vpn.render_config().is_err());
+    }
+
+    #[test]
+    fn parses_core_versions() {
+        let sing = SingBoxCore::new("sing-box");
+        assert_eq!(
+            sing.parse_version("sing-box version 1.10.1\n\nEnvironment: go1.23")
+                .as_deref(),
+            Some("1.10.1")
+        );
+        let xray = XrayCore::new("xray");
+        assert_eq!(
+            xray.parse_version(
+                "Xray 1.8.24 (Xray, Penetrates Everything.) 0ad7a6b (go1.22.5 linux/amd64)"
+            )
+            .as_deref(),
+            Some("1.8.24")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn mock_core_spawns_and_reports_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut vpn = VpnManager::new("sing-box", dir.path());
+        vpn.import("trojan://pw@vpn.example:443", VpnMode::Global, false)
+            .unwrap();
+        vpn.set_core(
+            CoreKind::SingBox,
+            Box::new(MockCore {
+                probe: listener.local_addr().unwrap().to_string(),
+            }),
+        );
+        assert_eq!(vpn.core_version().await.unwrap(), "0.1.0");
+        vpn.start_core().await.unwrap();
+        vpn.wait_ready(Duration::from_secs(2)).await.unwrap();
+        let written = std::fs::read_to_string(dir.path().join("mock-config.json")).unwrap();
+        assert!(written.contains("vpn.example:443"));
+        vpn.stop_core().await.unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_cidr() {
+        assert!(RouteRules::parse(&["cidr:10.0.0.0/33".into()]).is_err());
+        let rules = RouteRules::parse(&["fd00::/8".into(), "*.corp".into()]).unwrap();
+        assert_eq!(rules.ip_cidr, vec!["fd00::/8"]);
+        assert_eq!(rules.domain_suffix, vec!["corp"]);
+    }
 }
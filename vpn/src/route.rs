@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::{net::IpAddr, path::PathBuf};
+
+use crate::VpnMode;
+
+pub const PROXY_TAG: &str = "proxy";
+pub const DIRECT_TAG: &str = "direct";
+
+/// Набор правил маршрутизации, который переводится в `route.rules` sing-box.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteRules {
+    pub domain: Vec<String>,
+    pub domain_suffix: Vec<String>,
+    pub domain_keyword: Vec<String>,
+    pub ip_cidr: Vec<String>,
+    /// Локальные geosite/geoip rule-set файлы (`.srs` — бинарные, `.json` — исходные).
+    pub rule_sets: Vec<RuleSetFile>,
+}
+
+/// Вид rule-set файла — префикс записи в списке правил.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleSetKind {
+    Geosite,
+    Geoip,
+    /// `rule_set:` и пути без префикса из конфигов прежних версий.
+    Other,
+}
+
+impl RuleSetKind {
+    const ALL: [Self; 3] = [Self::Geosite, Self::Geoip, Self::Other];
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Geosite => "geosite",
+            Self::Geoip => "geoip",
+            Self::Other => "rule_set",
+        }
+    }
+}
+
+/// Rule-set файл. Хранится строкой записи (`geosite:/opt/plus/ru.srs`), как её
+/// вводят; строка без префикса — путь из конфига прежней версии.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct RuleSetFile {
+    pub kind: RuleSetKind,
+    pub path: PathBuf,
+}
+
+impl RuleSetFile {
+    fn parse(entry: &str) -> Option<Self> {
+        RuleSetKind::ALL.into_iter().find_map(|kind| {
+            let path = entry.strip_prefix(kind.prefix())?.strip_prefix(':')?;
+            Some(Self {
+                kind,
+                path: PathBuf::from(path),
+            })
+        })
+    }
+
+    /// Тег в конфиге sing-box: вид и имя файла, чтобы `geosite:ru.srs` и
+    /// `geoip:ru.srs` не получили один тег.
+    fn tag(&self) -> Result<String> {
+        let stem = self
+            .path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow!("invalid rule-set path: {}", self.path.display()))?;
+        Ok(format!("{}:{stem}", self.kind.prefix()))
+    }
+}
+
+impl From<String> for RuleSetFile {
+    fn from(entry: String) -> Self {
+        Self::parse(&entry).unwrap_or(Self {
+            kind: RuleSetKind::Other,
+            path: PathBuf::from(entry),
+        })
+    }
+}
+
+impl From<RuleSetFile> for String {
+    fn from(file: RuleSetFile) -> Self {
+        format!("{}:{}", file.kind.prefix(), file.path.display())
+    }
+}
+
+impl RouteRules {
+    /// Разбирает список вида `["example.com", ".ru", "keyword:google", "10.0.0.0/8",
+    /// "geosite:/opt/plus/geosite-ru.srs"]`.
+    ///
+    /// Префиксы: `domain:`, `suffix:`, `keyword:`, `cidr:`, `geosite:`/`geoip:`/`rule_set:`.
+    /// Без префикса: CIDR → `ip_cidr`, `.zone`/`*.zone` → `domain_suffix`, иначе `domain`.
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let mut rules = Self::default();
+        for entry in entries.iter().map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            if let Some(v) = entry.strip_prefix("domain:") {
+                rules.domain.push(v.to_string());
+            } else if let Some(v) = entry.strip_prefix("suffix:") {
+                rules.domain_suffix.push(v.to_string());
+            } else if let Some(v) = entry.strip_prefix("keyword:") {
+                rules.domain_keyword.push(v.to_string());
+            } else if let Some(v) = entry.strip_prefix("cidr:") {
+                if !is_cidr(v) {
+                    return Err(anyhow!("invalid CIDR: {v}"));
+                }
+                rules.ip_cidr.push(v.to_string());
+            } else if let Some(file) = RuleSetFile::parse(entry) {
+                rules.rule_sets.push(file);
+            } else if is_cidr(entry) {
+                rules.ip_cidr.push(entry.to_string());
+            } else if let Some(v) = entry.strip_prefix("*.").or_else(|| entry.strip_prefix('.')) {
+                rules.domain_suffix.push(v.to_string());
+            } else {
+                rules.domain.push(entry.to_string());
+            }
+        }
+        Ok(rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domain.is_empty()
+            && self.domain_suffix.is_empty()
+            && self.domain_keyword.is_empty()
+            && self.ip_cidr.is_empty()
+            && self.rule_sets.is_empty()
+    }
+
+    /// Правила sing-box вида `{<matcher>: [...], <target_key>: <target>}`.
+    /// `ip_cidr` пропускается для DNS-правил, где он означает фильтр по ответу.
+    pub(crate) fn to_singbox_rules(
+        &self,
+        target_key: &str,
+        target: &str,
+        with_ip: bool,
+    ) -> Result<Vec<Value>> {
+        let mut out = Vec::new();
+        let lists = [
+            ("domain", &self.domain),
+            ("domain_suffix", &self.domain_suffix),
+            ("domain_keyword", &self.domain_keyword),
+            ("ip_cidr", &self.ip_cidr),
+        ];
+        for (key, values) in lists {
+            if !values.is_empty() && (with_ip || key != "ip_cidr") {
+                out.push(json!({ key: values, target_key: target }));
+            }
+        }
+        if !self.rule_sets.is_empty() {
+            let tags = self
+                .rule_sets
+                .iter()
+                .map(RuleSetFile::tag)
+                .collect::<Result<Vec<_>>>()?;
+            out.push(json!({"rule_set": tags, target_key: target}));
+        }
+        Ok(out)
+    }
+
+    /// Правила `routing` для Xray. Rule-set файлы sing-box Xray не понимает.
+    pub(crate) fn to_xray_rules(&self, outbound: &str) -> Result<Vec<Value>> {
+        if !self.rule_sets.is_empty() {
+            return Err(anyhow!("rule-set files are not supported by xray"));
+        }
+        let domains: Vec<String> = self
+            .domain
+            .iter()
+            .map(|d| format!("full:{d}"))
+            .chain(self.domain_suffix.iter().map(|d| format!("domain:{d}")))
+            .chain(self.domain_keyword.iter().map(|d| format!("keyword:{d}")))
+            .collect();
+        let mut out = Vec::new();
+        if !domains.is_empty() {
+            out.push(json!({"type": "field", "domain": domains, "outboundTag": outbound}));
+        }
+        if !self.ip_cidr.is_empty() {
+            out.push(json!({"type": "field", "ip": self.ip_cidr, "outboundTag": outbound}));
+        }
+        Ok(out)
+    }
+
+    /// Одинаковые теги sing-box не принимает: два файла одного вида с одним
+    /// именем из разных каталогов — ошибка конфига.
+    fn to_singbox_rule_sets(&self) -> Result<Vec<Value>> {
+        let mut tags = HashSet::new();
+        self.rule_sets
+            .iter()
+            .map(|file| {
+                let tag = file.tag()?;
+                if !tags.insert(tag.clone()) {
+                    return Err(anyhow!("duplicate rule-set tag: {tag}"));
+                }
+                let format = match file.path.extension().and_then(|e| e.to_str()) {
+                    Some("json") => "source",
+                    _ => "binary",
+                };
+                Ok(json!({
+                    "tag": tag,
+                    "type": "local",
+                    "format": format,
+                    "path": file.path,
+                }))
+            })
+            .collect()
+    }
+}
+
+/// Секция `route` конфига sing-box для выбранного режима.
+///
+/// `Global` — всё через прокси; `DomainList`/`Split` — совпадения через прокси,
+/// остальное напрямую; `Bypass` — совпадения напрямую, остальное через прокси.
+pub fn route_config(mode: &VpnMode) -> Result<Value> {
+    let (rules, matched, fallback) = route_plan(mode)?;
+    let mut route = json!({
+        "rules": rules.to_singbox_rules("outbound", matched, true)?,
+        "final": fallback,
+    });
+    let rule_sets = rules.to_singbox_rule_sets()?;
+    if !rule_sets.is_empty() {
+        route["rule_set"] = Value::from(rule_sets);
+    }
+    Ok(route)
+}
+
+/// Правила режима, outbound для совпадений и outbound по умолчанию.
+pub(crate) fn route_plan(mode: &VpnMode) -> Result<(RouteRules, &'static str, &'static str)> {
+    Ok(match mode {
+        VpnMode::Off => (RouteRules::default(), DIRECT_TAG, DIRECT_TAG),
+        VpnMode::Global => (RouteRules::default(), PROXY_TAG, PROXY_TAG),
+        VpnMode::DomainList(list) => (RouteRules::parse(list)?, PROXY_TAG, DIRECT_TAG),
+        VpnMode::Split(rules) => (rules.clone(), PROXY_TAG, DIRECT_TAG),
+        VpnMode::Bypass(rules) => (rules.clone(), DIRECT_TAG, PROXY_TAG),
+    })
+}
+
+fn is_cidr(value: &str) -> bool {
+    let Some((addr, prefix)) = value.split_once('/') else {
+        return false;
+    };
+    let (Ok(addr), Ok(prefix)) = (addr.parse::<IpAddr>(), prefix.parse::<u8>()) else {
+        return false;
+    };
+    match addr {
+        IpAddr::V4(_) => prefix <= 32,
+        IpAddr::V6(_) => prefix <= 128,
+    }
+}
@@ -0,0 +1,188 @@
+use aes_gcm_siv::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256GcmSiv, Nonce,
+};
+use anyhow::{anyhow, bail, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use plus_privacy::{check_kdf_params, write_atomic};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+
+/// Заголовок формата v2: `plus-vpn$2$argon2id$m=…,t=…,p=…$<salt>$<nonce>$<ciphertext>`.
+/// Всё до ciphertext передаётся в AES-GCM-SIV как AAD, поэтому подмена параметров
+/// или соли ломает расшифровку.
+const MAGIC: &str = "plus-vpn";
+const VERSION: u32 = 2;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Результат чтения файла: открытый текст и признак старого формата,
+/// который нужно перешифровать.
+pub(crate) struct Opened {
+    pub plaintext: Vec<u8>,
+    pub legacy: bool,
+}
+
+pub(crate) fn seal(plaintext: &[u8], passphrase: &str, params: KdfParams) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut salt);
+    rand::rng().fill_bytes(&mut nonce);
+    let header = format!(
+        "{MAGIC}${VERSION}$argon2id$m={},t={},p={}${}${}",
+        params.m_cost,
+        params.t_cost,
+        params.p_cost,
+        B64.encode(salt),
+        B64.encode(nonce)
+    );
+    let key = derive_key(passphrase, &salt, params)?;
+    let cipher = Aes256GcmSiv::new_from_slice(&key)?;
+    let data = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: header.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("encrypt failed"))?;
+    Ok(format!("{header}${}", B64.encode(data)))
+}
+
+pub(crate) fn open(blob: &str, passphrase: &str) -> Result<Opened> {
+    let blob = blob.trim();
+    if !blob.starts_with(MAGIC) {
+        return open_legacy(blob, passphrase);
+    }
+    let (header, data) = blob
+        .rsplit_once('$')
+        .ok_or_else(|| anyhow!("malformed secure blob"))?;
+    let parts: Vec<&str> = header.split('$').collect();
+    let [MAGIC, version, "argon2id", params, salt, nonce] = parts.as_slice() else {
+        bail!("malformed secure blob header");
+    };
+    if version.parse::<u32>()? != VERSION {
+        bail!("unsupported secure blob version {version}");
+    }
+    let params = parse_params(params)?;
+    let salt = B64.decode(salt)?;
+    let nonce = B64.decode(nonce)?;
+    if nonce.len() != NONCE_LEN {
+        bail!("malformed secure blob nonce");
+    }
+    let key = derive_key(passphrase, &salt, params)?;
+    let cipher = Aes256GcmSiv::new_from_slice(&key)?;
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &B64.decode(data)?,
+                aad: header.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("decrypt failed: wrong passphrase or corrupted file"))?;
+    Ok(Opened {
+        plaintext,
+        legacy: false,
+    })
+}
+
+/// Читает зашифрованный файл; файл старого формата перешифровывается на месте.
+pub(crate) fn read_file(path: &Path, passphrase: &str) -> Result<Vec<u8>> {
+    let opened = open(&fs::read_to_string(path)?, passphrase)?;
+    if opened.legacy {
+        write_atomic(
+            path,
+            seal(&opened.plaintext, passphrase, KdfParams::default())?.as_bytes(),
+        )?;
+    }
+    Ok(opened.plaintext)
+}
+
+/// Формат v1: `<nonce>:<ciphertext>`, ключ — несолёный SHA-256 пароля.
+/// Поддерживается только на чтение для миграции.
+fn open_legacy(blob: &str, passphrase: &str) -> Result<Opened> {
+    let (nonce, data) = blob
+        .split_once(':')
+        .ok_or_else(|| anyhow!("unknown secure blob format"))?;
+    let nonce = B64.decode(nonce)?;
+    if nonce.len() != NONCE_LEN {
+        bail!("malformed legacy nonce");
+    }
+    let key: [u8; 32] = Sha256::digest(passphrase.as_bytes()).into();
+    let cipher = Aes256GcmSiv::new_from_slice(&key)?;
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), B64.decode(data)?.as_slice())
+        .map_err(|_| anyhow!("decrypt failed: wrong passphrase or corrupted file"))?;
+    Ok(Opened {
+        plaintext,
+        legacy: true,
+    })
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<[u8; 32]> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|e| anyhow!("argon2 params: {e}"))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("argon2: {e}"))?;
+    Ok(key)
+}
+
+/// `m=…,t=…,p=…` ровно в этом порядке и в допустимых границах.
+fn parse_params(raw: &str) -> Result<KdfParams> {
+    let values = raw
+        .split(',')
+        .zip(["m", "t", "p"])
+        .map(|(kv, name)| match kv.split_once('=') {
+            Some((k, v)) if k == name => Ok(v.parse::<u32>()?),
+            _ => bail!("malformed kdf params"),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let [m_cost, t_cost, p_cost] = values[..] else {
+        bail!("malformed kdf params");
+    };
+    if raw.split(',').count() != 3 {
+        bail!("malformed kdf params");
+    }
+    check_kdf_params(m_cost, t_cost, p_cost)?;
+    Ok(KdfParams {
+        m_cost,
+        t_cost,
+        p_cost,
+    })
+}
+
+/// Файл формата v1 — для тестов миграции.
+#[cfg(test)]
+pub(crate) fn seal_legacy(plaintext: &[u8], passphrase: &str) -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut nonce);
+    let key: [u8; 32] = Sha256::digest(passphrase.as_bytes()).into();
+    let data = Aes256GcmSiv::new_from_slice(&key)
+        .unwrap()
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .unwrap();
+    format!("{}:{}", B64.encode(nonce), B64.encode(data))
+}